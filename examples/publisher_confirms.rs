@@ -3,7 +3,7 @@ use lapin::{
     options::*,
     protocol::{AMQPErrorKind, AMQPSoftError},
     types::FieldTable,
-    BasicProperties, Connection, ConnectionProperties,
+    BasicProperties, Connection, ConnectionProperties, DeliveryLatency,
 };
 use tracing::info;
 
@@ -134,8 +134,9 @@ fn main() {
                     routing_key: "unroutable-routing-key-for-tests".into(),
                     redelivered: false,
                     properties: BasicProperties::default().with_priority(42),
-                    data: payload.to_vec(),
+                    data: payload.to_vec().into(),
                     acker,
+                    latency: DeliveryLatency::default(),
                 },
                 reply_code: 312,
                 reply_text: "NO_ROUTE".into(),
@@ -8,11 +8,9 @@ use lapin::{
 #[tokio::main]
 async fn main() {
     let uri = "amqp://localhost:5672";
-    let options = ConnectionProperties::default()
-        // Use tokio executor and reactor.
-        // At the moment the reactor is only available for unix.
-        .with_executor(tokio_executor_trait::Tokio::current())
-        .with_reactor(tokio_reactor_trait::Tokio);
+    // Use tokio executor and reactor.
+    // At the moment the reactor is only available for unix.
+    let options = ConnectionProperties::default().with_tokio(tokio::runtime::Handle::current());
 
     let connection = Connection::connect(uri, options).await.unwrap();
     let channel = connection.create_channel().await.unwrap();
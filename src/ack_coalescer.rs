@@ -0,0 +1,261 @@
+use crate::{types::DeliveryTag, ErrorKind, PromiseResolver};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// The acks of a channel waiting to be sent together as one basic.ack with `multiple`.
+///
+/// An ack with `multiple` covers all the deliveries up to its tag which are still unacked, so
+/// it can only be used up to the first delivery which wasn't acked by the user. The deliveries
+/// in ack mode are thus tracked from the opening of the channel, which is why coalescing can't
+/// be enabled afterwards.
+#[derive(Clone, Default)]
+pub(crate) struct AckCoalescer(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    max_delay: Option<Duration>,
+    outstanding: BTreeSet<DeliveryTag>,
+    pending: BTreeMap<DeliveryTag, PromiseResolver<()>>,
+    flush_scheduled: bool,
+}
+
+/// The acks to send at the end of a coalescing window
+#[derive(Default)]
+pub(crate) struct AckBatch {
+    /// The highest tag which can be acked with `multiple`, and the acks it covers
+    pub(crate) multiple: Option<(DeliveryTag, Vec<PromiseResolver<()>>)>,
+    /// The acks to send one by one, as some deliveries before them weren't acked
+    pub(crate) single: Vec<(DeliveryTag, PromiseResolver<()>)>,
+}
+
+impl AckCoalescer {
+    /* Only called before the channel gets opened */
+    pub(crate) fn set_max_delay(&self, max_delay: Duration) {
+        self.lock_inner().max_delay = Some(max_delay);
+    }
+
+    pub(crate) fn max_delay(&self) -> Option<Duration> {
+        self.lock_inner().max_delay
+    }
+
+    pub(crate) fn delivered(&self, delivery_tag: DeliveryTag) {
+        let mut inner = self.lock_inner();
+        if inner.max_delay.is_some() {
+            inner.outstanding.insert(delivery_tag);
+        }
+    }
+
+    /// Queue an ack, returning whether a flush needs to be scheduled
+    pub(crate) fn push(&self, delivery_tag: DeliveryTag, resolver: PromiseResolver<()>) -> bool {
+        let mut inner = self.lock_inner();
+        inner.pending.insert(delivery_tag, resolver);
+        !std::mem::replace(&mut inner.flush_scheduled, true)
+    }
+
+    /// Forget about the deliveries which got acked, nacked or rejected without us
+    ///
+    /// The queued acks they cover are resolved right away, as sending them would be an error.
+    pub(crate) fn settled(&self, delivery_tag: DeliveryTag, multiple: bool) {
+        let mut inner = self.lock_inner();
+        let resolvers = if multiple && delivery_tag == 0 {
+            inner.outstanding.clear();
+            std::mem::take(&mut inner.pending).into_values().collect()
+        } else if multiple {
+            inner.outstanding = inner.outstanding.split_off(&(delivery_tag + 1));
+            let remaining = inner.pending.split_off(&(delivery_tag + 1));
+            std::mem::replace(&mut inner.pending, remaining)
+                .into_values()
+                .collect()
+        } else {
+            inner.outstanding.remove(&delivery_tag);
+            inner
+                .pending
+                .remove(&delivery_tag)
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        drop(inner);
+        for resolver in resolvers {
+            resolver.complete(Ok(()));
+        }
+    }
+
+    /// Take the queued acks, acking with `multiple` up to the first delivery which wasn't acked
+    pub(crate) fn take_batch(&self) -> AckBatch {
+        let mut inner = self.lock_inner();
+        inner.flush_scheduled = false;
+        let mut pending = std::mem::take(&mut inner.pending);
+        let covered = inner
+            .outstanding
+            .iter()
+            .take_while(|tag| pending.contains_key(tag))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut batch = AckBatch::default();
+        if let [.., frontier] = covered[..] {
+            if covered.len() > 1 {
+                inner.outstanding = inner.outstanding.split_off(&(frontier + 1));
+                let remaining = pending.split_off(&(frontier + 1));
+                let resolvers = std::mem::replace(&mut pending, remaining)
+                    .into_values()
+                    .collect();
+                batch.multiple = Some((frontier, resolvers));
+            }
+        }
+        batch.single = pending.into_iter().collect();
+        batch
+    }
+
+    /// Forget about all the deliveries, failing the queued acks as their tags are no longer valid
+    pub(crate) fn clear(&self) {
+        let mut inner = self.lock_inner();
+        inner.outstanding.clear();
+        let pending = std::mem::take(&mut inner.pending);
+        drop(inner);
+        for (delivery_tag, resolver) in pending {
+            resolver.complete(Err(ErrorKind::StaleDeliveryTag(delivery_tag).into()));
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for AckCoalescer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("AckCoalescer");
+        if let Ok(inner) = self.0.try_lock() {
+            debug
+                .field("max_delay", &inner.max_delay)
+                .field("outstanding", &inner.outstanding.len())
+                .field("pending", &inner.pending.len());
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ChannelOptions,
+        ConnectionProperties, Promise,
+    };
+    use futures_lite::StreamExt;
+
+    fn push(coalescer: &AckCoalescer, delivery_tag: DeliveryTag) -> Promise<()> {
+        let (promise, resolver) = Promise::new();
+        coalescer.push(delivery_tag, resolver);
+        promise
+    }
+
+    #[test]
+    fn frontier() {
+        let coalescer = AckCoalescer::default();
+        coalescer.set_max_delay(Duration::from_millis(1));
+        for delivery_tag in 1..=6 {
+            coalescer.delivered(delivery_tag);
+        }
+        let _promises = [1, 2, 3, 5, 6].map(|tag| push(&coalescer, tag));
+        let batch = coalescer.take_batch();
+        let (frontier, resolvers) = batch.multiple.unwrap();
+        assert_eq!(frontier, 3);
+        assert_eq!(resolvers.len(), 3);
+        assert_eq!(
+            batch.single.iter().map(|(tag, _)| *tag).collect::<Vec<_>>(),
+            vec![5, 6]
+        );
+
+        // 4 is still outstanding
+        let _promise = push(&coalescer, 5);
+        let batch = coalescer.take_batch();
+        assert!(batch.multiple.is_none());
+        assert_eq!(batch.single.len(), 1);
+
+        let promise = push(&coalescer, 4);
+        coalescer.settled(4, false);
+        assert_eq!(promise.try_wait(), Some(Ok(())));
+        let promise = push(&coalescer, 7);
+        coalescer.clear();
+        assert_eq!(
+            promise.try_wait(),
+            Some(Err(ErrorKind::StaleDeliveryTag(7).into()))
+        );
+    }
+
+    #[test]
+    fn coalesced_acks() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection
+                .create_channel_with(
+                    ChannelOptions::default().with_ack_coalescing(Duration::from_millis(20)),
+                )
+                .await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for _ in 0..5 {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        b"job",
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let mut consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut deliveries = Vec::new();
+            for _ in 0..5 {
+                deliveries.push(consumer.next().await.unwrap()?);
+            }
+            let unacked = deliveries.remove(3);
+            let acks = deliveries
+                .into_iter()
+                .map(|delivery| {
+                    async_global_executor::spawn(async move {
+                        delivery.ack(BasicAckOptions::default()).await
+                    })
+                })
+                .collect::<Vec<_>>();
+            for ack in acks {
+                assert!(ack.await?);
+            }
+            // The multiple ack didn't cover the unacked delivery
+            unacked
+                .nack(BasicNackOptions {
+                    requeue: true,
+                    ..BasicNackOptions::default()
+                })
+                .await?;
+            let redelivered = consumer.next().await.unwrap()?;
+            assert!(redelivered.redelivered);
+            assert!(redelivered.ack(BasicAckOptions::default()).await?);
+            channel.close(0, "").await?;
+            assert_eq!(broker.message_count("jobs"), Some(0));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
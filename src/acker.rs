@@ -1,4 +1,5 @@
 use crate::{
+    dedup::DedupMark,
     error_holder::ErrorHolder,
     internal_rpc::InternalRPCHandle,
     killswitch::KillSwitch,
@@ -6,21 +7,25 @@ use crate::{
     types::{ChannelId, DeliveryTag},
     Promise, PromiseResolver, Result,
 };
+use tracing::debug;
 
 #[derive(Clone, Debug)]
 pub struct Acker {
     channel_id: ChannelId,
     delivery_tag: DeliveryTag,
+    delivery_count: u64,
     internal_rpc: Option<InternalRPCHandle>,
     error: Option<ErrorHolder>,
     killswitch: KillSwitch,
     channel_killswitch: Option<KillSwitch>,
+    dedup: Option<DedupMark>,
 }
 
 impl Acker {
     pub(crate) fn new(
         channel_id: ChannelId,
         delivery_tag: DeliveryTag,
+        delivery_count: u64,
         internal_rpc: Option<InternalRPCHandle>,
         error: Option<ErrorHolder>,
         channel_killswitch: Option<KillSwitch>,
@@ -28,14 +33,20 @@ impl Acker {
         Self {
             channel_id,
             delivery_tag,
+            delivery_count,
             internal_rpc,
             error,
             killswitch: KillSwitch::default(),
             channel_killswitch,
+            dedup: None,
         }
     }
 
     pub async fn ack(&self, options: BasicAckOptions) -> Result<bool> {
+        // Before the broker sends the next delivery, which could be a duplicate of this one
+        if let Some(dedup) = self.dedup.as_ref() {
+            dedup.mark();
+        }
         self.rpc(|internal_rpc, resolver| {
             internal_rpc.basic_ack(
                 self.channel_id,
@@ -43,6 +54,7 @@ impl Acker {
                 options,
                 resolver,
                 self.error.clone(),
+                self.channel_killswitch.clone(),
             )
         })
         .await
@@ -56,6 +68,7 @@ impl Acker {
                 options,
                 resolver,
                 self.error.clone(),
+                self.channel_killswitch.clone(),
             )
         })
         .await
@@ -69,11 +82,27 @@ impl Acker {
                 options,
                 resolver,
                 self.error.clone(),
+                self.channel_killswitch.clone(),
             )
         })
         .await
     }
 
+    /// Requeue the message if it was delivered at most `budget` times, reject it without
+    /// requeuing otherwise, so that it gets dead lettered if its queue has a dead letter
+    /// exchange
+    ///
+    /// See [`Delivery::delivery_count`] for how the deliveries are counted.
+    ///
+    /// [`Delivery::delivery_count`]: ../message/struct.Delivery.html#method.delivery_count
+    pub async fn reject_or_dead_letter(&self, budget: u64) -> Result<bool> {
+        let requeue = self.delivery_count <= budget;
+        if !requeue {
+            debug!(delivery_tag=%self.delivery_tag, delivery_count=%self.delivery_count, budget, "requeue budget exhausted, dead lettering");
+        }
+        self.reject(BasicRejectOptions { requeue }).await
+    }
+
     async fn rpc<F: Fn(&InternalRPCHandle, PromiseResolver<()>)>(&self, f: F) -> Result<bool> {
         if self.poisoned() || !self.killswitch.kill() {
             return Ok(false);
@@ -89,16 +118,68 @@ impl Acker {
         Ok(true)
     }
 
+    /* Reject a message nobody will handle, without waiting for the broker */
+    pub(crate) fn reject_detached(&self, requeue: bool) {
+        debug!(delivery_tag=%self.delivery_tag, requeue, "rejecting unhandled message");
+        self.detached(|internal_rpc, resolver| {
+            internal_rpc.basic_reject(
+                self.channel_id,
+                self.delivery_tag,
+                BasicRejectOptions { requeue },
+                resolver,
+                self.error.clone(),
+                self.channel_killswitch.clone(),
+            )
+        });
+    }
+
+    /* Ack a message nobody needs to handle, without waiting for the broker */
+    pub(crate) fn ack_detached(&self) {
+        self.detached(|internal_rpc, resolver| {
+            internal_rpc.basic_ack(
+                self.channel_id,
+                self.delivery_tag,
+                BasicAckOptions::default(),
+                resolver,
+                self.error.clone(),
+                self.channel_killswitch.clone(),
+            )
+        });
+    }
+
+    fn detached<F: FnOnce(&InternalRPCHandle, PromiseResolver<()>)>(&self, f: F) {
+        if self.poisoned() || !self.killswitch.kill() {
+            return;
+        }
+        if let Some(internal_rpc) = self.internal_rpc.as_ref() {
+            let (promise, resolver) = Promise::new();
+            f(internal_rpc, resolver);
+            internal_rpc.register_internal_future(async move {
+                // Failing to settle it means the channel is gone, which requeues it anyway
+                let _ = promise.await;
+                Ok(())
+            });
+        }
+    }
+
     pub fn poisoned(&self) -> bool {
         self.channel_killswitch
             .as_ref()
-            .map_or(false, |ks| ks.killed())
+            .is_some_and(|ks| ks.killed())
     }
 
     pub fn usable(&self) -> bool {
         !self.poisoned() && !self.killswitch.killed()
     }
 
+    pub(crate) fn set_dedup(&mut self, dedup: DedupMark) {
+        self.dedup = Some(dedup);
+    }
+
+    pub(crate) fn set_delivery_count(&mut self, delivery_count: u64) {
+        self.delivery_count = delivery_count;
+    }
+
     pub(crate) fn invalidate(&self) {
         self.killswitch.kill();
     }
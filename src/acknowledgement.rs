@@ -1,4 +1,5 @@
 use crate::{
+    delivery_latency::{LatencyHistogram, LatencyRecorder},
     id_sequence::IdSequence,
     protocol::{AMQPError, AMQPSoftError},
     publisher_confirm::{Confirmation, PublisherConfirm},
@@ -10,6 +11,7 @@ use std::{
     collections::{HashMap, HashSet},
     fmt,
     sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
 };
 use tracing::trace;
 
@@ -19,10 +21,15 @@ pub(crate) struct Acknowledgements(Arc<Mutex<Inner>>);
 type AMQPResult = std::result::Result<(), AMQPError>;
 
 impl Acknowledgements {
-    pub(crate) fn new(channel_id: u16, returned_messages: ReturnedMessages) -> Self {
+    pub(crate) fn new(
+        channel_id: u16,
+        returned_messages: ReturnedMessages,
+        recorder: LatencyRecorder,
+    ) -> Self {
         Self(Arc::new(Mutex::new(Inner::new(
             channel_id,
             returned_messages,
+            recorder,
         ))))
     }
 
@@ -34,6 +41,19 @@ impl Acknowledgements {
         self.lock_inner().last.take()
     }
 
+    /// Moving average of the time between publishing a message and receiving its confirm.
+    pub(crate) fn latency(&self) -> Option<Duration> {
+        self.lock_inner().latency
+    }
+
+    pub(crate) fn confirm_latency(&self) -> LatencyHistogram {
+        self.lock_inner().confirm_latency.clone()
+    }
+
+    pub(crate) fn pending_count(&self) -> usize {
+        self.lock_inner().pending.len()
+    }
+
     pub(crate) fn ack(&self, delivery_tag: DeliveryTag) -> AMQPResult {
         self.lock_inner().drop_pending(delivery_tag, true)
     }
@@ -90,16 +110,28 @@ struct Inner {
     channel_id: u16,
     delivery_tag: IdSequence<DeliveryTag>,
     last: Option<Promise<()>>,
-    pending: HashMap<DeliveryTag, (PromiseResolver<Confirmation>, PromiseResolver<()>)>,
+    latency: Option<Duration>,
+    confirm_latency: LatencyHistogram,
+    recorder: LatencyRecorder,
+    pending: HashMap<DeliveryTag, Pending>,
     returned_messages: ReturnedMessages,
 }
 
+type Pending = (PromiseResolver<Confirmation>, PromiseResolver<()>, Instant);
+
 impl Inner {
-    fn new(channel_id: u16, returned_messages: ReturnedMessages) -> Self {
+    fn new(
+        channel_id: u16,
+        returned_messages: ReturnedMessages,
+        recorder: LatencyRecorder,
+    ) -> Self {
         Self {
             channel_id,
             delivery_tag: IdSequence::new(false),
             last: None,
+            latency: None,
+            confirm_latency: LatencyHistogram::default(),
+            recorder,
             pending: HashMap::default(),
             returned_messages,
         }
@@ -112,16 +144,13 @@ impl Inner {
         let (err_promise, err_resolver) = Promise::new();
         let promise = PublisherConfirm::new(promise, self.returned_messages.clone());
         self.last = Some(err_promise);
-        self.pending.insert(delivery_tag, (resolver, err_resolver));
+        self.pending
+            .insert(delivery_tag, (resolver, err_resolver, Instant::now()));
         promise
     }
 
-    fn complete_pending(
-        &mut self,
-        success: bool,
-        delivery_tag: DeliveryTag,
-        resolvers: (PromiseResolver<Confirmation>, PromiseResolver<()>),
-    ) {
+    fn complete_pending(&mut self, success: bool, delivery_tag: DeliveryTag, resolvers: Pending) {
+        self.record_latency(resolvers.2.elapsed());
         let returned_message = self.returned_messages.get_waiting_message().map(Box::new);
         resolvers.0.resolve(if success {
             Confirmation::Ack(returned_message)
@@ -133,6 +162,16 @@ impl Inner {
         }
     }
 
+    fn record_latency(&mut self, sample: Duration) {
+        self.confirm_latency.record(sample);
+        self.recorder.record_confirm(sample);
+        // Exponentially weighted moving average, giving 1/8 of the weight to the new sample
+        self.latency = Some(
+            self.latency
+                .map_or(sample, |latency| (latency * 7 + sample) / 8),
+        );
+    }
+
     fn drop_all(&mut self, success: bool) {
         for (delivery_tag, resolvers) in std::mem::take(&mut self.pending) {
             self.complete_pending(success, delivery_tag, resolvers);
@@ -186,6 +225,7 @@ impl Inner {
 
     fn reset(&mut self, error: Error) {
         self.delivery_tag = IdSequence::new(false);
+        self.latency = None;
         self.on_channel_error(error);
     }
 }
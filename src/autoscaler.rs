@@ -0,0 +1,578 @@
+//! Scale the number of workers of a [`ConsumerGroup`] with the backlog of its queue.
+//!
+//! The [`Autoscaler`] periodically gets the number of messages ready in the queue with a passive
+//! declare, and the time taken by the handlers of the workers. It adds a worker when the
+//! backlog gets larger than what the current workers should handle, or when the handlers get
+//! slower than the configured maximum while messages are waiting, and removes one when the
+//! backlog could be handled by fewer workers. The number of workers stays within the configured
+//! bounds, and each change is reported as a [`ScalingEvent`].
+//!
+//! ```rust,no_run
+//! use lapin::{
+//!     autoscaler::{Autoscaler, AutoscalerOptions},
+//!     consumer_group::{ConsumerGroup, ConsumerGroupOptions},
+//!     message::DeliveryResult,
+//!     options::BasicAckOptions,
+//!     Connection, ConnectionProperties,
+//! };
+//! use std::time::Duration;
+//!
+//! # async_global_executor::block_on(async {
+//! let connection =
+//!     Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+//! let channels = vec![connection.create_channel().await?];
+//! let group = ConsumerGroup::start(
+//!     &channels,
+//!     "jobs",
+//!     ConsumerGroupOptions::default().with_prefetch_budget(20),
+//! )
+//! .await?;
+//! let autoscaler = Autoscaler::start(
+//!     group,
+//!     AutoscalerOptions::default()
+//!         .with_max_workers(10)
+//!         .with_max_processing_latency(Duration::from_millis(500)),
+//!     |_worker| {
+//!         |delivery: DeliveryResult| async move {
+//!             if let Ok(Some(delivery)) = delivery {
+//!                 // Process the message
+//!                 delivery.ack(BasicAckOptions::default()).await.expect("ack");
+//!             }
+//!         }
+//!     },
+//! )
+//! .await?;
+//! autoscaler.on_scale(|event| println!("scaled from {} to {} workers", event.from, event.to));
+//! # autoscaler.stop().await?;
+//! # Ok::<(), lapin::Error>(())
+//! # });
+//! ```
+//!
+//! [`ConsumerGroup`]: ../consumer_group/struct.ConsumerGroup.html
+//! [`Autoscaler`]: ./struct.Autoscaler.html
+//! [`ScalingEvent`]: ./struct.ScalingEvent.html
+
+use crate::{
+    consumer_group::ConsumerGroup, message::DeliveryResult, types::MessageCount, Channel,
+    ConsumerDelegate, LatencyHistogram, Promise, PromiseResolver, Result,
+};
+use flume::{Receiver, Sender};
+use std::{
+    fmt,
+    future::{poll_fn, Future},
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::Poll,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// When an [`Autoscaler`] adds and removes workers.
+///
+/// [`Autoscaler`]: ./struct.Autoscaler.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AutoscalerOptions {
+    /// The fewest workers to keep, at least one
+    pub min_workers: usize,
+    /// The most workers to run
+    pub max_workers: usize,
+    /// How long to wait between two checks of the backlog, and thus between two scalings
+    pub interval: Duration,
+    /// How many ready messages each worker is expected to handle
+    ///
+    /// A worker is added when there are more, and one is removed when the backlog is under half
+    /// of what one less worker would handle.
+    pub backlog_per_worker: MessageCount,
+    /// Add a worker when the handlers take longer than that to process 95% of the messages
+    /// while some are waiting, and don't remove any meanwhile
+    pub max_processing_latency: Option<Duration>,
+}
+
+impl Default for AutoscalerOptions {
+    fn default() -> Self {
+        Self {
+            min_workers: 1,
+            max_workers: 8,
+            interval: Duration::from_secs(5),
+            backlog_per_worker: 100,
+            max_processing_latency: None,
+        }
+    }
+}
+
+impl AutoscalerOptions {
+    #[must_use]
+    pub fn with_min_workers(mut self, min_workers: usize) -> Self {
+        self.min_workers = min_workers;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_workers(mut self, max_workers: usize) -> Self {
+        self.max_workers = max_workers;
+        self
+    }
+
+    #[must_use]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    #[must_use]
+    pub fn with_backlog_per_worker(mut self, backlog_per_worker: MessageCount) -> Self {
+        self.backlog_per_worker = backlog_per_worker;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_processing_latency(mut self, max_processing_latency: Duration) -> Self {
+        self.max_processing_latency = Some(max_processing_latency);
+        self
+    }
+
+    /* The bounds, with at least one worker */
+    fn bounds(&self) -> (usize, usize) {
+        let min = self.min_workers.max(1);
+        (min, self.max_workers.max(min))
+    }
+}
+
+/// Why an [`Autoscaler`] changed the number of workers.
+///
+/// [`Autoscaler`]: ./struct.Autoscaler.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalingReason {
+    /// The backlog was larger than what the workers should handle
+    Backlog,
+    /// The handlers were slower than the maximum processing latency
+    Latency,
+    /// The backlog could be handled by fewer workers
+    Idle,
+}
+
+/// A change in the number of workers of an [`Autoscaler`].
+///
+/// [`Autoscaler`]: ./struct.Autoscaler.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScalingEvent {
+    pub from: usize,
+    pub to: usize,
+    pub reason: ScalingReason,
+    /// The number of messages ready in the queue
+    pub backlog: MessageCount,
+    /// The 95th percentile of the processing latency since the previous check, if any message
+    /// was processed
+    pub processing_latency: Option<Duration>,
+}
+
+type ScalingHandler = Box<dyn FnMut(ScalingEvent) + Send>;
+
+/// Adds and removes workers to a [`ConsumerGroup`], following the backlog of its queue.
+///
+/// It runs in the background on the executor of the group's first channel until it gets
+/// stopped, either explicitly or by being dropped, which cancels the consumers of the group.
+/// It stops on its own, also canceling them, if checking the backlog or scaling fails.
+///
+/// [`ConsumerGroup`]: ../consumer_group/struct.ConsumerGroup.html
+pub struct Autoscaler {
+    shared: Arc<Shared>,
+    stop: Sender<()>,
+    stopped: Promise<()>,
+}
+
+#[derive(Default)]
+struct Shared {
+    workers: AtomicUsize,
+    processing: Mutex<Processing>,
+    handler: Mutex<Option<ScalingHandler>>,
+}
+
+/* The processing latency since the start, and since the previous check */
+#[derive(Default)]
+struct Processing {
+    total: LatencyHistogram,
+    window: LatencyHistogram,
+}
+
+impl Autoscaler {
+    /// Give each worker of the group the handler built by `make_delegate` from its index, then
+    /// start scaling them
+    ///
+    /// The group is first brought within the bounds of the options. It must have at least one
+    /// channel.
+    pub async fn start<D, F>(
+        mut group: ConsumerGroup,
+        options: AutoscalerOptions,
+        mut make_delegate: F,
+    ) -> Result<Self>
+    where
+        D: ConsumerDelegate + 'static,
+        F: FnMut(usize) -> D + Send + 'static,
+    {
+        let Some(channel) = group.channels().first().cloned() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the consumer group has no channel",
+            )
+            .into());
+        };
+        let (min, max) = options.bounds();
+        while group.workers() > max {
+            group.remove_worker().await?;
+        }
+        while group.workers() < min {
+            group.add_worker().await?;
+        }
+        let shared = Arc::new(Shared::default());
+        shared.workers.store(group.workers(), Ordering::Relaxed);
+        group.set_delegates(|index| TimedDelegate {
+            delegate: make_delegate(index),
+            shared: shared.clone(),
+        });
+        let (stop, stop_receiver) = flume::bounded(1);
+        let (stopped, resolver) = Promise::new();
+        let scaler = Scaler {
+            group,
+            options,
+            make_delegate,
+            shared: shared.clone(),
+            channel: channel.clone(),
+        };
+        channel.spawn(scaler.run(stop_receiver, resolver));
+        Ok(Self {
+            shared,
+            stop,
+            stopped,
+        })
+    }
+
+    /// The current number of workers
+    pub fn workers(&self) -> usize {
+        self.shared.workers.load(Ordering::Relaxed)
+    }
+
+    /// The time taken by the handlers to process each message since the start
+    pub fn processing_latency(&self) -> LatencyHistogram {
+        self.shared.lock_processing().total.clone()
+    }
+
+    /// Call `handler` each time the number of workers changes
+    pub fn on_scale<H: FnMut(ScalingEvent) + Send + 'static>(&self, handler: H) {
+        *self
+            .shared
+            .handler
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+    }
+
+    /// Stop scaling, and cancel the consumers of the group
+    ///
+    /// Returns the error which stopped the autoscaler if it stopped on its own.
+    pub async fn stop(self) -> Result<()> {
+        // Fails if it already stopped on its own
+        let _ = self.stop.send(());
+        self.stopped.await
+    }
+}
+
+impl fmt::Debug for Autoscaler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Autoscaler")
+            .field("workers", &self.workers())
+            .finish()
+    }
+}
+
+impl Shared {
+    fn record(&self, latency: Duration) {
+        let mut processing = self.lock_processing();
+        processing.total.record(latency);
+        processing.window.record(latency);
+    }
+
+    fn take_window(&self) -> LatencyHistogram {
+        std::mem::take(&mut self.lock_processing().window)
+    }
+
+    fn notify(&self, event: ScalingEvent) {
+        if let Some(handler) = self
+            .handler
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+        {
+            handler(event);
+        }
+    }
+
+    fn lock_processing(&self) -> MutexGuard<'_, Processing> {
+        self.processing.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/* Times the handling of each delivery by the wrapped delegate */
+struct TimedDelegate<D> {
+    delegate: D,
+    shared: Arc<Shared>,
+}
+
+impl<D: ConsumerDelegate> ConsumerDelegate for TimedDelegate<D> {
+    fn on_new_delivery(
+        &self,
+        delivery: DeliveryResult,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let timed = matches!(delivery, Ok(Some(_)));
+        let handled = self.delegate.on_new_delivery(delivery);
+        let shared = self.shared.clone();
+        Box::pin(async move {
+            let start = Instant::now();
+            handled.await;
+            if timed {
+                shared.record(start.elapsed());
+            }
+        })
+    }
+
+    fn drop_prefetched_messages(&self) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.delegate.drop_prefetched_messages()
+    }
+}
+
+/* The background task of an Autoscaler */
+struct Scaler<F> {
+    group: ConsumerGroup,
+    options: AutoscalerOptions,
+    make_delegate: F,
+    shared: Arc<Shared>,
+    channel: Channel,
+}
+
+impl<D, F> Scaler<F>
+where
+    D: ConsumerDelegate + 'static,
+    F: FnMut(usize) -> D + Send + 'static,
+{
+    async fn run(mut self, stop: Receiver<()>, resolver: PromiseResolver<()>) {
+        let mut res = Ok(());
+        while !self.stopped_while_waiting(&stop).await && self.group.consumers().count() > 0 {
+            if let Err(err) = self.check().await {
+                warn!(queue=%self.group.queue(), error=%err, "autoscaler failed, stopping it");
+                res = Err(err);
+                break;
+            }
+        }
+        let canceled = self.group.cancel().await;
+        resolver.complete(res.and(canceled));
+    }
+
+    /* Whether the autoscaler got stopped or dropped before the end of the interval */
+    async fn stopped_while_waiting(&mut self, stop: &Receiver<()>) -> bool {
+        let mut sleep = Box::pin(self.channel.sleep(self.options.interval));
+        let mut stopped = Box::pin(stop.recv_async());
+        poll_fn(|cx| {
+            if stopped.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(true);
+            }
+            sleep.as_mut().poll(cx).map(|()| false)
+        })
+        .await
+    }
+
+    async fn check(&mut self) -> Result<()> {
+        let backlog = self
+            .channel
+            .queue_depth(self.group.queue().as_str())
+            .await?
+            .message_count();
+        let processing_latency = self.shared.take_window().percentile(95.0);
+        let from = self.group.workers();
+        let Some((to, reason)) = scaling(&self.options, from, backlog, processing_latency) else {
+            return Ok(());
+        };
+        while self.group.workers() < to {
+            let index = self.group.workers();
+            let delegate = TimedDelegate {
+                delegate: (self.make_delegate)(index),
+                shared: self.shared.clone(),
+            };
+            self.group.add_worker().await?.set_delegate(delegate);
+        }
+        while self.group.workers() > to {
+            self.group.remove_worker().await?;
+        }
+        self.shared.workers.store(to, Ordering::Relaxed);
+        debug!(queue=%self.group.queue(), from, to, ?reason, backlog, ?processing_latency, "autoscaler scaled consumer group");
+        self.shared.notify(ScalingEvent {
+            from,
+            to,
+            reason,
+            backlog,
+            processing_latency,
+        });
+        Ok(())
+    }
+}
+
+/* The number of workers to switch to, if any */
+fn scaling(
+    options: &AutoscalerOptions,
+    workers: usize,
+    backlog: MessageCount,
+    processing_latency: Option<Duration>,
+) -> Option<(usize, ScalingReason)> {
+    let (min, max) = options.bounds();
+    let backlog = u64::from(backlog);
+    let handled_by = |workers: usize| workers as u64 * u64::from(options.backlog_per_worker);
+    let slow = processing_latency
+        .zip(options.max_processing_latency)
+        .is_some_and(|(latency, max_latency)| latency > max_latency);
+    if workers < max {
+        if backlog > handled_by(workers) {
+            return Some((workers + 1, ScalingReason::Backlog));
+        }
+        if slow && backlog > 0 {
+            return Some((workers + 1, ScalingReason::Latency));
+        }
+    }
+    if workers > min && !slow && backlog < handled_by(workers - 1) / 2 {
+        return Some((workers - 1, ScalingReason::Idle));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        consumer_group::ConsumerGroupOptions, message::Delivery, options::*, testing::MockBroker,
+        types::FieldTable, BasicProperties, ConnectionProperties,
+    };
+
+    #[test]
+    fn scaling_decisions() {
+        let options = AutoscalerOptions::default()
+            .with_min_workers(2)
+            .with_max_workers(4)
+            .with_backlog_per_worker(10)
+            .with_max_processing_latency(Duration::from_millis(100));
+        let slow = Some(Duration::from_millis(200));
+        let fast = Some(Duration::from_millis(50));
+        assert_eq!(
+            scaling(&options, 2, 21, fast),
+            Some((3, ScalingReason::Backlog))
+        );
+        assert_eq!(scaling(&options, 4, 1000, fast), None);
+        assert_eq!(
+            scaling(&options, 2, 1, slow),
+            Some((3, ScalingReason::Latency))
+        );
+        assert_eq!(
+            scaling(&options, 3, 0, fast),
+            Some((2, ScalingReason::Idle))
+        );
+        assert_eq!(scaling(&options, 3, 0, slow), None);
+        assert_eq!(
+            scaling(&options, 3, 4, None),
+            Some((2, ScalingReason::Idle))
+        );
+        assert_eq!(scaling(&options, 3, 10, fast), None);
+        assert_eq!(scaling(&options, 2, 0, None), None);
+    }
+
+    #[test]
+    fn follows_backlog() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for _ in 0..10 {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        b"job",
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let consumers = connection.create_channel().await?;
+            let group = ConsumerGroup::start(
+                &[consumers],
+                "jobs",
+                ConsumerGroupOptions::default().with_prefetch_budget(1),
+            )
+            .await?;
+            let (sender, receiver) = flume::unbounded::<Delivery>();
+            let autoscaler = Autoscaler::start(
+                group,
+                AutoscalerOptions::default()
+                    .with_max_workers(3)
+                    .with_interval(Duration::from_millis(10))
+                    .with_backlog_per_worker(2),
+                move |_| {
+                    let sender = sender.clone();
+                    move |delivery: DeliveryResult| {
+                        let sender = sender.clone();
+                        async move {
+                            if let Ok(Some(delivery)) = delivery {
+                                let _ = sender.send(delivery);
+                            }
+                        }
+                    }
+                },
+            )
+            .await?;
+            let events = Arc::new(Mutex::new(Vec::new()));
+            autoscaler.on_scale({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push(event)
+            });
+
+            while autoscaler.workers() < 3 {
+                channel.sleep(Duration::from_millis(5)).await;
+            }
+            for _ in 0..10 {
+                let delivery = receiver.recv_async().await.unwrap();
+                delivery.ack(BasicAckOptions::default()).await?;
+            }
+            while autoscaler.workers() > 1 {
+                channel.sleep(Duration::from_millis(5)).await;
+            }
+            assert_eq!(autoscaler.processing_latency().count(), 10);
+            let events = events
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|event| (event.from, event.to, event.reason))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                events,
+                vec![
+                    (1, 2, ScalingReason::Backlog),
+                    (2, 3, ScalingReason::Backlog),
+                    (3, 2, ScalingReason::Idle),
+                    (2, 1, ScalingReason::Idle),
+                ]
+            );
+            autoscaler.stop().await?;
+            assert_eq!(broker.consumer_count("jobs"), Some(0));
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+}
@@ -3,7 +3,7 @@ use crate::{
     options::BasicGetOptions,
     topology_internal::BasicGetDefinitionInternal,
     types::{PayloadSize, ShortString},
-    BasicProperties, PromiseResolver,
+    BasicProperties, DeliveryLatency, PromiseResolver,
 };
 use std::{
     fmt,
@@ -29,9 +29,10 @@ impl BasicGetDelivery {
         &self,
         size: PayloadSize,
         properties: BasicProperties,
+        latency: DeliveryLatency,
     ) {
         self.lock_inner()
-            .handle_content_header_frame(size, properties);
+            .handle_content_header_frame(size, properties, latency);
     }
 
     pub(crate) fn handle_body_frame(&self, remaining_size: PayloadSize, payload: Vec<u8>) {
@@ -79,9 +80,15 @@ impl Inner {
         });
     }
 
-    fn handle_content_header_frame(&mut self, size: PayloadSize, properties: BasicProperties) {
+    fn handle_content_header_frame(
+        &mut self,
+        size: PayloadSize,
+        properties: BasicProperties,
+        latency: DeliveryLatency,
+    ) {
         if let Some(inner) = self.0.as_mut() {
-            inner.message.properties = properties;
+            inner.message.set_properties(properties);
+            inner.message.latency = latency;
         }
         if size == 0 {
             self.new_delivery_complete();
@@ -90,7 +97,7 @@ impl Inner {
 
     fn handle_body_frame(&mut self, remaining_size: PayloadSize, payload: Vec<u8>) {
         if let Some(inner) = self.0.as_mut() {
-            inner.message.receive_content(payload);
+            inner.message.receive_content(payload, remaining_size);
         }
         if remaining_size == 0 {
             self.new_delivery_complete();
@@ -99,7 +106,12 @@ impl Inner {
 
     fn new_delivery_complete(&mut self) {
         if let Some(inner) = self.0.take() {
-            inner.resolver.resolve(Some(inner.message));
+            // The basic_get future was dropped: give the message back instead of leaving it unacked
+            if let Err(Some(message)) = inner.resolver.try_resolve(Some(inner.message)) {
+                if !inner.options.no_ack {
+                    message.delivery.acker.reject_detached(true);
+                }
+            }
         }
     }
 }
@@ -110,3 +122,63 @@ struct InnerData {
     message: BasicGetMessage,
     resolver: PromiseResolver<Option<BasicGetMessage>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{options::*, testing::MockBroker, types::FieldTable, ConnectionProperties};
+    use futures_lite::future;
+    use std::time::Duration;
+
+    #[test]
+    fn abandoned_get_requeues_the_message() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    Default::default(),
+                )
+                .await?
+                .await?;
+
+            // Sending basic.get, then giving up on it before the message arrives
+            let mut get = Box::pin(channel.basic_get("jobs", BasicGetOptions::default()));
+            assert!(future::poll_once(&mut get).await.is_none());
+            drop(get);
+
+            let mut message = None;
+            for _ in 0..1000 {
+                message = channel
+                    .basic_get("jobs", BasicGetOptions::default())
+                    .await?;
+                if message.is_some() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let message = message.expect("the message should have been requeued");
+            assert!(message.delivery.redelivered);
+            message.delivery.ack(BasicAckOptions::default()).await?;
+            assert!(channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .is_none());
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    timestamp,
+    types::{FieldTable, ShortShortUInt, ShortString},
+    BasicProperties, Error, ErrorKind, Result,
+};
+use std::time::Duration;
+use uuid::Uuid;
+
+/* A shortstr is prefixed with its length on a single byte */
+const SHORT_STRING_MAX_LEN: usize = 255;
+
+/// Build [`BasicProperties`], checking their values before the message is published instead of
+/// having the broker close the channel or the connection because of them.
+///
+/// The first invalid value is reported by [`build`].
+///
+/// ```rust
+/// use lapin::BasicPropertiesBuilder;
+/// use std::time::Duration;
+///
+/// let properties = BasicPropertiesBuilder::new()
+///     .content_type("application/json")
+///     .correlation_id_uuid()
+///     .expiration_ms(Duration::from_secs(60))
+///     .persistent()
+///     .build()
+///     .unwrap();
+/// assert_eq!(properties.expiration(), &Some("60000".into()));
+/// assert_eq!(properties.delivery_mode(), &Some(2));
+/// ```
+///
+/// [`build`]: #method.build
+#[derive(Clone, Debug, Default)]
+pub struct BasicPropertiesBuilder {
+    properties: BasicProperties,
+    error: Option<Error>,
+}
+
+impl BasicPropertiesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        if let Some(value) = self.short_string("content_type", content_type) {
+            self.properties = self.properties.with_content_type(value);
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn content_encoding(mut self, content_encoding: &str) -> Self {
+        if let Some(value) = self.short_string("content_encoding", content_encoding) {
+            self.properties = self.properties.with_content_encoding(value);
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn headers(mut self, headers: FieldTable) -> Self {
+        if let Some(key) = headers
+            .inner()
+            .keys()
+            .find(|key| key.as_str().len() > SHORT_STRING_MAX_LEN)
+        {
+            let reason = format!("header name {} is too long", key.as_str());
+            self.fail("headers", reason);
+        }
+        self.properties = self.properties.with_headers(headers);
+        self
+    }
+
+    /// Either 1 for transient messages or 2 for persistent ones
+    #[must_use]
+    pub fn delivery_mode(mut self, delivery_mode: ShortShortUInt) -> Self {
+        if ![1, 2].contains(&delivery_mode) {
+            let reason = format!(
+                "{} is neither 1 (transient) nor 2 (persistent)",
+                delivery_mode
+            );
+            self.fail("delivery_mode", reason);
+        }
+        self.properties = self.properties.with_delivery_mode(delivery_mode);
+        self
+    }
+
+    /// Have the broker store the message on disk, in durable queues
+    #[must_use]
+    pub fn persistent(self) -> Self {
+        self.delivery_mode(2)
+    }
+
+    #[must_use]
+    pub fn priority(mut self, priority: ShortShortUInt) -> Self {
+        self.properties = self.properties.with_priority(priority);
+        self
+    }
+
+    #[must_use]
+    pub fn correlation_id(mut self, correlation_id: &str) -> Self {
+        if let Some(value) = self.short_string("correlation_id", correlation_id) {
+            self.properties = self.properties.with_correlation_id(value);
+        }
+        self
+    }
+
+    /// Use a random UUID as correlation id
+    #[must_use]
+    pub fn correlation_id_uuid(self) -> Self {
+        self.correlation_id(&Uuid::new_v4().to_string())
+    }
+
+    #[must_use]
+    pub fn reply_to(mut self, reply_to: &str) -> Self {
+        if let Some(value) = self.short_string("reply_to", reply_to) {
+            self.properties = self.properties.with_reply_to(value);
+        }
+        self
+    }
+
+    /// The message TTL in milliseconds, as a string
+    #[must_use]
+    pub fn expiration(mut self, expiration: &str) -> Self {
+        if expiration.is_empty() || !expiration.bytes().all(|b| b.is_ascii_digit()) {
+            let reason = format!("{:?} is not a number of milliseconds", expiration);
+            self.fail("expiration", reason);
+        }
+        if let Some(value) = self.short_string("expiration", expiration) {
+            self.properties = self.properties.with_expiration(value);
+        }
+        self
+    }
+
+    /// The message TTL, rounded down to the millisecond
+    #[must_use]
+    pub fn expiration_ms(self, ttl: Duration) -> Self {
+        self.expiration(&ttl.as_millis().to_string())
+    }
+
+    #[must_use]
+    pub fn message_id(mut self, message_id: &str) -> Self {
+        if let Some(value) = self.short_string("message_id", message_id) {
+            self.properties = self.properties.with_message_id(value);
+        }
+        self
+    }
+
+    /// Use a random UUID as message id
+    #[must_use]
+    pub fn message_id_uuid(self) -> Self {
+        self.message_id(&Uuid::new_v4().to_string())
+    }
+
+    /// Seconds since the UNIX epoch
+    #[must_use]
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.properties = self.properties.with_timestamp(timestamp);
+        self
+    }
+
+    /// Use the current time as timestamp
+    #[must_use]
+    pub fn timestamp_now(self) -> Self {
+        self.timestamp(timestamp::now())
+    }
+
+    /// The message type
+    #[must_use]
+    pub fn kind(mut self, kind: &str) -> Self {
+        if let Some(value) = self.short_string("type", kind) {
+            self.properties = self.properties.with_type(value);
+        }
+        self
+    }
+
+    /// RabbitMQ rejects the message if this isn't the user the connection authenticated as
+    #[must_use]
+    pub fn user_id(mut self, user_id: &str) -> Self {
+        if let Some(value) = self.short_string("user_id", user_id) {
+            self.properties = self.properties.with_user_id(value);
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn app_id(mut self, app_id: &str) -> Self {
+        if let Some(value) = self.short_string("app_id", app_id) {
+            self.properties = self.properties.with_app_id(value);
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn cluster_id(mut self, cluster_id: &str) -> Self {
+        if let Some(value) = self.short_string("cluster_id", cluster_id) {
+            self.properties = self.properties.with_cluster_id(value);
+        }
+        self
+    }
+
+    /// Get the properties, or the first invalid value which was given
+    pub fn build(self) -> Result<BasicProperties> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.properties),
+        }
+    }
+
+    fn short_string(&mut self, property: &'static str, value: &str) -> Option<ShortString> {
+        if value.len() > SHORT_STRING_MAX_LEN {
+            let reason = format!(
+                "{} bytes long, at most {} are allowed",
+                value.len(),
+                SHORT_STRING_MAX_LEN
+            );
+            self.fail(property, reason);
+            return None;
+        }
+        Some(value.into())
+    }
+
+    fn fail(&mut self, property: &'static str, reason: String) {
+        self.error
+            .get_or_insert_with(|| ErrorKind::InvalidProperty(property, reason).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_error_wins() {
+        let error = BasicPropertiesBuilder::new()
+            .app_id(&"a".repeat(256))
+            .delivery_mode(3)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ErrorKind::InvalidProperty("app_id", "256 bytes long, at most 255 are allowed".into())
+                .into()
+        );
+        assert_eq!(
+            BasicPropertiesBuilder::new().expiration("-1").build(),
+            Err(ErrorKind::InvalidProperty(
+                "expiration",
+                "\"-1\" is not a number of milliseconds".into()
+            )
+            .into())
+        );
+        let properties = BasicPropertiesBuilder::new()
+            .app_id(&"a".repeat(255))
+            .message_id_uuid()
+            .kind("order.created")
+            .build()
+            .unwrap();
+        assert_eq!(properties.kind(), &Some("order.created".into()));
+        assert!(properties.message_id().is_some());
+    }
+}
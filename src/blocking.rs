@@ -0,0 +1,298 @@
+//! A blocking API on top of the async one
+//!
+//! This is meant for CLI tools and threads that don't run an async runtime. Each call blocks
+//! the current thread until the underlying future completes. The connection still needs an
+//! executor and a reactor to run its background tasks, through the default runtime or the
+//! ones given in the [`ConnectionProperties`].
+//!
+//! ```rust,no_run
+//! use lapin::{blocking::BlockingConnection, options::*, types::FieldTable, BasicProperties, ConnectionProperties};
+//!
+//! let connection = BlockingConnection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())?;
+//! let channel = connection.create_channel()?;
+//! channel.queue_declare("hello", QueueDeclareOptions::default(), FieldTable::default())?;
+//! channel.basic_publish("", "hello", BasicPublishOptions::default(), b"Hello world!", BasicProperties::default())?;
+//! for delivery in channel.basic_consume("hello", "my_consumer", BasicConsumeOptions::default(), FieldTable::default())? {
+//!     let delivery = delivery?;
+//!     channel.basic_ack(delivery.delivery_tag, BasicAckOptions::default())?;
+//! }
+//! # Ok::<(), lapin::Error>(())
+//! ```
+//!
+//! [`ConnectionProperties`]: ../struct.ConnectionProperties.html
+
+use crate::{
+    message::{BasicGetMessage, Delivery},
+    options::*,
+    publisher_confirm::Confirmation,
+    types::{FieldTable, LongLongUInt, MessageCount, ReplyCode, ShortUInt},
+    BasicProperties, Channel, Connection, ConnectionProperties, Consumer, Envelope, ExchangeKind,
+    Queue, Result,
+};
+use futures_core::Stream;
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    task::{Context, Poll},
+    thread,
+};
+
+/// Block the current thread until the given future completes.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let thread = thread::current();
+    let waker = waker_fn::waker_fn(move || thread.unpark());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// A blocking wrapper around a [`Connection`]
+///
+/// [`Connection`]: ../struct.Connection.html
+#[derive(Debug)]
+pub struct BlockingConnection(Connection);
+
+impl BlockingConnection {
+    /// Connect to an AMQP Server, see [`Connection::connect`].
+    ///
+    /// [`Connection::connect`]: ../struct.Connection.html#method.connect
+    pub fn connect(uri: &str, options: ConnectionProperties) -> Result<Self> {
+        block_on(Connection::connect(uri, options)).map(Self)
+    }
+
+    pub fn create_channel(&self) -> Result<BlockingChannel> {
+        block_on(self.0.create_channel()).map(BlockingChannel)
+    }
+
+    pub fn close(&self, reply_code: ReplyCode, reply_text: &str) -> Result<()> {
+        block_on(self.0.close(reply_code, reply_text))
+    }
+
+    /// Access the underlying async connection
+    pub fn inner(&self) -> &Connection {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Connection {
+        self.0
+    }
+}
+
+impl From<Connection> for BlockingConnection {
+    fn from(connection: Connection) -> Self {
+        Self(connection)
+    }
+}
+
+/// A blocking wrapper around a [`Channel`]
+///
+/// [`Channel`]: ../struct.Channel.html
+#[derive(Clone, Debug)]
+pub struct BlockingChannel(Channel);
+
+impl BlockingChannel {
+    pub fn close(&self, reply_code: ReplyCode, reply_text: &str) -> Result<()> {
+        block_on(self.0.close(reply_code, reply_text))
+    }
+
+    pub fn basic_qos(&self, prefetch_count: ShortUInt, options: BasicQosOptions) -> Result<()> {
+        block_on(self.0.basic_qos(prefetch_count, options))
+    }
+
+    pub fn confirm_select(&self, options: ConfirmSelectOptions) -> Result<()> {
+        block_on(self.0.confirm_select(options))
+    }
+
+    pub fn exchange_declare(
+        &self,
+        exchange: &str,
+        kind: ExchangeKind,
+        options: ExchangeDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        block_on(self.0.exchange_declare(exchange, kind, options, arguments))
+    }
+
+    pub fn exchange_delete(&self, exchange: &str, options: ExchangeDeleteOptions) -> Result<()> {
+        block_on(self.0.exchange_delete(exchange, options))
+    }
+
+    pub fn queue_declare(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<Queue> {
+        block_on(self.0.queue_declare(queue, options, arguments))
+    }
+
+    pub fn queue_bind(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        options: QueueBindOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        block_on(
+            self.0
+                .queue_bind(queue, exchange, routing_key, options, arguments),
+        )
+    }
+
+    pub fn queue_unbind(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        block_on(self.0.queue_unbind(queue, exchange, routing_key, arguments))
+    }
+
+    pub fn queue_purge(&self, queue: &str, options: QueuePurgeOptions) -> Result<MessageCount> {
+        block_on(self.0.queue_purge(queue, options))
+    }
+
+    pub fn queue_delete(&self, queue: &str, options: QueueDeleteOptions) -> Result<MessageCount> {
+        block_on(self.0.queue_delete(queue, options))
+    }
+
+    /// Publish a message and wait for the broker to confirm it, if the channel is in confirm
+    /// mode.
+    pub fn basic_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<Confirmation> {
+        block_on(async {
+            self.0
+                .basic_publish(exchange, routing_key, options, payload, properties)
+                .await?
+                .await
+        })
+    }
+
+    /// Publish a message described by an [`Envelope`] and wait for the broker to confirm it, if
+    /// the channel is in confirm mode.
+    ///
+    /// [`Envelope`]: ../struct.Envelope.html
+    pub fn publish(&self, envelope: &Envelope) -> Result<Confirmation> {
+        block_on(async { self.0.publish(envelope).await?.await })
+    }
+
+    pub fn basic_get(
+        &self,
+        queue: &str,
+        options: BasicGetOptions,
+    ) -> Result<Option<BasicGetMessage>> {
+        block_on(self.0.basic_get(queue, options))
+    }
+
+    pub fn basic_consume(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+    ) -> Result<BlockingConsumer> {
+        block_on(
+            self.0
+                .basic_consume(queue, consumer_tag, options, arguments),
+        )
+        .map(BlockingConsumer)
+    }
+
+    pub fn basic_cancel(&self, consumer_tag: &str, options: BasicCancelOptions) -> Result<()> {
+        block_on(self.0.basic_cancel(consumer_tag, options))
+    }
+
+    pub fn basic_ack(&self, delivery_tag: LongLongUInt, options: BasicAckOptions) -> Result<()> {
+        block_on(self.0.basic_ack(delivery_tag, options))
+    }
+
+    pub fn basic_nack(&self, delivery_tag: LongLongUInt, options: BasicNackOptions) -> Result<()> {
+        block_on(self.0.basic_nack(delivery_tag, options))
+    }
+
+    pub fn basic_reject(
+        &self,
+        delivery_tag: LongLongUInt,
+        options: BasicRejectOptions,
+    ) -> Result<()> {
+        block_on(self.0.basic_reject(delivery_tag, options))
+    }
+
+    /// Access the underlying async channel
+    pub fn inner(&self) -> &Channel {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Channel {
+        self.0
+    }
+}
+
+impl From<Channel> for BlockingChannel {
+    fn from(channel: Channel) -> Self {
+        Self(channel)
+    }
+}
+
+/// An iterator over the deliveries of a [`Consumer`], blocking while waiting for the next one.
+///
+/// The iteration ends once the consumer is canceled.
+///
+/// [`Consumer`]: ../struct.Consumer.html
+#[derive(Debug)]
+pub struct BlockingConsumer(Consumer);
+
+impl BlockingConsumer {
+    /// Access the underlying async consumer
+    pub fn inner(&self) -> &Consumer {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Consumer {
+        self.0
+    }
+}
+
+impl Iterator for BlockingConsumer {
+    type Item = Result<Delivery>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut consumer = Pin::new(&mut self.0);
+        block_on(std::future::poll_fn(|cx| consumer.as_mut().poll_next(cx)))
+    }
+}
+
+impl From<Consumer> for BlockingConsumer {
+    fn from(consumer: Consumer) -> Self {
+        Self(consumer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_on_wakes_up() {
+        let (sender, receiver) = flume::bounded(1);
+        let handle = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(10));
+            sender.send(42).unwrap();
+        });
+        assert_eq!(block_on(receiver.recv_async()), Ok(42));
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use crate::capabilities::Capabilities;
+
+/// The kind of broker a [`Connection`] talks to, set with
+/// [`ConnectionProperties::with_broker_profile`].
+///
+/// lapin assumes a RabbitMQ broker by default, advertising and using its extensions to the
+/// AMQP 0.9.1 protocol. Other brokers speaking AMQP 0.9.1, such as Qpid, may reject the
+/// handshake or close the connection with a protocol error when they see them.
+///
+/// [`Connection`]: ./struct.Connection.html
+/// [`ConnectionProperties::with_broker_profile`]: ./struct.ConnectionProperties.html#method.with_broker_profile
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrokerProfile {
+    /// RabbitMQ, including the managed offerings based on it such as Amazon MQ for RabbitMQ
+    #[default]
+    RabbitMq,
+    /// Any broker implementing AMQP 0.9.1, which gets no capabilities table during the
+    /// handshake.
+    ///
+    /// The extensions are only used when the broker advertised them in its own capabilities:
+    /// - `basic_nack` falls back to `basic.reject` for a single delivery, and fails with
+    ///   `ErrorKind::MissingCapability` when `multiple` is set;
+    /// - `confirm_select`, `exchange_bind`, `exchange_unbind` and consuming from the direct
+    ///   reply-to pseudo queue fail with `ErrorKind::MissingCapability` instead of sending a
+    ///   method which would close the channel or the connection.
+    ///
+    /// The locale of the broker is used when it doesn't support the requested one.
+    Generic,
+}
+
+impl BrokerProfile {
+    /// The capabilities advertised to brokers of this kind by default
+    pub fn capabilities(&self) -> Capabilities {
+        match self {
+            BrokerProfile::RabbitMq => Capabilities::default(),
+            BrokerProfile::Generic => Capabilities::none(),
+        }
+    }
+
+    /* Whether the extensions are only used when the broker advertised them */
+    pub(crate) fn degrades(&self) -> bool {
+        *self == BrokerProfile::Generic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+        ErrorKind,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn graceful_degradation() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default().with_capabilities(Capabilities::none());
+            let connection = broker
+                .connect(
+                    ConnectionProperties::default().with_broker_profile(BrokerProfile::Generic),
+                )
+                .await?;
+            let channel = connection.create_channel().await?;
+            let missing = |capability: &str| ErrorKind::MissingCapability(capability.into()).into();
+            assert_eq!(
+                channel
+                    .confirm_select(ConfirmSelectOptions::default())
+                    .await
+                    .unwrap_err(),
+                missing("publisher_confirms")
+            );
+            assert_eq!(
+                channel
+                    .exchange_bind(
+                        "amq.fanout",
+                        "amq.direct",
+                        "",
+                        ExchangeBindOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+                    .unwrap_err(),
+                missing("exchange_exchange_bindings")
+            );
+            assert_eq!(
+                channel
+                    .basic_consume(
+                        "amq.rabbitmq.reply-to",
+                        "",
+                        BasicConsumeOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await
+                    .unwrap_err(),
+                missing("direct_reply_to")
+            );
+
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default(),
+                )
+                .await?;
+            let mut consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let delivery = consumer.next().await.unwrap()?;
+            assert_eq!(
+                channel
+                    .basic_nack(
+                        delivery.delivery_tag,
+                        BasicNackOptions {
+                            multiple: true,
+                            requeue: true,
+                        },
+                    )
+                    .await
+                    .unwrap_err(),
+                missing("basic.nack")
+            );
+            // Rejected instead
+            delivery
+                .nack(BasicNackOptions {
+                    requeue: true,
+                    ..BasicNackOptions::default()
+                })
+                .await?;
+            let redelivered = consumer.next().await.unwrap()?;
+            assert!(redelivered.redelivered);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -107,6 +107,30 @@ impl Buffer {
         self.capacity - self.available_data
     }
 
+    /// Copy the first bytes of available data without consuming them
+    pub(crate) fn peek<const N: usize>(&self) -> Option<[u8; N]> {
+        if self.available_data < N {
+            return None;
+        }
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.memory[(self.position + i) % self.capacity];
+        }
+        Some(bytes)
+    }
+
+    /// Copy the first `count` bytes of available data without consuming them
+    pub(crate) fn peek_vec(&self, count: usize) -> Option<Vec<u8>> {
+        if self.available_data < count {
+            return None;
+        }
+        Some(
+            (0..count)
+                .map(|i| self.memory[(self.position + i) % self.capacity])
+                .collect(),
+        )
+    }
+
     pub(crate) fn consume(&mut self, count: usize) -> usize {
         let cnt = cmp::min(count, self.available_data());
         self.position += cnt;
@@ -236,6 +260,7 @@ impl BackToTheBuffer for &mut Buffer {
             let mut end = s.write.checkpoint();
             end.backwards = false;
             s.write.rollback(start);
+            #[allow(clippy::manual_inspect)]
             before(s, tmp).map(|s| {
                 s.write.rollback(end);
                 s
@@ -0,0 +1,68 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Default number of buffers kept around for reuse
+pub(crate) const DEFAULT_BUFFER_POOL_SIZE: usize = 16;
+
+/// A pool of buffers reused for the body frames of published messages, to avoid an allocation
+/// per frame.
+#[derive(Clone)]
+pub(crate) struct BufferPool(Arc<Mutex<Inner>>);
+
+struct Inner {
+    buffers: Vec<Vec<u8>>,
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    pub(crate) fn new(max_buffers: usize) -> Self {
+        Self(Arc::new(Mutex::new(Inner {
+            buffers: Vec::with_capacity(max_buffers),
+            max_buffers,
+        })))
+    }
+
+    /// Get a buffer filled with the given data
+    pub(crate) fn get(&self, data: &[u8]) -> Vec<u8> {
+        let mut buffer = self.lock_inner().buffers.pop().unwrap_or_default();
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    /// Give a buffer back to the pool once we're done with it
+    pub(crate) fn recycle(&self, mut buffer: Vec<u8>) {
+        let mut inner = self.lock_inner();
+        if inner.buffers.len() < inner.max_buffers {
+            buffer.clear();
+            inner.buffers.push(buffer);
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_POOL_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_buffers() {
+        let pool = BufferPool::new(1);
+        let buffer = pool.get(b"hello");
+        assert_eq!(buffer, b"hello");
+        let ptr = buffer.as_ptr();
+        pool.recycle(buffer);
+        pool.recycle(Vec::with_capacity(5));
+        let buffer = pool.get(b"world");
+        assert_eq!(buffer, b"world");
+        assert_eq!(buffer.as_ptr(), ptr);
+        assert_eq!(pool.get(b"").capacity(), 0);
+    }
+}
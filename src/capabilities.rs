@@ -0,0 +1,219 @@
+use crate::{
+    types::{AMQPValue, FieldTable, ShortString},
+    ErrorKind, Result,
+};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/* What lapin advertises by default, all of them being RabbitMQ extensions */
+const DEFAULT_CAPABILITIES: [&str; 9] = [
+    "publisher_confirms",
+    "exchange_exchange_bindings",
+    "basic.nack",
+    "consumer_cancel_notify",
+    "connection.blocked",
+    "consumer_priorities",
+    "authentication_failure_close",
+    "per_consumer_qos",
+    "direct_reply_to",
+];
+
+/// A table of capabilities, as exchanged in the `capabilities` entry of the client and server
+/// properties during the handshake.
+///
+/// Set the ones lapin advertises with [`ConnectionProperties::with_capabilities`]. By default,
+/// all the RabbitMQ extensions lapin supports are enabled. Brokers which misbehave when they
+/// see some of them, or the table itself, can be given fewer:
+///
+/// ```rust
+/// use lapin::{Capabilities, ConnectionProperties};
+///
+/// let properties = ConnectionProperties::default().with_capabilities(
+///     Capabilities::default()
+///         .without("connection.blocked")
+///         .with("consumer_cancel_notify", false)
+///         .require("publisher_confirms"),
+/// );
+/// ```
+///
+/// The capabilities the broker advertised in return are available from
+/// [`ConnectionStatus::server_capabilities`] once connected.
+///
+/// [`ConnectionProperties::with_capabilities`]: ./struct.ConnectionProperties.html#method.with_capabilities
+/// [`ConnectionStatus::server_capabilities`]: ./struct.ConnectionStatus.html#method.server_capabilities
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    capabilities: BTreeMap<ShortString, bool>,
+    required: Vec<ShortString>,
+}
+
+impl Capabilities {
+    /// An empty table
+    pub fn none() -> Self {
+        Self {
+            capabilities: BTreeMap::default(),
+            required: Vec::default(),
+        }
+    }
+
+    /// Advertise this capability as enabled or disabled
+    #[must_use]
+    pub fn with(mut self, capability: &str, enabled: bool) -> Self {
+        self.capabilities.insert(capability.into(), enabled);
+        self
+    }
+
+    /// Don't advertise this capability at all
+    #[must_use]
+    pub fn without(mut self, capability: &str) -> Self {
+        self.capabilities.remove(capability);
+        self.required
+            .retain(|required| required.as_str() != capability);
+        self
+    }
+
+    /// Advertise this capability as enabled and fail the handshake with
+    /// [`ErrorKind::MissingCapability`] unless the broker advertises it too
+    ///
+    /// [`ErrorKind::MissingCapability`]: ./enum.ErrorKind.html#variant.MissingCapability
+    #[must_use]
+    pub fn require(mut self, capability: &str) -> Self {
+        self.capabilities.insert(capability.into(), true);
+        if !self
+            .required
+            .iter()
+            .any(|required| required.as_str() == capability)
+        {
+            self.required.push(capability.into());
+        }
+        self
+    }
+
+    /// Whether this capability is in the table and enabled
+    pub fn enabled(&self, capability: &str) -> bool {
+        self.capabilities.get(capability).copied().unwrap_or(false)
+    }
+
+    /// The capabilities in the table, enabled or not
+    pub fn iter(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.capabilities
+            .iter()
+            .map(|(capability, enabled)| (capability.as_str(), *enabled))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.capabilities.is_empty()
+    }
+
+    /// Read the `capabilities` entry of the properties sent by the broker
+    pub(crate) fn from_server_properties(server_properties: &FieldTable) -> Self {
+        let mut capabilities = Self::none();
+        if let Some(AMQPValue::FieldTable(table)) = server_properties.inner().get("capabilities") {
+            for (capability, value) in table {
+                capabilities = capabilities.with(
+                    capability.as_str(),
+                    matches!(value, AMQPValue::Boolean(true)),
+                );
+            }
+        }
+        capabilities
+    }
+
+    pub(crate) fn field_table(&self) -> FieldTable {
+        let mut table = FieldTable::default();
+        for (capability, enabled) in &self.capabilities {
+            table.insert(capability.clone(), (*enabled).into());
+        }
+        table
+    }
+
+    /// Compare our capabilities with the ones the broker advertised, failing if it lacks a
+    /// required one
+    pub(crate) fn check(&self, server: &Self) -> Result<()> {
+        if let Some(missing) = self
+            .required
+            .iter()
+            .find(|required| !server.enabled(required.as_str()))
+        {
+            return Err(ErrorKind::MissingCapability(missing.clone()).into());
+        }
+        for (capability, _) in self.iter().filter(|(_, enabled)| *enabled) {
+            if !server.enabled(capability) {
+                warn!(%capability, "the server doesn't advertise this capability");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        DEFAULT_CAPABILITIES
+            .into_iter()
+            .fold(Self::none(), |capabilities, capability| {
+                capabilities.with(capability, true)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::MockBroker, ConnectionProperties};
+
+    #[test]
+    fn negotiation() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker
+                .connect(
+                    ConnectionProperties::default().with_capabilities(
+                        Capabilities::default()
+                            .without("connection.blocked")
+                            .require("basic.nack"),
+                    ),
+                )
+                .await?;
+            let server = connection.status().server_capabilities();
+            assert!(server.enabled("publisher_confirms"));
+            assert!(!server.enabled("connection.blocked"));
+            connection.close(0, "").await?;
+
+            // The mock broker doesn't support direct reply-to
+            let error = broker
+                .connect(
+                    ConnectionProperties::default()
+                        .with_capabilities(Capabilities::none().require("direct_reply_to")),
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(
+                error,
+                ErrorKind::MissingCapability("direct_reply_to".into()).into()
+            );
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn field_table() {
+        let capabilities = Capabilities::none()
+            .with("publisher_confirms", true)
+            .with("basic.nack", false);
+        let table = capabilities.field_table();
+        assert_eq!(
+            table.inner().get("basic.nack"),
+            Some(&AMQPValue::Boolean(false))
+        );
+        let mut server_properties = FieldTable::default();
+        server_properties.insert("capabilities".into(), AMQPValue::FieldTable(table));
+        assert_eq!(
+            Capabilities::from_server_properties(&server_properties),
+            capabilities
+        );
+        assert_eq!(Capabilities::default().iter().count(), 9);
+    }
+}
@@ -1,36 +1,66 @@
 use crate::{
+    ack_coalescer::AckCoalescer,
     acknowledgement::Acknowledgements,
-    auth::Credentials,
+    backoff::Backoff,
     basic_get_delivery::BasicGetDelivery,
+    capabilities::Capabilities,
     channel_closer::ChannelCloser,
     channel_receiver_state::DeliveryCause,
     channel_status::{ChannelState, ChannelStatus},
+    confirm_throttle::ConfirmThrottle,
     connection_closer::ConnectionCloser,
     connection_status::{ConnectionState, ConnectionStep},
     consumer::Consumer,
+    consumer_tag::ConsumerTagStrategy,
     consumers::Consumers,
+    declare_cache::DeclareCache,
     error_handler::ErrorHandler,
-    frames::{ExpectedReply, Frames},
+    flow_handler::FlowHandler,
+    frames::{ExpectedReply, Frames, OutgoingFrame},
+    getter::Getter,
     internal_rpc::InternalRPCHandle,
     message::{BasicGetMessage, BasicReturnMessage, Delivery},
+    notifier::RecoveryEvents,
     protocol::{self, AMQPClass, AMQPError, AMQPHardError},
-    publisher_confirm::PublisherConfirm,
+    publish_defaults::PublishDefaults,
+    publish_events::PublishEvents,
+    publish_template::PublishTemplate,
+    publisher_confirm::{DroppedConfirmPolicy, PublisherConfirm, UnhandledConfirms},
+    publisher_handle::PublisherHandle,
     queue::Queue,
+    queue_depth::{QueueDepth, QueueDepthWatcher},
+    rate_limit::{RateLimit, RateLimiter},
+    reactor::FullReactor,
     recovery_config::RecoveryConfig,
     registry::Registry,
     returned_messages::ReturnedMessages,
+    secrets::Credentials,
+    slow_consumer::{SlowConsumer, UnackedDeliveries},
     socket_state::SocketStateHandle,
-    topology::RestoredChannel,
+    topology::{BindingDefinition, RestoredChannel},
     topology_internal::ChannelDefinitionInternal,
     types::*,
-    BasicProperties, Configuration, Connection, ConnectionStatus, Error, ErrorKind, ExchangeKind,
-    Promise, PromiseResolver, Result,
+    BasicProperties, Configuration, Connection, ConnectionStatus, Envelope, Error, ErrorKind,
+    ExchangeKind, LatencyHistogram, Pipeline, Promise, PromiseResolver, Result,
 };
 use amq_protocol::frame::{AMQPContentHeader, AMQPFrame};
 use executor_trait::FullExecutor;
+use futures_core::stream::Stream;
 use serde::{Deserialize, Serialize};
-use std::{convert::TryFrom, fmt, sync::Arc};
-use tracing::{error, info, level_enabled, trace, Level};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    future::{self, Future},
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    task::Poll,
+    time::{Duration, Instant},
+};
+use tracing::{debug, debug_span, error, field, info, level_enabled, trace, Level, Span};
+
+/* The pseudo queue of RabbitMQ's direct reply-to */
+const DIRECT_REPLY_TO_QUEUE: &str = "amq.rabbitmq.reply-to";
 
 /// Main entry point for most AMQP operations.
 ///
@@ -39,6 +69,23 @@ use tracing::{error, info, level_enabled, trace, Level};
 ///
 /// See also the RabbitMQ documentation on [channels](https://www.rabbitmq.com/channels.html).
 ///
+/// A channel can be used by several tasks at once. The method, header and body frames of a
+/// publish are queued together, so those of concurrent publishes are never interleaved, even
+/// when a publish future gets dropped: before its frames are queued, nothing gets sent, and
+/// after, all of them do.
+///
+/// All the futures of a channel are cancel safe, so they can be used in `select!`: once polled,
+/// the request is sent and takes effect whether its future is kept or not, dropping it only
+/// discards the reply. A consumer whose [`basic_consume`] future was dropped gets cancelled, the
+/// message fetched by a dropped [`basic_get`] future gets requeued, and a publish dropped while
+/// buffered during a recovery gives its slot back.
+///
+/// Requests don't wait for the reply to the previous ones: await several of them together,
+/// with a [`Pipeline`] for instance, to have them all in flight at once.
+///
+/// [`basic_consume`]: #method.basic_consume
+/// [`basic_get`]: #method.basic_get
+/// [`Pipeline`]: ./struct.Pipeline.html
 /// [`Connection`]: ./struct.Connection.html
 /// [`Connection::create_channel`]: ./struct.Connection.html#method.create_channel
 #[derive(Clone)]
@@ -57,10 +104,21 @@ pub struct Channel {
     internal_rpc: InternalRPCHandle,
     frames: Frames,
     error_handler: ErrorHandler,
+    flow_handler: FlowHandler,
     executor: Arc<dyn FullExecutor + Send + Sync>,
+    reactor: Arc<dyn FullReactor + Send + Sync>,
     channel_closer: Option<Arc<ChannelCloser>>,
     connection_closer: Option<Arc<ConnectionCloser>>,
-    recovery_config: RecoveryConfig,
+    recovery_config: Arc<RwLock<RecoveryConfig>>,
+    publish_defaults: Arc<RwLock<PublishDefaults>>,
+    rate_limiter: Arc<Mutex<Option<RateLimiter>>>,
+    confirm_throttle: Arc<RwLock<Option<ConfirmThrottle>>>,
+    unacked_deliveries: UnackedDeliveries,
+    ack_coalescer: AckCoalescer,
+    consumer_tag_strategy: Arc<RwLock<Option<ConsumerTagStrategy>>>,
+    declare_cache: DeclareCache,
+    /* Held while giving a publish its delivery tag and queuing its frames, so that they stay in the same order */
+    publish_sequencer: Arc<Mutex<()>>,
 }
 
 impl PartialEq for Channel {
@@ -96,10 +154,16 @@ impl Channel {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
         connection_closer: Option<Arc<ConnectionCloser>>,
         recovery_config: RecoveryConfig,
     ) -> Channel {
         let returned_messages = ReturnedMessages::default();
+        let acknowledgements = Acknowledgements::new(
+            channel_id,
+            returned_messages.clone(),
+            configuration.latency().clone(),
+        );
         let status = ChannelStatus::new(channel_id, internal_rpc.clone());
         let channel_closer = if channel_id == 0 {
             None
@@ -117,7 +181,7 @@ impl Channel {
             connection_status,
             global_registry,
             local_registry: Registry::default(),
-            acknowledgements: Acknowledgements::new(channel_id, returned_messages.clone()),
+            acknowledgements,
             consumers: Consumers::default(),
             basic_get_delivery: BasicGetDelivery::default(),
             returned_messages,
@@ -125,10 +189,20 @@ impl Channel {
             internal_rpc,
             frames,
             error_handler: ErrorHandler::default(),
+            flow_handler: FlowHandler::default(),
             executor,
+            reactor,
             channel_closer,
             connection_closer,
-            recovery_config,
+            recovery_config: Arc::new(RwLock::new(recovery_config)),
+            publish_defaults: Arc::default(),
+            rate_limiter: Arc::default(),
+            confirm_throttle: Arc::default(),
+            unacked_deliveries: UnackedDeliveries::default(),
+            ack_coalescer: AckCoalescer::default(),
+            consumer_tag_strategy: Arc::default(),
+            declare_cache: DeclareCache::default(),
+            publish_sequencer: Arc::default(),
         }
     }
 
@@ -136,42 +210,344 @@ impl Channel {
         &self.status
     }
 
+    /// Get the defaults applied to every message published on this channel.
+    pub fn publish_defaults(&self) -> PublishDefaults {
+        self.publish_defaults
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Set the defaults applied to every message published on this channel.
+    ///
+    /// They are shared with all the clones of this channel.
+    pub fn set_publish_defaults(&self, defaults: PublishDefaults) {
+        *self
+            .publish_defaults
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = defaults;
+    }
+
+    /// Override the recovery config inherited from the connection for this channel.
+    ///
+    /// This allows enabling or disabling the automatic reopening of this channel only, after
+    /// the server closed it because of a soft error. It is shared with all the clones of this
+    /// channel.
+    pub fn set_experimental_recovery_config(&self, config: RecoveryConfig) {
+        *self
+            .recovery_config
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = config;
+    }
+
+    fn recovery_config(&self) -> RecoveryConfig {
+        self.recovery_config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Get the rate limit applied to the messages published on this channel, if any.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(RateLimiter::limit)
+    }
+
+    /// Limit the rate of the messages published on this channel, or remove the limit with `None`.
+    ///
+    /// The limit is shared with all the clones of this channel.
+    pub fn set_rate_limit(&self, limit: Option<RateLimit>) {
+        *self.rate_limiter.lock().unwrap_or_else(|e| e.into_inner()) = limit.map(RateLimiter::new);
+    }
+
+    /// The distribution of the time between publishing a message on this channel and receiving
+    /// its confirm, with [`LatencyHistogram::summary`] giving its percentiles.
+    ///
+    /// The confirms of all the channels of the connection also go in
+    /// [`Connection::delivery_latency`].
+    ///
+    /// [`LatencyHistogram::summary`]: ./struct.LatencyHistogram.html#method.summary
+    /// [`Connection::delivery_latency`]: ./struct.Connection.html#method.delivery_latency
+    pub fn confirm_latency(&self) -> LatencyHistogram {
+        self.acknowledgements.confirm_latency()
+    }
+
+    /// Get the adaptive throttling applied to publishers based on the confirms latency, if any.
+    pub fn confirm_throttle(&self) -> Option<ConfirmThrottle> {
+        *self
+            .confirm_throttle
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Slow down publishers when the broker is late confirming messages, or disable it with `None`.
+    ///
+    /// This only has an effect once the channel is in confirm mode.
+    /// The configuration is shared with all the clones of this channel.
+    pub fn set_confirm_throttle(&self, throttle: Option<ConfirmThrottle>) {
+        *self
+            .confirm_throttle
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = throttle;
+    }
+
+    /// A handle publishing on this channel which can be shared between threads, sequencing the
+    /// publishes of all its clones and sharing the confirm window of the channel.
+    ///
+    /// See the [`publisher_handle`] module.
+    ///
+    /// [`publisher_handle`]: ./publisher_handle/index.html
+    pub fn publisher_handle(&self) -> PublisherHandle {
+        PublisherHandle::new(self.clone())
+    }
+
+    /// Pre-serialize the method and header frames of messages sharing the same exchange, routing
+    /// key, options and properties, to publish them with [`Channel::basic_publish_with_template`].
+    ///
+    /// The publish defaults of this channel are applied when creating the template, except for
+    /// the generated message_id and timestamp which are different for each message.
+    pub fn publish_template(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        properties: BasicProperties,
+    ) -> Result<PublishTemplate> {
+        let defaults = self
+            .publish_defaults
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        PublishTemplate::new(exchange, routing_key, options, properties, &defaults)
+    }
+
+    /// Publish a message using frames pre-serialized with [`Channel::publish_template`], only
+    /// serializing its body.
+    pub async fn basic_publish_with_template(
+        &self,
+        template: &PublishTemplate,
+        payload: &[u8],
+    ) -> Result<PublisherConfirm> {
+        if !self.status.connected_or_recovering() {
+            return Err(self.status.state_error());
+        }
+
+        self.throttle_basic_publish(payload).await?;
+        let confirm = self.before_basic_publish();
+        let mut frames = self.frames.body_buffer(&template.frames()?);
+        template.patch(&mut frames, self.id, payload.len());
+        self.send_frames_with_body(
+            vec![OutgoingFrame::Serialized(self.id, frames)],
+            payload,
+            confirm,
+        )
+        .await
+    }
+
+    /// Publish a message like [`Channel::basic_publish`] would, tracking how far it went with
+    /// the returned [`PublishEvents`] stream.
+    ///
+    /// The message is enqueued once this returns. The stream then tells when it got written to
+    /// the socket and, when the channel is in confirm mode, when the broker returned, acked or
+    /// nacked it, with the time elapsed since calling this.
+    ///
+    /// [`PublishEvents`]: ./struct.PublishEvents.html
+    pub async fn basic_publish_with_events(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<PublishEvents> {
+        let start = Instant::now();
+        if !self.status.connected_or_recovering() {
+            return Err(self.status.state_error());
+        }
+
+        self.throttle_basic_publish(payload).await?;
+        let (options, properties) = self.prepare_basic_publish(options, properties);
+        let confirm = self.before_basic_publish();
+        let BasicPublishOptions {
+            mandatory,
+            immediate,
+        } = options;
+        let method = AMQPClass::Basic(protocol::basic::AMQPMethod::Publish(
+            protocol::basic::Publish {
+                exchange: exchange.into(),
+                routing_key: routing_key.into(),
+                mandatory,
+                immediate,
+            },
+        ));
+        let frames = self.method_frames_with_header(method, payload, properties);
+        let (written, confirm) = self
+            .enqueue_frames_with_body(frames, payload, confirm)
+            .await;
+        let confirm = confirm
+            .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone()));
+        Ok(PublishEvents::new(start, written, confirm))
+    }
+
+    /// Publish a message described by an [`Envelope`], like [`Channel::basic_publish`] would.
+    ///
+    /// [`Envelope`]: ./struct.Envelope.html
+    pub async fn publish(&self, envelope: &Envelope) -> Result<PublisherConfirm> {
+        self.basic_publish(
+            envelope.exchange.as_str(),
+            envelope.routing_key.as_str(),
+            envelope.options,
+            &envelope.payload,
+            envelope.properties.clone(),
+        )
+        .await
+    }
+
+    /// Publish a received message again to `exchange` with `routing_key`, keeping its payload
+    /// and properties.
+    ///
+    /// The exchange and routing key it was first published with are recorded in its
+    /// `x-forwarded-exchange` and `x-forwarded-routing-key` headers, unless it was forwarded
+    /// already, and its `x-hop-count` header is incremented. See [`Delivery::origin`] and
+    /// [`Delivery::hop_count`] on the receiving side.
+    ///
+    /// The returned confirm is the one of the forwarded message: when the channel is in confirm
+    /// mode, only ack the received message once it resolved to an ack so that it can't get
+    /// lost in between.
+    ///
+    /// ```rust,no_run
+    /// use futures_lite::StreamExt;
+    /// use lapin::{options::*, types::FieldTable, Connection, ConnectionProperties};
+    ///
+    /// # async fn run(connection: Connection) -> lapin::Result<()> {
+    /// let channel = connection.create_channel().await?;
+    /// channel.confirm_select(ConfirmSelectOptions::default()).await?;
+    /// let mut consumer = channel
+    ///     .basic_consume("orders", "router", BasicConsumeOptions::default(), FieldTable::default())
+    ///     .await?;
+    /// while let Some(delivery) = consumer.next().await {
+    ///     let delivery = delivery?;
+    ///     let region = if delivery.routing_key().ends_with(".eu") { "eu" } else { "us" };
+    ///     if channel.forward(&delivery, "regions", region).await?.await?.is_ack() {
+    ///         delivery.ack(BasicAckOptions::default()).await?;
+    ///     } else {
+    ///         delivery.nack(BasicNackOptions::default()).await?;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Delivery::origin`]: ./message/struct.Delivery.html#method.origin
+    /// [`Delivery::hop_count`]: ./message/struct.Delivery.html#method.hop_count
+    pub async fn forward(
+        &self,
+        delivery: &Delivery,
+        exchange: &str,
+        routing_key: &str,
+    ) -> Result<PublisherConfirm> {
+        self.basic_publish(
+            exchange,
+            routing_key,
+            BasicPublishOptions::default(),
+            &delivery.data,
+            delivery.forwarded_properties(),
+        )
+        .await
+    }
+
+    /// Send a method frame of a class or method lapin doesn't know about, to experiment with
+    /// protocol extensions provided by broker plugins.
+    ///
+    /// The arguments are sent as is and have to be encoded as the extension specifies. Nothing
+    /// checks that the server supports the method, and no reply is waited for: register a
+    /// handler with [`Connection::on_raw_method`] to receive it.
+    ///
+    /// [`Connection::on_raw_method`]: ./struct.Connection.html#method.on_raw_method
+    #[cfg(feature = "unstable")]
+    pub async fn send_raw_method(
+        &self,
+        class_id: Identifier,
+        method_id: Identifier,
+        arguments: &[u8],
+    ) -> Result<()> {
+        if !self.status.connected() {
+            return Err(self.status.state_error());
+        }
+
+        let method = crate::RawMethod {
+            channel_id: self.id,
+            class_id,
+            method_id,
+            arguments: arguments.to_vec(),
+        };
+        trace!(channel=%self.id, class=%class_id, method=%method_id, "send raw method");
+        self.status.touch();
+        let promise = self
+            .frames
+            .push_frames(vec![OutgoingFrame::Serialized(self.id, method.serialize())]);
+        self.wake();
+        promise.await
+    }
+
+    /// The label given to this channel through [`ChannelOptions::with_label`], if any.
+    ///
+    /// [`ChannelOptions::with_label`]: ./struct.ChannelOptions.html#method.with_label
+    pub fn label(&self) -> Option<String> {
+        self.status.label()
+    }
+
     pub fn on_error<E: FnMut(Error) + Send + 'static>(&self, handler: E) {
         self.error_handler.set_handler(handler);
     }
 
+    /// Register a callback notified each time the server pauses (`false`) or resumes (`true`)
+    /// publishing on this channel through channel.flow.
+    ///
+    /// While paused, `basic_publish` waits for the flow to be resumed before sending anything.
+    pub fn on_flow<F: FnMut(bool) + Send + 'static>(&self, handler: F) {
+        self.flow_handler.set_handler(handler);
+    }
+
     pub(crate) async fn restore(
         &self,
         ch: &ChannelDefinitionInternal,
         c: &mut RestoredChannel,
     ) -> Result<()> {
         // First, redeclare all queues
-        for queue in &ch.queues {
-            if queue.is_declared() {
-                c.queues.push(
+        c.queues.extend(
+            ch.queues
+                .iter()
+                .filter(|queue| queue.is_declared())
+                .map(|queue| {
                     self.queue_declare(
                         queue.name.as_str(),
                         queue.options.unwrap_or_default(),
                         queue.arguments.clone().unwrap_or_default(),
                     )
-                    .await?,
-                );
-            }
-        }
+                })
+                .collect::<Pipeline<'_, _>>()
+                .await?,
+        );
 
         // Second, redeclare all queues bindings
-        for queue in &ch.queues {
-            for binding in &queue.bindings {
-                self.queue_bind(
-                    queue.name.as_str(),
-                    binding.source.as_str(),
-                    binding.routing_key.as_str(),
-                    QueueBindOptions::default(),
-                    binding.arguments.clone(),
-                )
-                .await?;
-            }
-        }
+        ch.queues
+            .iter()
+            .flat_map(|queue| {
+                queue.bindings.iter().map(|binding| {
+                    self.queue_bind(
+                        queue.name.as_str(),
+                        binding.source.as_str(),
+                        binding.routing_key.as_str(),
+                        QueueBindOptions::default(),
+                        binding.arguments.clone(),
+                    )
+                })
+            })
+            .collect::<Pipeline<'_, _>>()
+            .await?;
 
         // Third, redeclare all consumers
         for consumer in &ch.consumers {
@@ -206,6 +582,9 @@ impl Channel {
 
     pub(crate) fn set_closing(&self, error: Option<Error>) {
         self.set_state(ChannelState::Closing);
+        self.unacked_deliveries.clear();
+        self.ack_coalescer.clear();
+        self.declare_cache.clear();
         if let Some(error) = error {
             self.error_publisher_confirms(error.clone());
             self.error_consumers(error); // ignore the returned error here, only happens with default executor if we cannot spawn a thread
@@ -223,7 +602,11 @@ impl Channel {
 
     // Only called in case of a protocol failure
     pub(crate) fn set_connection_error(&self, error: Error) {
+        self.status.abort_recovery(error.clone());
         self.set_state(ChannelState::Error);
+        self.unacked_deliveries.clear();
+        self.ack_coalescer.clear();
+        self.declare_cache.clear();
         self.error_publisher_confirms(error.clone());
         self.error_consumers(error.clone());
         self.internal_rpc.remove_channel(self.id, error.clone());
@@ -245,6 +628,17 @@ impl Channel {
         self.status.set_state(state);
     }
 
+    /// The span wrapping the handling of the frames received on this channel
+    pub(crate) fn span(&self) -> Span {
+        let span = debug_span!("channel", id = %self.id, label = field::Empty);
+        if !span.is_disabled() {
+            if let Some(label) = self.label() {
+                span.record("label", label);
+            }
+        }
+        span
+    }
+
     pub fn id(&self) -> ChannelId {
         self.id
     }
@@ -265,13 +659,58 @@ impl Channel {
             internal_rpc: self.internal_rpc.clone(),
             frames: self.frames.clone(),
             error_handler: self.error_handler.clone(),
+            flow_handler: self.flow_handler.clone(),
             executor: self.executor.clone(),
+            reactor: self.reactor.clone(),
             channel_closer: None,
             connection_closer: self.connection_closer.clone(),
             recovery_config: self.recovery_config.clone(),
+            publish_defaults: self.publish_defaults.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            confirm_throttle: self.confirm_throttle.clone(),
+            unacked_deliveries: self.unacked_deliveries.clone(),
+            ack_coalescer: self.ack_coalescer.clone(),
+            consumer_tag_strategy: self.consumer_tag_strategy.clone(),
+            declare_cache: self.declare_cache.clone(),
+            publish_sequencer: self.publish_sequencer.clone(),
         }
     }
 
+    /// The consumers which have had as many unacked messages as the prefetch count allows for
+    /// longer than `threshold`, each of them being reported once until it acks some messages
+    pub(crate) fn slow_consumers(&self, threshold: Duration) -> Vec<SlowConsumer> {
+        self.unacked_deliveries
+            .report_saturated(threshold)
+            .into_iter()
+            .filter_map(|(consumer_tag, prefetch_count, saturated_for)| {
+                let queue = self.consumers.get(&consumer_tag)?.queue();
+                Some(SlowConsumer {
+                    channel_id: self.id,
+                    consumer_tag,
+                    queue,
+                    prefetch_count,
+                    saturated_for,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) async fn sleep(&self, duration: Duration) {
+        self.reactor.sleep(duration).await
+    }
+
+    pub(crate) fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+        self.executor.spawn(Box::pin(future));
+    }
+
+    pub(crate) async fn spawn_blocking(&self, f: impl FnOnce() + Send + 'static) {
+        self.executor.spawn_blocking(Box::new(f)).await
+    }
+
+    pub(crate) fn recovery_events(&self) -> RecoveryEvents {
+        self.connection_status.recovery_events()
+    }
+
     fn wake(&self) {
         trace!(channel=%self.id, "wake");
         self.waker.wake()
@@ -300,9 +739,57 @@ impl Channel {
     }
 
     pub async fn close(&self, reply_code: ReplyCode, reply_text: &str) -> Result<()> {
+        self.flush_acks().await;
         self.do_channel_close(reply_code, reply_text, 0, 0).await
     }
 
+    /* Only called before the channel gets opened, see AckCoalescer */
+    pub(crate) fn set_ack_coalescing(&self, max_delay: Duration) {
+        self.ack_coalescer.set_max_delay(max_delay);
+    }
+
+    /// Ack a delivery on behalf of its Acker, delaying it by up to the coalescing delay to send
+    /// it together with the other acks of the window, if enabled
+    pub(crate) async fn ack_delivery(
+        &self,
+        delivery_tag: DeliveryTag,
+        options: BasicAckOptions,
+    ) -> Result<()> {
+        let Some(max_delay) = self.ack_coalescer.max_delay().filter(|_| !options.multiple) else {
+            return self.basic_ack(delivery_tag, options).await;
+        };
+        let (promise, resolver) = Promise::new();
+        if self.ack_coalescer.push(delivery_tag, resolver) {
+            let channel = self.clone();
+            self.spawn(async move {
+                channel.sleep(max_delay).await;
+                channel.flush_acks().await;
+            });
+        }
+        promise.await
+    }
+
+    /// Send the acks waiting to be coalesced, as one basic.ack with `multiple` for as many of
+    /// them as possible
+    pub(crate) async fn flush_acks(&self) {
+        let batch = self.ack_coalescer.take_batch();
+        if let Some((delivery_tag, resolvers)) = batch.multiple {
+            trace!(channel=%self.id, delivery_tag, acks=resolvers.len(), "sending coalesced acks");
+            let res = self
+                .basic_ack(delivery_tag, BasicAckOptions { multiple: true })
+                .await;
+            for resolver in resolvers {
+                resolver.complete(res.clone());
+            }
+        }
+        for (delivery_tag, resolver) in batch.single {
+            resolver.complete(
+                self.basic_ack(delivery_tag, BasicAckOptions::default())
+                    .await,
+            );
+        }
+    }
+
     pub async fn basic_consume(
         &self,
         queue: &str,
@@ -310,10 +797,125 @@ impl Channel {
         options: BasicConsumeOptions,
         arguments: FieldTable,
     ) -> Result<Consumer> {
+        let generated;
+        let consumer_tag = if consumer_tag.is_empty() {
+            generated = self.consumer_tag_strategy().generate(queue);
+            generated.as_str()
+        } else {
+            consumer_tag
+        };
+        if queue == DIRECT_REPLY_TO_QUEUE {
+            self.require_capability("direct_reply_to")?;
+        }
         self.do_basic_consume(queue, consumer_tag, options, arguments, None)
             .await
     }
 
+    /* Whether the broker supports this extension, always assumed with the RabbitMQ profile */
+    fn supports_capability(&self, capability: &str) -> bool {
+        !self.configuration.broker_profile().degrades()
+            || self
+                .connection_status
+                .server_capabilities()
+                .enabled(capability)
+    }
+
+    /* Fail instead of using an extension the broker doesn't support */
+    fn require_capability(&self, capability: &str) -> Result<()> {
+        if self.supports_capability(capability) {
+            Ok(())
+        } else {
+            Err(ErrorKind::MissingCapability(capability.into()).into())
+        }
+    }
+
+    pub async fn basic_nack(
+        &self,
+        delivery_tag: DeliveryTag,
+        options: BasicNackOptions,
+    ) -> Result<()> {
+        if !options.multiple && !self.supports_capability("basic.nack") {
+            trace!(channel=%self.id, %delivery_tag, "basic.nack unsupported, rejecting instead");
+            return self
+                .basic_reject(
+                    delivery_tag,
+                    BasicRejectOptions {
+                        requeue: options.requeue,
+                    },
+                )
+                .await;
+        }
+        self.require_capability("basic.nack")?;
+        self.do_basic_nack(delivery_tag, options).await
+    }
+
+    /// Set how the consumers started without a consumer tag on this channel get one, `None`
+    /// following the strategy of the connection.
+    ///
+    /// The configuration is shared with all the clones of this channel.
+    pub fn set_consumer_tag_strategy(&self, strategy: Option<ConsumerTagStrategy>) {
+        *self
+            .consumer_tag_strategy
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = strategy;
+    }
+
+    fn consumer_tag_strategy(&self) -> ConsumerTagStrategy {
+        self.consumer_tag_strategy
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .unwrap_or_else(|| self.configuration.consumer_tag_strategy())
+    }
+
+    /// Consume exactly one message from the given queue.
+    ///
+    /// This registers a consumer, waits for its first delivery and cancels it.
+    /// Returns `None` if no message was received before the timeout expired, or if the
+    /// consumer got canceled by the server.
+    ///
+    /// Messages received after the first one but before the cancellation went through are
+    /// requeued, unless `no_ack` is set in which case they're lost. Setting a prefetch count
+    /// with [`basic_qos`] limits the number of such messages.
+    ///
+    /// [`basic_qos`]: #method.basic_qos
+    pub async fn consume_one(
+        &self,
+        queue: &str,
+        options: BasicConsumeOptions,
+        timeout: Duration,
+    ) -> Result<Option<Delivery>> {
+        let mut consumer = self
+            .basic_consume(queue, "", options, FieldTable::default())
+            .await?;
+        let mut sleep = Box::pin(self.sleep(timeout));
+        let delivery = future::poll_fn(|cx| {
+            if let Poll::Ready(delivery) = Pin::new(&mut consumer).poll_next(cx) {
+                return Poll::Ready(delivery.transpose());
+            }
+            sleep.as_mut().poll(cx).map(|()| Ok(None))
+        })
+        .await;
+
+        if consumer.state().is_active() {
+            self.basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+                .await?;
+            while let Some(Ok(extra)) =
+                future::poll_fn(|cx| Pin::new(&mut consumer).poll_next(cx)).await
+            {
+                if !options.no_ack {
+                    extra
+                        .nack(BasicNackOptions {
+                            requeue: true,
+                            ..BasicNackOptions::default()
+                        })
+                        .await?;
+                }
+            }
+        }
+        delivery
+    }
+
     pub async fn basic_get(
         &self,
         queue: &str,
@@ -322,6 +924,86 @@ impl Channel {
         self.do_basic_get(queue, options, None).await
     }
 
+    /// Poll the given queue with basic.get, yielding each message as part of a Stream.
+    ///
+    /// While the queue is empty, polling is delayed according to the given backoff.
+    /// This is a lightweight alternative to [`basic_consume`] for low-volume queues.
+    ///
+    /// [`basic_consume`]: #method.basic_consume
+    pub fn getter(&self, queue: &str, options: BasicGetOptions, backoff: Backoff) -> Getter {
+        Getter::new(self.clone(), queue.into(), options, backoff)
+    }
+
+    /// Get the number of messages ready in the given queue and of consumers attached to it,
+    /// using a passive queue.declare.
+    ///
+    /// The server closes the channel if the queue doesn't exist.
+    pub async fn queue_depth(&self, queue: &str) -> Result<QueueDepth> {
+        let queue = self
+            .do_queue_declare(
+                queue,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..QueueDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        Ok(QueueDepth::from(&queue))
+    }
+
+    /// Poll the depth of the given queue every `interval`, yielding it as part of a Stream.
+    ///
+    /// See [`queue_depth`] for how it is obtained.
+    ///
+    /// [`queue_depth`]: #method.queue_depth
+    pub fn watch_queue_depth(&self, queue: &str, interval: Duration) -> QueueDepthWatcher {
+        QueueDepthWatcher::new(self.clone(), queue.into(), interval)
+    }
+
+    /// Declare a queue.
+    ///
+    /// With the declare cache enabled, a declare identical to one already sent on this channel
+    /// isn't sent again, and resolves to the result of the first one, message and consumer
+    /// counts included. Passive declares and server named queues are never cached.
+    pub async fn queue_declare(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<Queue> {
+        let cacheable = !queue.is_empty() && !options.passive && self.declare_cache.enabled();
+        if cacheable {
+            if let Some(queue) = self.declare_cache.queue(queue, &options, &arguments) {
+                trace!(channel=%self.id, queue=%queue.name(), "skipping cached queue declare");
+                return Ok(queue);
+            }
+        }
+        self.queue_declare_forced(queue, options, arguments).await
+    }
+
+    /// Declare a queue, even if the declare cache holds an identical declare, which gets
+    /// refreshed.
+    pub async fn queue_declare_forced(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<Queue> {
+        let declared = self
+            .do_queue_declare(queue, options, arguments.clone())
+            .await?;
+        if !queue.is_empty() && !options.passive {
+            self.declare_cache
+                .register_queue(options, arguments, declared.clone());
+        }
+        Ok(declared)
+    }
+
+    /// Declare an exchange.
+    ///
+    /// With the declare cache enabled, a declare identical to one already sent on this channel
+    /// isn't sent again. Passive declares are never cached.
     pub async fn exchange_declare(
         &self,
         exchange: &str,
@@ -329,10 +1011,154 @@ impl Channel {
         options: ExchangeDeclareOptions,
         arguments: FieldTable,
     ) -> Result<()> {
-        self.do_exchange_declare(exchange, kind.kind(), options, arguments, kind.clone())
+        if !options.passive
+            && self.declare_cache.enabled()
+            && self
+                .declare_cache
+                .exchange(exchange, &kind, &options, &arguments)
+        {
+            trace!(channel=%self.id, %exchange, "skipping cached exchange declare");
+            return Ok(());
+        }
+        self.exchange_declare_forced(exchange, kind, options, arguments)
             .await
     }
 
+    /// Declare an exchange, even if the declare cache holds an identical declare.
+    pub async fn exchange_declare_forced(
+        &self,
+        exchange: &str,
+        kind: ExchangeKind,
+        options: ExchangeDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        self.do_exchange_declare(
+            exchange,
+            kind.kind(),
+            options,
+            arguments.clone(),
+            kind.clone(),
+        )
+        .await?;
+        if !options.passive {
+            self.declare_cache
+                .register_exchange(exchange, kind, options, arguments);
+        }
+        Ok(())
+    }
+
+    /// Skip the declares identical to one already sent on this channel, or stop doing so and
+    /// forget them.
+    ///
+    /// This avoids round trips to the broker for the code which defensively declares its
+    /// topology before each publish. The cache is shared with all the clones of this channel,
+    /// and gets cleared when the channel closes. Use [`queue_declare_forced`] and
+    /// [`exchange_declare_forced`] to bypass it.
+    ///
+    /// [`queue_declare_forced`]: #method.queue_declare_forced
+    /// [`exchange_declare_forced`]: #method.exchange_declare_forced
+    pub fn set_declare_cache(&self, enabled: bool) {
+        self.declare_cache.set_enabled(enabled);
+    }
+
+    /// Exclude the given exchange from automatic topology recovery.
+    pub fn skip_exchange_recovery(&self, exchange: &str) {
+        self.global_registry.skip_exchange_recovery(exchange.into());
+    }
+
+    /// Exclude the given queue, declared on this channel, from automatic topology recovery,
+    /// along with its bindings.
+    pub fn skip_queue_recovery(&self, queue: &str) {
+        self.local_registry.skip_queue_recovery(queue.into());
+    }
+
+    /// Get the bindings of the given queue that were created through this channel.
+    pub fn queue_bindings(&self, queue: &str) -> Vec<BindingDefinition> {
+        self.local_registry.queue_bindings(queue)
+    }
+
+    /// Remove all the bindings of the given queue that were created through this channel.
+    pub async fn queue_unbind_all(&self, queue: &str) -> Result<()> {
+        for binding in self.queue_bindings(queue) {
+            self.queue_unbind(
+                queue,
+                binding.source.as_str(),
+                binding.routing_key.as_str(),
+                binding.arguments,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the bindings between the given queue and exchange with the given routing keys.
+    ///
+    /// Missing bindings are created before the obsolete ones get removed, so that the queue
+    /// doesn't miss any message during the migration.
+    /// Only the bindings created through this channel are considered.
+    pub async fn queue_rebind(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_keys: &[&str],
+        arguments: FieldTable,
+    ) -> Result<()> {
+        let bindings = self
+            .queue_bindings(queue)
+            .into_iter()
+            .filter(|binding| binding.source.as_str() == exchange)
+            .collect::<Vec<_>>();
+
+        for routing_key in routing_keys {
+            if !bindings.iter().any(|binding| {
+                binding.routing_key.as_str() == *routing_key && binding.arguments == arguments
+            }) {
+                self.queue_bind(
+                    queue,
+                    exchange,
+                    routing_key,
+                    QueueBindOptions::default(),
+                    arguments.clone(),
+                )
+                .await?;
+            }
+        }
+
+        for binding in bindings {
+            if binding.arguments != arguments
+                || !routing_keys.contains(&binding.routing_key.as_str())
+            {
+                self.queue_unbind(
+                    queue,
+                    exchange,
+                    binding.routing_key.as_str(),
+                    binding.arguments,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Choose what happens to the outcome of the [`PublisherConfirm`]s of this channel dropped
+    /// without being awaited, instead of keeping it for [`Channel::wait_for_confirms`].
+    ///
+    /// The policy is shared with all the clones of this channel.
+    ///
+    /// [`PublisherConfirm`]: ./publisher_confirm/struct.PublisherConfirm.html
+    pub fn set_dropped_confirm_policy(&self, policy: DroppedConfirmPolicy) {
+        self.returned_messages.set_dropped_confirm_policy(policy);
+    }
+
+    /// The nacks and returned messages of the [`PublisherConfirm`]s of this channel dropped
+    /// without being awaited, with [`DroppedConfirmPolicy::Forward`].
+    ///
+    /// [`PublisherConfirm`]: ./publisher_confirm/struct.PublisherConfirm.html
+    /// [`DroppedConfirmPolicy::Forward`]: ./publisher_confirm/enum.DroppedConfirmPolicy.html#variant.Forward
+    pub fn unhandled_confirms(&self) -> UnhandledConfirms {
+        UnhandledConfirms::new(self.returned_messages.clone())
+    }
+
     pub async fn wait_for_confirms(&self) -> Result<Vec<BasicReturnMessage>> {
         if let Some(last_pending) = self.acknowledgements.get_last_pending() {
             trace!("Waiting for pending confirms");
@@ -374,6 +1200,7 @@ impl Channel {
         expected_reply: Option<ExpectedReply>,
     ) {
         trace!(channel=%self.id, "send_frame");
+        self.status.touch();
         self.frames.push(self.id, frame, resolver, expected_reply);
         self.wake();
     }
@@ -383,32 +1210,74 @@ impl Channel {
         method: AMQPClass,
         payload: &[u8],
         properties: BasicProperties,
-        publisher_confirms_result: Option<PublisherConfirm>,
+        confirm: bool,
     ) -> Result<PublisherConfirm> {
+        let frames = self.method_frames_with_header(method, payload, properties);
+        self.send_frames_with_body(frames, payload, confirm).await
+    }
+
+    fn method_frames_with_header(
+        &self,
+        method: AMQPClass,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Vec<OutgoingFrame> {
         let class_id = method.get_amqp_class_id();
         let header = AMQPContentHeader {
             class_id,
             body_size: payload.len() as PayloadSize,
             properties,
         };
-        let frame_max = self.configuration.frame_max();
-        let mut frames = vec![
-            AMQPFrame::Method(self.id, method),
-            AMQPFrame::Header(self.id, class_id, Box::new(header)),
-        ];
+        vec![
+            AMQPFrame::Method(self.id, method).into(),
+            AMQPFrame::Header(self.id, class_id, Box::new(header)).into(),
+        ]
+    }
+
+    async fn send_frames_with_body(
+        &self,
+        frames: Vec<OutgoingFrame>,
+        payload: &[u8],
+        confirm: bool,
+    ) -> Result<PublisherConfirm> {
+        let (written, publisher_confirms_result) = self
+            .enqueue_frames_with_body(frames, payload, confirm)
+            .await;
+        written.await?;
+        Ok(publisher_confirms_result
+            .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone())))
+    }
 
+    /* The returned promise gets resolved once the frames are written */
+    async fn enqueue_frames_with_body(
+        &self,
+        mut frames: Vec<OutgoingFrame>,
+        payload: &[u8],
+        confirm: bool,
+    ) -> (Promise<()>, Option<PublisherConfirm>) {
+        let frame_max = self.configuration.frame_max();
         frames.extend(
             payload
                 .chunks(frame_max as usize - 8 /* An empty body frame weighs 8 bytes of overhead that we cannot use for payload */)
-                .map(|chunk| AMQPFrame::Body(self.id, chunk.into())),
+                .map(|chunk| AMQPFrame::Body(self.id, self.frames.body_buffer(chunk)).into()),
         );
 
+        future::poll_fn(|cx| self.frames.poll_reserve(payload.len(), cx)).await;
+        // Nothing gets awaited past this point, so that a publish future dropped midway queues
+        // either all of its frames or none of them.
         trace!(channel=%self.id, "send_frames");
+        self.status.touch();
+        // The broker numbers the publishes in the order it receives them, which has to match
+        // our delivery tags even when publishing concurrently from several threads.
+        let sequencer = self
+            .publish_sequencer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let publisher_confirm = confirm.then(|| self.acknowledgements.register_pending());
         let promise = self.frames.push_frames(frames);
+        drop(sequencer);
         self.wake();
-        promise.await?;
-        Ok(publisher_confirms_result
-            .unwrap_or_else(|| PublisherConfirm::not_requested(self.returned_messages.clone())))
+        (promise, publisher_confirm)
     }
 
     fn handle_invalid_contents(
@@ -440,12 +1309,18 @@ impl Channel {
             size,
             |delivery_cause, confirm_mode| match delivery_cause {
                 DeliveryCause::Consume(consumer_tag) => {
-                    self.consumers
-                        .handle_content_header_frame(consumer_tag, size, properties);
+                    let latency = self.configuration.latency().measure(&properties);
+                    self.consumers.handle_content_header_frame(
+                        consumer_tag,
+                        size,
+                        properties,
+                        latency,
+                    );
                 }
                 DeliveryCause::Get => {
+                    let latency = self.configuration.latency().measure(&properties);
                     self.basic_get_delivery
-                        .handle_content_header_frame(size, properties);
+                        .handle_content_header_frame(size, properties, latency);
                 }
                 DeliveryCause::Return => {
                     self.returned_messages.handle_content_header_frame(
@@ -502,12 +1377,184 @@ impl Channel {
         }
     }
 
-    fn before_basic_publish(&self) -> Option<PublisherConfirm> {
-        if self.status.confirm() {
-            Some(self.acknowledgements.register_pending())
-        } else {
-            None
+    /// Restore the topology lost when the server closed the channel, as configured in the
+    /// recovery config
+    async fn recover_topology(&self) -> Result<()> {
+        let config = self.recovery_config();
+        let exchanges = self.global_registry.recoverable_exchanges();
+        let queues = self.local_registry.recoverable_queues();
+        let mut renamed = HashMap::new();
+
+        if config.recover_exchanges {
+            // Exchanges only known through their bindings haven't been declared by us
+            exchanges
+                .iter()
+                .filter(|ex| ex.kind.is_some())
+                .map(|ex| {
+                    self.exchange_declare(
+                        ex.name.as_str(),
+                        ex.kind.clone().unwrap_or_default(),
+                        ex.options.unwrap_or_default(),
+                        ex.arguments.clone().unwrap_or_default(),
+                    )
+                })
+                .collect::<Pipeline<'_, _>>()
+                .await?;
+        }
+
+        if config.recover_queues {
+            let queues = queues
+                .iter()
+                .filter(|queue| queue.is_declared())
+                .filter(|queue| !queue.is_server_named() || config.rename_server_named_queues)
+                .collect::<Vec<_>>();
+            let declared = queues
+                .iter()
+                .map(|queue| {
+                    self.queue_declare(
+                        if queue.is_server_named() {
+                            ""
+                        } else {
+                            queue.name.as_str()
+                        },
+                        queue.options.unwrap_or_default(),
+                        queue.arguments.clone().unwrap_or_default(),
+                    )
+                })
+                .collect::<Pipeline<'_, _>>()
+                .await?;
+            for (queue, declared) in queues.into_iter().zip(declared) {
+                if queue.is_server_named() {
+                    trace!(channel=%self.id, old=%queue.name, new=%declared.name(), "renamed server-named queue");
+                    self.local_registry.deregister_queue(queue.name.as_str());
+                    self.global_registry.deregister_queue(queue.name.as_str());
+                    renamed.insert(queue.name.clone(), declared.name().clone());
+                }
+            }
+        }
+        let queue_name = |name: &ShortString| renamed.get(name).unwrap_or(name).clone();
+
+        if config.recover_bindings {
+            let mut bindings = Pipeline::new();
+            for ex in &exchanges {
+                for binding in &ex.bindings {
+                    bindings.push(self.exchange_bind(
+                        ex.name.as_str(),
+                        binding.source.as_str(),
+                        binding.routing_key.as_str(),
+                        ExchangeBindOptions::default(),
+                        binding.arguments.clone(),
+                    ));
+                }
+            }
+            for queue in &queues {
+                for binding in &queue.bindings {
+                    let queue = queue_name(&queue.name);
+                    bindings.push(async move {
+                        self.queue_bind(
+                            queue.as_str(),
+                            binding.source.as_str(),
+                            binding.routing_key.as_str(),
+                            QueueBindOptions::default(),
+                            binding.arguments.clone(),
+                        )
+                        .await
+                    });
+                }
+            }
+            bindings.await?;
+        }
+
+        if config.recover_consumers {
+            for consumer in self.consumers.topology() {
+                let original = consumer.original();
+                if let Some(original) = original.as_ref() {
+                    original.reset();
+                }
+                self.do_basic_consume(
+                    queue_name(&consumer.queue).as_str(),
+                    consumer.tag.as_str(),
+                    consumer.options,
+                    consumer.arguments.clone(),
+                    original,
+                )
+                .await?
+                .detach();
+            }
+        }
+        Ok(())
+    }
+
+    async fn throttle_basic_publish(&self, payload: &[u8]) -> Result<()> {
+        if self.status.reconnecting() {
+            self.buffer_basic_publish().await?;
+        }
+        if !self.status.flow() {
+            trace!(channel=%self.id, "publishing paused by server, waiting for channel.flow");
+            future::poll_fn(|cx| self.status.poll_flow(cx)).await;
+            if !self.status.connected() {
+                return Err(self.status.state_error());
+            }
+        }
+        let delay = self
+            .rate_limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_mut()
+            .map_or(Duration::ZERO, |limiter| {
+                limiter.reserve(payload.len(), Instant::now())
+            });
+        if !delay.is_zero() {
+            trace!(channel=%self.id, ?delay, "publish rate limit reached, delaying");
+            self.sleep(delay).await;
+        }
+        let throttle = *self
+            .confirm_throttle
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(throttle) = throttle.filter(|_| self.status.confirm()) {
+            let delay = throttle.delay(
+                self.acknowledgements.latency(),
+                self.acknowledgements.pending_count(),
+            );
+            if !delay.is_zero() {
+                trace!(channel=%self.id, ?delay, "broker is late confirming messages, delaying");
+                self.sleep(delay).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn buffer_basic_publish(&self) -> Result<()> {
+        let config = self.recovery_config();
+        let capacity = config.publish_buffer_capacity;
+        if capacity == 0 {
+            return Err(self.status.state_error());
         }
+        trace!(channel=%self.id, "channel is recovering, buffering publish");
+        self.status
+            .buffer_publish(capacity, config.publish_buffer_overflow)?
+            .await?;
+        if !self.status.connected() {
+            return Err(self.status.state_error());
+        }
+        Ok(())
+    }
+
+    fn prepare_basic_publish(
+        &self,
+        options: BasicPublishOptions,
+        properties: BasicProperties,
+    ) -> (BasicPublishOptions, BasicProperties) {
+        self.publish_defaults
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .apply(options, properties)
+    }
+
+    /* Whether the publish needs a delivery tag, which it only gets once its frames are queued */
+    fn before_basic_publish(&self) -> bool {
+        self.status.confirm()
     }
 
     fn before_basic_cancel(&self, consumer_tag: &str) {
@@ -571,7 +1618,7 @@ impl Channel {
     }
 
     fn on_channel_close_ok_sent(&self, error: Option<Error>) {
-        if !self.recovery_config.auto_recover_channels
+        if !self.recovery_config().auto_recover_channels
             || !error.as_ref().is_some_and(Error::is_amqp_soft_error)
         {
             self.set_closed(
@@ -586,21 +1633,32 @@ impl Channel {
     }
 
     fn on_basic_recover_async_sent(&self) {
+        self.unacked_deliveries.clear();
+        self.ack_coalescer.clear();
         self.consumers.drop_prefetched_messages();
     }
 
     fn on_basic_ack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
+        self.unacked_deliveries.acked(delivery_tag, multiple);
+        self.ack_coalescer.settled(delivery_tag, multiple);
         if multiple && delivery_tag == 0 {
             self.consumers.drop_prefetched_messages();
         }
     }
 
     fn on_basic_nack_sent(&self, multiple: bool, delivery_tag: DeliveryTag) {
+        self.unacked_deliveries.acked(delivery_tag, multiple);
+        self.ack_coalescer.settled(delivery_tag, multiple);
         if multiple && delivery_tag == 0 {
             self.consumers.drop_prefetched_messages();
         }
     }
 
+    fn on_basic_reject_sent(&self, delivery_tag: DeliveryTag) {
+        self.unacked_deliveries.acked(delivery_tag, false);
+        self.ack_coalescer.settled(delivery_tag, false);
+    }
+
     fn tune_connection_configuration(
         &self,
         channel_max: ChannelId,
@@ -656,7 +1714,7 @@ impl Channel {
         ) = (state, self.connection_status.connection_step())
         {
             let mechanism_str = mechanism.to_string();
-            let locale = options.locale.clone();
+            let mut locale = options.locale.clone();
 
             if !method
                 .mechanisms
@@ -666,13 +1724,15 @@ impl Channel {
             {
                 error!(%mechanism, "unsupported mechanism");
             }
-            if !method
-                .locales
-                .to_string()
-                .split_whitespace()
-                .any(|l| l == locale)
-            {
-                error!(%locale, "unsupported locale");
+            let locales = method.locales.to_string();
+            if !locales.split_whitespace().any(|l| l == locale) {
+                match locales.split_whitespace().next() {
+                    Some(server_locale) if options.broker_profile.degrades() => {
+                        debug!(%locale, %server_locale, "unsupported locale, using the server one");
+                        locale = server_locale.into();
+                    }
+                    _ => error!(%locale, "unsupported locale"),
+                }
             }
 
             if !options.client_properties.contains_key("product")
@@ -692,20 +1752,23 @@ impl Channel {
                 .client_properties
                 .insert("platform".into(), AMQPValue::LongString("rust".into()));
 
-            let mut capabilities = FieldTable::default();
-            capabilities.insert("publisher_confirms".into(), true.into());
-            capabilities.insert("exchange_exchange_bindings".into(), true.into());
-            capabilities.insert("basic.nack".into(), true.into());
-            capabilities.insert("consumer_cancel_notify".into(), true.into());
-            capabilities.insert("connection.blocked".into(), true.into());
-            capabilities.insert("consumer_priorities".into(), true.into());
-            capabilities.insert("authentication_failure_close".into(), true.into());
-            capabilities.insert("per_consumer_qos".into(), true.into());
-            capabilities.insert("direct_reply_to".into(), true.into());
+            let server_capabilities =
+                Capabilities::from_server_properties(&method.server_properties);
+            if let Err(error) = options.capabilities.check(&server_capabilities) {
+                error!(%error, "capabilities negotiation failed");
+                resolver.reject(error.clone());
+                self.internal_rpc.set_connection_error(error.clone());
+                return Err(error);
+            }
+            self.connection_status
+                .set_server_capabilities(server_capabilities);
 
-            options
-                .client_properties
-                .insert("capabilities".into(), AMQPValue::FieldTable(capabilities));
+            if !options.capabilities.is_empty() {
+                options.client_properties.insert(
+                    "capabilities".into(),
+                    AMQPValue::FieldTable(options.capabilities.field_table()),
+                );
+            }
 
             let channel = self.clone();
             self.internal_rpc.register_internal_future(async move {
@@ -865,14 +1928,21 @@ impl Channel {
         resolver: PromiseResolver<Channel>,
         channel: Channel,
     ) -> Result<()> {
-        if self.recovery_config.auto_recover_channels {
+        let config = self.recovery_config();
+        if config.auto_recover_channels {
             self.status.update_recovery_context(|ctx| {
                 ctx.set_expected_replies(self.frames.take_expected_replies(self.id));
                 self.frames.drop_frames_for_channel(channel.id, ctx.cause());
                 self.acknowledgements.reset(ctx.cause());
-                // FIXME: don't close consumers
-                self.consumers.error(ctx.cause());
+                self.unacked_deliveries.clear();
+                self.ack_coalescer.clear();
+                if !config.recover_consumers {
+                    self.consumers.error(ctx.cause());
+                }
             });
+            if !config.recover_confirm_mode {
+                self.status.reset_confirm();
+            }
             if !self.status.confirm() {
                 self.status.finalize_recovery();
             }
@@ -885,6 +1955,7 @@ impl Channel {
 
     fn on_channel_flow_received(&self, method: protocol::channel::Flow) -> Result<()> {
         self.status.set_send_flow(method.active);
+        self.flow_handler.on_flow(method.active);
         let channel = self.clone();
         self.internal_rpc.register_internal_future(async move {
             channel
@@ -909,27 +1980,51 @@ impl Channel {
     fn on_channel_close_received(&self, method: protocol::channel::Close) -> Result<()> {
         let error = AMQPError::try_from(method.clone()).map(|error| {
                 error!(
-                    channel=%self.id, ?method, ?error,
+                    channel=%self.id, label=self.label(), ?method, ?error,
                     "Channel closed"
                 );
                 Error::from(ErrorKind::ProtocolError(error))
-            }).map_err(|error| info!(channel=%self.id, ?method, code_to_error=%error, "Channel closed with a non-error code")).ok();
-        match (self.recovery_config.auto_recover_channels, error.as_ref()) {
+            }).map_err(|error| info!(channel=%self.id, label=self.label(), ?method, code_to_error=%error, "Channel closed with a non-error code")).ok();
+        let config = self.recovery_config();
+        let recover = match (config.auto_recover_channels, error.as_ref()) {
             (true, Some(error)) if error.is_amqp_soft_error() => {
-                self.status.set_reconnecting(error.clone())
+                if config
+                    .max_recovery_attempts
+                    .is_some_and(|max| self.status.recovery_attempts() >= u64::from(max))
+                {
+                    self.give_up_recovery(error.clone());
+                    return Ok(());
+                }
+                let new_episode = self.status.recovery_episode().is_none();
+                self.status.set_reconnecting(
+                    error.clone(),
+                    self.connection_status.start_channel_recovery(),
+                    self.reactor.clone(),
+                );
+                if let Some(max) = config.max_recovery_time.filter(|_| new_episode) {
+                    self.schedule_recovery_deadline(max, error.clone());
+                }
+                true
             }
-            (_, err) => self.set_closing(err.cloned()),
-        }
+            (_, err) => {
+                self.set_closing(err.cloned());
+                false
+            }
+        };
         let channel = self.clone();
         self.internal_rpc.register_internal_future(async move {
             channel.channel_close_ok(error).await?;
-            if channel.recovery_config.auto_recover_channels {
-                let ch = channel.clone();
-                channel.channel_open(ch).await?;
-                if channel.status.confirm() {
-                    channel
-                        .confirm_select(ConfirmSelectOptions::default())
-                        .await?;
+            if recover {
+                match channel.recover().await {
+                    Ok(()) => channel.status.end_recovery_episode(),
+                    // The server closed the channel again, which starts a new recovery attempt,
+                    // or the connection is going away
+                    Err(err)
+                        if err.is_amqp_soft_error() || !channel.connection_status.connected() => {}
+                    Err(err) => {
+                        channel.consumers.error(err.clone());
+                        return Err(err);
+                    }
                 }
             }
             Ok(())
@@ -937,6 +2032,41 @@ impl Channel {
         Ok(())
     }
 
+    async fn recover(&self) -> Result<()> {
+        self.channel_open(self.clone()).await?;
+        if self.status.confirm() {
+            self.confirm_select(ConfirmSelectOptions::default()).await?;
+        }
+        if self.recovery_config().recover_qos {
+            for (prefetch_count, global) in self.status.qos() {
+                self.basic_qos(prefetch_count, BasicQosOptions { global })
+                    .await?;
+            }
+        }
+        self.recover_topology().await
+    }
+
+    /// Give up on the recovery if it is still ongoing after the given duration
+    fn schedule_recovery_deadline(&self, max: Duration, cause: Error) {
+        let channel = self.clone();
+        let episode = self.status.recovery_generation();
+        self.internal_rpc.register_internal_future(async move {
+            channel.sleep(max).await;
+            if channel.status.recovery_episode() == Some(episode)
+                && channel.connection_status.connected()
+            {
+                channel.give_up_recovery(cause);
+            }
+            Ok(())
+        });
+    }
+
+    fn give_up_recovery(&self, cause: Error) {
+        let error = Error::from(ErrorKind::RecoveryFailed(Box::new(cause)));
+        error!(channel=%self.id, %error, "Giving up on channel recovery");
+        self.internal_rpc.set_connection_failed(error);
+    }
+
     fn on_channel_close_ok_received(&self) -> Result<()> {
         self.set_closed(ErrorKind::InvalidChannelState(ChannelState::Closed).into());
         Ok(())
@@ -985,6 +2115,7 @@ impl Channel {
     }
 
     fn on_exchange_delete_ok_received(&self, exchange: ShortString) -> Result<()> {
+        self.declare_cache.forget_exchange(exchange.as_str());
         self.global_registry.deregister_exchange(exchange.as_str());
         Ok(())
     }
@@ -997,6 +2128,7 @@ impl Channel {
     ) -> Result<()> {
         self.local_registry.deregister_queue(queue.as_str());
         self.global_registry.deregister_queue(queue.as_str());
+        self.declare_cache.forget_queue(queue.as_str());
         resolver.resolve(method.message_count);
         Ok(())
     }
@@ -1077,6 +2209,9 @@ impl Channel {
         options: BasicGetOptions,
     ) -> Result<()> {
         let class_id = method.get_amqp_class_id();
+        if !options.no_ack {
+            self.ack_coalescer.delivered(method.delivery_tag);
+        }
         let killswitch = self.status.set_will_receive(class_id, DeliveryCause::Get);
         self.basic_get_delivery.start_new_delivery(
             queue,
@@ -1127,12 +2262,18 @@ impl Channel {
             Consumer::new(
                 method.consumer_tag.clone(),
                 self.executor.clone(),
+                self.reactor.clone(),
                 channel_closer,
                 queue,
                 options,
                 arguments,
             )
         });
+        consumer.set_channel_context(
+            self.id,
+            self.internal_rpc.clone(),
+            self.error_handler.clone(),
+        );
         let external_consumer = consumer.external(self.id, self.internal_rpc.clone());
         self.consumers.register(method.consumer_tag, consumer);
         resolver.resolve(external_consumer);
@@ -1142,6 +2283,21 @@ impl Channel {
     fn on_basic_deliver_received(&self, method: protocol::basic::Deliver) -> Result<()> {
         let class_id = method.get_amqp_class_id();
         let consumer_tag = method.consumer_tag.clone();
+        // Deliveries to no_ack consumers are neither acked nor limited by the prefetch count
+        let acked = self
+            .consumers
+            .get(&consumer_tag)
+            .is_some_and(|consumer| !consumer.options().no_ack);
+        if acked {
+            self.ack_coalescer.delivered(method.delivery_tag);
+            if let Some(prefetch_count) = self.status.prefetch_count().filter(|&count| count > 0) {
+                self.unacked_deliveries.delivered(
+                    method.delivery_tag,
+                    consumer_tag.clone(),
+                    prefetch_count,
+                );
+            }
+        }
         self.consumers.start_delivery(&consumer_tag, |error| {
             Delivery::new(
                 self.id,
@@ -1251,7 +2407,18 @@ impl Channel {
         Ok(())
     }
 
+    fn on_basic_qos_ok_received(
+        &self,
+        prefetch_count: ShortUInt,
+        options: BasicQosOptions,
+    ) -> Result<()> {
+        self.status.set_qos(prefetch_count, options.global);
+        Ok(())
+    }
+
     fn on_basic_recover_ok_received(&self) -> Result<()> {
+        self.unacked_deliveries.clear();
+        self.ack_coalescer.clear();
         self.consumers.drop_prefetched_messages();
         Ok(())
     }
@@ -1266,6 +2433,128 @@ impl Channel {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+        PublishDefaults,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn consume_one() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .queue_declare(
+                    "replies",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let timeout = Duration::from_millis(50);
+            assert!(channel
+                .consume_one("replies", BasicConsumeOptions::default(), timeout)
+                .await?
+                .is_none());
+            assert_eq!(broker.consumer_count("replies"), Some(0));
+
+            for payload in [b"first", b"other"] {
+                channel
+                    .basic_publish(
+                        "",
+                        "replies",
+                        BasicPublishOptions::default(),
+                        payload,
+                        Default::default(),
+                    )
+                    .await?
+                    .await?;
+            }
+            let delivery = channel
+                .consume_one("replies", BasicConsumeOptions::default(), timeout)
+                .await?
+                .unwrap();
+            assert_eq!(&delivery.data[..], b"first");
+            delivery.ack(BasicAckOptions::default()).await?;
+            // The consumer is gone, and what it prefetched went back to the queue
+            assert_eq!(broker.consumer_count("replies"), Some(0));
+            assert_eq!(broker.messages("replies"), [b"other"]);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn publish_template_stamps() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel.set_publish_defaults(
+                PublishDefaults::default()
+                    .with_app_id("telemetry".into())
+                    .with_message_id(true)
+                    .with_timestamp(true),
+            );
+            channel
+                .queue_declare(
+                    "metrics",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let stamped = channel.publish_template(
+                "",
+                "metrics",
+                BasicPublishOptions::default(),
+                BasicProperties::default(),
+            )?;
+            let explicit = channel.publish_template(
+                "",
+                "metrics",
+                BasicPublishOptions::default(),
+                BasicProperties::default()
+                    .with_message_id("explicit".into())
+                    .with_timestamp(42),
+            )?;
+            for template in [&stamped, &stamped, &explicit] {
+                channel
+                    .basic_publish_with_template(template, b"sample")
+                    .await?;
+            }
+
+            let mut messages = Vec::new();
+            while let Some(message) = channel
+                .basic_get("metrics", BasicGetOptions::default())
+                .await?
+            {
+                assert_eq!(&message.data[..], b"sample");
+                assert_eq!(message.properties.app_id(), &Some("telemetry".into()));
+                assert!(message.properties.timestamp().is_some());
+                messages.push(message.properties.clone());
+            }
+            assert_eq!(messages.len(), 3);
+            // Each message got its own message_id, unless the template had one
+            assert!(messages[0].message_id().is_some());
+            assert_ne!(messages[0].message_id(), messages[1].message_id());
+            assert_eq!(messages[2].message_id(), &Some("explicit".into()));
+            assert_eq!(messages[2].timestamp(), &Some(42));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
+
 #[cfg(feature = "codegen")]
 include!(concat!(env!("OUT_DIR"), "/channel.rs"));
 #[cfg(not(feature = "codegen"))]
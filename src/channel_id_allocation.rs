@@ -0,0 +1,158 @@
+use crate::{id_sequence::IdSequence, types::ChannelId};
+use std::{backtrace::Backtrace, fmt, sync::Arc, time::Duration};
+
+/// How a [`Connection`] picks the id of the channels it creates
+///
+/// [`Connection`]: ./struct.Connection.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelIdAllocation {
+    /// Use the id following the last allocated one, wrapping around once `channel_max` is
+    /// reached
+    #[default]
+    Sequential,
+    /// Use the lowest available id
+    ReuseLowest,
+    /// Use a random available id
+    Random,
+}
+
+/// A channel which was still open when a connection ran out of channel ids, or which has been
+/// idle for too long
+#[derive(Clone)]
+pub struct OpenChannel {
+    pub id: ChannelId,
+    pub label: Option<String>,
+    /// For how long no frame was sent or received on the channel
+    pub idle: Duration,
+    /// Where the channel was created from
+    ///
+    /// This is only captured in debug builds, when backtraces are enabled through the
+    /// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables.
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+impl fmt::Display for OpenChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        if let Some(label) = self.label.as_ref() {
+            write!(f, " ({})", label)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for OpenChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("OpenChannel");
+        debug
+            .field("id", &self.id)
+            .field("label", &self.label)
+            .field("idle", &self.idle);
+        if let Some(backtrace) = self.backtrace.as_ref() {
+            debug.field("backtrace", &format_args!("\n{}", backtrace));
+        }
+        debug.finish()
+    }
+}
+
+pub(crate) struct ChannelIdAllocator {
+    strategy: ChannelIdAllocation,
+    sequence: IdSequence<ChannelId>,
+}
+
+impl ChannelIdAllocator {
+    pub(crate) fn set_strategy(&mut self, strategy: ChannelIdAllocation) {
+        self.strategy = strategy;
+    }
+
+    /// Pick an id which isn't in use, if there is any left
+    pub(crate) fn allocate(
+        &mut self,
+        channel_max: ChannelId,
+        in_use: impl Fn(ChannelId) -> bool,
+    ) -> Option<ChannelId> {
+        // Like the sequential allocation, never hand out channel_max itself
+        let last = if channel_max == 0 {
+            ChannelId::MAX
+        } else {
+            channel_max - 1
+        };
+        match self.strategy {
+            ChannelIdAllocation::Sequential => {
+                self.sequence.set_max(channel_max);
+                let first_id = self.sequence.next();
+                let mut id = first_id;
+                loop {
+                    if !in_use(id) {
+                        return Some(id);
+                    }
+                    id = self.sequence.next();
+                    if id == first_id {
+                        return None;
+                    }
+                }
+            }
+            ChannelIdAllocation::ReuseLowest => (1..=last).find(|id| !in_use(*id)),
+            ChannelIdAllocation::Random if last == 0 => None,
+            ChannelIdAllocation::Random => {
+                let start = fastrand::u16(1..=last);
+                (start..=last).chain(1..start).find(|id| !in_use(*id))
+            }
+        }
+    }
+}
+
+impl Default for ChannelIdAllocator {
+    fn default() -> Self {
+        Self {
+            strategy: ChannelIdAllocation::default(),
+            sequence: IdSequence::new(false),
+        }
+    }
+}
+
+impl fmt::Debug for ChannelIdAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelIdAllocator")
+            .field("strategy", &self.strategy)
+            .field("sequence", &self.sequence)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn allocate_all(strategy: ChannelIdAllocation, used: &[ChannelId]) -> Vec<ChannelId> {
+        let mut allocator = ChannelIdAllocator::default();
+        allocator.set_strategy(strategy);
+        let mut used = used.iter().copied().collect::<HashSet<_>>();
+        let mut allocated = Vec::new();
+        while let Some(id) = allocator.allocate(5, |id| used.contains(&id)) {
+            used.insert(id);
+            allocated.push(id);
+        }
+        allocated
+    }
+
+    #[test]
+    fn allocation_strategies() {
+        assert_eq!(
+            allocate_all(ChannelIdAllocation::Sequential, &[2]),
+            vec![1, 3, 4]
+        );
+        assert_eq!(
+            allocate_all(ChannelIdAllocation::ReuseLowest, &[2]),
+            vec![1, 3, 4]
+        );
+        let mut random = allocate_all(ChannelIdAllocation::Random, &[2]);
+        random.sort_unstable();
+        assert_eq!(random, vec![1, 3, 4]);
+
+        let mut allocator = ChannelIdAllocator::default();
+        allocator.set_strategy(ChannelIdAllocation::ReuseLowest);
+        assert_eq!(allocator.allocate(5, |id| id != 2 && id != 4), Some(2));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{
+    consumer_tag::ConsumerTagStrategy,
+    options::BasicQosOptions,
+    publisher_confirm::DroppedConfirmPolicy,
+    types::{ChannelId, ShortUInt},
+    Error,
+};
+use std::{fmt, time::Duration};
+
+type ErrorFn = Box<dyn FnMut(Error) + Send + 'static>;
+
+/// How [`Connection::create_channel_with`] sets up the channel it opens.
+///
+/// ```rust,no_run
+/// use lapin::{options::BasicQosOptions, ChannelOptions, Connection, ConnectionProperties};
+///
+/// # async_global_executor::block_on(async {
+/// let connection = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+/// let channel = connection
+///     .create_channel_with(
+///         ChannelOptions::default()
+///             .with_label("orders-publisher")
+///             .with_confirm(true)
+///             .with_qos(10, BasicQosOptions::default())
+///             .on_error(|error| eprintln!("channel error: {}", error)),
+///     )
+///     .await?;
+/// # Ok::<(), lapin::Error>(())
+/// # });
+/// ```
+///
+/// [`Connection::create_channel_with`]: ./struct.Connection.html#method.create_channel_with
+#[derive(Default)]
+pub struct ChannelOptions {
+    pub(crate) id: Option<ChannelId>,
+    pub(crate) confirm: bool,
+    pub(crate) qos: Option<(ShortUInt, BasicQosOptions)>,
+    pub(crate) label: Option<String>,
+    pub(crate) error_handler: Option<ErrorFn>,
+    pub(crate) consumer_tag_strategy: Option<ConsumerTagStrategy>,
+    pub(crate) dropped_confirm_policy: Option<DroppedConfirmPolicy>,
+    pub(crate) declare_cache: bool,
+    pub(crate) ack_coalescing: Option<Duration>,
+}
+
+impl ChannelOptions {
+    /// Open the channel with this id instead of the next available one.
+    ///
+    /// Creating the channel fails with [`ErrorKind::InvalidChannel`] if the id is already in
+    /// use or out of the negotiated range.
+    ///
+    /// [`ErrorKind::InvalidChannel`]: ./enum.ErrorKind.html#variant.InvalidChannel
+    #[must_use]
+    pub fn with_id(mut self, id: ChannelId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Enable publisher confirms on the channel.
+    #[must_use]
+    pub fn with_confirm(mut self, confirm: bool) -> Self {
+        self.confirm = confirm;
+        self
+    }
+
+    /// Apply this `basic_qos` to the channel.
+    #[must_use]
+    pub fn with_qos(mut self, prefetch_count: ShortUInt, options: BasicQosOptions) -> Self {
+        self.qos = Some((prefetch_count, options));
+        self
+    }
+
+    /// Name the channel in logs and in its `Debug` output.
+    #[must_use]
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Register an error handler on the channel, as [`Channel::on_error`] does, before opening
+    /// it.
+    ///
+    /// [`Channel::on_error`]: ./struct.Channel.html#method.on_error
+    #[must_use]
+    pub fn on_error<E: FnMut(Error) + Send + 'static>(mut self, handler: E) -> Self {
+        self.error_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Generate the tags of the consumers started without one on this channel this way,
+    /// instead of following the connection's strategy.
+    #[must_use]
+    pub fn with_consumer_tag_strategy(mut self, strategy: ConsumerTagStrategy) -> Self {
+        self.consumer_tag_strategy = Some(strategy);
+        self
+    }
+
+    /// Apply this policy to the publisher confirms dropped without being awaited, as
+    /// [`Channel::set_dropped_confirm_policy`] does.
+    ///
+    /// [`Channel::set_dropped_confirm_policy`]: ./struct.Channel.html#method.set_dropped_confirm_policy
+    #[must_use]
+    pub fn with_dropped_confirm_policy(mut self, policy: DroppedConfirmPolicy) -> Self {
+        self.dropped_confirm_policy = Some(policy);
+        self
+    }
+
+    /// Skip the declares identical to one already sent on the channel, as
+    /// [`Channel::set_declare_cache`] does.
+    ///
+    /// [`Channel::set_declare_cache`]: ./struct.Channel.html#method.set_declare_cache
+    #[must_use]
+    pub fn with_declare_cache(mut self) -> Self {
+        self.declare_cache = true;
+        self
+    }
+
+    /// Coalesce the acks sent through [`Acker::ack`] during up to `max_delay` into one
+    /// basic.ack with `multiple`, to send far fewer frames when consuming a lot of messages.
+    ///
+    /// Each ack is delayed by up to `max_delay`, and its promise only resolves once it got sent.
+    /// Only the acks following each other without any delivery left unacked in between can be
+    /// sent together, the other ones being sent one by one at the end of the window. The
+    /// pending acks are sent before closing the channel or its connection.
+    ///
+    /// [`Acker::ack`]: ./acker/struct.Acker.html#method.ack
+    #[must_use]
+    pub fn with_ack_coalescing(mut self, max_delay: Duration) -> Self {
+        self.ack_coalescing = Some(max_delay);
+        self
+    }
+}
+
+impl fmt::Debug for ChannelOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelOptions")
+            .field("id", &self.id)
+            .field("confirm", &self.confirm)
+            .field("qos", &self.qos)
+            .field("label", &self.label)
+            .field("error_handler", &self.error_handler.is_some())
+            .field("consumer_tag_strategy", &self.consumer_tag_strategy)
+            .field("dropped_confirm_policy", &self.dropped_confirm_policy)
+            .field("declare_cache", &self.declare_cache)
+            .field("ack_coalescing", &self.ack_coalescing)
+            .finish()
+    }
+}
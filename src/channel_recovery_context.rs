@@ -1,24 +1,37 @@
 use crate::{
+    connection_status::RecoveryGuard,
     frames::{ExpectedReply, Frames},
-    notifier::Notifier,
-    Error,
+    notifier::{Notifier, RecoveryEvent, RecoveryOutcome},
+    recovery_config::PublishBufferOverflow,
+    types::ChannelId,
+    Error, Promise, PromiseResolver, Result,
 };
 
 use std::collections::VecDeque;
 
 pub(crate) struct ChannelRecoveryContext {
+    channel_id: ChannelId,
     cause: Error,
     expected_replies: Option<VecDeque<ExpectedReply>>,
     notifier: Notifier,
+    buffered_publishes: VecDeque<PromiseResolver<()>>,
+    guard: RecoveryGuard,
 }
 
 impl ChannelRecoveryContext {
-    pub(crate) fn new(cause: Error) -> Self {
-        let notifier = Notifier::default();
+    pub(crate) fn new(
+        channel_id: ChannelId,
+        cause: Error,
+        guard: RecoveryGuard,
+        notifier: Notifier,
+    ) -> Self {
         Self {
+            channel_id,
             cause: cause.with_notifier(Some(notifier.clone())),
             expected_replies: None,
             notifier,
+            buffered_publishes: VecDeque::new(),
+            guard,
         }
     }
 
@@ -37,8 +50,64 @@ impl ChannelRecoveryContext {
         self.expected_replies = expected_replies;
     }
 
-    pub(crate) fn finalize_recovery(self) {
-        self.notifier.notify_all();
+    /// Get a promise resolved once the publish can be sent on the recovered channel
+    pub(crate) fn buffer_publish(
+        &mut self,
+        capacity: usize,
+        overflow: PublishBufferOverflow,
+    ) -> Result<Promise<()>> {
+        // Cancelled publishes don't hold on to their slot
+        self.buffered_publishes
+            .retain(|publish| !publish.is_abandoned());
+        if self.buffered_publishes.len() >= capacity {
+            match overflow {
+                PublishBufferOverflow::DropOldest => {
+                    if let Some(oldest) = self.buffered_publishes.pop_front() {
+                        oldest.reject(self.cause());
+                    }
+                }
+                _ => return Err(self.cause()),
+            }
+        }
+        let (promise, resolver) = Promise::new();
+        self.buffered_publishes.push_back(resolver);
+        Ok(promise)
+    }
+
+    /// Give up on this recovery attempt to start a new one, which inherits the notifier
+    pub(crate) fn retry_recovery(mut self, error: Error) -> Notifier {
+        self.reject_buffered_publishes(error);
+        let notifier = self.notifier.clone();
+        self.cancel_expected_replies();
+        notifier
+    }
+
+    pub(crate) fn abort_recovery(mut self, error: Error) {
+        self.reject_buffered_publishes(error.clone());
+        self.notifier
+            .notify_all(RecoveryOutcome::PermanentlyFailed(error.clone()));
+        self.guard
+            .notify(RecoveryEvent::ChannelFailed(self.channel_id, error));
+        self.cancel_expected_replies();
+    }
+
+    pub(crate) fn finalize_recovery(mut self) {
+        self.notifier.notify_all(RecoveryOutcome::Recovered);
+        self.guard
+            .notify(RecoveryEvent::ChannelRecovered(self.channel_id));
+        for publish in self.buffered_publishes.drain(..) {
+            publish.resolve(());
+        }
+        self.cancel_expected_replies();
+    }
+
+    fn reject_buffered_publishes(&mut self, error: Error) {
+        for publish in self.buffered_publishes.drain(..) {
+            publish.reject(error.clone());
+        }
+    }
+
+    fn cancel_expected_replies(self) {
         if let Some(replies) = self.expected_replies {
             Frames::cancel_expected_replies(replies, self.cause);
         }
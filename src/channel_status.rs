@@ -1,15 +1,21 @@
 use crate::{
     channel_receiver_state::{ChannelReceiverStates, DeliveryCause},
     channel_recovery_context::ChannelRecoveryContext,
+    connection_status::RecoveryGuard,
     internal_rpc::InternalRPCHandle,
     killswitch::KillSwitch,
     notifier::Notifier,
-    types::{ChannelId, Identifier, PayloadSize},
-    Error, ErrorKind, Result,
+    reactor::FullReactor,
+    recovery_config::PublishBufferOverflow,
+    types::{ChannelId, Identifier, PayloadSize, ShortUInt},
+    wakers::Wakers,
+    Error, ErrorKind, Promise, Result,
 };
 use std::{
     fmt,
     sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 use tracing::trace;
 
@@ -21,6 +27,38 @@ impl ChannelStatus {
         Self(Arc::new(Mutex::new(Inner::new(id, internal_rpc))))
     }
 
+    pub(crate) fn label(&self) -> Option<String> {
+        self.lock_inner().label.clone()
+    }
+
+    pub(crate) fn set_label(&self, label: Option<String>) {
+        self.lock_inner().label = label;
+    }
+
+    /// Record that a frame was sent or received on this channel
+    pub(crate) fn touch(&self) {
+        let mut inner = self.lock_inner();
+        inner.last_activity = Instant::now();
+        inner.idle_reported = false;
+    }
+
+    /// For how long no frame was sent or received on this channel
+    pub(crate) fn idle_for(&self) -> Duration {
+        self.lock_inner().last_activity.elapsed()
+    }
+
+    /// Whether the channel has been idle for at least `threshold` and wasn't reported yet since
+    /// its last activity
+    pub(crate) fn report_idle(&self, threshold: Duration) -> Option<Duration> {
+        let mut inner = self.lock_inner();
+        let idle = inner.last_activity.elapsed();
+        if inner.idle_reported || idle < threshold {
+            return None;
+        }
+        inner.idle_reported = true;
+        Some(idle)
+    }
+
     pub fn initializing(&self) -> bool {
         [ChannelState::Initial, ChannelState::Reconnecting].contains(&self.lock_inner().state)
     }
@@ -37,6 +75,42 @@ impl ChannelStatus {
         self.lock_inner().state == ChannelState::Reconnecting
     }
 
+    /// Whether the channel is being reopened after the server closed it
+    pub fn is_recovering(&self) -> bool {
+        self.lock_inner().recovery_context.is_some()
+    }
+
+    /// How many times the channel started recovering
+    ///
+    /// Deliveries received in a previous generation cannot be acknowledged anymore.
+    pub fn recovery_generation(&self) -> u64 {
+        self.lock_inner().recovery_generation
+    }
+
+    /// How many recovery attempts were started since the channel last fully recovered
+    pub(crate) fn recovery_attempts(&self) -> u64 {
+        let inner = self.lock_inner();
+        inner
+            .recovery_episode
+            .map_or(0, |start| inner.recovery_generation - start + 1)
+    }
+
+    /// The generation at which the ongoing recovery started, if any
+    pub(crate) fn recovery_episode(&self) -> Option<u64> {
+        self.lock_inner().recovery_episode
+    }
+
+    /// Mark the channel, including its topology, as fully recovered
+    pub(crate) fn end_recovery_episode(&self) {
+        self.lock_inner().recovery_episode = None;
+    }
+
+    pub(crate) fn abort_recovery(&self, error: Error) {
+        if let Some(context) = self.lock_inner().recovery_context.take() {
+            context.abort_recovery(error);
+        }
+    }
+
     pub(crate) fn connected_or_recovering(&self) -> bool {
         [ChannelState::Connected, ChannelState::Reconnecting].contains(&self.lock_inner().state)
     }
@@ -52,6 +126,21 @@ impl ChannelStatus {
         self.lock_inner().finalize_recovery();
     }
 
+    pub(crate) fn buffer_publish(
+        &self,
+        capacity: usize,
+        overflow: PublishBufferOverflow,
+    ) -> Result<Promise<()>> {
+        let inner = &mut *self.lock_inner();
+        match inner.recovery_context.as_mut() {
+            Some(context) if inner.state == ChannelState::Reconnecting => {
+                context.buffer_publish(capacity, overflow)
+            }
+            // Recovery is already over
+            _ => Ok(Promise::new_with_data(Ok(()))),
+        }
+    }
+
     pub(crate) fn can_receive_messages(&self) -> bool {
         [
             ChannelState::Closing,
@@ -65,6 +154,36 @@ impl ChannelStatus {
         self.lock_inner().confirm
     }
 
+    pub(crate) fn reset_confirm(&self) {
+        self.lock_inner().confirm = false;
+    }
+
+    /// The prefetch counts set with basic.qos, per consumer then global
+    pub(crate) fn qos(&self) -> Vec<(ShortUInt, bool)> {
+        let inner = self.lock_inner();
+        [
+            (inner.prefetch_count, false),
+            (inner.global_prefetch_count, true),
+        ]
+        .into_iter()
+        .filter_map(|(prefetch_count, global)| Some((prefetch_count?, global)))
+        .collect()
+    }
+
+    /// The per consumer prefetch count set with basic.qos, if any
+    pub(crate) fn prefetch_count(&self) -> Option<ShortUInt> {
+        self.lock_inner().prefetch_count
+    }
+
+    pub(crate) fn set_qos(&self, prefetch_count: ShortUInt, global: bool) {
+        let mut inner = self.lock_inner();
+        if global {
+            inner.global_prefetch_count = Some(prefetch_count);
+        } else {
+            inner.prefetch_count = Some(prefetch_count);
+        }
+    }
+
     pub(crate) fn set_confirm(&self) {
         let mut inner = self.lock_inner();
         inner.confirm = true;
@@ -77,7 +196,22 @@ impl ChannelStatus {
     }
 
     pub(crate) fn set_state(&self, state: ChannelState) {
-        self.lock_inner().state = state;
+        let mut inner = self.lock_inner();
+        inner.state = state;
+        // A new channel starts with flow enabled, and a closed one has nothing to publish anymore
+        inner.set_send_flow(true);
+        if [
+            ChannelState::Closing,
+            ChannelState::Closed,
+            ChannelState::Error,
+        ]
+        .contains(&state)
+        {
+            inner.recovery_episode = None;
+            if let Some(context) = inner.recovery_context.take() {
+                context.abort_recovery(ErrorKind::InvalidChannelState(state).into());
+            }
+        }
     }
 
     pub(crate) fn state_error(&self) -> Error {
@@ -85,8 +219,13 @@ impl ChannelStatus {
         Error::from(ErrorKind::InvalidChannelState(inner.state)).with_notifier(inner.notifier())
     }
 
-    pub(crate) fn set_reconnecting(&self, error: Error) {
-        self.lock_inner().set_reconnecting(error);
+    pub(crate) fn set_reconnecting(
+        &self,
+        error: Error,
+        guard: RecoveryGuard,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
+    ) {
+        self.lock_inner().set_reconnecting(error, guard, reactor);
     }
 
     pub(crate) fn auto_close(&self, id: ChannelId) -> bool {
@@ -154,13 +293,24 @@ impl ChannelStatus {
     }
 
     pub(crate) fn set_send_flow(&self, flow: bool) {
-        self.lock_inner().send_flow = flow;
+        self.lock_inner().set_send_flow(flow);
     }
 
-    pub(crate) fn flow(&self) -> bool {
+    /// Whether the server currently allows us to publish on this channel (channel.flow)
+    pub fn flow(&self) -> bool {
         self.lock_inner().send_flow
     }
 
+    pub(crate) fn poll_flow(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let inner = self.lock_inner();
+        if inner.send_flow {
+            Poll::Ready(())
+        } else {
+            inner.flow_wakers.register(cx.waker());
+            Poll::Pending
+        }
+    }
+
     fn lock_inner(&self) -> MutexGuard<'_, Inner> {
         self.0.lock().unwrap_or_else(|e| e.into_inner())
     }
@@ -181,11 +331,15 @@ impl fmt::Debug for ChannelStatus {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut debug = f.debug_struct("ChannelStatus");
         if let Ok(inner) = self.0.try_lock() {
+            if let Some(label) = inner.label.as_ref() {
+                debug.field("label", label);
+            }
             debug
                 .field("state", &inner.state)
                 .field("receiver_state", &inner.receiver_state)
                 .field("confirm", &inner.confirm)
-                .field("send_flow", &inner.send_flow);
+                .field("send_flow", &inner.send_flow)
+                .field("recovery_generation", &inner.recovery_generation);
         }
         debug.finish()
     }
@@ -193,11 +347,19 @@ impl fmt::Debug for ChannelStatus {
 
 struct Inner {
     id: ChannelId,
+    label: Option<String>,
+    last_activity: Instant,
+    idle_reported: bool,
     confirm: bool,
+    prefetch_count: Option<ShortUInt>,
+    global_prefetch_count: Option<ShortUInt>,
     send_flow: bool,
+    flow_wakers: Wakers,
     state: ChannelState,
     receiver_state: ChannelReceiverStates,
     recovery_context: Option<ChannelRecoveryContext>,
+    recovery_generation: u64,
+    recovery_episode: Option<u64>,
     killswitch: KillSwitch,
     internal_rpc: InternalRPCHandle,
 }
@@ -206,11 +368,19 @@ impl Inner {
     fn new(id: ChannelId, internal_rpc: InternalRPCHandle) -> Self {
         let this = Self {
             id,
+            label: None,
+            last_activity: Instant::now(),
+            idle_reported: false,
             confirm: false,
+            prefetch_count: None,
+            global_prefetch_count: None,
             send_flow: true,
+            flow_wakers: Wakers::default(),
             state: ChannelState::default(),
             receiver_state: ChannelReceiverStates::default(),
             recovery_context: None,
+            recovery_generation: 0,
+            recovery_episode: None,
             killswitch: KillSwitch::default(),
             internal_rpc,
         };
@@ -223,12 +393,32 @@ impl Inner {
             .set_channel_status(self.id, self.killswitch.clone());
     }
 
-    fn set_reconnecting(&mut self, error: Error) {
+    fn set_send_flow(&mut self, flow: bool) {
+        self.send_flow = flow;
+        if flow {
+            self.flow_wakers.wake();
+        }
+    }
+
+    fn set_reconnecting(
+        &mut self,
+        error: Error,
+        guard: RecoveryGuard,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
+    ) {
         self.state = ChannelState::Reconnecting;
+        self.recovery_generation += 1;
+        self.recovery_episode
+            .get_or_insert(self.recovery_generation);
+        self.set_send_flow(true);
         std::mem::take(&mut self.killswitch).kill();
         self.update_rpc_status();
         self.receiver_state.reset();
-        self.recovery_context = Some(ChannelRecoveryContext::new(error));
+        let notifier = match self.recovery_context.take() {
+            Some(context) => context.retry_recovery(error.clone()),
+            None => Notifier::new(reactor),
+        };
+        self.recovery_context = Some(ChannelRecoveryContext::new(self.id, error, guard, notifier));
     }
 
     pub(crate) fn finalize_recovery(&mut self) {
@@ -1,12 +1,16 @@
 use crate::{
+    channel_id_allocation::{ChannelIdAllocation, ChannelIdAllocator, OpenChannel},
     connection_closer::ConnectionCloser,
     error_handler::ErrorHandler,
     frames::Frames,
-    id_sequence::IdSequence,
     internal_rpc::InternalRPCHandle,
+    notifier::RecoveryEvent,
     protocol::{AMQPClass, AMQPError, AMQPHardError},
+    raw_method::RawMethodHandlers,
+    reactor::FullReactor,
     recovery_config::RecoveryConfig,
     registry::Registry,
+    slow_consumer::{SlowConsumer, SlowConsumerHandler},
     socket_state::SocketStateHandle,
     topology_internal::ChannelDefinitionInternal,
     types::{ChannelId, Identifier, PayloadSize},
@@ -16,11 +20,15 @@ use crate::{
 use amq_protocol::frame::{AMQPFrame, ProtocolVersion};
 use executor_trait::FullExecutor;
 use std::{
+    backtrace::{Backtrace, BacktraceStatus},
     collections::HashMap,
     fmt,
     sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
 };
-use tracing::{debug, error, level_enabled, trace, Level};
+use tracing::{debug, error, level_enabled, trace, warn, Level};
+
+type LeakHandler = Arc<Mutex<Option<Box<dyn FnMut(OpenChannel) + Send + 'static>>>>;
 
 #[derive(Clone)]
 pub(crate) struct Channels {
@@ -29,8 +37,12 @@ pub(crate) struct Channels {
     global_registry: Registry,
     internal_rpc: InternalRPCHandle,
     executor: Arc<dyn FullExecutor + Send + Sync>,
+    reactor: Arc<dyn FullReactor + Send + Sync>,
     frames: Frames,
     error_handler: ErrorHandler,
+    leak_handler: LeakHandler,
+    slow_consumer_handler: SlowConsumerHandler,
+    raw_method_handlers: RawMethodHandlers,
 }
 
 impl Channels {
@@ -42,6 +54,7 @@ impl Channels {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
         recovery_config: RecoveryConfig,
     ) -> Self {
         Self {
@@ -54,8 +67,12 @@ impl Channels {
             global_registry,
             internal_rpc,
             executor,
+            reactor,
             frames,
             error_handler: ErrorHandler::default(),
+            leak_handler: LeakHandler::default(),
+            slow_consumer_handler: SlowConsumerHandler::default(),
+            raw_method_handlers: RawMethodHandlers::default(),
         }
     }
 
@@ -66,6 +83,28 @@ impl Channels {
             self.internal_rpc.clone(),
             self.frames.clone(),
             self.executor.clone(),
+            self.reactor.clone(),
+            connection_closer,
+        )
+    }
+
+    pub(crate) fn set_id_allocation(&self, strategy: ChannelIdAllocation) {
+        self.lock_inner().channel_id.set_strategy(strategy);
+    }
+
+    pub(crate) fn create_with_id(
+        &self,
+        id: ChannelId,
+        connection_closer: Arc<ConnectionCloser>,
+    ) -> Result<Channel> {
+        self.lock_inner().create_with_id(
+            id,
+            self.connection_status.clone(),
+            self.global_registry.clone(),
+            self.internal_rpc.clone(),
+            self.frames.clone(),
+            self.executor.clone(),
+            self.reactor.clone(),
             connection_closer,
         )
     }
@@ -79,6 +118,7 @@ impl Channels {
                 self.internal_rpc.clone(),
                 self.frames.clone(),
                 self.executor.clone(),
+                self.reactor.clone(),
                 None,
             )
             .set_state(ChannelState::Connected);
@@ -90,7 +130,9 @@ impl Channels {
 
     pub(crate) fn remove(&self, id: ChannelId, error: Error) -> Result<()> {
         self.frames.clear_expected_replies(id, error);
-        if self.lock_inner().channels.remove(&id).is_some() {
+        let mut inner = self.lock_inner();
+        inner.backtraces.remove(&id);
+        if inner.channels.remove(&id).is_some() {
             Ok(())
         } else {
             Err(ErrorKind::InvalidChannel(id).into())
@@ -99,7 +141,11 @@ impl Channels {
 
     pub(crate) fn receive_method(&self, id: ChannelId, method: AMQPClass) -> Result<()> {
         self.get(id)
-            .map(|channel| channel.receive_method(method))
+            .map(|channel| {
+                let _span = channel.span().entered();
+                channel.status().touch();
+                channel.receive_method(method)
+            })
             .unwrap_or_else(|| Err(ErrorKind::InvalidChannel(id).into()))
     }
 
@@ -111,16 +157,37 @@ impl Channels {
         properties: BasicProperties,
     ) -> Result<()> {
         self.get(id)
-            .map(|channel| channel.handle_content_header_frame(class_id, size, properties))
+            .map(|channel| {
+                let _span = channel.span().entered();
+                channel.status().touch();
+                channel.handle_content_header_frame(class_id, size, properties)
+            })
             .unwrap_or_else(|| Err(ErrorKind::InvalidChannel(id).into()))
     }
 
     pub(crate) fn handle_body_frame(&self, id: ChannelId, payload: Vec<u8>) -> Result<()> {
         self.get(id)
-            .map(|channel| channel.handle_body_frame(payload))
+            .map(|channel| {
+                let _span = channel.span().entered();
+                channel.status().touch();
+                channel.handle_body_frame(payload)
+            })
             .unwrap_or_else(|| Err(ErrorKind::InvalidChannel(id).into()))
     }
 
+    /// Send the acks waiting to be coalesced on all the channels
+    pub(crate) async fn flush_acks(&self) {
+        let channels = self
+            .lock_inner()
+            .channels
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for channel in channels {
+            channel.flush_acks().await;
+        }
+    }
+
     pub(crate) fn set_connection_closing(&self) {
         self.connection_status.set_state(ConnectionState::Closing);
         for channel in self.lock_inner().channels.values() {
@@ -134,11 +201,24 @@ impl Channels {
             self.frames.clear_expected_replies(*id, error.clone());
             channel.set_closed(error.clone());
         }
+        self.connection_status
+            .notify(RecoveryEvent::ConnectionUnusable(error));
     }
 
     pub(crate) fn set_connection_error(&self, error: Error) {
-        // Do nothing if we were already in error
-        if let ConnectionState::Error = self.connection_status.set_state(ConnectionState::Error) {
+        self.fail_connection(ConnectionState::Error, error);
+    }
+
+    /// Terminally fail the connection once we gave up on recovering it
+    pub(crate) fn set_connection_failed(&self, error: Error) {
+        self.fail_connection(ConnectionState::Failed, error);
+    }
+
+    fn fail_connection(&self, state: ConnectionState, error: Error) {
+        // Do nothing, keeping the previous state, if we were already in error
+        let previous = self.connection_status.set_state(state);
+        if [ConnectionState::Error, ConnectionState::Failed].contains(&previous) {
+            self.connection_status.set_state(previous);
             return;
         }
 
@@ -153,6 +233,8 @@ impl Channels {
             self.frames.clear_expected_replies(*id, error.clone());
             channel.set_connection_error(error.clone());
         }
+        self.connection_status
+            .notify(RecoveryEvent::ConnectionUnusable(error));
     }
 
     pub(crate) fn flow(&self) -> bool {
@@ -270,6 +352,91 @@ impl Channels {
         self.error_handler.set_handler(handler);
     }
 
+    pub(crate) fn raw_method_handlers(&self) -> &RawMethodHandlers {
+        &self.raw_method_handlers
+    }
+
+    pub(crate) fn set_leak_handler<L: FnMut(OpenChannel) + Send + 'static>(&self, handler: L) {
+        *self.leak_handler.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+    }
+
+    /// Periodically report the channels which have been idle for longer than `threshold`
+    pub(crate) fn start_leak_detection(&self, threshold: Duration) {
+        let channels = self.clone();
+        let interval = (threshold / 2).max(Duration::from_millis(10));
+        self.executor.spawn(Box::pin(async move {
+            loop {
+                channels.reactor.sleep(interval).await;
+                if !channels.connection_status.connected() {
+                    break;
+                }
+                channels.report_idle_channels(threshold);
+            }
+        }));
+    }
+
+    /// Report each idle channel once, until it sees some activity again
+    pub(crate) fn report_idle_channels(&self, threshold: Duration) {
+        let idle_channels = self.lock_inner().idle_channels(threshold);
+        for channel in idle_channels {
+            if let Some(backtrace) = channel.backtrace.as_ref() {
+                warn!(channel=%channel.id, label=?channel.label, idle=?channel.idle, %backtrace, "Channel has been idle for too long, it may have leaked");
+            } else {
+                warn!(channel=%channel.id, label=?channel.label, idle=?channel.idle, "Channel has been idle for too long, it may have leaked");
+            }
+            if let Some(handler) = self
+                .leak_handler
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_mut()
+            {
+                handler(channel);
+            }
+        }
+    }
+
+    pub(crate) fn set_slow_consumer_handler<S: FnMut(SlowConsumer) + Send + 'static>(
+        &self,
+        handler: S,
+    ) {
+        *self
+            .slow_consumer_handler
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+    }
+
+    /// Periodically report the consumers which have been stuck at their prefetch count for
+    /// longer than `threshold`
+    pub(crate) fn start_slow_consumer_detection(&self, threshold: Duration) {
+        let channels = self.clone();
+        let interval = (threshold / 2).max(Duration::from_millis(10));
+        self.executor.spawn(Box::pin(async move {
+            loop {
+                channels.reactor.sleep(interval).await;
+                if !channels.connection_status.connected() {
+                    break;
+                }
+                channels.report_slow_consumers(threshold);
+            }
+        }));
+    }
+
+    /// Report each slow consumer once, until it acks some messages
+    pub(crate) fn report_slow_consumers(&self, threshold: Duration) {
+        let slow_consumers = self.lock_inner().slow_consumers(threshold);
+        for consumer in slow_consumers {
+            warn!(channel=%consumer.channel_id, consumer_tag=%consumer.consumer_tag, queue=%consumer.queue, prefetch_count=%consumer.prefetch_count, saturated_for=?consumer.saturated_for, "Consumer has been at its prefetch count for too long, it may be too slow");
+            if let Some(handler) = self
+                .slow_consumer_handler
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_mut()
+            {
+                handler(consumer);
+            }
+        }
+    }
+
     pub(crate) fn topology(&self) -> Vec<ChannelDefinitionInternal> {
         self.lock_inner()
             .channels
@@ -303,7 +470,8 @@ impl fmt::Debug for Channels {
 
 struct Inner {
     channels: HashMap<ChannelId, Channel>,
-    channel_id: IdSequence<ChannelId>,
+    backtraces: HashMap<ChannelId, Arc<Backtrace>>,
+    channel_id: ChannelIdAllocator,
     configuration: Configuration,
     waker: SocketStateHandle,
     recovery_config: RecoveryConfig,
@@ -317,7 +485,8 @@ impl Inner {
     ) -> Self {
         Self {
             channels: HashMap::default(),
-            channel_id: IdSequence::new(false),
+            backtraces: HashMap::default(),
+            channel_id: ChannelIdAllocator::default(),
             configuration,
             waker,
             recovery_config,
@@ -332,9 +501,16 @@ impl Inner {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
         connection_closer: Option<Arc<ConnectionCloser>>,
     ) -> Channel {
         debug!(%id, "create channel");
+        if cfg!(debug_assertions) && id != 0 {
+            let backtrace = Backtrace::capture();
+            if backtrace.status() == BacktraceStatus::Captured {
+                self.backtraces.insert(id, Arc::new(backtrace));
+            }
+        }
         let channel = Channel::new(
             id,
             self.configuration.clone(),
@@ -344,6 +520,7 @@ impl Inner {
             internal_rpc,
             frames,
             executor,
+            reactor,
             connection_closer,
             self.recovery_config.clone(),
         );
@@ -358,33 +535,103 @@ impl Inner {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
         connection_closer: Arc<ConnectionCloser>,
     ) -> Result<Channel> {
         debug!("create channel");
-        self.channel_id.set_max(self.configuration.channel_max());
-        let first_id = self.channel_id.next();
-        let mut id = first_id;
-        let mut met_first_id = false;
-        loop {
-            if id == first_id {
-                if met_first_id {
-                    break;
-                }
-                met_first_id = true;
-            }
-            if !self.channels.contains_key(&id) {
-                return Ok(self.create_channel(
-                    id,
-                    connection_status,
-                    global_registry,
-                    internal_rpc,
-                    frames,
-                    executor,
-                    Some(connection_closer),
-                ));
-            }
-            id = self.channel_id.next();
+        let channels = &self.channels;
+        match self
+            .channel_id
+            .allocate(self.configuration.channel_max(), |id| {
+                channels.contains_key(&id)
+            }) {
+            Some(id) => Ok(self.create_channel(
+                id,
+                connection_status,
+                global_registry,
+                internal_rpc,
+                frames,
+                executor,
+                reactor,
+                Some(connection_closer),
+            )),
+            None => Err(ErrorKind::ChannelsLimitReached(self.open_channels()).into()),
+        }
+    }
+
+    fn open_channels(&self) -> Vec<OpenChannel> {
+        let mut open_channels = self
+            .channels
+            .values()
+            .filter(|channel| channel.id() != 0)
+            .map(|channel| OpenChannel {
+                id: channel.id(),
+                label: channel.label(),
+                idle: channel.status().idle_for(),
+                backtrace: self.backtraces.get(&channel.id()).cloned(),
+            })
+            .collect::<Vec<_>>();
+        open_channels.sort_unstable_by_key(|channel| channel.id);
+        open_channels
+    }
+
+    fn slow_consumers(&self, threshold: Duration) -> Vec<SlowConsumer> {
+        let mut slow_consumers = self
+            .channels
+            .values()
+            .filter(|channel| channel.id() != 0 && channel.status().connected())
+            .flat_map(|channel| channel.slow_consumers(threshold))
+            .collect::<Vec<_>>();
+        slow_consumers.sort_unstable_by(|a, b| {
+            (a.channel_id, &a.consumer_tag).cmp(&(b.channel_id, &b.consumer_tag))
+        });
+        slow_consumers
+    }
+
+    fn idle_channels(&self, threshold: Duration) -> Vec<OpenChannel> {
+        let mut idle_channels = self
+            .channels
+            .values()
+            .filter(|channel| channel.id() != 0 && channel.status().connected())
+            .filter_map(|channel| {
+                let idle = channel.status().report_idle(threshold)?;
+                Some(OpenChannel {
+                    id: channel.id(),
+                    label: channel.label(),
+                    idle,
+                    backtrace: self.backtraces.get(&channel.id()).cloned(),
+                })
+            })
+            .collect::<Vec<_>>();
+        idle_channels.sort_unstable_by_key(|channel| channel.id);
+        idle_channels
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_with_id(
+        &mut self,
+        id: ChannelId,
+        connection_status: ConnectionStatus,
+        global_registry: Registry,
+        internal_rpc: InternalRPCHandle,
+        frames: Frames,
+        executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
+        connection_closer: Arc<ConnectionCloser>,
+    ) -> Result<Channel> {
+        let channel_max = self.configuration.channel_max();
+        if id == 0 || (channel_max != 0 && id > channel_max) || self.channels.contains_key(&id) {
+            return Err(ErrorKind::InvalidChannel(id).into());
         }
-        Err(ErrorKind::ChannelsLimitReached.into())
+        Ok(self.create_channel(
+            id,
+            connection_status,
+            global_registry,
+            internal_rpc,
+            frames,
+            executor,
+            reactor,
+            Some(connection_closer),
+        ))
     }
 }
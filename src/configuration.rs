@@ -1,4 +1,7 @@
 use crate::{
+    broker_profile::BrokerProfile,
+    consumer_tag::ConsumerTagStrategy,
+    delivery_latency::LatencyRecorder,
     protocol,
     types::{ChannelId, FrameSize, Heartbeat},
 };
@@ -10,6 +13,7 @@ use std::{
 #[derive(Clone, Default)]
 pub struct Configuration {
     inner: Arc<RwLock<Inner>>,
+    latency: LatencyRecorder,
 }
 
 impl Configuration {
@@ -38,6 +42,34 @@ impl Configuration {
         self.write_inner().heartbeat = heartbeat;
     }
 
+    pub(crate) fn consumer_tag_strategy(&self) -> ConsumerTagStrategy {
+        self.read_inner().consumer_tag_strategy.clone()
+    }
+
+    pub(crate) fn set_consumer_tag_strategy(&self, strategy: ConsumerTagStrategy) {
+        self.write_inner().consumer_tag_strategy = strategy;
+    }
+
+    pub(crate) fn confirm_channels(&self) -> bool {
+        self.read_inner().confirm_channels
+    }
+
+    pub(crate) fn set_confirm_channels(&self, confirm_channels: bool) {
+        self.write_inner().confirm_channels = confirm_channels;
+    }
+
+    pub(crate) fn broker_profile(&self) -> BrokerProfile {
+        self.read_inner().broker_profile
+    }
+
+    pub(crate) fn set_broker_profile(&self, broker_profile: BrokerProfile) {
+        self.write_inner().broker_profile = broker_profile;
+    }
+
+    pub(crate) fn latency(&self) -> &LatencyRecorder {
+        &self.latency
+    }
+
     fn read_inner(&self) -> RwLockReadGuard<'_, Inner> {
         self.inner.read().unwrap_or_else(|e| e.into_inner())
     }
@@ -52,6 +84,9 @@ struct Inner {
     channel_max: ChannelId,
     frame_max: FrameSize,
     heartbeat: Heartbeat,
+    consumer_tag_strategy: ConsumerTagStrategy,
+    confirm_channels: bool,
+    broker_profile: BrokerProfile,
 }
 
 impl fmt::Debug for Configuration {
@@ -61,6 +96,9 @@ impl fmt::Debug for Configuration {
             .field("channel_max", &inner.channel_max)
             .field("frame_max", &inner.frame_max)
             .field("heartbeat", &inner.heartbeat)
+            .field("consumer_tag_strategy", &inner.consumer_tag_strategy)
+            .field("confirm_channels", &inner.confirm_channels)
+            .field("broker_profile", &inner.broker_profile)
             .finish()
     }
 }
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+/// Slow down publishers on a [`Channel`] in confirm mode when the broker falls behind.
+///
+/// The round-trip latency of publisher confirms is tracked as a moving average. When it
+/// exceeds `target_latency`, or when more than `max_outstanding` confirms are pending,
+/// `basic_publish` is delayed before sending the message, by at most `max_delay`.
+///
+/// [`Channel`]: ./struct.Channel.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfirmThrottle {
+    pub target_latency: Duration,
+    pub max_outstanding: Option<usize>,
+    pub max_delay: Duration,
+}
+
+impl Default for ConfirmThrottle {
+    fn default() -> Self {
+        Self {
+            target_latency: Duration::from_millis(100),
+            max_outstanding: None,
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ConfirmThrottle {
+    #[must_use]
+    pub fn with_target_latency(mut self, target_latency: Duration) -> Self {
+        self.target_latency = target_latency;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_outstanding(mut self, max_outstanding: usize) -> Self {
+        self.max_outstanding = Some(max_outstanding);
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Compute how long to wait before publishing, given the current confirm latency and the
+    /// number of pending confirms.
+    pub(crate) fn delay(&self, latency: Option<Duration>, outstanding: usize) -> Duration {
+        let latency_delay = latency
+            .map(|latency| latency.saturating_sub(self.target_latency))
+            .unwrap_or_default();
+        let outstanding_delay = match self.max_outstanding {
+            Some(max) if outstanding >= max => latency.unwrap_or(self.target_latency),
+            _ => Duration::ZERO,
+        };
+        latency_delay.max(outstanding_delay).min(self.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_delay() {
+        let throttle = ConfirmThrottle::default();
+        assert_eq!(throttle.delay(None, 1000), Duration::ZERO);
+        assert_eq!(
+            throttle.delay(Some(Duration::from_millis(50)), 10),
+            Duration::ZERO
+        );
+        assert_eq!(
+            throttle.delay(Some(Duration::from_millis(300)), 10),
+            Duration::from_millis(200)
+        );
+        assert_eq!(
+            throttle.delay(Some(Duration::from_secs(5)), 10),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn outstanding_delay() {
+        let throttle = ConfirmThrottle::default().with_max_outstanding(10);
+        assert_eq!(throttle.delay(None, 9), Duration::ZERO);
+        assert_eq!(throttle.delay(None, 10), Duration::from_millis(100));
+        assert_eq!(
+            throttle.delay(Some(Duration::from_millis(20)), 10),
+            Duration::from_millis(20)
+        );
+    }
+}
@@ -1,32 +1,69 @@
 use crate::{
+    backoff::Backoff,
     channel::Channel,
+    channel_id_allocation::OpenChannel,
+    channel_options::ChannelOptions,
     channels::Channels,
     configuration::Configuration,
     connection_closer::ConnectionCloser,
     connection_properties::ConnectionProperties,
     connection_status::{ConnectionState, ConnectionStatus, ConnectionStep},
+    consumer::Consumer,
+    declare_all,
+    delivery_latency::LatencyMetrics,
     frames::Frames,
+    health::{HealthCheck, HealthStatus},
     heartbeat::Heartbeat,
     internal_rpc::{InternalRPC, InternalRPCHandle},
-    io_loop::IoLoop,
-    options::{ExchangeBindOptions, QueueBindOptions},
+    io_loop::{IoLoop, IoLoopDriver},
+    notifier::RecoveryEvents,
+    options::{BasicConsumeOptions, ConfirmSelectOptions, ExchangeBindOptions, QueueBindOptions},
+    pipeline::Pipeline,
+    protocol::{constants::REPLY_SUCCESS, AMQPErrorKind, AMQPSoftError},
+    reactor::FullReactor,
     recovery_config::RecoveryConfig,
     registry::Registry,
+    retrying_channel::{RetryPolicy, RetryingChannel},
+    slow_consumer::SlowConsumer,
     socket_state::{SocketState, SocketStateHandle},
     tcp::{AMQPUriTcpExt, HandshakeResult, OwnedTLSConfig},
     thread::ThreadHandle,
-    topology::{RestoredChannel, RestoredTopology, TopologyDefinition},
+    topology::{DeclareReport, RestoredChannel, RestoredTopology, TopologyDefinition},
     topology_internal::TopologyInternal,
-    types::ReplyCode,
+    types::{FieldTable, ReplyCode},
     uri::AMQPUri,
     Error, ErrorKind, Promise, Result,
 };
 use amq_protocol::frame::{AMQPFrame, ProtocolVersion};
 use async_trait::async_trait;
 use executor_trait::FullExecutor;
-use reactor_trait::IOHandle;
-use std::{fmt, io, sync::Arc};
-use tracing::{level_enabled, Level};
+use futures_io::{AsyncRead, AsyncWrite};
+use reactor_trait::{AsyncIOHandle, IOHandle};
+use std::{fmt, future::Future, io, pin::Pin, sync::Arc, task::Poll, time::Instant};
+use tracing::{debug, level_enabled, Level};
+use zeroize::Zeroize;
+
+type SiblingConnector = Arc<
+    dyn Fn(
+            AMQPUri,
+            ConnectionProperties,
+        ) -> Pin<Box<dyn Future<Output = Result<Connection>> + Send>>
+        + Send
+        + Sync,
+>;
+
+/* What it takes to open another connection to the same endpoint */
+pub(crate) struct Sibling {
+    uri: AMQPUri,
+    options: ConnectionProperties,
+    connector: SiblingConnector,
+}
+
+impl Drop for Sibling {
+    fn drop(&mut self) {
+        self.uri.authority.userinfo.password.zeroize();
+    }
+}
 
 /// A TCP connection to the AMQP server.
 ///
@@ -45,7 +82,9 @@ pub struct Connection {
     global_registry: Registry,
     channels: Channels,
     io_loop: ThreadHandle,
+    driver: IoLoopDriver,
     closer: Arc<ConnectionCloser>,
+    sibling: Option<Arc<Sibling>>,
 }
 
 impl Connection {
@@ -54,6 +93,7 @@ impl Connection {
         internal_rpc: InternalRPCHandle,
         frames: Frames,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
         recovery_config: RecoveryConfig,
     ) -> Self {
         let configuration = Configuration::default();
@@ -67,6 +107,7 @@ impl Connection {
             internal_rpc.clone(),
             frames,
             executor,
+            reactor,
             recovery_config,
         );
         let closer = Arc::new(ConnectionCloser::new(status.clone(), internal_rpc));
@@ -76,7 +117,9 @@ impl Connection {
             global_registry,
             channels,
             io_loop: ThreadHandle::default(),
+            driver: IoLoopDriver::default(),
             closer,
+            sibling: None,
         };
 
         connection.channels.create_zero();
@@ -128,11 +171,165 @@ impl Connection {
     /// [`Channel`]: ./struct.Channel.html
     /// [`InvalidConnectionState`]: ./enum.Error.html#variant.InvalidConnectionState
     pub async fn create_channel(&self) -> Result<Channel> {
+        self.create_channel_with(ChannelOptions::default()).await
+    }
+
+    /// Creates a new [`Channel`] on this connection, set up according to the given options.
+    ///
+    /// The channel gets opened, then confirm mode gets enabled and the `basic_qos` applied,
+    /// as requested. Confirm mode is always enabled if the connection was opened with
+    /// `ConnectionProperties::with_confirm_channels`.
+    ///
+    /// [`Channel`]: ./struct.Channel.html
+    pub async fn create_channel_with(&self, options: ChannelOptions) -> Result<Channel> {
         if !self.status.connected() {
             return Err(ErrorKind::InvalidConnectionState(self.status.state()).into());
         }
-        let channel = self.channels.create(self.closer.clone())?;
-        channel.clone().channel_open(channel).await
+        let ChannelOptions {
+            id,
+            confirm,
+            qos,
+            label,
+            error_handler,
+            consumer_tag_strategy,
+            dropped_confirm_policy,
+            declare_cache,
+            ack_coalescing,
+        } = options;
+        let channel = match id {
+            Some(id) => self.channels.create_with_id(id, self.closer.clone())?,
+            None => self.channels.create(self.closer.clone())?,
+        };
+        channel.status().set_label(label);
+        if let Some(handler) = error_handler {
+            channel.on_error(handler);
+        }
+        if consumer_tag_strategy.is_some() {
+            channel.set_consumer_tag_strategy(consumer_tag_strategy);
+        }
+        if let Some(policy) = dropped_confirm_policy {
+            channel.set_dropped_confirm_policy(policy);
+        }
+        if declare_cache {
+            channel.set_declare_cache(true);
+        }
+        if let Some(max_delay) = ack_coalescing {
+            channel.set_ack_coalescing(max_delay);
+        }
+        let channel = channel.clone().channel_open(channel).await?;
+        if confirm || self.configuration.confirm_channels() {
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+        }
+        if let Some((prefetch_count, options)) = qos {
+            channel.basic_qos(prefetch_count, options).await?;
+        }
+        Ok(channel)
+    }
+
+    /// Creates a new [`RetryingChannel`], retrying its idempotent operations according to
+    /// `policy`.
+    ///
+    /// [`RetryingChannel`]: ./retrying_channel/struct.RetryingChannel.html
+    pub async fn create_retrying_channel(
+        &self,
+        policy: RetryPolicy,
+    ) -> Result<RetryingChannel<'_>> {
+        Ok(RetryingChannel::new(
+            self,
+            self.create_channel().await?,
+            policy,
+        ))
+    }
+
+    /// Run `f` with a temporary [`Channel`], which gets closed once it's done, whatever its
+    /// outcome.
+    ///
+    /// This is meant for short lived operations such as passive declares, which close their
+    /// channel when failing: running them on their own channel keeps the long lived ones safe,
+    /// and the temporary one can't be leaked. If `f` succeeds but closing the channel fails,
+    /// that error is returned.
+    ///
+    /// ```rust,no_run
+    /// use lapin::{options::QueueDeclareOptions, types::FieldTable, Connection, ConnectionProperties};
+    ///
+    /// # async_global_executor::block_on(async {
+    /// let connection = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+    /// let exists = connection
+    ///     .with_scratch_channel(|channel| async move {
+    ///         let passive = QueueDeclareOptions {
+    ///             passive: true,
+    ///             ..QueueDeclareOptions::default()
+    ///         };
+    ///         Ok(channel
+    ///             .queue_declare("jobs", passive, FieldTable::default())
+    ///             .await
+    ///             .is_ok())
+    ///     })
+    ///     .await?;
+    /// # Ok::<(), lapin::Error>(())
+    /// # });
+    /// ```
+    ///
+    /// [`Channel`]: ./struct.Channel.html
+    pub async fn with_scratch_channel<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(Channel) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let channel = self.create_channel().await?;
+        let res = f(channel.clone()).await;
+        // A failed operation may have closed it already
+        let closed = if channel.status().connected() {
+            channel.close(REPLY_SUCCESS, "scratch channel").await
+        } else {
+            Ok(())
+        };
+        let res = res?;
+        closed?;
+        Ok(res)
+    }
+
+    /// Creates a new [`Channel`] with an exclusive consumer on the given queue.
+    ///
+    /// If another consumer is already subscribed to the queue (or if the queue is exclusive to
+    /// another connection), wait according to `backoff` and try again, until we win the lock.
+    /// This can be used as a simple leader election primitive.
+    ///
+    /// As the server closes the channel when refusing the consumer, each attempt is made on a
+    /// new channel. Closing the returned channel releases the lock.
+    ///
+    /// [`Channel`]: ./struct.Channel.html
+    pub async fn basic_consume_exclusive(
+        &self,
+        queue: &str,
+        consumer_tag: &str,
+        options: BasicConsumeOptions,
+        arguments: FieldTable,
+        backoff: Backoff,
+    ) -> Result<(Channel, Consumer)> {
+        let options = BasicConsumeOptions {
+            exclusive: true,
+            ..options
+        };
+        let mut attempt = 0;
+        loop {
+            let channel = self.create_channel().await?;
+            match channel
+                .basic_consume(queue, consumer_tag, options, arguments.clone())
+                .await
+            {
+                Ok(consumer) => return Ok((channel, consumer)),
+                Err(error) if is_exclusive_lock_error(&error) => {
+                    let delay = backoff.delay(attempt);
+                    debug!(%queue, %error, ?delay, "Exclusive consumer refused, will retry");
+                    channel.sleep(delay).await;
+                    attempt = attempt.saturating_add(1);
+                }
+                Err(error) => return Err(error),
+            }
+        }
     }
 
     /// Restore the specified topology
@@ -140,6 +337,24 @@ impl Connection {
         self.restore_internal(topology.into()).await
     }
 
+    /// Declare the exchanges, queues and bindings of a topology, using up to `parallelism`
+    /// channels at once.
+    ///
+    /// Exchanges come first, then their bindings, then queues and finally their bindings. Each
+    /// step is spread across the channels, which make one declaration at a time. A declaration
+    /// which fails doesn't stop the other ones: it gets listed in the returned report, and its
+    /// channel, closed by the broker, gets replaced. Exchanges without kind are only bound, and
+    /// the channel specific parts of the topology are ignored.
+    ///
+    /// This only fails if a channel cannot be opened.
+    pub async fn declare_all(
+        &self,
+        topology: TopologyDefinition,
+        parallelism: usize,
+    ) -> Result<DeclareReport> {
+        declare_all::declare_all(self, &topology, parallelism).await
+    }
+
     pub(crate) async fn restore_internal(
         &self,
         topology: TopologyInternal,
@@ -152,7 +367,13 @@ impl Connection {
                 .channels
                 .push(RestoredChannel::new(if let Some(c) = c.channel.clone() {
                     let channel = c.clone();
-                    c.channel_open(channel).await?
+                    let channel = c.channel_open(channel).await?;
+                    if self.configuration.confirm_channels() {
+                        channel
+                            .confirm_select(ConfirmSelectOptions::default())
+                            .await?;
+                    }
+                    channel
                 } else {
                     self.create_channel().await?
                 }));
@@ -165,62 +386,75 @@ impl Connection {
             self.create_channel().await?
         };
 
+        // Each step is pipelined, only waiting for the replies before starting the next one
+
         // First, redeclare all exchanges
-        for ex in &topology.exchanges {
-            channel
-                .exchange_declare(
+        topology
+            .exchanges
+            .iter()
+            .map(|ex| {
+                channel.exchange_declare(
                     ex.name.as_str(),
                     ex.kind.clone().unwrap_or_default(),
                     ex.options.unwrap_or_default(),
                     ex.arguments.clone().unwrap_or_default(),
                 )
-                .await?;
-        }
+            })
+            .collect::<Pipeline<'_, _>>()
+            .await?;
 
         // Second, redeclare all exchange bindings
-        for ex in &topology.exchanges {
-            for binding in &ex.bindings {
-                channel
-                    .exchange_bind(
+        topology
+            .exchanges
+            .iter()
+            .flat_map(|ex| {
+                ex.bindings.iter().map(|binding| {
+                    channel.exchange_bind(
                         ex.name.as_str(),
                         binding.source.as_str(),
                         binding.routing_key.as_str(),
                         ExchangeBindOptions::default(),
                         binding.arguments.clone(),
                     )
-                    .await?;
-            }
-        }
+                })
+            })
+            .collect::<Pipeline<'_, _>>()
+            .await?;
 
         // Third, redeclare all "global" (e.g. non exclusive) queues
-        for queue in &topology.queues {
-            if queue.is_declared() {
-                restored.queues.push(
-                    channel
-                        .queue_declare(
-                            queue.name.as_str(),
-                            queue.options.unwrap_or_default(),
-                            queue.arguments.clone().unwrap_or_default(),
-                        )
-                        .await?,
-                );
-            }
-        }
+        restored.queues.extend(
+            topology
+                .queues
+                .iter()
+                .filter(|queue| queue.is_declared())
+                .map(|queue| {
+                    channel.queue_declare(
+                        queue.name.as_str(),
+                        queue.options.unwrap_or_default(),
+                        queue.arguments.clone().unwrap_or_default(),
+                    )
+                })
+                .collect::<Pipeline<'_, _>>()
+                .await?,
+        );
 
         // Fourth, redeclare all global queues bindings
-        for queue in &topology.queues {
-            for binding in &queue.bindings {
-                channel
-                    .queue_bind(
+        topology
+            .queues
+            .iter()
+            .flat_map(|queue| {
+                queue.bindings.iter().map(|binding| {
+                    channel.queue_bind(
                         queue.name.as_str(),
                         binding.source.as_str(),
                         binding.routing_key.as_str(),
                         QueueBindOptions::default(),
                         binding.arguments.clone(),
                     )
-                    .await?;
-            }
-        }
+                })
+            })
+            .collect::<Pipeline<'_, _>>()
+            .await?;
 
         // Fifth, restore all channel-specific queues/bindings/consumers
         for (n, ch) in topology.channels.iter().enumerate() {
@@ -239,10 +473,98 @@ impl Connection {
         io_loop.wait("io loop")
     }
 
+    /// Drive the io loop of a connection created with
+    /// [`ConnectionProperties::with_manual_io_loop`], on the current task.
+    ///
+    /// This future has to be polled for as long as the connection is used, concurrently with
+    /// the other operations (including `close`). It resolves once the connection is closed.
+    /// It resolves right away when the io loop runs in its own thread, and isn't needed when it
+    /// runs as a task spawned on the executor.
+    ///
+    /// [`ConnectionProperties::with_manual_io_loop`]: ./struct.ConnectionProperties.html#method.with_manual_io_loop
+    pub async fn drive(&self) -> Result<()> {
+        let driver = self.driver.clone();
+        std::future::poll_fn(move |cx| driver.poll_drive(cx)).await
+    }
+
     pub fn on_error<E: FnMut(Error) + Send + 'static>(&self, handler: E) {
         self.channels.set_error_handler(handler);
     }
 
+    /// Register a handler called with the channels reported by the leak detection
+    ///
+    /// See [`ConnectionProperties::with_channel_leak_detection`].
+    ///
+    /// [`ConnectionProperties::with_channel_leak_detection`]: ./struct.ConnectionProperties.html#method.with_channel_leak_detection
+    pub fn on_channel_leak<L: FnMut(OpenChannel) + Send + 'static>(&self, handler: L) {
+        self.channels.set_leak_handler(handler);
+    }
+
+    /// Register a handler called with the consumers reported by the slow consumer detection
+    ///
+    /// See [`ConnectionProperties::with_slow_consumer_detection`].
+    ///
+    /// [`ConnectionProperties::with_slow_consumer_detection`]: ./struct.ConnectionProperties.html#method.with_slow_consumer_detection
+    pub fn on_slow_consumer<S: FnMut(SlowConsumer) + Send + 'static>(&self, handler: S) {
+        self.channels.set_slow_consumer_handler(handler);
+    }
+
+    /// The latencies of all the messages received on this connection so far
+    ///
+    /// Each [`Delivery`] also carries its own latency.
+    ///
+    /// [`Delivery`]: ./message/struct.Delivery.html
+    pub fn delivery_latency(&self) -> LatencyMetrics {
+        self.configuration.latency().metrics()
+    }
+
+    /// Register a handler for the method frames with the given class and method ids, to
+    /// experiment with protocol extensions provided by broker plugins.
+    ///
+    /// These frames are handed to the handler as is instead of being parsed, which would fail
+    /// for methods lapin doesn't know about. The handler runs on the io loop and must not block.
+    /// Methods lapin knows about can be intercepted too, in which case lapin never sees them:
+    /// this can easily break the connection.
+    #[cfg(feature = "unstable")]
+    pub fn on_raw_method<H: FnMut(crate::RawMethod) + Send + 'static>(
+        &self,
+        class_id: crate::types::Identifier,
+        method_id: crate::types::Identifier,
+        handler: H,
+    ) {
+        self.channels
+            .raw_method_handlers()
+            .set_handler(class_id, method_id, handler);
+    }
+
+    /// Get a stream telling whenever any channel of this connection recovers or gives up on
+    /// recovering, and once the connection becomes unusable.
+    ///
+    /// This saves waiting on the [`Notifier`] of each error one by one.
+    ///
+    /// ```rust,no_run
+    /// use futures_lite::stream::StreamExt;
+    /// use lapin::{Connection, ConnectionProperties, RecoveryEvent};
+    ///
+    /// # async_global_executor::block_on(async {
+    /// let connection = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+    /// let mut events = connection.recovery_events();
+    /// while let Some(event) = events.next().await {
+    ///     match event {
+    ///         RecoveryEvent::ChannelRecovered(id) => println!("channel {id} is back"),
+    ///         RecoveryEvent::ChannelFailed(id, error) => eprintln!("channel {id} is gone: {error}"),
+    ///         RecoveryEvent::ConnectionUnusable(error) => eprintln!("connection is gone: {error}"),
+    ///     }
+    /// }
+    /// # Ok::<(), lapin::Error>(())
+    /// # });
+    /// ```
+    ///
+    /// [`Notifier`]: ./struct.Notifier.html
+    pub fn recovery_events(&self) -> RecoveryEvents {
+        self.status.recovery_events()
+    }
+
     pub fn configuration(&self) -> &Configuration {
         &self.configuration
     }
@@ -251,6 +573,13 @@ impl Connection {
         &self.status
     }
 
+    /// The label given to this connection through [`ConnectionProperties::with_label`], if any.
+    ///
+    /// [`ConnectionProperties::with_label`]: ./struct.ConnectionProperties.html#method.with_label
+    pub fn label(&self) -> Option<String> {
+        self.status.label()
+    }
+
     /// Request a connection close.
     ///
     /// This method is only successful if the connection is in the connected state,
@@ -258,18 +587,7 @@ impl Connection {
     ///
     /// [`InvalidConnectionState`]: ./enum.Error.html#variant.InvalidConnectionState
     pub async fn close(&self, reply_code: ReplyCode, reply_text: &str) -> Result<()> {
-        if !self.status.connected() {
-            return Err(ErrorKind::InvalidConnectionState(self.status.state()).into());
-        }
-
-        self.channels.set_connection_closing();
-        if let Some(channel0) = self.channels.get(0) {
-            channel0
-                .connection_close(reply_code, reply_text, 0, 0)
-                .await
-        } else {
-            Ok(())
-        }
+        close(&self.status, &self.channels, reply_code, reply_text).await
     }
 
     /// Block all consumers and publishers on this connection
@@ -290,6 +608,65 @@ impl Connection {
         }
     }
 
+    /// Open another connection to the same endpoint, with the same credentials, TLS
+    /// configuration and properties, but to another vhost.
+    ///
+    /// The properties being the same, so are the recovery configuration and the credentials
+    /// provider. This is only available for the connections opened from a uri, as the stream of
+    /// the other ones can't be opened again.
+    pub async fn sibling(&self, vhost: &str) -> Result<Connection> {
+        let Some(sibling) = self.sibling.as_ref() else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "this connection wasn't opened from a uri",
+            )
+            .into());
+        };
+        let mut uri = sibling.uri.clone();
+        uri.vhost = vhost.into();
+        (sibling.connector)(uri, sibling.options.clone()).await
+    }
+
+    pub(crate) fn set_sibling(
+        &mut self,
+        uri: AMQPUri,
+        options: ConnectionProperties,
+        connector: SiblingConnector,
+    ) {
+        self.sibling = Some(Arc::new(Sibling {
+            uri,
+            options,
+            connector,
+        }));
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn connect_with_tls(
+        uri: AMQPUri,
+        options: ConnectionProperties,
+        config: Arc<OwnedTLSConfig>,
+    ) -> Pin<Box<dyn Future<Output = Result<Connection>> + Send>> {
+        Box::pin(async move {
+            let tls_config = config.clone();
+            let mut connection = Connection::connector(
+                uri.clone(),
+                Box::new(move |uri| {
+                    AMQPUriTcpExt::connect_with_config(uri, tls_config.as_ref().as_ref())
+                }),
+                options.clone(),
+            )
+            .await?;
+            connection.set_sibling(
+                uri,
+                options,
+                Arc::new(move |uri, options| {
+                    Connection::connect_with_tls(uri, options, config.clone())
+                }),
+            );
+            Ok(connection)
+        })
+    }
+
     /// Update the secret used by some authentication module such as OAuth2
     pub async fn update_secret(&self, new_secret: &str, reason: &str) -> Result<()> {
         if let Some(channel0) = self.channels.get(0) {
@@ -336,17 +713,69 @@ impl Connection {
             })
         });
 
+        let nodelay = options.nodelay;
+        let stream = {
+            let reactor = reactor.clone();
+            async move {
+                connect_promise.await.and_then(|stream| {
+                    stream.set_nodelay(nodelay)?;
+                    stream.set_nonblocking(true)?;
+                    reactor
+                        .register(IOHandle::new(stream))
+                        .map(Into::into)
+                        .map_err(Into::into)
+                })
+            }
+        };
+        Self::establish(uri, stream, options, executor, reactor).await
+    }
+
+    /// Connect to an AMQP Server over an already established stream.
+    ///
+    /// The stream is used as is, without going through the reactor of the
+    /// [`ConnectionProperties`]: this allows wrapping the socket (e.g. to count or record what
+    /// goes through it) or connecting to an in-memory broker such as [`MockBroker`].
+    ///
+    /// [`MockBroker`]: ./testing/struct.MockBroker.html
+    pub async fn connector_with_stream<S: AsyncRead + AsyncWrite + Send + 'static>(
+        uri: AMQPUri,
+        stream: S,
+        mut options: ConnectionProperties,
+    ) -> Result<Connection> {
+        let executor = options.take_executor()?;
+        let reactor = options.take_reactor()?;
+        let stream: Pin<Box<dyn AsyncIOHandle + Send>> = Box::pin(stream);
+        Self::establish(uri, async move { Ok(stream) }, options, executor, reactor).await
+    }
+
+    async fn establish(
+        mut uri: AMQPUri,
+        stream: impl Future<Output = Result<Pin<Box<dyn AsyncIOHandle + Send>>>>,
+        options: ConnectionProperties,
+        executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
+    ) -> Result<Connection> {
+        if let Some(provider) = options.credentials_provider.as_ref() {
+            uri.authority.userinfo = provider.credentials().await?;
+        }
         let socket_state = SocketState::default();
         let waker = socket_state.handle();
         let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
-        let frames = Frames::default();
+        let frames = Frames::new(
+            options.publish_buffer_size,
+            options.buffer_pool_size,
+            options.prioritize_control_frames,
+        );
         let conn = Connection::new(
             waker,
             internal_rpc.handle(),
             frames.clone(),
             executor.clone(),
+            reactor.clone(),
             options.recovery_config.clone().unwrap_or_default(),
         );
+        conn.channels
+            .set_id_allocation(options.channel_id_allocation);
         let status = conn.status.clone();
         let configuration = conn.configuration.clone();
         status.set_vhost(&uri.vhost);
@@ -377,42 +806,142 @@ impl Connection {
             promise_in.set_marker("ProtocolHeader.Ok".into());
         }
         let io_loop_handle = conn.io_loop.clone();
+        let driver = options.manual_io_loop.then(|| conn.driver.clone());
+        let spawned_driver =
+            (options.spawn_io_loop && !options.manual_io_loop).then(|| conn.driver.clone());
+        let shutdown_signal = options.take_shutdown_signal();
+        let channel_leak_threshold = options.channel_leak_threshold;
+        let slow_consumer_threshold = options.slow_consumer_threshold;
+        conn.configuration
+            .set_consumer_tag_strategy(options.consumer_tag_strategy.clone());
+        conn.configuration
+            .set_confirm_channels(options.confirm_channels);
+        conn.configuration
+            .set_broker_profile(options.broker_profile);
+        conn.configuration
+            .latency()
+            .set_header(options.latency_header.clone());
+        let write_coalescing = options
+            .write_coalescing
+            .filter(|_| !options.manual_io_loop && !options.spawn_io_loop);
+        let io_buffer_frames = options.io_buffer_frames;
+        let dispatch_workers = options.dispatch_workers;
+        status.set_label(options.label.clone());
         status.set_state(ConnectionState::Connecting);
         status.set_connection_step(ConnectionStep::ProtocolHeader(
             resolver,
             conn,
             uri.authority.userinfo.into(),
             uri.query.auth_mechanism.unwrap_or_default(),
-            options,
+            Box::new(options),
         ));
-        let stream = connect_promise
-            .await
-            .and_then(|stream| reactor.register(IOHandle::new(stream)).map_err(Into::into))
-            .inspect_err(|_| {
-                // We don't actually need the resolver as we already pass it around to the failing
-                // code which will propagate the error. We only want to flush the status internal
-                // state.
-                let _ = status.connection_resolver();
-            })?
-            .into();
+        let stream = stream.await.inspect_err(|_| {
+            // We don't actually need the resolver as we already pass it around to the failing
+            // code which will propagate the error. We only want to flush the status internal
+            // state.
+            let _ = status.connection_resolver();
+        })?;
         let heartbeat = Heartbeat::new(status.clone(), channels.clone(), executor.clone(), reactor);
         let internal_rpc_handle = internal_rpc.handle();
         executor.spawn(Box::pin(internal_rpc.run(channels.clone())));
-        IoLoop::new(
-            status,
+        let io_loop = IoLoop::new(
+            status.clone(),
             configuration,
-            channels,
+            channels.clone(),
             internal_rpc_handle,
             frames,
             socket_state,
             io_loop_handle,
             stream,
             heartbeat,
+            write_coalescing,
+            io_buffer_frames,
+            dispatch_workers,
         )
-        .await
-        .and_then(IoLoop::start)?;
-        promise_out.await?;
-        promise_in.await
+        .await?;
+        let connection = if let Some(driver) = driver {
+            // Drive the io loop ourselves until the connection is established
+            driver.set(io_loop);
+            let mut handshake = Box::pin(async move {
+                promise_out.await?;
+                promise_in.await
+            });
+            let status = status.clone();
+            std::future::poll_fn(move |cx| {
+                if let Poll::Ready(res) = handshake.as_mut().poll(cx) {
+                    return Poll::Ready(res);
+                }
+                match driver.poll_drive(cx) {
+                    Poll::Ready(Ok(())) => match handshake.as_mut().poll(cx) {
+                        Poll::Ready(res) => Poll::Ready(res),
+                        // The io loop stopped before the connection got established
+                        Poll::Pending => Poll::Ready(Err(ErrorKind::InvalidConnectionState(
+                            status.state(),
+                        )
+                        .into())),
+                    },
+                    Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+                    Poll::Pending => Poll::Pending,
+                }
+            })
+            .await?
+        } else if let Some(driver) = spawned_driver {
+            driver.set(io_loop);
+            executor.spawn(Box::pin(async move {
+                // Errors are reported through the status of the connection
+                let _ = std::future::poll_fn(|cx| driver.poll_drive(cx)).await;
+            }));
+            promise_out.await?;
+            promise_in.await?
+        } else {
+            io_loop.start()?;
+            promise_out.await?;
+            promise_in.await?
+        };
+        if let Some(threshold) = channel_leak_threshold {
+            channels.start_leak_detection(threshold);
+        }
+        if let Some(threshold) = slow_consumer_threshold {
+            channels.start_slow_consumer_detection(threshold);
+        }
+        if let Some(mut shutdown_signal) = shutdown_signal {
+            executor.spawn(Box::pin(async move {
+                // Stop waiting for the signal once the connection is gone
+                let shutdown = std::future::poll_fn(|cx| {
+                    if shutdown_signal.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(true);
+                    }
+                    status.poll_finished(cx).map(|()| false)
+                })
+                .await;
+                if shutdown {
+                    debug!("Shutdown signal received, closing connection");
+                    if let Err(err) = close(&status, &channels, REPLY_SUCCESS, "shutdown").await {
+                        debug!(?err, "Failed to close connection on shutdown");
+                    }
+                }
+            }));
+        }
+        Ok(connection)
+    }
+
+    /// Check whether the connection is usable, for health checks and readiness probes.
+    pub async fn health_check(&self, check: HealthCheck) -> HealthStatus {
+        let mut status = HealthStatus {
+            state: self.status.state(),
+            blocked: self.status.blocked(),
+            latency: None,
+            error: None,
+        };
+        if check == HealthCheck::ScratchChannel && status.state == ConnectionState::Connected {
+            let start = Instant::now();
+            match self.with_scratch_channel(|_| async { Ok(()) }).await {
+                Ok(()) => status.latency = Some(start.elapsed()),
+                Err(err) => status.error = Some(err),
+            }
+            status.state = self.status.state();
+        }
+        status
     }
 
     /// Get the current topology
@@ -431,6 +960,37 @@ impl Connection {
     }
 }
 
+fn is_exclusive_lock_error(error: &Error) -> bool {
+    if let ErrorKind::ProtocolError(e) = error.kind() {
+        return matches!(
+            e.kind(),
+            AMQPErrorKind::Soft(AMQPSoftError::ACCESSREFUSED | AMQPSoftError::RESOURCELOCKED)
+        );
+    }
+    false
+}
+
+async fn close(
+    status: &ConnectionStatus,
+    channels: &Channels,
+    reply_code: ReplyCode,
+    reply_text: &str,
+) -> Result<()> {
+    if !status.connected() {
+        return Err(ErrorKind::InvalidConnectionState(status.state()).into());
+    }
+
+    channels.flush_acks().await;
+    channels.set_connection_closing();
+    if let Some(channel0) = channels.get(0) {
+        channel0
+            .connection_close(reply_code, reply_text, 0, 0)
+            .await
+    } else {
+        Ok(())
+    }
+}
+
 impl fmt::Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Connection")
@@ -459,12 +1019,7 @@ impl Connect for AMQPUri {
         options: ConnectionProperties,
         config: OwnedTLSConfig,
     ) -> Result<Connection> {
-        Connection::connector(
-            self,
-            Box::new(move |uri| AMQPUriTcpExt::connect_with_config(uri, config.as_ref())),
-            options,
-        )
-        .await
+        Connection::connect_with_tls(self, options, Arc::new(config)).await
     }
 }
 
@@ -477,7 +1032,7 @@ impl Connect for &str {
     ) -> Result<Connection> {
         match self.parse::<AMQPUri>() {
             Ok(uri) => Connect::connect(uri, options, config).await,
-            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err).into()),
+            Err(err) => Err(io::Error::other(err).into()),
         }
     }
 }
@@ -507,6 +1062,7 @@ mod tests {
             internal_rpc.handle(),
             Frames::default(),
             executor.clone(),
+            Arc::new(async_reactor_trait::AsyncIo),
             RecoveryConfig::default(),
         );
         conn.status.set_state(ConnectionState::Connected);
@@ -515,9 +1071,63 @@ mod tests {
             conn.channels.create(conn.closer.clone()).unwrap();
         }
 
+        let error = conn.channels.create(conn.closer.clone()).unwrap_err();
+        assert_eq!(error, ErrorKind::ChannelsLimitReached(Vec::new()).into());
+        match error.kind() {
+            ErrorKind::ChannelsLimitReached(open_channels) => {
+                assert_eq!(open_channels.len(), 65_534);
+                assert_eq!(open_channels[0].id, 1);
+            }
+            kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn channel_leak_detection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let executor = Arc::new(async_global_executor_trait::AsyncGlobalExecutor);
+        let socket_state = SocketState::default();
+        let waker = socket_state.handle();
+        let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let conn = Connection::new(
+            waker,
+            internal_rpc.handle(),
+            Frames::default(),
+            executor,
+            Arc::new(async_reactor_trait::AsyncIo),
+            RecoveryConfig::default(),
+        );
+        conn.status.set_state(ConnectionState::Connected);
+        conn.configuration.set_channel_max(2047);
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handler_reported = reported.clone();
+        conn.on_channel_leak(move |channel| handler_reported.lock().unwrap().push(channel.id));
+        let idle = conn.channels.create(conn.closer.clone()).unwrap();
+        idle.set_state(ChannelState::Connected);
+        let busy = conn.channels.create(conn.closer.clone()).unwrap();
+        busy.set_state(ChannelState::Connected);
+        let threshold = std::time::Duration::from_millis(20);
+
+        // Nothing is reported before the threshold is reached
+        conn.channels.report_idle_channels(threshold);
+        assert!(reported.lock().unwrap().is_empty());
+
+        std::thread::sleep(threshold);
+        busy.status().touch();
+        conn.channels.report_idle_channels(threshold);
+        assert_eq!(*reported.lock().unwrap(), vec![idle.id()]);
+
+        // Each idle channel is only reported once until it sees some activity
+        std::thread::sleep(threshold);
+        conn.channels.report_idle_channels(threshold);
+        assert_eq!(*reported.lock().unwrap(), vec![idle.id(), busy.id()]);
+        idle.status().touch();
+        std::thread::sleep(threshold);
+        conn.channels.report_idle_channels(threshold);
         assert_eq!(
-            conn.channels.create(conn.closer.clone()),
-            Err(ErrorKind::ChannelsLimitReached.into())
+            *reported.lock().unwrap(),
+            vec![idle.id(), busy.id(), idle.id()]
         );
     }
 
@@ -537,6 +1147,7 @@ mod tests {
             internal_rpc.handle(),
             Frames::default(),
             executor.clone(),
+            Arc::new(async_reactor_trait::AsyncIo),
             RecoveryConfig::default(),
         );
         conn.status.set_state(ConnectionState::Connected);
@@ -548,6 +1159,7 @@ mod tests {
         let consumer = Consumer::new(
             consumer_tag.clone(),
             executor,
+            Arc::new(async_reactor_trait::AsyncIo),
             None,
             queue_name.clone(),
             BasicConsumeOptions::default(),
@@ -617,6 +1229,7 @@ mod tests {
             internal_rpc.handle(),
             Frames::default(),
             executor.clone(),
+            Arc::new(async_reactor_trait::AsyncIo),
             RecoveryConfig::default(),
         );
         conn.status.set_state(ConnectionState::Connected);
@@ -628,6 +1241,7 @@ mod tests {
         let consumer = Consumer::new(
             consumer_tag.clone(),
             executor,
+            Arc::new(async_reactor_trait::AsyncIo),
             None,
             queue_name.clone(),
             BasicConsumeOptions::default(),
@@ -672,4 +1286,594 @@ mod tests {
             assert_eq!(channel_state, expected_state);
         }
     }
+
+    #[test]
+    fn channel_flow() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use amq_protocol::protocol::channel;
+        use std::sync::Mutex;
+
+        let executor = Arc::new(async_global_executor_trait::AsyncGlobalExecutor);
+        let socket_state = SocketState::default();
+        let waker = socket_state.handle();
+        let internal_rpc = InternalRPC::new(executor.clone(), waker.clone());
+        let conn = Connection::new(
+            waker,
+            internal_rpc.handle(),
+            Frames::default(),
+            executor,
+            Arc::new(async_reactor_trait::AsyncIo),
+            RecoveryConfig::default(),
+        );
+        conn.status.set_state(ConnectionState::Connected);
+        conn.configuration.set_channel_max(2047);
+        let channel = conn.channels.create(conn.closer.clone()).unwrap();
+        channel.set_state(ChannelState::Connected);
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let n = notified.clone();
+        channel.on_flow(move |active| n.lock().unwrap().push(active));
+        assert!(channel.status().flow());
+        for active in [false, true] {
+            let method = AMQPClass::Channel(channel::AMQPMethod::Flow(channel::Flow { active }));
+            conn.channels
+                .handle_frame(AMQPFrame::Method(channel.id(), method))
+                .unwrap();
+            assert_eq!(channel.status().flow(), active);
+        }
+        assert_eq!(*notified.lock().unwrap(), vec![false, true]);
+    }
+
+    #[test]
+    fn connect_with_stream() {
+        use amq_protocol::{
+            frame::{gen_frame, parse_frame, WriteContext},
+            protocol::connection,
+        };
+        use reactor_trait::Reactor;
+        use std::{
+            io::{Read, Write},
+            os::unix::net::UnixStream,
+        };
+
+        let _ = tracing_subscriber::fmt::try_init();
+
+        fn send(socket: &mut UnixStream, method: connection::AMQPMethod) {
+            let frame = AMQPFrame::Method(0, AMQPClass::Connection(method));
+            let buf = gen_frame(&frame)(WriteContext::from(Vec::new()))
+                .unwrap()
+                .write;
+            socket.write_all(&buf).unwrap();
+        }
+
+        fn receive(socket: &mut UnixStream) -> AMQPFrame {
+            let mut buf = vec![0; 7];
+            socket.read_exact(&mut buf).unwrap();
+            let size = u32::from_be_bytes([buf[3], buf[4], buf[5], buf[6]]) as usize;
+            buf.resize(7 + size + 1, 0);
+            socket.read_exact(&mut buf[7..]).unwrap();
+            parse_frame(buf.as_slice()).unwrap().1
+        }
+
+        // A minimal AMQP server only handling the connection handshake
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut header = [0; 8];
+            server.read_exact(&mut header).unwrap();
+            assert_eq!(&header, b"AMQP\x00\x00\x09\x01");
+            send(
+                &mut server,
+                connection::AMQPMethod::Start(connection::Start {
+                    version_major: 0,
+                    version_minor: 9,
+                    server_properties: FieldTable::default(),
+                    mechanisms: "PLAIN".into(),
+                    locales: "en_US".into(),
+                }),
+            );
+            assert!(matches!(
+                receive(&mut server),
+                AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::StartOk(_)))
+            ));
+            send(
+                &mut server,
+                connection::AMQPMethod::Tune(connection::Tune {
+                    channel_max: 16,
+                    frame_max: 8192,
+                    heartbeat: 0,
+                }),
+            );
+            assert!(matches!(
+                receive(&mut server),
+                AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::TuneOk(_)))
+            ));
+            assert!(matches!(
+                receive(&mut server),
+                AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::Open(_)))
+            ));
+            send(
+                &mut server,
+                connection::AMQPMethod::OpenOk(connection::OpenOk {}),
+            );
+            server
+        });
+
+        let stream = Box::into_pin(
+            async_reactor_trait::AsyncIo
+                .register(IOHandle::new(client))
+                .unwrap(),
+        );
+        let connection = async_global_executor::block_on(Connection::connector_with_stream(
+            "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+            stream,
+            ConnectionProperties::default(),
+        ))
+        .unwrap();
+        assert!(connection.status().connected());
+        assert_eq!(connection.configuration().frame_max(), 8192);
+        let server = server.join().unwrap();
+        drop(connection);
+        drop(server);
+    }
+
+    #[test]
+    fn sibling_connection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = crate::testing::MockBroker::default();
+            let connection = broker
+                .connect(ConnectionProperties::default().with_label("tenants"))
+                .await?;
+            let sibling = connection.sibling("tenant-a").await?;
+            assert_eq!(sibling.status().vhost(), "tenant-a");
+            assert_eq!(sibling.label().as_deref(), Some("tenants"));
+            assert_eq!(connection.status().vhost(), "/");
+            let nested = sibling.sibling("tenant-b").await?;
+            assert_eq!(nested.status().vhost(), "tenant-b");
+            nested.create_channel().await?;
+            nested.close(0, "").await?;
+            sibling.close(0, "").await?;
+
+            let standalone = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                broker.stream(),
+                ConnectionProperties::default(),
+            )
+            .await?;
+            assert!(standalone.sibling("tenant-a").await.is_err());
+            standalone.close(0, "").await?;
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn connection_label() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = crate::testing::MockBroker::default();
+            let connection = broker
+                .connect(ConnectionProperties::default().with_label("billing"))
+                .await?;
+            assert_eq!(connection.label().as_deref(), Some("billing"));
+            assert!(format!("{:?}", connection).contains("billing"));
+            let unlabelled = broker.connect(ConnectionProperties::default()).await?;
+            assert_eq!(unlabelled.label(), None);
+            unlabelled.close(0, "").await?;
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn confirm_channels() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = crate::testing::MockBroker::default();
+            let connection = broker
+                .connect(ConnectionProperties::default().with_confirm_channels(true))
+                .await?;
+            assert!(connection.create_channel().await?.status().confirm());
+            let channel = connection
+                .create_channel_with(ChannelOptions::default().with_confirm(false))
+                .await?;
+            assert!(channel.status().confirm());
+            let confirm = channel
+                .basic_publish(
+                    "",
+                    "missing",
+                    crate::options::BasicPublishOptions::default(),
+                    b"",
+                    crate::BasicProperties::default(),
+                )
+                .await?;
+            assert!(confirm.await?.is_ack());
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn create_channel_with_options() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = crate::testing::MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let (sender, receiver) = flume::unbounded();
+            let channel = connection
+                .create_channel_with(
+                    ChannelOptions::default()
+                        .with_id(42)
+                        .with_label("orders")
+                        .with_confirm(true)
+                        .with_qos(5, crate::options::BasicQosOptions::default())
+                        .on_error(move |error| {
+                            let _ = sender.send(error);
+                        }),
+                )
+                .await?;
+            assert_eq!(channel.id(), 42);
+            assert_eq!(channel.label().as_deref(), Some("orders"));
+            assert!(channel.status().confirm());
+            assert_eq!(channel.status().qos(), vec![(5, false)]);
+            assert!(format!("{:?}", channel).contains("orders"));
+            assert_eq!(
+                connection
+                    .create_channel_with(ChannelOptions::default().with_id(42))
+                    .await
+                    .unwrap_err(),
+                ErrorKind::InvalidChannel(42).into()
+            );
+
+            let passive = crate::options::QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            };
+            assert!(channel
+                .queue_declare("missing", passive, FieldTable::default())
+                .await
+                .is_err());
+            assert!(receiver.recv_async().await.unwrap().is_amqp_soft_error());
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn scratch_channel() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = crate::testing::MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let long_lived = connection.create_channel().await?;
+            let passive = crate::options::QueueDeclareOptions {
+                passive: true,
+                ..Default::default()
+            };
+
+            let mut scratch = None;
+            let error = connection
+                .with_scratch_channel(|channel| {
+                    scratch = Some(channel.clone());
+                    async move {
+                        channel
+                            .queue_declare("missing", passive, FieldTable::default())
+                            .await
+                    }
+                })
+                .await
+                .unwrap_err();
+            assert!(error.is_amqp_soft_error());
+            assert!(!scratch.take().unwrap().status().connected());
+            assert!(long_lived.status().connected());
+
+            long_lived
+                .queue_declare("jobs", Default::default(), FieldTable::default())
+                .await?;
+            let queue = connection
+                .with_scratch_channel(|channel| {
+                    scratch = Some(channel.clone());
+                    async move {
+                        channel
+                            .queue_declare("jobs", passive, FieldTable::default())
+                            .await
+                    }
+                })
+                .await?;
+            assert_eq!(queue.name().as_str(), "jobs");
+            assert_eq!(scratch.unwrap().status().state(), ChannelState::Closed);
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn exclusive_consumer() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = crate::testing::MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare("leader", Default::default(), FieldTable::default())
+                .await?;
+            let backoff = Backoff::constant(std::time::Duration::from_millis(10));
+            let (leader, _consumer) = connection
+                .basic_consume_exclusive(
+                    "leader",
+                    "first",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                    backoff,
+                )
+                .await?;
+
+            // Any other consumer is refused while the leader holds the queue
+            let error = channel
+                .basic_consume(
+                    "leader",
+                    "second",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .unwrap_err();
+            assert!(is_exclusive_lock_error(&error));
+
+            // The follower keeps retrying until the leader goes away
+            let follower = connection.basic_consume_exclusive(
+                "leader",
+                "third",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+                backoff,
+            );
+            let mut follower = Box::pin(follower);
+            let waited = futures_lite::future::or(async { Some((&mut follower).await) }, async {
+                channel.sleep(std::time::Duration::from_millis(50)).await;
+                None
+            })
+            .await;
+            assert!(waited.is_none());
+            leader.close(200, "OK").await?;
+            let (_, consumer) = follower.await?;
+            assert_eq!(consumer.tag().as_str(), "third");
+            assert_eq!(broker.consumer_count("leader"), Some(1));
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn write_coalescing() {
+        use crate::testing::{MockBroker, MockStream};
+        use std::{
+            sync::atomic::{AtomicUsize, Ordering},
+            task::{Context, Poll},
+            time::{Duration, Instant},
+        };
+
+        /* Count the writes to the socket */
+        struct CountingStream(MockStream, Arc<AtomicUsize>);
+
+        impl AsyncRead for CountingStream {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.0).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for CountingStream {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                let res = Pin::new(&mut self.0).poll_write(cx, buf);
+                if res.is_ready() {
+                    self.1.fetch_add(1, Ordering::SeqCst);
+                }
+                res
+            }
+
+            fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.0).poll_flush(cx)
+            }
+
+            fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.0).poll_close(cx)
+            }
+        }
+
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let writes = Arc::new(AtomicUsize::default());
+            let window = Duration::from_millis(100);
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                CountingStream(broker.stream(), writes.clone()),
+                ConnectionProperties::default().with_write_coalescing(window),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare("events", Default::default(), FieldTable::default())
+                .await?;
+
+            // The small publishes trickling in wait for the window to end to be written together
+            let written = writes.load(Ordering::SeqCst);
+            let start = Instant::now();
+            let publishes = (0..10)
+                .map(|i| {
+                    let channel = channel.clone();
+                    async_global_executor::spawn(async move {
+                        channel.sleep(Duration::from_millis(5 * i)).await;
+                        channel
+                            .basic_publish(
+                                "",
+                                "events",
+                                Default::default(),
+                                b"event",
+                                BasicProperties::default(),
+                            )
+                            .await
+                    })
+                })
+                .collect::<Vec<_>>();
+            for publish in publishes {
+                publish.await?;
+            }
+            assert!(start.elapsed() >= window);
+            assert!(writes.load(Ordering::SeqCst) - written <= 2);
+            assert_eq!(broker.message_count("events"), Some(10));
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn partial_reads() {
+        use crate::{
+            options::BasicGetOptions,
+            testing::{MockBroker, MockStream},
+        };
+        use std::task::{Context, Poll};
+
+        /* Receive what the broker sends a few bytes at a time, splitting all the frames */
+        struct TrickleStream(MockStream);
+
+        impl AsyncRead for TrickleStream {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                let len = buf.len().min(5);
+                Pin::new(&mut self.0).poll_read(cx, &mut buf[..len])
+            }
+        }
+
+        impl AsyncWrite for TrickleStream {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                Pin::new(&mut self.0).poll_write(cx, buf)
+            }
+
+            fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.0).poll_flush(cx)
+            }
+
+            fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Pin::new(&mut self.0).poll_close(cx)
+            }
+        }
+
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                TrickleStream(broker.stream()),
+                ConnectionProperties::default(),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare("large", Default::default(), FieldTable::default())
+                .await?;
+
+            // Spanning several body frames, each one received in many parts
+            let frame_max = connection.configuration().frame_max() as usize;
+            let payload = (0..frame_max * 2 + 1000)
+                .map(|i| i as u8)
+                .collect::<Vec<_>>();
+            channel
+                .basic_publish(
+                    "",
+                    "large",
+                    Default::default(),
+                    &payload,
+                    BasicProperties::default().with_content_type("application/octet-stream".into()),
+                )
+                .await?;
+            let message = channel
+                .basic_get("large", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(message.data.len(), payload.len());
+            assert!(message.data[..] == payload[..]);
+            assert_eq!(
+                message.properties.content_type(),
+                &Some("application/octet-stream".into())
+            );
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn tuning_presets() {
+        use crate::{options::BasicGetOptions, testing::MockBroker};
+
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let latency = ConnectionProperties::latency_optimized();
+        assert!(latency.nodelay);
+        assert!(latency.write_coalescing.is_none());
+        let throughput = ConnectionProperties::throughput_optimized();
+        assert!(!throughput.nodelay);
+        assert!(throughput.write_coalescing.is_some());
+        assert!(throughput.io_buffer_frames > latency.io_buffer_frames);
+
+        // Whatever the tuning, even with buffers too small for a whole message, messages go
+        // through intact
+        let smallest = ConnectionProperties::default().with_io_buffer_frames(0);
+        for properties in [latency, throughput, smallest] {
+            async_global_executor::block_on(async {
+                let broker = MockBroker::default();
+                let connection = broker.connect(properties).await?;
+                let channel = connection.create_channel().await?;
+                channel
+                    .queue_declare("tuned", Default::default(), FieldTable::default())
+                    .await?;
+                let frame_max = connection.configuration().frame_max() as usize;
+                let large = vec![42; frame_max * 3];
+                for payload in [&b"small"[..], &large] {
+                    channel
+                        .basic_publish(
+                            "",
+                            "tuned",
+                            Default::default(),
+                            payload,
+                            BasicProperties::default(),
+                        )
+                        .await?;
+                }
+                for payload in [&b"small"[..], &large] {
+                    let message = channel
+                        .basic_get("tuned", BasicGetOptions::default())
+                        .await?
+                        .unwrap();
+                    assert!(message.data[..] == payload[..]);
+                }
+                connection.close(200, "OK").await
+            })
+            .unwrap();
+        }
+    }
 }
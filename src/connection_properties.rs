@@ -1,19 +1,81 @@
 use crate::{
+    broker_profile::BrokerProfile,
+    buffer_pool::DEFAULT_BUFFER_POOL_SIZE,
+    capabilities::Capabilities,
+    channel_id_allocation::ChannelIdAllocation,
+    consumer_tag::ConsumerTagStrategy,
+    credentials_provider::CredentialsProvider,
     reactor::FullReactor,
     recovery_config::RecoveryConfig,
-    types::{AMQPValue, FieldTable, LongString},
+    types::{AMQPValue, FieldTable, LongString, ShortString},
     ErrorKind, Result,
 };
 use executor_trait::FullExecutor;
-use std::sync::Arc;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const DEFAULT_IO_BUFFER_FRAMES: usize = 32;
+
+type ShutdownSignal = Arc<Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>>;
 
 #[derive(Clone)]
 pub struct ConnectionProperties {
     pub locale: String,
     pub client_properties: FieldTable,
+    /// The capabilities advertised to the server in the client properties
+    pub capabilities: Capabilities,
+    /// The kind of broker to talk to, and thus which protocol extensions to use
+    pub broker_profile: BrokerProfile,
     pub executor: Option<Arc<dyn FullExecutor + Send + Sync>>,
     pub reactor: Option<Arc<dyn FullReactor + Send + Sync>>,
     pub recovery_config: Option<RecoveryConfig>,
+    /// Maximum number of bytes of publish payloads waiting to be written to the socket.
+    /// Publishers wait for this buffer to drain instead of growing it without limit.
+    pub publish_buffer_size: Option<usize>,
+    /// How long to wait for more frames to be queued before writing a small amount of data to
+    /// the socket, to send several messages in one syscall.
+    pub write_coalescing: Option<Duration>,
+    /// How many buffers to keep around for reuse when serializing published messages
+    pub buffer_pool_size: usize,
+    /// Disable Nagle's algorithm on the TCP socket
+    pub nodelay: bool,
+    /// How many frames of the negotiated maximum size the read and write buffers can hold
+    pub io_buffer_frames: usize,
+    /// How many threads handle the frames received on the channels instead of the io loop, if any
+    pub dispatch_workers: usize,
+    /// Don't spawn a thread for the io loop, it has to be driven through `Connection::drive`
+    pub manual_io_loop: bool,
+    /// Run the io loop as a task spawned on the executor instead of in a thread of its own
+    pub spawn_io_loop: bool,
+    /// How the ids of new channels are picked
+    pub channel_id_allocation: ChannelIdAllocation,
+    /// Report the channels on which no frame was sent or received for this long
+    pub channel_leak_threshold: Option<Duration>,
+    /// The header carrying the publish time of the messages, in milliseconds since the UNIX epoch
+    pub latency_header: Option<ShortString>,
+    /// Report the consumers which had as many unacked messages as the prefetch count allows for
+    /// this long
+    pub slow_consumer_threshold: Option<Duration>,
+    /// How the consumers started without a consumer tag get one
+    pub consumer_tag_strategy: ConsumerTagStrategy,
+    /// A name for this connection, in the tracing spans of its io loop and in its Debug output
+    pub label: Option<String>,
+    /// Where to get the credentials from before each connection, instead of the uri
+    pub credentials_provider: Option<Arc<dyn CredentialsProvider>>,
+    /// Enable publisher confirms on every channel created on the connection
+    pub confirm_channels: bool,
+    /// Send heartbeats, acks, nacks and rejects ahead of the other frames waiting to be written
+    pub prioritize_control_frames: bool,
+    /// The protocol an [`AnyConnection`] speaks, unless its uri picks one
+    ///
+    /// [`AnyConnection`]: crate::amqp1::AnyConnection
+    #[cfg(feature = "amqp1")]
+    pub protocol: crate::amqp1::Protocol,
+    pub(crate) shutdown_signal: Option<ShutdownSignal>,
 }
 
 impl Default for ConnectionProperties {
@@ -21,14 +83,70 @@ impl Default for ConnectionProperties {
         Self {
             locale: "en_US".into(),
             client_properties: FieldTable::default(),
+            capabilities: Capabilities::default(),
+            broker_profile: BrokerProfile::default(),
             executor: None,
             reactor: None,
             recovery_config: None,
+            publish_buffer_size: None,
+            write_coalescing: None,
+            buffer_pool_size: DEFAULT_BUFFER_POOL_SIZE,
+            nodelay: true,
+            io_buffer_frames: DEFAULT_IO_BUFFER_FRAMES,
+            dispatch_workers: 0,
+            manual_io_loop: false,
+            spawn_io_loop: false,
+            channel_id_allocation: ChannelIdAllocation::default(),
+            channel_leak_threshold: None,
+            latency_header: None,
+            slow_consumer_threshold: None,
+            consumer_tag_strategy: ConsumerTagStrategy::default(),
+            label: None,
+            credentials_provider: None,
+            confirm_channels: false,
+            prioritize_control_frames: true,
+            #[cfg(feature = "amqp1")]
+            protocol: crate::amqp1::Protocol::default(),
+            shutdown_signal: None,
         }
     }
 }
 
 impl ConnectionProperties {
+    /// Tune the connection for the lowest latency.
+    ///
+    /// Every frame is written to the socket as soon as it is queued, without Nagle's algorithm
+    /// nor write coalescing, and the read and write buffers are kept small. This costs one
+    /// syscall (and often one TCP segment) per message, which limits throughput when
+    /// publishing a lot of small messages.
+    #[must_use]
+    pub fn latency_optimized() -> Self {
+        Self {
+            nodelay: true,
+            write_coalescing: None,
+            io_buffer_frames: 8,
+            ..Self::default()
+        }
+    }
+
+    /// Tune the connection for the highest throughput.
+    ///
+    /// Frames are batched for up to 1ms before being written, Nagle's algorithm is left
+    /// enabled so that the kernel can merge small writes, and the read and write buffers are
+    /// larger, as is the pool of reusable publish buffers. This adds latency to each message
+    /// (a few milliseconds, up to the delayed ACK timeout of the peer for isolated messages)
+    /// and uses more memory per connection.
+    #[must_use]
+    pub fn throughput_optimized() -> Self {
+        Self {
+            nodelay: false,
+            write_coalescing: Some(Duration::from_millis(1)),
+            io_buffer_frames: 128,
+            buffer_pool_size: 4 * DEFAULT_BUFFER_POOL_SIZE,
+            ..Self::default()
+        }
+    }
+
     #[must_use]
     pub fn with_connection_name(mut self, connection_name: LongString) -> Self {
         self.client_properties.insert(
@@ -38,6 +156,67 @@ impl ConnectionProperties {
         self
     }
 
+    /// The locale to ask the server to use for its error messages, `en_US` by default
+    #[must_use]
+    pub fn with_locale(mut self, locale: &str) -> Self {
+        self.locale = locale.into();
+        self
+    }
+
+    /// Replace the capabilities advertised to the server during the handshake, which default
+    /// to all the RabbitMQ extensions lapin supports.
+    ///
+    /// Brokers other than RabbitMQ may choke on some of them. An empty table isn't sent at all.
+    #[must_use]
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Talk to a broker of this kind, only using the protocol extensions it supports.
+    ///
+    /// This also replaces the advertised capabilities with the default ones of the profile,
+    /// which [`with_capabilities`] can then change.
+    ///
+    /// [`with_capabilities`]: #method.with_capabilities
+    #[must_use]
+    pub fn with_broker_profile(mut self, broker_profile: BrokerProfile) -> Self {
+        self.broker_profile = broker_profile;
+        self.capabilities = broker_profile.capabilities();
+        self
+    }
+
+    /// Name this connection in the logs, to tell it apart from the other ones of the
+    /// application. Unlike the connection name, it isn't sent to the server.
+    #[must_use]
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Query this provider for the username and password before each connection, which
+    /// overrides the ones of the uri. See the [`credentials_provider`] module.
+    ///
+    /// [`credentials_provider`]: ./credentials_provider/index.html
+    #[must_use]
+    pub fn with_credentials_provider<P: CredentialsProvider + 'static>(
+        mut self,
+        provider: P,
+    ) -> Self {
+        self.credentials_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Speak this protocol through [`AnyConnection`], unless the uri picks one
+    ///
+    /// [`AnyConnection`]: crate::amqp1::AnyConnection
+    #[cfg(feature = "amqp1")]
+    #[must_use]
+    pub fn with_protocol(mut self, protocol: crate::amqp1::Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
     #[must_use]
     pub fn with_executor<E: FullExecutor + Send + Sync + 'static>(mut self, executor: E) -> Self {
         self.executor = Some(Arc::new(executor));
@@ -50,12 +229,190 @@ impl ConnectionProperties {
         self
     }
 
+    /// Run the connection on the given tokio runtime, for its tasks, io loop included, and its
+    /// IO. See the [`tokio`] module.
+    ///
+    /// Only available on unix, where tokio can register the socket of the connection.
+    ///
+    /// [`tokio`]: ./tokio/index.html
+    #[cfg(all(feature = "tokio", unix))]
+    #[must_use]
+    pub fn with_tokio(self, handle: ::tokio::runtime::Handle) -> Self {
+        self.with_executor(crate::tokio::TokioExecutor::new(handle.clone()))
+            .with_reactor(crate::tokio::TokioReactor::new(handle))
+            .with_spawned_io_loop()
+    }
+
     #[must_use]
     pub fn with_experimental_recovery_config(mut self, config: RecoveryConfig) -> Self {
         self.recovery_config = Some(config);
         self
     }
 
+    #[must_use]
+    pub fn with_publish_buffer_size(mut self, publish_buffer_size: usize) -> Self {
+        self.publish_buffer_size = Some(publish_buffer_size);
+        self
+    }
+
+    #[must_use]
+    pub fn with_write_coalescing(mut self, window: Duration) -> Self {
+        self.write_coalescing = Some(window);
+        self
+    }
+
+    #[must_use]
+    pub fn with_buffer_pool_size(mut self, buffer_pool_size: usize) -> Self {
+        self.buffer_pool_size = buffer_pool_size;
+        self
+    }
+
+    #[must_use]
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    #[must_use]
+    pub fn with_io_buffer_frames(mut self, io_buffer_frames: usize) -> Self {
+        self.io_buffer_frames = io_buffer_frames;
+        self
+    }
+
+    /// Handle the frames received on the channels, assembling the deliveries and routing them to
+    /// their consumers, on a pool of `workers` threads instead of the io loop, which then only
+    /// reads from the socket and parses the frames.
+    ///
+    /// All the frames of a channel are handled by the same worker, in order. The frames of the
+    /// connection itself are still handled by the io loop, once the workers are done with the
+    /// frames received before them. This keeps reading from the socket while an expensive
+    /// delivery path holds a worker, at the cost of a hand-off per frame: it only pays off
+    /// with several busy channels. `0`, the default, handles everything on the io loop.
+    #[must_use]
+    pub fn with_dispatch_workers(mut self, workers: usize) -> Self {
+        self.dispatch_workers = workers;
+        self
+    }
+
+    /// Drive the io loop from the current task through `Connection::drive` instead of running
+    /// it in a dedicated thread. Write coalescing is not applied in this mode.
+    #[must_use]
+    pub fn with_manual_io_loop(mut self) -> Self {
+        self.manual_io_loop = true;
+        self
+    }
+
+    /// Run the io loop as a task spawned on the executor instead of in a dedicated thread.
+    /// Write coalescing is not applied in this mode, and `with_manual_io_loop` takes precedence.
+    #[must_use]
+    pub fn with_spawned_io_loop(mut self) -> Self {
+        self.spawn_io_loop = true;
+        self
+    }
+
+    #[must_use]
+    pub fn with_channel_id_allocation(
+        mut self,
+        channel_id_allocation: ChannelIdAllocation,
+    ) -> Self {
+        self.channel_id_allocation = channel_id_allocation;
+        self
+    }
+
+    /// Warn about the channels which stayed open without sending or receiving any frame for
+    /// longer than `threshold`, as they were probably forgotten.
+    ///
+    /// Each idle channel is reported once, until it sees some activity again. The reports are
+    /// logged and passed to the handler set with `Connection::on_channel_leak`.
+    #[must_use]
+    pub fn with_channel_leak_detection(mut self, threshold: Duration) -> Self {
+        self.channel_leak_threshold = Some(threshold);
+        self
+    }
+
+    /// Warn about the consumers which had as many unacked messages as the prefetch count allows
+    /// for longer than `threshold`, as the broker stops sending them messages and they're thus
+    /// the bottleneck of their queue.
+    ///
+    /// Each slow consumer is reported once, until it acks some messages. The reports are logged
+    /// and passed to the handler set with `Connection::on_slow_consumer`. Only the per consumer
+    /// prefetch count, set with `basic_qos` without `global`, is taken into account.
+    #[must_use]
+    pub fn with_slow_consumer_detection(mut self, threshold: Duration) -> Self {
+        self.slow_consumer_threshold = Some(threshold);
+        self
+    }
+
+    /// Enable publisher confirms on every channel created on the connection, whatever their
+    /// [`ChannelOptions`], so that no code path can forget it.
+    ///
+    /// The channels stay in confirm mode when they get recovered or restored.
+    ///
+    /// [`ChannelOptions`]: ./struct.ChannelOptions.html
+    #[must_use]
+    pub fn with_confirm_channels(mut self, confirm_channels: bool) -> Self {
+        self.confirm_channels = confirm_channels;
+        self
+    }
+
+    /// Whether heartbeats and the acks, nacks and rejects of deliveries are sent ahead of the
+    /// other frames waiting to be written, which is the default.
+    ///
+    /// They then only wait for the frames queued earlier on their own channel, and can be
+    /// interleaved with the body frames of a large publish on another channel. Saturating the
+    /// connection with publishes thus doesn't make the server miss heartbeats or close consuming
+    /// channels because their acks came too late. Without it, they are queued with the other
+    /// methods and only let through a large publish every few body frames.
+    #[must_use]
+    pub fn with_control_frames_priority(mut self, prioritize_control_frames: bool) -> Self {
+        self.prioritize_control_frames = prioritize_control_frames;
+        self
+    }
+
+    /// Generate the tags of the consumers started without one instead of letting the broker do
+    /// it, to identify them in the management UI and in logs.
+    ///
+    /// Channels can override it with [`ChannelOptions::with_consumer_tag_strategy`].
+    ///
+    /// [`ChannelOptions::with_consumer_tag_strategy`]: ./struct.ChannelOptions.html#method.with_consumer_tag_strategy
+    #[must_use]
+    pub fn with_consumer_tag_strategy(mut self, strategy: ConsumerTagStrategy) -> Self {
+        self.consumer_tag_strategy = strategy;
+        self
+    }
+
+    /// Measure how long messages took since they were published using this header, holding a
+    /// number of milliseconds since the UNIX epoch, instead of the `timestamp` property, which
+    /// only has a precision of one second.
+    ///
+    /// Messages without this header still fall back to their `timestamp` property.
+    #[must_use]
+    pub fn with_latency_header(mut self, header: &str) -> Self {
+        self.latency_header = Some(header.into());
+        self
+    }
+
+    /// Gracefully close the connection once the given future resolves.
+    ///
+    /// With tokio, this can be `CancellationToken::cancelled_owned()`. The signal is only used
+    /// by the first connection made with these properties.
+    #[must_use]
+    pub fn with_shutdown_signal<F: Future<Output = ()> + Send + 'static>(
+        mut self,
+        signal: F,
+    ) -> Self {
+        self.shutdown_signal = Some(Arc::new(Mutex::new(Some(Box::pin(signal)))));
+        self
+    }
+
+    pub(crate) fn take_shutdown_signal(&self) -> Option<Pin<Box<dyn Future<Output = ()> + Send>>> {
+        self.shutdown_signal
+            .as_ref()?
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+
     pub(crate) fn take_executor(&mut self) -> Result<Arc<dyn FullExecutor + Send + Sync>> {
         if let Some(executor) = self.executor.take() {
             return Ok(executor);
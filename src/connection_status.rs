@@ -1,11 +1,17 @@
 use crate::{
-    auth::{Credentials, SASLMechanism},
+    auth::SASLMechanism,
+    capabilities::Capabilities,
+    notifier::{NotifierRegistry, RecoveryEvent, RecoveryEvents},
+    secrets::Credentials,
+    wakers::Wakers,
     Connection, ConnectionProperties, PromiseResolver,
 };
 use std::{
     fmt,
     sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
 };
+use tracing::{debug_span, field, Span};
 
 #[derive(Clone, Default)]
 pub struct ConnectionStatus(Arc<Mutex<Inner>>);
@@ -15,9 +21,51 @@ impl ConnectionStatus {
         self.lock_inner().state
     }
 
+    pub fn label(&self) -> Option<String> {
+        self.lock_inner().label.clone()
+    }
+
+    pub(crate) fn set_label(&self, label: Option<String>) {
+        self.lock_inner().label = label;
+    }
+
+    /// The span wrapping what the io loop of this connection does
+    pub(crate) fn span(&self) -> Span {
+        let span = debug_span!("connection", label = field::Empty);
+        if !span.is_disabled() {
+            if let Some(label) = self.label() {
+                span.record("label", label);
+            }
+        }
+        span
+    }
+
     pub(crate) fn set_state(&self, state: ConnectionState) -> ConnectionState {
-        let mut inner = self.lock_inner();
-        std::mem::replace(&mut inner.state, state)
+        let (previous, wakers) = {
+            let mut inner = self.lock_inner();
+            (
+                std::mem::replace(&mut inner.state, state),
+                inner.state_wakers.clone(),
+            )
+        };
+        wakers.wake();
+        previous
+    }
+
+    /// Resolves once the connection is closed, errored or failed
+    pub(crate) fn poll_finished(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let inner = self.lock_inner();
+        if [
+            ConnectionState::Closed,
+            ConnectionState::Error,
+            ConnectionState::Failed,
+        ]
+        .contains(&inner.state)
+        {
+            return Poll::Ready(());
+        }
+        inner.state_wakers.register(cx.waker());
+        Poll::Pending
     }
 
     pub(crate) fn connection_step(&self) -> Option<ConnectionStep> {
@@ -54,6 +102,15 @@ impl ConnectionStatus {
         self.lock_inner().username = username.into();
     }
 
+    /// The capabilities advertised by the server during the handshake
+    pub fn server_capabilities(&self) -> Capabilities {
+        self.lock_inner().server_capabilities.clone()
+    }
+
+    pub(crate) fn set_server_capabilities(&self, capabilities: Capabilities) {
+        self.lock_inner().server_capabilities = capabilities;
+    }
+
     pub(crate) fn block(&self) {
         self.lock_inner().blocked = true;
     }
@@ -82,6 +139,37 @@ impl ConnectionStatus {
         self.lock_inner().state == ConnectionState::Error
     }
 
+    /// Whether the connection has been given up on after failing to recover
+    pub fn failed(&self) -> bool {
+        self.lock_inner().state == ConnectionState::Failed
+    }
+
+    /// How many times channels of this connection started recovering
+    pub fn recovery_generation(&self) -> u64 {
+        self.lock_inner().recovery_generation
+    }
+
+    /// Whether some channels of this connection are currently recovering
+    pub fn is_recovering(&self) -> bool {
+        self.lock_inner().recovering_channels > 0
+    }
+
+    /// Mark the connection as recovering until the returned guard is dropped
+    pub(crate) fn start_channel_recovery(&self) -> RecoveryGuard {
+        let mut inner = self.lock_inner();
+        inner.recovery_generation += 1;
+        inner.recovering_channels += 1;
+        RecoveryGuard(self.clone())
+    }
+
+    pub(crate) fn recovery_events(&self) -> RecoveryEvents {
+        self.lock_inner().notifiers.subscribe()
+    }
+
+    pub(crate) fn notify(&self, event: RecoveryEvent) {
+        self.lock_inner().notifiers.notify(event);
+    }
+
     pub(crate) fn auto_close(&self) -> bool {
         [ConnectionState::Connecting, ConnectionState::Connected].contains(&self.lock_inner().state)
     }
@@ -91,13 +179,27 @@ impl ConnectionStatus {
     }
 }
 
+pub(crate) struct RecoveryGuard(ConnectionStatus);
+
+impl RecoveryGuard {
+    pub(crate) fn notify(&self, event: RecoveryEvent) {
+        self.0.notify(event);
+    }
+}
+
+impl Drop for RecoveryGuard {
+    fn drop(&mut self) {
+        self.0.lock_inner().recovering_channels -= 1;
+    }
+}
+
 pub(crate) enum ConnectionStep {
     ProtocolHeader(
         PromiseResolver<Connection>,
         Connection,
         Credentials,
         SASLMechanism,
-        ConnectionProperties,
+        Box<ConnectionProperties>,
     ),
     StartOk(PromiseResolver<Connection>, Connection, Credentials),
     Open(PromiseResolver<Connection>),
@@ -112,17 +214,23 @@ pub enum ConnectionState {
     Closing,
     Closed,
     Error,
+    /// Terminal state reached when giving up on recovering a channel
+    Failed,
 }
 
 impl fmt::Debug for ConnectionStatus {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut debug = f.debug_struct("ConnectionStatus");
         if let Ok(inner) = self.0.try_lock() {
+            if let Some(label) = inner.label.as_ref() {
+                debug.field("label", label);
+            }
             debug
                 .field("state", &inner.state)
                 .field("vhost", &inner.vhost)
                 .field("username", &inner.username)
-                .field("blocked", &inner.blocked);
+                .field("blocked", &inner.blocked)
+                .field("recovering_channels", &inner.recovering_channels);
         }
         debug.finish()
     }
@@ -133,7 +241,13 @@ struct Inner {
     state: ConnectionState,
     vhost: String,
     username: String,
+    server_capabilities: Capabilities,
     blocked: bool,
+    state_wakers: Wakers,
+    recovery_generation: u64,
+    recovering_channels: usize,
+    notifiers: NotifierRegistry,
+    label: Option<String>,
 }
 
 impl Default for Inner {
@@ -143,7 +257,13 @@ impl Default for Inner {
             state: ConnectionState::default(),
             vhost: "/".into(),
             username: "guest".into(),
+            server_capabilities: Capabilities::none(),
             blocked: false,
+            state_wakers: Wakers::default(),
+            recovery_generation: 0,
+            recovering_channels: 0,
+            notifiers: NotifierRegistry::default(),
+            label: None,
         }
     }
 }
@@ -173,3 +293,28 @@ impl Inner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn poll_finished() {
+        let status = ConnectionStatus::default();
+        let woken = Arc::new(AtomicBool::new(false));
+        let waker = {
+            let woken = woken.clone();
+            waker_fn::waker_fn(move || woken.store(true, Ordering::SeqCst))
+        };
+        let mut cx = Context::from_waker(&waker);
+        status.set_state(ConnectionState::Connected);
+        assert!(status.poll_finished(&mut cx).is_pending());
+        status.set_state(ConnectionState::Closing);
+        assert!(woken.swap(false, Ordering::SeqCst));
+        assert!(status.poll_finished(&mut cx).is_pending());
+        status.set_state(ConnectionState::Closed);
+        assert!(woken.load(Ordering::SeqCst));
+        assert!(status.poll_finished(&mut cx).is_ready());
+    }
+}
@@ -1,27 +1,35 @@
 use crate::{
     channel_closer::ChannelCloser,
     consumer_canceler::ConsumerCanceler,
+    consumer_ordering::{ConsumerOrdering, Lanes},
     consumer_status::{ConsumerState, ConsumerStatus},
+    consumer_stream::{AckPolicy, AckedStream, ChunksTimeout, Decode, DecodedStream},
+    dedup::{DedupMark, DedupStore},
+    delegate_panic::{PanicGuard, PanicPolicy},
+    error_handler::ErrorHandler,
     error_holder::ErrorHolder,
     internal_rpc::InternalRPCHandle,
     message::{Delivery, DeliveryResult},
     options::BasicConsumeOptions,
+    reactor::FullReactor,
+    shutdown_signal::ShutdownSignal,
     types::{ChannelId, PayloadSize},
     types::{FieldTable, ShortString},
     wakers::Wakers,
-    BasicProperties, Error, Result,
+    BasicProperties, DeliveryLatency, Error, Result,
 };
-use executor_trait::FullExecutor;
+use executor_trait::{Executor, FullExecutor};
 use flume::{Receiver, Sender};
 use futures_core::stream::Stream;
 use std::{
     fmt,
-    future::Future,
+    future::{self, Future},
     pin::Pin,
     sync::{Arc, Mutex, MutexGuard},
-    task::{Context, Poll},
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
 };
-use tracing::trace;
+use tracing::{debug, trace};
 
 pub trait ConsumerDelegate: Send + Sync {
     fn on_new_delivery(&self, delivery: DeliveryResult)
@@ -44,6 +52,112 @@ impl<
     }
 }
 
+/// Where the futures returned by a [`ConsumerDelegate`] run.
+///
+/// [`ConsumerDelegate`]: ./trait.ConsumerDelegate.html
+#[derive(Clone, Default)]
+pub enum DelegateExecutor {
+    /// Spawned on the executor of the connection
+    #[default]
+    Connection,
+    /// Polled right away by the thread dispatching the delivery, usually the io loop, and only
+    /// spawned on the executor of the connection if it needs to wait
+    ///
+    /// This saves spawning a task for handlers which complete without waiting, but stalls the
+    /// processing of the frames of the connection while they run.
+    Inline,
+    /// Polled right away like with [`Inline`] when the payload of the delivery is at most this
+    /// many bytes, spawned on the executor of the connection otherwise
+    ///
+    /// For synchronous handlers of small messages, for which scheduling a task would cost more
+    /// than handling the message, while larger ones don't stall the connection.
+    ///
+    /// [`Inline`]: #variant.Inline
+    InlineUpTo(usize),
+    /// Spawned on a dedicated executor, such as a thread pool keeping CPU heavy handlers away
+    /// from the executor of the connection
+    Custom(Arc<dyn Executor + Send + Sync>),
+}
+
+impl DelegateExecutor {
+    pub(crate) fn spawn(
+        &self,
+        connection_executor: &dyn FullExecutor,
+        payload_size: usize,
+        future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    ) {
+        match self {
+            Self::InlineUpTo(max_payload_size) if payload_size > *max_payload_size => {
+                connection_executor.spawn(future);
+            }
+            Self::Connection => {
+                connection_executor.spawn(future);
+            }
+            Self::Inline | Self::InlineUpTo(_) => {
+                if let Some(future) = poll_inline(future) {
+                    connection_executor.spawn(future);
+                }
+            }
+            Self::Custom(executor) => {
+                executor.spawn(future);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DelegateExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Connection => "Connection",
+            Self::Inline => "Inline",
+            Self::InlineUpTo(_) => "InlineUpTo",
+            Self::Custom(_) => "Custom",
+        })
+    }
+}
+
+pub(crate) fn payload_size(delivery: &DeliveryResult) -> usize {
+    delivery
+        .as_ref()
+        .ok()
+        .and_then(Option::as_ref)
+        .map_or(0, |delivery| delivery.data.len())
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/* The executor polls the future again when it gets spawned, which registers its real waker */
+fn poll_inline(
+    mut future: Pin<Box<dyn Future<Output = ()> + Send>>,
+) -> Option<Pin<Box<dyn Future<Output = ()> + Send>>> {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    future
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker))
+        .is_pending()
+        .then_some(future)
+}
+
+/// What [`Consumer::next_or_shutdown`] returned
+///
+/// [`Consumer::next_or_shutdown`]: ./struct.Consumer.html#method.next_or_shutdown
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum ConsumerEvent {
+    /// A new delivery, to be handled
+    Delivery(Delivery),
+    /// The [`ShutdownSignal`] has been triggered
+    ///
+    /// [`ShutdownSignal`]: ./struct.ShutdownSignal.html
+    ShutdownRequested,
+    /// The consumer got canceled, no more deliveries will come
+    ConsumerCanceled,
+}
+
 /// Continuously consumes message from a Queue.
 ///
 /// A consumer represents a stream of messages created from
@@ -146,12 +260,17 @@ pub struct Consumer {
     wakers: Wakers,
     error: ErrorHolder,
     executor: Arc<dyn FullExecutor + Send + Sync>,
+    reactor: Arc<dyn FullReactor + Send + Sync>,
+    lanes: Lanes,
+    panics: PanicGuard,
+    dedup: Arc<Mutex<Option<Arc<dyn DedupStore>>>>,
 }
 
 impl Consumer {
     pub(crate) fn new(
         consumer_tag: ShortString,
         executor: Arc<dyn FullExecutor + Send + Sync>,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
         channel_closer: Option<Arc<ChannelCloser>>,
         queue: ShortString,
         options: BasicConsumeOptions,
@@ -172,6 +291,17 @@ impl Consumer {
             wakers: Wakers::default(),
             error: ErrorHolder::default(),
             executor,
+            reactor,
+            lanes: Lanes::default(),
+            panics: PanicGuard::default(),
+            dedup: Arc::default(),
+        }
+    }
+
+    /// Drop this handle without canceling the consumer, which is still owned by other handles
+    pub(crate) fn detach(mut self) {
+        if let Some(canceler) = self.consumer_canceler.take().and_then(Arc::into_inner) {
+            canceler.disarm();
         }
     }
 
@@ -198,6 +328,10 @@ impl Consumer {
             wakers: self.wakers.clone(),
             error: self.error.clone(),
             executor: self.executor.clone(),
+            reactor: self.reactor.clone(),
+            lanes: self.lanes.clone(),
+            panics: self.panics.clone(),
+            dedup: self.dedup.clone(),
         }
     }
 
@@ -237,15 +371,224 @@ impl Consumer {
     pub fn set_delegate<D: ConsumerDelegate + 'static>(&self, delegate: D) {
         let mut inner = self.lock_inner();
         let mut status = self.status.write();
+        let delegate_executor = status.delegate_executor();
         while let Some(delivery) = inner.next_delivery() {
-            self.executor.spawn(delegate.on_new_delivery(delivery));
+            self.spawn_delegate(&delegate, delivery, &delegate_executor);
         }
         status.set_delegate(Some(Arc::new(Box::new(delegate))));
     }
 
+    pub(crate) fn has_delegate(&self) -> bool {
+        self.status.delegate().is_some()
+    }
+
+    /// Choose where the futures of the delegate run, on the executor of the connection by
+    /// default.
+    ///
+    /// This applies to the deliveries dispatched from now on.
+    pub fn set_delegate_executor(&self, delegate_executor: DelegateExecutor) {
+        self.status.write().set_delegate_executor(delegate_executor);
+    }
+
+    /// Choose in which order the delegate handles the deliveries, concurrently by default.
+    ///
+    /// With [`ConsumerOrdering::ByRoutingKey`], the deliveries of a given routing key are
+    /// handled one after the other while the other keys make progress:
+    ///
+    /// ```rust,no_run
+    /// use lapin::{options::*, types::FieldTable, ConsumerOrdering};
+    ///
+    /// # async fn consume(channel: lapin::Channel) -> lapin::Result<()> {
+    /// let consumer = channel
+    ///     .basic_consume(
+    ///         "orders",
+    ///         "",
+    ///         BasicConsumeOptions::default(),
+    ///         FieldTable::default(),
+    ///     )
+    ///     .await?;
+    /// consumer.set_ordering(ConsumerOrdering::ByRoutingKey(8));
+    /// consumer.set_delegate(|delivery: lapin::message::DeliveryResult| async move {
+    ///     if let Ok(Some(delivery)) = delivery {
+    ///         delivery.ack(BasicAckOptions::default()).await.expect("ack");
+    ///     }
+    /// });
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Once the consumer gets canceled or fails, the delegate only gets notified after handling
+    /// all the deliveries received before. Set it before the delegate: the deliveries already
+    /// dispatched with another ordering aren't waited for.
+    ///
+    /// [`ConsumerOrdering::ByRoutingKey`]: ./enum.ConsumerOrdering.html#variant.ByRoutingKey
+    pub fn set_ordering(&self, ordering: ConsumerOrdering) {
+        self.lanes.set_ordering(ordering);
+    }
+
+    pub fn ordering(&self) -> ConsumerOrdering {
+        self.lanes.ordering()
+    }
+
+    /// Choose what happens when the delegate panics while handling a delivery.
+    ///
+    /// By default, the delivery is rejected and requeued, the panic is reported through
+    /// [`Channel::on_error`] and the consumer keeps going.
+    ///
+    /// [`Channel::on_error`]: ./struct.Channel.html#method.on_error
+    pub fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.panics.set_policy(policy);
+    }
+
+    pub fn panic_policy(&self) -> PanicPolicy {
+        self.panics.policy()
+    }
+
+    /// Drop the deliveries of the messages already handled, according to their `message_id`.
+    ///
+    /// A message counts as handled once one of its deliveries gets acked through its [`Acker`],
+    /// and its later deliveries are then acked and dropped without reaching the stream or the
+    /// delegate. This mostly saves handling twice the messages redelivered after a recovery,
+    /// whose first ack got lost. The messages without a `message_id` are never dropped, nor the
+    /// ones whose deliveries are handled concurrently.
+    ///
+    /// With [`BasicConsumeOptions::no_ack`], a message counts as handled once it's received.
+    /// Set this right after creating the consumer: the deliveries already received aren't
+    /// checked.
+    ///
+    /// ```rust,no_run
+    /// use lapin::{options::*, types::FieldTable, Connection, ConnectionProperties, DedupWindow};
+    /// use std::time::Duration;
+    ///
+    /// # async fn run(connection: Connection) -> lapin::Result<()> {
+    /// let channel = connection.create_channel().await?;
+    /// let consumer = channel
+    ///     .basic_consume("jobs", "worker", BasicConsumeOptions::default(), FieldTable::default())
+    ///     .await?;
+    /// consumer.set_deduplication(DedupWindow::new(10_000, Duration::from_secs(600)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Acker`]: ./struct.Acker.html
+    /// [`BasicConsumeOptions::no_ack`]: ./options/struct.BasicConsumeOptions.html#structfield.no_ack
+    pub fn set_deduplication<S: DedupStore + 'static>(&self, store: S) {
+        *self.lock_dedup() = Some(Arc::new(store));
+    }
+
+    /// Stop dropping the deliveries of the messages already handled
+    pub fn clear_deduplication(&self) {
+        *self.lock_dedup() = None;
+    }
+
+    pub(crate) fn set_channel_context(
+        &self,
+        channel_id: ChannelId,
+        internal_rpc: InternalRPCHandle,
+        error_handler: ErrorHandler,
+    ) {
+        self.panics
+            .set_channel(channel_id, internal_rpc, error_handler);
+    }
+
+    /// Get the next delivery if it was already received, without waiting for it.
+    ///
+    /// Returns `None` if nothing is buffered, and otherwise what the stream would yield, with
+    /// `Ok(None)` meaning that the consumer got canceled. This doesn't register any waker.
+    pub fn try_next(&mut self) -> Option<DeliveryResult> {
+        let mut inner = self.lock_inner();
+        let delivery = inner.next_delivery();
+        trace!(consumer_tag=%inner.tag, ready=delivery.is_some(), "consumer try_next");
+        delivery
+    }
+
+    /// Wait for the next delivery, unless `shutdown` gets triggered first.
+    ///
+    /// The shutdown takes precedence over the deliveries already received: those are requeued
+    /// so that another consumer can handle them, unless the consumer was created with
+    /// [`BasicConsumeOptions::no_ack`], in which case they can still be obtained from the
+    /// stream. Once the shutdown has been requested, each call requeues the deliveries received
+    /// in the meantime, such as the requeued ones the broker sent again before the consumer got
+    /// canceled, and returns [`ConsumerEvent::ShutdownRequested`], until the consumer gets
+    /// canceled or its channel closed. With `no_ack`, it keeps returning
+    /// [`ConsumerEvent::ShutdownRequested`].
+    ///
+    /// This is cancel safe: dropping the future doesn't lose any delivery.
+    ///
+    /// ```rust,no_run
+    /// use lapin::{
+    ///     options::*, types::FieldTable, Connection, ConnectionProperties, ConsumerEvent,
+    ///     ShutdownSignal,
+    /// };
+    ///
+    /// # async fn run(shutdown: ShutdownSignal) -> lapin::Result<()> {
+    /// let connection = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+    /// let channel = connection.create_channel().await?;
+    /// let mut consumer = channel
+    ///     .basic_consume("jobs", "worker", BasicConsumeOptions::default(), FieldTable::default())
+    ///     .await?;
+    /// while let ConsumerEvent::Delivery(delivery) = consumer.next_or_shutdown(&shutdown).await? {
+    ///     delivery.ack(BasicAckOptions::default()).await?;
+    /// }
+    /// connection.close(200, "OK").await
+    /// # }
+    /// ```
+    ///
+    /// [`BasicConsumeOptions::no_ack`]: ./options/struct.BasicConsumeOptions.html#structfield.no_ack
+    /// [`ConsumerEvent::ShutdownRequested`]: ./enum.ConsumerEvent.html#variant.ShutdownRequested
+    pub async fn next_or_shutdown(&mut self, shutdown: &ShutdownSignal) -> Result<ConsumerEvent> {
+        future::poll_fn(|cx| {
+            if shutdown.poll_triggered(cx).is_ready() {
+                let next = if self.options.no_ack {
+                    None
+                } else {
+                    self.requeue_prefetched_messages()
+                };
+                return Poll::Ready(match next {
+                    Some(Err(error)) => Err(error),
+                    Some(Ok(None)) => Ok(ConsumerEvent::ConsumerCanceled),
+                    _ => Ok(ConsumerEvent::ShutdownRequested),
+                });
+            }
+            Pin::new(&mut *self).poll_next(cx).map(|next| match next {
+                Some(Ok(delivery)) => Ok(ConsumerEvent::Delivery(delivery)),
+                Some(Err(error)) => Err(error),
+                None => Ok(ConsumerEvent::ConsumerCanceled),
+            })
+        })
+        .await
+    }
+
+    /// Turn this consumer into a stream acking the deliveries according to `policy`.
+    ///
+    /// See [`AckPolicy`] for when each delivery gets acked.
+    ///
+    /// [`AckPolicy`]: ./consumer_stream/enum.AckPolicy.html
+    pub fn into_acked_stream(self, policy: AckPolicy) -> AckedStream {
+        AckedStream::new(self, policy)
+    }
+
+    /// Turn this consumer into a stream of deliveries with their payload decoded as `T`.
+    ///
+    /// The deliveries which cannot be decoded are rejected without being requeued.
+    pub fn map_decoded<T: Decode>(self) -> DecodedStream<T> {
+        DecodedStream::new(self)
+    }
+
+    /// Turn this consumer into a stream of batches of at most `size` deliveries, yielding
+    /// partial batches once `timeout` elapsed since their first delivery.
+    pub fn chunks_timeout(self, size: usize, timeout: Duration) -> ChunksTimeout {
+        let reactor = self.reactor.clone();
+        ChunksTimeout::new(self, reactor, size, timeout)
+    }
+
     pub(crate) fn reset(&self) {
-        self.lock_inner()
-            .reset(self.options.no_ack, &self.executor, self.status.delegate());
+        self.lock_inner().reset(
+            self.options.no_ack,
+            &self.executor,
+            self.status.delegate(),
+            &self.status.delegate_executor(),
+        );
     }
 
     pub(crate) fn start_new_delivery(&self, delivery: Delivery) {
@@ -256,10 +599,11 @@ impl Consumer {
         &self,
         size: PayloadSize,
         properties: BasicProperties,
+        latency: DeliveryLatency,
     ) {
         self.check_new_delivery(
             self.lock_inner()
-                .handle_content_header_frame(size, properties),
+                .handle_content_header_frame(size, properties, latency),
         );
     }
 
@@ -268,8 +612,32 @@ impl Consumer {
     }
 
     pub(crate) fn drop_prefetched_messages(&self) {
-        self.lock_inner()
-            .drop_prefetched_messages(&self.executor, self.status.delegate());
+        self.lock_inner().drop_prefetched_messages(
+            &self.executor,
+            self.status.delegate(),
+            &self.status.delegate_executor(),
+        );
+    }
+
+    /* Requeue the deliveries received so far, returning the cancellation or error following them */
+    fn requeue_prefetched_messages(&self) -> Option<DeliveryResult> {
+        let mut inner = self.lock_inner();
+        let mut pending = Vec::new();
+        while let Some(delivery) = inner.next_delivery() {
+            match delivery {
+                Ok(Some(delivery)) => delivery.acker.reject_detached(true),
+                other => pending.push(other),
+            }
+        }
+        let mut pending = pending.into_iter();
+        let next = pending.next();
+        // Keep the other ones for the next ones asking
+        for delivery in pending {
+            self.deliveries_in
+                .send(delivery)
+                .expect("failed to send back to consumer");
+        }
+        next
     }
 
     pub(crate) fn start_cancel(&self) {
@@ -283,6 +651,7 @@ impl Consumer {
             Ok(None),
             "failed to send cancel to consumer",
             status.delegate(),
+            &status.delegate_executor(),
         );
         status.cancel();
     }
@@ -294,6 +663,7 @@ impl Consumer {
             Err(error),
             "failed to send error to consumer",
             self.status.delegate(),
+            &self.status.delegate_executor(),
         );
         self.cancel();
     }
@@ -302,12 +672,42 @@ impl Consumer {
         self.inner.lock().unwrap_or_else(|e| e.into_inner())
     }
 
+    fn lock_dedup(&self) -> MutexGuard<'_, Option<Arc<dyn DedupStore>>> {
+        self.dedup.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /* Drop the delivery if its message was already handled */
+    fn deduplicate(&self, mut delivery: Delivery) -> Option<Delivery> {
+        let Some(store) = self.lock_dedup().clone() else {
+            return Some(delivery);
+        };
+        let Some(message_id) = delivery.properties.message_id().as_ref() else {
+            return Some(delivery);
+        };
+        let message_id = message_id.as_str();
+        if store.contains(message_id) {
+            debug!(consumer_tag=%self.consumer_tag, delivery_tag=%delivery.delivery_tag, %message_id, "dropping duplicate delivery");
+            if !self.options.no_ack {
+                delivery.acker.ack_detached();
+            }
+            return None;
+        }
+        if self.options.no_ack {
+            store.insert(message_id);
+        } else {
+            let mark = DedupMark::new(store, message_id.to_owned());
+            delivery.acker.set_dedup(mark);
+        }
+        Some(delivery)
+    }
+
     fn check_new_delivery(&self, delivery: Option<Delivery>) {
-        if let Some(delivery) = delivery {
+        if let Some(delivery) = delivery.and_then(|delivery| self.deduplicate(delivery)) {
             self.dispatch(
                 Ok(Some(delivery)),
                 "failed to send delivery to consumer",
                 self.status.delegate(),
+                &self.status.delegate_executor(),
             );
         }
     }
@@ -317,14 +717,50 @@ impl Consumer {
         delivery: DeliveryResult,
         error: &'static str,
         delegate: Option<Arc<Box<dyn ConsumerDelegate>>>,
+        delegate_executor: &DelegateExecutor,
     ) {
         if let Some(delegate) = delegate {
-            self.executor.spawn(delegate.on_new_delivery(delivery));
+            self.spawn_delegate(&**delegate, delivery, delegate_executor);
         } else {
             self.deliveries_in.send(delivery).expect(error);
         }
         self.wakers.wake();
     }
+
+    fn spawn_delegate(
+        &self,
+        delegate: &dyn ConsumerDelegate,
+        delivery: DeliveryResult,
+        delegate_executor: &DelegateExecutor,
+    ) {
+        let delegate = GuardedDelegate {
+            delegate,
+            consumer: self,
+        };
+        self.lanes
+            .spawn(&self.executor, delegate_executor, &delegate, delivery);
+    }
+}
+
+/* Catches the panics of the delegate according to the PanicPolicy of the consumer */
+struct GuardedDelegate<'a> {
+    delegate: &'a dyn ConsumerDelegate,
+    consumer: &'a Consumer,
+}
+
+impl ConsumerDelegate for GuardedDelegate<'_> {
+    fn on_new_delivery(
+        &self,
+        delivery: DeliveryResult,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.consumer.panics.guard(
+            &self.consumer.consumer_tag,
+            &self.consumer.status,
+            self.consumer.options.no_ack,
+            delivery,
+            |delivery| self.delegate.on_new_delivery(delivery),
+        )
+    }
 }
 
 impl fmt::Debug for Consumer {
@@ -368,9 +804,10 @@ impl Inner {
         no_ack: bool,
         executor: &dyn FullExecutor,
         delegate: Option<Arc<Box<dyn ConsumerDelegate>>>,
+        delegate_executor: &DelegateExecutor,
     ) {
         if !no_ack {
-            self.drop_prefetched_messages(executor, delegate);
+            self.drop_prefetched_messages(executor, delegate, delegate_executor);
         }
         self.current_message = None;
     }
@@ -383,9 +820,11 @@ impl Inner {
         &mut self,
         size: PayloadSize,
         properties: BasicProperties,
+        latency: DeliveryLatency,
     ) -> Option<Delivery> {
         if let Some(delivery) = self.current_message.as_mut() {
-            delivery.properties = properties;
+            delivery.set_properties(properties);
+            delivery.latency = latency;
         }
         self.check_new_delivery_complete(size == 0)
     }
@@ -396,7 +835,7 @@ impl Inner {
         payload: Vec<u8>,
     ) -> Option<Delivery> {
         if let Some(delivery) = self.current_message.as_mut() {
-            delivery.receive_content(payload);
+            delivery.receive_content(payload, remaining_size);
         }
         self.check_new_delivery_complete(remaining_size == 0)
     }
@@ -414,10 +853,11 @@ impl Inner {
         &mut self,
         executor: &dyn FullExecutor,
         delegate: Option<Arc<Box<dyn ConsumerDelegate>>>,
+        delegate_executor: &DelegateExecutor,
     ) {
         trace!(consumer_tag=%self.tag, "drop_prefetched_messages");
         if let Some(delegate) = delegate {
-            executor.spawn(delegate.drop_prefetched_messages());
+            delegate_executor.spawn(executor, 0, delegate.drop_prefetched_messages());
         }
         while let Some(delivery) = self.next_delivery() {
             if let Ok(Some(delivery)) = delivery {
@@ -490,6 +930,7 @@ mod futures_tests {
         let mut consumer = Consumer::new(
             ShortString::from("test-consumer"),
             Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            Arc::new(async_reactor_trait::AsyncIo),
             None,
             "test".into(),
             BasicConsumeOptions::default(),
@@ -526,6 +967,7 @@ mod futures_tests {
         let mut consumer = Consumer::new(
             ShortString::from("test-consumer"),
             Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            Arc::new(async_reactor_trait::AsyncIo),
             None,
             "test".into(),
             BasicConsumeOptions::default(),
@@ -538,7 +980,7 @@ mod futures_tests {
             assert_eq!(Pin::new(&mut next).poll(&mut cx), Poll::Pending);
         }
 
-        consumer.set_error(ErrorKind::ChannelsLimitReached.into());
+        consumer.set_error(ErrorKind::ChannelsLimitReached(Vec::new()).into());
 
         {
             let mut next = consumer.next();
@@ -546,8 +988,511 @@ mod futures_tests {
             assert_eq!(awoken_count.load(Ordering::SeqCst), 1);
             assert_eq!(
                 Pin::new(&mut next).poll(&mut cx),
-                Poll::Ready(Some(Err(ErrorKind::ChannelsLimitReached.into())))
+                Poll::Ready(Some(
+                    Err(ErrorKind::ChannelsLimitReached(Vec::new()).into())
+                ))
             );
         }
     }
+
+    #[test]
+    fn inline_delegate() {
+        let canceled = Arc::new(AtomicUsize::new(0));
+        let consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            Arc::new(async_reactor_trait::AsyncIo),
+            None,
+            "test".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        consumer.set_delegate_executor(DelegateExecutor::Inline);
+        consumer.set_delegate({
+            let canceled = canceled.clone();
+            move |delivery: DeliveryResult| {
+                let canceled = canceled.clone();
+                async move {
+                    if let Ok(None) = delivery {
+                        canceled.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            }
+        });
+
+        consumer.cancel();
+        assert_eq!(canceled.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn try_next() {
+        let mut consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            Arc::new(async_reactor_trait::AsyncIo),
+            None,
+            "test".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        assert_eq!(consumer.try_next(), None);
+
+        consumer.start_new_delivery(Delivery::new(
+            1,
+            1,
+            "".into(),
+            "test".into(),
+            false,
+            None,
+            None,
+            None,
+        ));
+        assert_eq!(consumer.try_next(), None);
+        consumer.handle_content_header_frame(
+            0,
+            BasicProperties::default(),
+            DeliveryLatency::default(),
+        );
+        assert!(matches!(
+            consumer.try_next(),
+            Some(Ok(Some(delivery))) if delivery.delivery_tag == 1
+        ));
+        assert_eq!(consumer.try_next(), None);
+
+        consumer.cancel();
+        assert_eq!(consumer.try_next(), Some(Ok(None)));
+    }
+
+    #[test]
+    fn custom_delegate_executor() {
+        struct CountingExecutor(AtomicUsize);
+
+        impl Executor for CountingExecutor {
+            fn block_on(&self, f: Pin<Box<dyn Future<Output = ()>>>) {
+                async_global_executor_trait::AsyncGlobalExecutor.block_on(f);
+            }
+
+            fn spawn(
+                &self,
+                f: Pin<Box<dyn Future<Output = ()> + Send>>,
+            ) -> Box<dyn executor_trait::Task> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                async_global_executor_trait::AsyncGlobalExecutor.spawn(f)
+            }
+        }
+
+        let executor = Arc::new(CountingExecutor(AtomicUsize::new(0)));
+        let consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            Arc::new(async_reactor_trait::AsyncIo),
+            None,
+            "test".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        consumer.set_delegate_executor(DelegateExecutor::Custom(executor.clone()));
+        consumer.set_delegate(|_delivery: DeliveryResult| async {});
+
+        consumer.set_error(ErrorKind::ChannelsLimitReached(Vec::new()).into());
+        // The error, then the cancellation
+        assert_eq!(executor.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn inline_small_deliveries() {
+        use crate::{
+            options::{BasicAckOptions, BasicPublishOptions, QueueDeclareOptions},
+            testing::MockBroker,
+            BasicProperties, ConnectionProperties,
+        };
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let (sender, receiver) = flume::unbounded();
+            consumer.set_delegate_executor(DelegateExecutor::InlineUpTo(4));
+            consumer.set_delegate(move |delivery: DeliveryResult| {
+                let sender = sender.clone();
+                async move {
+                    if let Ok(Some(delivery)) = delivery {
+                        let thread = std::thread::current().name().map(str::to_owned);
+                        delivery.ack(BasicAckOptions::default()).await.unwrap();
+                        sender.send((delivery.data, thread)).unwrap();
+                    }
+                }
+            });
+            for payload in [&b"tiny"[..], &b"larger"[..]] {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        payload,
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+
+            let mut handled = [
+                receiver.recv_async().await.unwrap(),
+                receiver.recv_async().await.unwrap(),
+            ];
+            handled.sort();
+            assert_eq!(&handled[0].0[..], b"larger");
+            assert_ne!(handled[0].1.as_deref(), Some("lapin-io-loop"));
+            assert_eq!(&handled[1].0[..], b"tiny");
+            assert_eq!(handled[1].1.as_deref(), Some("lapin-io-loop"));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn inline_errors() {
+        let consumer = Consumer::new(
+            ShortString::from("test-consumer"),
+            Arc::new(async_global_executor_trait::AsyncGlobalExecutor),
+            Arc::new(async_reactor_trait::AsyncIo),
+            None,
+            "test".into(),
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        );
+        let (sender, receiver) = flume::unbounded();
+        consumer.set_delegate_executor(DelegateExecutor::InlineUpTo(0));
+        consumer.set_delegate(move |_delivery: DeliveryResult| {
+            let sender = sender.clone();
+            async move {
+                sender.send(std::thread::current().id()).unwrap();
+            }
+        });
+
+        // Without a payload, the error and the cancellation are handled right away
+        consumer.set_error(ErrorKind::ChannelsLimitReached(Vec::new()).into());
+        let handled = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(handled, [std::thread::current().id(); 2]);
+    }
+
+    #[test]
+    fn ordered_by_routing_key() {
+        use crate::{
+            options::{
+                BasicPublishOptions, QueueBindOptions, QueueDeclareOptions, QueueDeleteOptions,
+            },
+            testing::MockBroker,
+            BasicProperties, ConnectionProperties,
+        };
+
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for key in ["a", "b"] {
+                channel
+                    .queue_bind(
+                        "jobs",
+                        "amq.direct",
+                        key,
+                        QueueBindOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await?;
+            }
+            let consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions {
+                        no_ack: true,
+                        ..BasicConsumeOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+            let (gate_sender, gate) = flume::unbounded::<()>();
+            let (sender, receiver) = flume::unbounded();
+            consumer.set_ordering(ConsumerOrdering::ByRoutingKey(8));
+            consumer.set_delegate(move |delivery: DeliveryResult| {
+                let gate = gate.clone();
+                let sender = sender.clone();
+                async move {
+                    let Ok(Some(delivery)) = delivery else {
+                        sender.send(None).unwrap();
+                        return;
+                    };
+                    // The first delivery of "a" holds its lane until the test lets it go
+                    if delivery.routing_key.as_str() == "a" && delivery.data[..] == [0] {
+                        gate.recv_async().await.unwrap();
+                    }
+                    sender
+                        .send(Some((delivery.routing_key.to_string(), delivery.data[0])))
+                        .unwrap();
+                }
+            });
+            for key in ["a", "b"] {
+                for i in 0..3u8 {
+                    channel
+                        .basic_publish(
+                            "amq.direct",
+                            key,
+                            BasicPublishOptions::default(),
+                            &[i],
+                            BasicProperties::default(),
+                        )
+                        .await?;
+                }
+            }
+
+            // "b" isn't held up by "a"
+            for i in 0..3u8 {
+                assert_eq!(
+                    receiver.recv_async().await.unwrap(),
+                    Some(("b".to_owned(), i))
+                );
+            }
+            // The cancellation by the broker waits for the deliveries received before it
+            channel
+                .queue_delete("jobs", QueueDeleteOptions::default())
+                .await?;
+            gate_sender.send(()).unwrap();
+            for i in 0..3u8 {
+                assert_eq!(
+                    receiver.recv_async().await.unwrap(),
+                    Some(("a".to_owned(), i))
+                );
+            }
+            assert_eq!(receiver.recv_async().await.unwrap(), None);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn delegate_panics() {
+        use crate::{
+            options::{BasicAckOptions, BasicPublishOptions, QueueDeclareOptions},
+            testing::MockBroker,
+            BasicProperties, ConnectionProperties, DelegatePanic,
+        };
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            let (errors_sender, errors) = flume::unbounded();
+            channel.on_error(move |error| errors_sender.send(error).unwrap());
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let (sender, receiver) = flume::unbounded();
+            consumer.set_delegate(move |delivery: DeliveryResult| {
+                let sender = sender.clone();
+                async move {
+                    if let Ok(Some(delivery)) = delivery {
+                        assert!(delivery.redelivered, "not ready yet");
+                        delivery.ack(BasicAckOptions::default()).await.unwrap();
+                        sender.send(delivery.data).unwrap();
+                    }
+                }
+            });
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default().with_message_id("job-1".into()),
+                )
+                .await?;
+
+            // The delivery got requeued, and handled once redelivered
+            assert_eq!(&receiver.recv_async().await.unwrap()[..], b"job");
+            assert_eq!(
+                errors.recv_async().await.unwrap(),
+                ErrorKind::DelegatePanicked(Box::new(DelegatePanic {
+                    consumer_tag: "worker".into(),
+                    delivery_tag: Some(1),
+                    exchange: Some("".into()),
+                    routing_key: Some("jobs".into()),
+                    message_id: Some("job-1".into()),
+                    correlation_id: None,
+                    message: "not ready yet".into(),
+                }))
+                .into()
+            );
+            assert_eq!(consumer.state(), ConsumerState::ActiveWithDelegate);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn next_or_shutdown() {
+        use crate::{
+            options::{
+                BasicAckOptions, BasicCancelOptions, BasicPublishOptions, QueueDeclareOptions,
+            },
+            testing::MockBroker,
+            BasicProperties, ConnectionProperties,
+        };
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for i in 0..3u8 {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        &[i],
+                        BasicProperties::default(),
+                    )
+                    .await?
+                    .await?;
+            }
+            let shutdown = ShutdownSignal::default();
+            let ConsumerEvent::Delivery(delivery) = consumer.next_or_shutdown(&shutdown).await?
+            else {
+                panic!("expected a delivery");
+            };
+            assert_eq!(delivery.data[..], [0]);
+            delivery.ack(BasicAckOptions::default()).await?;
+
+            // The deliveries already received go back to the queue
+            shutdown.trigger();
+            assert_eq!(
+                consumer.next_or_shutdown(&shutdown).await?,
+                ConsumerEvent::ShutdownRequested
+            );
+            channel
+                .basic_cancel("worker", BasicCancelOptions::default())
+                .await?;
+            // Along with the ones the broker sent again before getting the cancellation
+            while consumer.next_or_shutdown(&shutdown).await? == ConsumerEvent::ShutdownRequested {}
+            // The rejects are sent in the background
+            for _ in 0..100 {
+                if broker.message_count("jobs") == Some(2) {
+                    break;
+                }
+                channel.sleep(Duration::from_millis(5)).await;
+            }
+            let mut requeued = broker.messages("jobs");
+            requeued.sort();
+            assert_eq!(requeued, [[1], [2]]);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn deduplication() {
+        use crate::{
+            options::{BasicAckOptions, BasicPublishOptions, BasicQosOptions, QueueDeclareOptions},
+            testing::MockBroker,
+            BasicProperties, ConnectionProperties, DedupWindow,
+        };
+        use std::time::Duration;
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            // The next delivery only comes once the previous one got acked
+            channel.basic_qos(1, BasicQosOptions::default()).await?;
+            let mut consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            consumer.set_deduplication(DedupWindow::new(16, Duration::from_secs(60)));
+            for (payload, message_id) in [(b"a", "job-1"), (b"b", "job-1"), (b"c", "job-2")] {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        payload,
+                        BasicProperties::default().with_message_id(message_id.into()),
+                    )
+                    .await?
+                    .await?;
+            }
+
+            // The duplicate of job-1 got acked and dropped
+            for expected in [b"a", b"c"] {
+                let delivery = consumer.next().await.unwrap()?;
+                assert_eq!(&delivery.data[..], expected);
+                delivery.ack(BasicAckOptions::default()).await?;
+            }
+            channel.close(0, "").await?;
+            assert_eq!(broker.message_count("jobs"), Some(0));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
 }
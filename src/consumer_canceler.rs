@@ -9,6 +9,7 @@ pub(crate) struct ConsumerCanceler {
     consumer_tag: String,
     status: ConsumerStatus,
     internal_rpc: InternalRPCHandle,
+    armed: bool,
 }
 
 impl ConsumerCanceler {
@@ -23,13 +24,19 @@ impl ConsumerCanceler {
             consumer_tag,
             status,
             internal_rpc,
+            armed: true,
         }
     }
+
+    /// Don't cancel the consumer when dropped
+    pub(crate) fn disarm(mut self) {
+        self.armed = false;
+    }
 }
 
 impl Drop for ConsumerCanceler {
     fn drop(&mut self) {
-        if self.status.state() == ConsumerState::Active {
+        if self.armed && self.status.state() == ConsumerState::Active {
             self.internal_rpc.cancel_consumer(
                 self.channel_id,
                 self.consumer_tag.clone(),
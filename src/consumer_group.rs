@@ -0,0 +1,420 @@
+use crate::{
+    message::Delivery,
+    options::{BasicCancelOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions},
+    types::{FieldTable, ShortString, ShortUInt},
+    Channel, Consumer, ConsumerDelegate, Result,
+};
+use futures_core::stream::Stream;
+use std::{
+    fmt,
+    future::poll_fn,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tracing::debug;
+
+/// How a [`ConsumerGroup`] consumes its queue.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsumerGroupOptions {
+    /// How many consumers to start, at least one
+    pub workers: usize,
+    /// The prefetch count shared by all the consumers of the group
+    pub prefetch_budget: Option<ShortUInt>,
+    pub consume_options: BasicConsumeOptions,
+    pub arguments: FieldTable,
+}
+
+impl Default for ConsumerGroupOptions {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            prefetch_budget: None,
+            consume_options: BasicConsumeOptions::default(),
+            arguments: FieldTable::default(),
+        }
+    }
+}
+
+impl ConsumerGroupOptions {
+    #[must_use]
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Split this prefetch count between the consumers, so that the whole group never has more
+    /// unacked messages than that
+    ///
+    /// Each consumer gets at least one message, so the budget is raised to the number of
+    /// workers if it's lower.
+    #[must_use]
+    pub fn with_prefetch_budget(mut self, prefetch_budget: ShortUInt) -> Self {
+        self.prefetch_budget = Some(prefetch_budget);
+        self
+    }
+
+    #[must_use]
+    pub fn with_consume_options(mut self, consume_options: BasicConsumeOptions) -> Self {
+        self.consume_options = consume_options;
+        self
+    }
+
+    #[must_use]
+    pub fn with_arguments(mut self, arguments: FieldTable) -> Self {
+        self.arguments = arguments;
+        self
+    }
+}
+
+/// Several consumers of the same queue, spread over channels which may belong to different
+/// connections, handled as one.
+///
+/// The group is a `Stream` of the deliveries of all its consumers, which ends once all of them
+/// got canceled. Each consumer can instead get its own handler with [`set_delegates`].
+///
+/// ```rust,no_run
+/// use futures_lite::StreamExt;
+/// use lapin::{
+///     consumer_group::{ConsumerGroup, ConsumerGroupOptions},
+///     options::BasicAckOptions,
+///     Connection, ConnectionProperties,
+/// };
+///
+/// # async_global_executor::block_on(async {
+/// let uri = "amqp://127.0.0.1:5672/%2f";
+/// let mut channels = Vec::new();
+/// for _ in 0..2 {
+///     let connection = Connection::connect(uri, ConnectionProperties::default()).await?;
+///     channels.push(connection.create_channel().await?);
+/// }
+/// let mut group = ConsumerGroup::start(
+///     &channels,
+///     "jobs",
+///     ConsumerGroupOptions::default()
+///         .with_workers(4)
+///         .with_prefetch_budget(40),
+/// )
+/// .await?;
+/// while let Some(delivery) = group.next().await {
+///     delivery?.ack(BasicAckOptions::default()).await?;
+/// }
+/// # Ok::<(), lapin::Error>(())
+/// # });
+/// ```
+///
+/// [`set_delegates`]: #method.set_delegates
+pub struct ConsumerGroup {
+    channels: Vec<Channel>,
+    queue: ShortString,
+    options: ConsumerGroupOptions,
+    workers: Vec<Worker>,
+    next: usize,
+}
+
+struct Worker {
+    channel: Channel,
+    consumer: Consumer,
+    done: bool,
+}
+
+impl ConsumerGroup {
+    /// Start the consumers on `queue`, using the channels in turn
+    ///
+    /// The consumers get the tags generated by the consumer tag strategy of their channel. No
+    /// consumer is started if there is no channel.
+    pub async fn start(
+        channels: &[Channel],
+        queue: &str,
+        options: ConsumerGroupOptions,
+    ) -> Result<Self> {
+        let workers = options.workers.max(1);
+        let mut group = Self {
+            channels: channels.to_vec(),
+            queue: queue.into(),
+            options,
+            workers: Vec::with_capacity(workers),
+            next: 0,
+        };
+        if channels.is_empty() {
+            return Ok(group);
+        }
+        for _ in 0..workers {
+            if let Err(err) = group.start_worker(workers).await {
+                group.cancel().await?;
+                return Err(err);
+            }
+        }
+        Ok(group)
+    }
+
+    /// Start one more consumer, on the next channel in turn
+    ///
+    /// With a prefetch budget, the new consumer gets its share of the budget split between the
+    /// new number of workers, the existing ones keeping theirs, so the group may exceed the
+    /// budget.
+    ///
+    /// The new consumer doesn't have a handler, even if [`set_delegates`] was called.
+    ///
+    /// [`set_delegates`]: #method.set_delegates
+    pub async fn add_worker(&mut self) -> Result<&Consumer> {
+        if self.channels.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the consumer group has no channel",
+            )
+            .into());
+        }
+        self.start_worker(self.workers.len() + 1).await?;
+        Ok(&self.workers[self.workers.len() - 1].consumer)
+    }
+
+    /// Cancel the most recently started consumer and remove it from the group
+    ///
+    /// The deliveries it received which weren't yielded by the group's `Stream` yet get
+    /// requeued, unless the consumer uses `no_ack`. Returns `false` if the group was empty.
+    pub async fn remove_worker(&mut self) -> Result<bool> {
+        let Some(mut worker) = self.workers.pop() else {
+            return Ok(false);
+        };
+        if !worker.consumer.state().is_active() || !worker.channel.status().connected() {
+            return Ok(true);
+        }
+        worker
+            .channel
+            .basic_cancel(
+                worker.consumer.tag().as_str(),
+                BasicCancelOptions::default(),
+            )
+            .await?;
+        if worker.consumer.has_delegate() {
+            return Ok(true);
+        }
+        while let Some(Ok(extra)) = poll_fn(|cx| Pin::new(&mut worker.consumer).poll_next(cx)).await
+        {
+            if !self.options.consume_options.no_ack {
+                extra
+                    .nack(BasicNackOptions {
+                        requeue: true,
+                        ..BasicNackOptions::default()
+                    })
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// The number of consumers in the group
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn queue(&self) -> &ShortString {
+        &self.queue
+    }
+
+    pub(crate) fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    /* Start the consumer at the next index, its prefetch share computed for that many workers */
+    async fn start_worker(&mut self, workers: usize) -> Result<()> {
+        let index = self.workers.len();
+        let channel = self.channels[index % self.channels.len()].clone();
+        if let Some(budget) = self.options.prefetch_budget {
+            // Applies to the consumers started afterwards on this channel
+            channel
+                .basic_qos(
+                    prefetch_share(budget, workers, index),
+                    BasicQosOptions::default(),
+                )
+                .await?;
+        }
+        let consumer = channel
+            .basic_consume(
+                self.queue.as_str(),
+                "",
+                self.options.consume_options,
+                self.options.arguments.clone(),
+            )
+            .await?;
+        debug!(
+            queue=%self.queue,
+            consumer_tag=%consumer.tag(),
+            channel=%channel.id(),
+            "started consumer group worker"
+        );
+        self.workers.push(Worker {
+            channel,
+            consumer,
+            done: false,
+        });
+        Ok(())
+    }
+
+    pub fn consumers(&self) -> impl Iterator<Item = &Consumer> {
+        self.workers.iter().map(|worker| &worker.consumer)
+    }
+
+    pub fn tags(&self) -> Vec<ShortString> {
+        self.consumers().map(Consumer::tag).collect()
+    }
+
+    /// Give each consumer its own handler, built from its index in the group
+    ///
+    /// The deliveries are then no longer available through the group's `Stream`.
+    pub fn set_delegates<D: ConsumerDelegate + 'static, F: FnMut(usize) -> D>(
+        &self,
+        mut make_delegate: F,
+    ) {
+        for (index, worker) in self.workers.iter().enumerate() {
+            worker.consumer.set_delegate(make_delegate(index));
+        }
+    }
+
+    /// Cancel all the consumers which are still active
+    ///
+    /// All of them are canceled even if some fail, the first error being returned.
+    pub async fn cancel(&self) -> Result<()> {
+        let mut res = Ok(());
+        for worker in &self.workers {
+            if !worker.consumer.state().is_active() || !worker.channel.status().connected() {
+                continue;
+            }
+            let canceled = worker
+                .channel
+                .basic_cancel(
+                    worker.consumer.tag().as_str(),
+                    BasicCancelOptions::default(),
+                )
+                .await;
+            if res.is_ok() {
+                res = canceled;
+            }
+        }
+        res
+    }
+}
+
+/* Split the budget evenly, the first workers getting the remainder */
+fn prefetch_share(budget: ShortUInt, workers: usize, index: usize) -> ShortUInt {
+    let budget = usize::from(budget).max(workers);
+    let share = budget / workers + usize::from(index < budget % workers);
+    ShortUInt::try_from(share).unwrap_or(ShortUInt::MAX)
+}
+
+impl Stream for ConsumerGroup {
+    type Item = Result<Delivery>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let workers = this.workers.len();
+        // Start from a different consumer each time so that none of them gets starved
+        for offset in 0..workers {
+            let index = (this.next + offset) % workers;
+            let worker = &mut this.workers[index];
+            if worker.done {
+                continue;
+            }
+            match Pin::new(&mut worker.consumer).poll_next(cx) {
+                Poll::Ready(Some(delivery)) => {
+                    this.next = index + 1;
+                    return Poll::Ready(Some(delivery));
+                }
+                Poll::Ready(None) => worker.done = true,
+                Poll::Pending => {}
+            }
+        }
+        if this.workers.iter().all(|worker| worker.done) {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl fmt::Debug for ConsumerGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConsumerGroup")
+            .field("consumers", &self.tags())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, BasicProperties, ConnectionProperties, ConsumerState,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn prefetch_budget() {
+        let shares = (0..3)
+            .map(|index| prefetch_share(10, 3, index))
+            .collect::<Vec<_>>();
+        assert_eq!(shares, vec![4, 3, 3]);
+        assert_eq!(prefetch_share(1, 2, 1), 1);
+    }
+
+    #[test]
+    fn aggregated_stream() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let first = broker.connect(ConnectionProperties::default()).await?;
+            let second = broker.connect(ConnectionProperties::default()).await?;
+            let channels = vec![
+                first.create_channel().await?,
+                second.create_channel().await?,
+            ];
+            channels[0]
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut group = ConsumerGroup::start(
+                &channels,
+                "jobs",
+                ConsumerGroupOptions::default()
+                    .with_workers(3)
+                    .with_prefetch_budget(6),
+            )
+            .await?;
+            assert_eq!(group.consumers().count(), 3);
+            assert_eq!(channels[0].status().prefetch_count(), Some(2));
+            for payload in [b"a", b"b", b"c", b"d"] {
+                channels[1]
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        payload,
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let mut received = Vec::new();
+            while received.len() < 4 {
+                let delivery = group.next().await.unwrap()?;
+                delivery.ack(BasicAckOptions::default()).await?;
+                received.push(delivery.data.to_vec());
+            }
+            received.sort();
+            assert_eq!(received, vec![b"a", b"b", b"c", b"d"]);
+
+            group.cancel().await?;
+            assert!(group
+                .consumers()
+                .all(|consumer| consumer.state() == ConsumerState::Canceled));
+            assert!(group.next().await.is_none());
+            first.close(0, "").await?;
+            second.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    consumer::{payload_size, ConsumerDelegate, DelegateExecutor},
+    message::DeliveryResult,
+    types::ShortString,
+    FieldTableExt,
+};
+use executor_trait::FullExecutor;
+use flume::Sender;
+use std::{
+    collections::hash_map::DefaultHasher,
+    future::Future,
+    hash::{Hash, Hasher},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+};
+
+type DelegateFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// In which order the delegate of a [`Consumer`] handles its deliveries, set with
+/// [`Consumer::set_ordering`].
+///
+/// This only applies to delegates: the deliveries read from the [`Consumer`] stream always come
+/// in order. Keep in mind that the deliveries waiting behind a slow one still count against
+/// the prefetch count, and thus delay the other ones when it is reached.
+///
+/// [`Consumer`]: ./struct.Consumer.html
+/// [`Consumer::set_ordering`]: ./struct.Consumer.html#method.set_ordering
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ConsumerOrdering {
+    /// Each delivery is handled as soon as it is received, concurrently with the previous ones
+    #[default]
+    Unordered,
+    /// One delivery at a time, in the order in which they were received
+    Strict,
+    /// The deliveries sharing the same routing key are handled one at a time in order, while
+    /// the ones with different routing keys are handled concurrently by this many lanes
+    ByRoutingKey(usize),
+    /// Like [`ByRoutingKey`], with the value of this header as the key, deliveries without it
+    /// sharing the same lane
+    ///
+    /// [`ByRoutingKey`]: #variant.ByRoutingKey
+    ByHeader(ShortString, usize),
+}
+
+impl ConsumerOrdering {
+    fn lanes(&self) -> usize {
+        match self {
+            ConsumerOrdering::Unordered => 0,
+            ConsumerOrdering::Strict => 1,
+            ConsumerOrdering::ByRoutingKey(lanes) | ConsumerOrdering::ByHeader(_, lanes) => {
+                (*lanes).max(1)
+            }
+        }
+    }
+
+    /* The lane of a delivery, the last notification of the consumer waiting for all of them */
+    fn lane(&self, delivery: &DeliveryResult) -> Option<usize> {
+        let Ok(Some(delivery)) = delivery else {
+            return None;
+        };
+        let mut hasher = DefaultHasher::new();
+        match self {
+            ConsumerOrdering::Unordered | ConsumerOrdering::Strict => return Some(0),
+            ConsumerOrdering::ByRoutingKey(_) => delivery.routing_key.hash(&mut hasher),
+            ConsumerOrdering::ByHeader(header, _) => delivery
+                .properties
+                .headers()
+                .as_ref()
+                .and_then(|headers| headers.get_str(header.as_str()))
+                .hash(&mut hasher),
+        }
+        Some((hasher.finish() % self.lanes() as u64) as usize)
+    }
+}
+
+/// The sequential lanes running the futures of a delegate, created with the first delivery
+#[derive(Clone, Default)]
+pub(crate) struct Lanes(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    ordering: ConsumerOrdering,
+    senders: Vec<Sender<DelegateFuture>>,
+    finisher: Option<Arc<Finisher>>,
+    closing: Weak<Finisher>,
+}
+
+/* Spawns the last notifications of the consumer once all the lanes are done */
+struct Finisher {
+    last: Mutex<Vec<DelegateFuture>>,
+    executor: Arc<dyn FullExecutor + Send + Sync>,
+    delegate_executor: DelegateExecutor,
+}
+
+impl Drop for Finisher {
+    fn drop(&mut self) {
+        let last = std::mem::take(self.last.get_mut().unwrap_or_else(|e| e.into_inner()));
+        if !last.is_empty() {
+            self.delegate_executor.spawn(
+                &*self.executor,
+                0,
+                Box::pin(async move {
+                    for future in last {
+                        future.await;
+                    }
+                }),
+            );
+        }
+    }
+}
+
+impl Lanes {
+    /* Only applies to the lanes created afterwards, the current ones finish their deliveries */
+    pub(crate) fn set_ordering(&self, ordering: ConsumerOrdering) {
+        let mut inner = self.lock_inner();
+        inner.ordering = ordering;
+        inner.senders.clear();
+        inner.finisher = None;
+        inner.closing = Weak::new();
+    }
+
+    pub(crate) fn ordering(&self) -> ConsumerOrdering {
+        self.lock_inner().ordering.clone()
+    }
+
+    pub(crate) fn spawn(
+        &self,
+        executor: &Arc<dyn FullExecutor + Send + Sync>,
+        delegate_executor: &DelegateExecutor,
+        delegate: &dyn ConsumerDelegate,
+        delivery: DeliveryResult,
+    ) {
+        let payload_size = payload_size(&delivery);
+        let mut inner = self.lock_inner();
+        let lane = inner.ordering.lane(&delivery);
+        let future = delegate.on_new_delivery(delivery);
+        if inner.ordering == ConsumerOrdering::Unordered {
+            drop(inner);
+            delegate_executor.spawn(&**executor, payload_size, future);
+            return;
+        }
+        let Some(lane) = lane else {
+            // Closing the lanes lets them finish, the last one spawning this future
+            inner.senders.clear();
+            if let Some(finisher) = inner.finisher.take() {
+                inner.closing = Arc::downgrade(&finisher);
+            }
+            match inner.closing.upgrade() {
+                Some(finisher) => {
+                    drop(inner);
+                    finisher
+                        .last
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push(future);
+                }
+                None => {
+                    drop(inner);
+                    delegate_executor.spawn(&**executor, payload_size, future);
+                }
+            }
+            return;
+        };
+        if inner.senders.is_empty() {
+            inner.start(executor, delegate_executor);
+        }
+        if let Err(flume::SendError(future)) = inner.senders[lane].send(future) {
+            drop(inner);
+            delegate_executor.spawn(&**executor, payload_size, future);
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Inner {
+    fn start(
+        &mut self,
+        executor: &Arc<dyn FullExecutor + Send + Sync>,
+        delegate_executor: &DelegateExecutor,
+    ) {
+        let finisher = Arc::new(Finisher {
+            last: Mutex::new(Vec::new()),
+            executor: executor.clone(),
+            delegate_executor: delegate_executor.clone(),
+        });
+        self.senders = (0..self.ordering.lanes())
+            .map(|_| {
+                let (sender, receiver) = flume::unbounded::<DelegateFuture>();
+                let finisher = finisher.clone();
+                delegate_executor.spawn(
+                    &**executor,
+                    0,
+                    Box::pin(async move {
+                        let _finisher = finisher;
+                        while let Ok(future) = receiver.recv_async().await {
+                            future.await;
+                        }
+                    }),
+                );
+                sender
+            })
+            .collect();
+        self.finisher = Some(finisher);
+    }
+}
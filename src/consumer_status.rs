@@ -1,4 +1,4 @@
-use crate::consumer::ConsumerDelegate;
+use crate::consumer::{ConsumerDelegate, DelegateExecutor};
 
 use std::{
     fmt,
@@ -17,6 +17,10 @@ impl ConsumerStatus {
         self.read().delegate()
     }
 
+    pub(crate) fn delegate_executor(&self) -> DelegateExecutor {
+        self.read().delegate_executor()
+    }
+
     pub(crate) fn try_read(&self) -> Option<RwLockReadGuard<'_, ConsumerStatusInner>> {
         self.0.try_read().ok()
     }
@@ -40,8 +44,9 @@ impl fmt::Debug for ConsumerStatus {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum ConsumerState {
+    #[default]
     Active,
     ActiveWithDelegate,
     Canceling,
@@ -57,16 +62,11 @@ impl ConsumerState {
     }
 }
 
-impl Default for ConsumerState {
-    fn default() -> Self {
-        Self::Active
-    }
-}
-
 #[derive(Default)]
 pub(crate) struct ConsumerStatusInner {
     state: ConsumerState,
     delegate: Option<Arc<Box<dyn ConsumerDelegate>>>,
+    delegate_executor: DelegateExecutor,
 }
 
 impl ConsumerStatusInner {
@@ -78,6 +78,14 @@ impl ConsumerStatusInner {
         self.delegate.clone()
     }
 
+    pub(crate) fn delegate_executor(&self) -> DelegateExecutor {
+        self.delegate_executor.clone()
+    }
+
+    pub(crate) fn set_delegate_executor(&mut self, delegate_executor: DelegateExecutor) {
+        self.delegate_executor = delegate_executor;
+    }
+
     pub(crate) fn set_delegate(&mut self, delegate: Option<Arc<Box<dyn ConsumerDelegate>>>) {
         if self.state.is_active() {
             self.state = ConsumerState::ActiveWithDelegate;
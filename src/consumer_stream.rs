@@ -0,0 +1,479 @@
+//! Adapters turning a [`Consumer`] into streams handling the acks for you.
+//!
+//! - [`Consumer::into_acked_stream`] acks the deliveries according to an [`AckPolicy`]
+//! - [`Consumer::map_decoded`] decodes the payload of the deliveries, rejecting the ones which
+//!   cannot be decoded
+//! - [`Consumer::chunks_timeout`] groups the deliveries in batches
+//!
+//! ```rust,no_run
+//! use futures_lite::stream::StreamExt;
+//! use lapin::{
+//!     consumer_stream::AckPolicy, options::*, types::FieldTable, Connection,
+//!     ConnectionProperties,
+//! };
+//!
+//! # async_global_executor::block_on(async {
+//! let connection = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+//! let channel = connection.create_channel().await?;
+//! let mut deliveries = channel
+//!     .basic_consume("jobs", "worker", BasicConsumeOptions::default(), FieldTable::default())
+//!     .await?
+//!     .into_acked_stream(AckPolicy::AtLeastOnce);
+//! while let Some(delivery) = deliveries.next().await {
+//!     // Acked once we ask for the next one
+//!     println!("received {:?}", delivery?.data);
+//! }
+//! # Ok::<(), lapin::Error>(())
+//! # });
+//! ```
+//!
+//! [`Consumer`]: ../struct.Consumer.html
+//! [`Consumer::into_acked_stream`]: ../struct.Consumer.html#method.into_acked_stream
+//! [`Consumer::map_decoded`]: ../struct.Consumer.html#method.map_decoded
+//! [`Consumer::chunks_timeout`]: ../struct.Consumer.html#method.chunks_timeout
+
+use crate::{
+    acker::Acker, message::Delivery, options::BasicAckOptions, reactor::FullReactor, Consumer,
+    ErrorKind, Result,
+};
+use bytes::Bytes;
+use futures_core::stream::Stream;
+use std::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tracing::debug;
+
+type AckFuture = Pin<Box<dyn Future<Output = Result<bool>> + Send>>;
+
+/// When an [`AckedStream`] acks the deliveries it yields
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AckPolicy {
+    /// Ack each delivery before yielding it: a delivery whose handling fails is lost
+    AtMostOnce,
+    /// Ack each delivery once the next one is asked for, or the stream ends: a delivery whose
+    /// handling fails gets redelivered. The last delivery yielded is requeued if the stream is
+    /// dropped instead.
+    AtLeastOnce,
+}
+
+/// Stream of deliveries acked according to an [`AckPolicy`], obtained by calling
+/// [`Consumer::into_acked_stream`].
+///
+/// Deliveries can still be acked, nacked or rejected by hand, the stream then leaves them be.
+///
+/// [`Consumer::into_acked_stream`]: ../struct.Consumer.html#method.into_acked_stream
+pub struct AckedStream {
+    consumer: Consumer,
+    policy: AckPolicy,
+    /* The last delivery yielded with AtLeastOnce, acked when asked for the next one */
+    handling: Option<Acker>,
+    /* The ack in flight, along with the delivery waiting for it with AtMostOnce */
+    acking: Option<(AckFuture, Option<Delivery>)>,
+}
+
+impl AckedStream {
+    pub(crate) fn new(consumer: Consumer, policy: AckPolicy) -> Self {
+        Self {
+            consumer,
+            policy,
+            handling: None,
+            acking: None,
+        }
+    }
+
+    pub fn policy(&self) -> AckPolicy {
+        self.policy
+    }
+
+    fn ack(acker: Acker) -> AckFuture {
+        Box::pin(async move { acker.ack(BasicAckOptions::default()).await })
+    }
+}
+
+impl Stream for AckedStream {
+    type Item = Result<Delivery>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((ack, _)) = this.acking.as_mut() {
+                let Poll::Ready(res) = ack.as_mut().poll(cx) else {
+                    return Poll::Pending;
+                };
+                let delivery = this.acking.take().and_then(|(_, delivery)| delivery);
+                match (res, delivery) {
+                    // The delivery will come again, don't let it get handled twice
+                    (Err(error), _) => return Poll::Ready(Some(Err(error))),
+                    (Ok(_), Some(delivery)) => return Poll::Ready(Some(Ok(delivery))),
+                    (Ok(_), None) => {}
+                }
+            }
+            if let Some(acker) = this.handling.take() {
+                this.acking = Some((Self::ack(acker), None));
+                continue;
+            }
+            let Poll::Ready(next) = Pin::new(&mut this.consumer).poll_next(cx) else {
+                return Poll::Pending;
+            };
+            match (next, this.policy) {
+                (Some(Ok(delivery)), AckPolicy::AtMostOnce) => {
+                    this.acking = Some((Self::ack(delivery.acker.clone()), Some(delivery)));
+                }
+                (Some(Ok(delivery)), AckPolicy::AtLeastOnce) => {
+                    this.handling = Some(delivery.acker.clone());
+                    return Poll::Ready(Some(Ok(delivery)));
+                }
+                (next, _) => return Poll::Ready(next),
+            }
+        }
+    }
+}
+
+impl Drop for AckedStream {
+    fn drop(&mut self) {
+        if let Some(acker) = self.handling.take() {
+            acker.reject_detached(true);
+        }
+        if let Some((_, Some(delivery))) = self.acking.take() {
+            debug!(delivery_tag=%delivery.delivery_tag, "dropping delivery acked but never yielded");
+        }
+    }
+}
+
+impl fmt::Debug for AckedStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AckedStream")
+            .field("consumer", &self.consumer)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+/// A payload [`Consumer::map_decoded`] knows how to decode
+///
+/// [`Consumer::map_decoded`]: ../struct.Consumer.html#method.map_decoded
+pub trait Decode: Sized {
+    type Error: fmt::Display;
+
+    fn decode(delivery: &Delivery) -> std::result::Result<Self, Self::Error>;
+}
+
+impl Decode for Bytes {
+    type Error = std::convert::Infallible;
+
+    fn decode(delivery: &Delivery) -> std::result::Result<Self, Self::Error> {
+        Ok(delivery.data.clone())
+    }
+}
+
+impl Decode for Vec<u8> {
+    type Error = std::convert::Infallible;
+
+    fn decode(delivery: &Delivery) -> std::result::Result<Self, Self::Error> {
+        Ok(delivery.data.to_vec())
+    }
+}
+
+impl Decode for String {
+    type Error = std::str::Utf8Error;
+
+    fn decode(delivery: &Delivery) -> std::result::Result<Self, Self::Error> {
+        std::str::from_utf8(&delivery.data).map(str::to_owned)
+    }
+}
+
+/// A decoded payload, along with its delivery to ack it
+#[derive(Debug)]
+pub struct Decoded<T> {
+    pub value: T,
+    pub delivery: Delivery,
+}
+
+/// Stream of decoded deliveries, obtained by calling [`Consumer::map_decoded`].
+///
+/// A delivery which cannot be decoded is rejected without being requeued, so that it gets dead
+/// lettered if its queue has a dead letter exchange, and yields an [`ErrorKind::InvalidPayload`]
+/// error. The stream goes on after such errors.
+///
+/// [`Consumer::map_decoded`]: ../struct.Consumer.html#method.map_decoded
+/// [`ErrorKind::InvalidPayload`]: ../enum.ErrorKind.html#variant.InvalidPayload
+pub struct DecodedStream<T> {
+    consumer: Consumer,
+    decoded: PhantomData<fn() -> T>,
+}
+
+impl<T: Decode> DecodedStream<T> {
+    pub(crate) fn new(consumer: Consumer) -> Self {
+        Self {
+            consumer,
+            decoded: PhantomData,
+        }
+    }
+}
+
+impl<T: Decode> Stream for DecodedStream<T> {
+    type Item = Result<Decoded<T>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.consumer).poll_next(cx).map(|next| {
+            next.map(|delivery| {
+                let delivery = delivery?;
+                match T::decode(&delivery) {
+                    Ok(value) => Ok(Decoded { value, delivery }),
+                    Err(error) => {
+                        debug!(delivery_tag=%delivery.delivery_tag, %error, "rejecting undecodable delivery");
+                        delivery.acker.reject_detached(false);
+                        Err(ErrorKind::InvalidPayload(error.to_string()).into())
+                    }
+                }
+            })
+        })
+    }
+}
+
+impl<T> fmt::Debug for DecodedStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecodedStream")
+            .field("consumer", &self.consumer)
+            .finish()
+    }
+}
+
+/// Stream of batches of deliveries, obtained by calling [`Consumer::chunks_timeout`].
+///
+/// A batch is yielded once it is full, or once the timeout elapsed since its first delivery.
+/// The deliveries received before an error are yielded before it, and the remaining ones once
+/// the consumer ends. Each delivery of a batch still needs to be acked.
+///
+/// [`Consumer::chunks_timeout`]: ../struct.Consumer.html#method.chunks_timeout
+pub struct ChunksTimeout {
+    consumer: Consumer,
+    reactor: Arc<dyn FullReactor + Send + Sync>,
+    size: usize,
+    timeout: Duration,
+    chunk: Vec<Delivery>,
+    deadline: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    error: Option<crate::Error>,
+    done: bool,
+}
+
+impl ChunksTimeout {
+    pub(crate) fn new(
+        consumer: Consumer,
+        reactor: Arc<dyn FullReactor + Send + Sync>,
+        size: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            consumer,
+            reactor,
+            size: size.max(1),
+            timeout,
+            chunk: Vec::new(),
+            deadline: None,
+            error: None,
+            done: false,
+        }
+    }
+
+    fn flush(&mut self) -> Poll<Option<Result<Vec<Delivery>>>> {
+        self.deadline = None;
+        Poll::Ready(Some(Ok(std::mem::take(&mut self.chunk))))
+    }
+}
+
+impl Stream for ChunksTimeout {
+    type Item = Result<Vec<Delivery>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(error) = this.error.take() {
+            return Poll::Ready(Some(Err(error)));
+        }
+        while !this.done {
+            let Poll::Ready(next) = Pin::new(&mut this.consumer).poll_next(cx) else {
+                break;
+            };
+            match next {
+                Some(Ok(delivery)) => {
+                    if this.chunk.is_empty() {
+                        let (reactor, timeout) = (this.reactor.clone(), this.timeout);
+                        this.deadline = Some(Box::pin(async move { reactor.sleep(timeout).await }));
+                    }
+                    this.chunk.push(delivery);
+                    if this.chunk.len() >= this.size {
+                        return this.flush();
+                    }
+                }
+                Some(Err(error)) if this.chunk.is_empty() => {
+                    return Poll::Ready(Some(Err(error)));
+                }
+                Some(Err(error)) => {
+                    this.error = Some(error);
+                    return this.flush();
+                }
+                None => this.done = true,
+            }
+        }
+        if this.chunk.is_empty() {
+            return if this.done {
+                Poll::Ready(None)
+            } else {
+                Poll::Pending
+            };
+        }
+        if this.done {
+            return this.flush();
+        }
+        match this
+            .deadline
+            .as_mut()
+            .map(|deadline| deadline.as_mut().poll(cx))
+        {
+            Some(Poll::Pending) => Poll::Pending,
+            _ => this.flush(),
+        }
+    }
+}
+
+impl fmt::Debug for ChunksTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunksTimeout")
+            .field("consumer", &self.consumer)
+            .field("size", &self.size)
+            .field("timeout", &self.timeout)
+            .field("pending", &self.chunk.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+    };
+    use futures_lite::stream::StreamExt;
+
+    #[test]
+    fn acked_and_batched() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for i in 0..3u8 {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        &[i],
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let first = connection.create_channel().await?;
+            let mut deliveries = first
+                .basic_consume(
+                    "jobs",
+                    "first",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?
+                .into_acked_stream(AckPolicy::AtLeastOnce);
+            assert_eq!(deliveries.next().await.unwrap()?.data[..], [0]);
+            assert_eq!(deliveries.next().await.unwrap()?.data[..], [1]);
+            // Only the first one got acked
+            drop(deliveries);
+            first.close(200, "OK").await?;
+
+            let mut batches = channel
+                .basic_consume(
+                    "jobs",
+                    "second",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?
+                .chunks_timeout(3, Duration::from_millis(100));
+            let batch = batches.next().await.unwrap()?;
+            let mut payloads = batch
+                .iter()
+                .map(|delivery| delivery.data[0])
+                .collect::<Vec<_>>();
+            payloads.sort();
+            assert_eq!(payloads, [1, 2]);
+            for delivery in batch {
+                delivery.ack(BasicAckOptions::default()).await?;
+            }
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn undecodable_rejected() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for payload in [&[0xff][..], &b"hello"[..]] {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        payload,
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let mut messages = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?
+                .map_decoded::<String>();
+            let error = messages.next().await.unwrap().unwrap_err();
+            assert!(
+                matches!(error.kind(), ErrorKind::InvalidPayload(_)),
+                "{error}"
+            );
+            let message = messages.next().await.unwrap()?;
+            assert_eq!(message.value, "hello");
+            message.delivery.ack(BasicAckOptions::default()).await?;
+            assert_eq!(broker.message_count("jobs"), Some(0));
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use uuid::Uuid;
+
+static NEXT_CONSUMER_ID: AtomicU64 = AtomicU64::new(1);
+
+type TagFn = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// How the consumers started without a consumer tag get one
+///
+/// Set it for a whole connection with [`ConnectionProperties::with_consumer_tag_strategy`], or
+/// for a channel with [`ChannelOptions::with_consumer_tag_strategy`]. Explicit consumer tags
+/// given to `basic_consume` are always used as is.
+///
+/// ```rust
+/// use lapin::ConsumerTagStrategy;
+///
+/// let strategy = ConsumerTagStrategy::PrefixedUuid("billing".into());
+/// assert!(strategy.generate("invoices").starts_with("billing-"));
+/// let strategy = ConsumerTagStrategy::custom(|queue| format!("billing-{}", queue));
+/// assert_eq!(strategy.generate("invoices"), "billing-invoices");
+/// ```
+///
+/// [`ConnectionProperties::with_consumer_tag_strategy`]: ./struct.ConnectionProperties.html#method.with_consumer_tag_strategy
+/// [`ChannelOptions::with_consumer_tag_strategy`]: ./struct.ChannelOptions.html#method.with_consumer_tag_strategy
+#[derive(Clone, Default)]
+pub enum ConsumerTagStrategy {
+    /// Let the broker generate an `amq.ctag-...` tag
+    #[default]
+    Server,
+    /// The prefix followed by a random UUID, such as `billing-67e55044-10b1-426f-9247-bb680e5fe0c8`
+    PrefixedUuid(String),
+    /// The host name and process id followed by a counter, such as `worker-3.4242.1`, to find
+    /// which process a consumer belongs to
+    HostnamePid,
+    /// Compute the tag from the name of the queue
+    Custom(TagFn),
+}
+
+impl ConsumerTagStrategy {
+    pub fn custom<F: Fn(&str) -> String + Send + Sync + 'static>(generate: F) -> Self {
+        Self::Custom(Arc::new(generate))
+    }
+
+    /// Generate a tag for a consumer of `queue`, the empty string letting the broker generate
+    /// it
+    pub fn generate(&self, queue: &str) -> String {
+        match self {
+            Self::Server => String::new(),
+            Self::PrefixedUuid(prefix) => format!("{}-{}", prefix, Uuid::new_v4()),
+            Self::HostnamePid => format!(
+                "{}.{}.{}",
+                hostname(),
+                std::process::id(),
+                NEXT_CONSUMER_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+            Self::Custom(generate) => generate(queue),
+        }
+    }
+}
+
+impl fmt::Debug for ConsumerTagStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Server => f.write_str("Server"),
+            Self::PrefixedUuid(prefix) => f.debug_tuple("PrefixedUuid").field(prefix).finish(),
+            Self::HostnamePid => f.write_str("HostnamePid"),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/* The standard library has no way to get it, so look where it usually is */
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|hostname| hostname.trim().to_owned())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "localhost".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, ChannelOptions, ConnectionProperties,
+    };
+
+    #[test]
+    fn strategies() {
+        assert_eq!(ConsumerTagStrategy::Server.generate("jobs"), "");
+        let tag = ConsumerTagStrategy::PrefixedUuid("app".into()).generate("jobs");
+        assert!(Uuid::parse_str(tag.strip_prefix("app-").unwrap()).is_ok());
+        let first = ConsumerTagStrategy::HostnamePid.generate("jobs");
+        let second = ConsumerTagStrategy::HostnamePid.generate("jobs");
+        assert_ne!(first, second);
+        assert!(first.contains(&format!(".{}.", std::process::id())));
+    }
+
+    #[test]
+    fn channel_overrides_connection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker
+                .connect(ConnectionProperties::default().with_consumer_tag_strategy(
+                    ConsumerTagStrategy::custom(|queue| format!("connection-{}", queue)),
+                ))
+                .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            assert_eq!(consumer.tag().as_str(), "connection-jobs");
+            let consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "explicit",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            assert_eq!(consumer.tag().as_str(), "explicit");
+
+            let channel = connection
+                .create_channel_with(ChannelOptions::default().with_consumer_tag_strategy(
+                    ConsumerTagStrategy::custom(|queue| format!("channel-{}", queue)),
+                ))
+                .await?;
+            let consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            assert_eq!(consumer.tag().as_str(), "channel-jobs");
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
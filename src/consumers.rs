@@ -4,7 +4,7 @@ use crate::{
     message::Delivery,
     topology_internal::ConsumerDefinitionInternal,
     types::{PayloadSize, ShortString},
-    BasicProperties, Error,
+    BasicProperties, DeliveryLatency, Error,
 };
 use std::{
     borrow::Borrow,
@@ -33,6 +33,13 @@ impl Consumers {
         }
     }
 
+    pub(crate) fn get<S: Hash + Eq + ?Sized>(&self, consumer_tag: &S) -> Option<Consumer>
+    where
+        ShortString: Borrow<S>,
+    {
+        self.lock_inner().get(consumer_tag).cloned()
+    }
+
     pub(crate) fn start_cancel_one<S: Hash + Eq + ?Sized>(&self, consumer_tag: &S)
     where
         ShortString: Borrow<S>,
@@ -59,11 +66,12 @@ impl Consumers {
         consumer_tag: &S,
         size: PayloadSize,
         properties: BasicProperties,
+        latency: DeliveryLatency,
     ) where
         ShortString: Borrow<S>,
     {
         if let Some(consumer) = self.lock_inner().get_mut(consumer_tag) {
-            consumer.handle_content_header_frame(size, properties);
+            consumer.handle_content_header_frame(size, properties, latency);
         }
     }
 
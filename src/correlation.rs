@@ -0,0 +1,369 @@
+//! Match the responses of request/response exchanges with their requests, according to their
+//! `correlation_id`.
+//!
+//! Requests are registered in a [`CorrelationMap`] before being published, which gives a
+//! [`PendingResponse`] to wait on. The consumer of the responses hands them to the map, which
+//! wakes up whoever waits on the matching request. Pending requests are forgotten once their
+//! response arrived, once they timed out, or once nobody waits on them anymore, so the map never
+//! grows unbounded.
+//!
+//! ```rust,no_run
+//! use futures_lite::StreamExt;
+//! use lapin::{
+//!     correlation::CorrelationMap, options::*, types::FieldTable, BasicProperties, Connection,
+//!     ConnectionProperties,
+//! };
+//! use std::time::Duration;
+//!
+//! # async_global_executor::block_on(async {
+//! let uri = "amqp://127.0.0.1:5672/%2f";
+//! let connection = Connection::connect(uri, ConnectionProperties::default()).await?;
+//! let channel = connection.create_channel().await?;
+//! let requests = CorrelationMap::new(&channel, 1_000, Duration::from_secs(5));
+//!
+//! let consume_options = BasicConsumeOptions {
+//!     no_ack: true,
+//!     ..BasicConsumeOptions::default()
+//! };
+//! let mut replies = channel
+//!     .basic_consume("amq.rabbitmq.reply-to", "", consume_options, FieldTable::default())
+//!     .await?;
+//! let router = requests.clone();
+//! async_global_executor::spawn(async move {
+//!     while let Some(Ok(reply)) = replies.next().await {
+//!         router.complete_delivery(reply);
+//!     }
+//! })
+//! .detach();
+//!
+//! let response = requests.register("request-1".into())?;
+//! channel
+//!     .basic_publish(
+//!         "",
+//!         "rpc",
+//!         BasicPublishOptions::default(),
+//!         b"ping",
+//!         BasicProperties::default()
+//!             .with_correlation_id("request-1".into())
+//!             .with_reply_to("amq.rabbitmq.reply-to".into()),
+//!     )
+//!     .await?;
+//! match response.await {
+//!     Some(reply) => println!("got {:?}", reply.data),
+//!     None => println!("no reply in time"),
+//! }
+//! # Ok::<(), lapin::Error>(())
+//! # });
+//! ```
+
+use crate::{message::Delivery, types::ShortString, Channel, ErrorKind, Result};
+use flume::{r#async::RecvFut, Receiver, Sender};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tracing::trace;
+
+type Timeout = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// What happened to the requests of a [`CorrelationMap`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CorrelationMetrics {
+    /// The requests currently waiting for their response
+    pub outstanding: usize,
+    /// The requests which got their response
+    pub completed: u64,
+    /// The requests which didn't get their response in time
+    pub expired: u64,
+    /// The responses which didn't match any outstanding request, e.g. because it expired
+    pub unmatched: u64,
+}
+
+/// The requests waiting for their response, by correlation id
+///
+/// At most `capacity` requests can be outstanding at once, each one for at most `ttl`. Clones
+/// share the same requests.
+pub struct CorrelationMap<T = Delivery> {
+    channel: Channel,
+    capacity: usize,
+    ttl: Duration,
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+struct Inner<T> {
+    pending: HashMap<ShortString, Sender<T>>,
+    metrics: CorrelationMetrics,
+}
+
+impl<T: Send + 'static> CorrelationMap<T> {
+    /// Track requests sent on `channel`, which is used to time them out
+    pub fn new(channel: &Channel, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            channel: channel.clone(),
+            capacity,
+            ttl,
+            inner: Arc::new(Mutex::new(Inner {
+                pending: HashMap::default(),
+                metrics: CorrelationMetrics::default(),
+            })),
+        }
+    }
+
+    /// Register a request before sending it, to wait for its response
+    ///
+    /// This fails if `capacity` requests are already outstanding, or if one of them has the
+    /// same correlation id.
+    pub fn register(&self, correlation_id: ShortString) -> Result<PendingResponse<T>> {
+        let (sender, receiver) = flume::bounded(1);
+        {
+            let mut inner = self.lock_inner();
+            if inner.pending.contains_key(&correlation_id) {
+                return Err(ErrorKind::DuplicateCorrelationId(correlation_id).into());
+            }
+            if inner.pending.len() >= self.capacity {
+                return Err(ErrorKind::PendingRequestsLimitReached(self.capacity).into());
+            }
+            inner.pending.insert(correlation_id.clone(), sender);
+            inner.metrics.outstanding = inner.pending.len();
+        }
+        let channel = self.channel.clone();
+        let ttl = self.ttl;
+        Ok(PendingResponse {
+            correlation_id,
+            response: receiver.clone().into_recv_async(),
+            receiver,
+            timeout: Box::pin(async move { channel.sleep(ttl).await }),
+            inner: self.inner.clone(),
+            done: false,
+        })
+    }
+
+    /// Hand the response of a request to whoever waits on it
+    ///
+    /// The response is given back if no outstanding request matches it.
+    pub fn complete(&self, correlation_id: &str, response: T) -> std::result::Result<(), T> {
+        let mut inner = self.lock_inner();
+        let Some(sender) = inner.pending.remove(correlation_id) else {
+            trace!(%correlation_id, "unmatched response");
+            inner.metrics.unmatched += 1;
+            return Err(response);
+        };
+        inner.metrics.outstanding = inner.pending.len();
+        inner.metrics.completed += 1;
+        // The request always waits on it while it's in the map
+        let _ = sender.try_send(response);
+        Ok(())
+    }
+
+    /// Give up on all the outstanding requests, their [`PendingResponse`] resolving to `None`
+    pub fn clear(&self) {
+        let mut inner = self.lock_inner();
+        inner.pending.clear();
+        inner.metrics.outstanding = 0;
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.lock_inner().pending.len()
+    }
+
+    pub fn metrics(&self) -> CorrelationMetrics {
+        self.lock_inner().metrics
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner<T>> {
+        lock(&self.inner)
+    }
+}
+
+impl CorrelationMap<Delivery> {
+    /// Hand a response to whoever waits on the request matching its `correlation_id`
+    ///
+    /// The delivery is given back if it has no correlation id, or if no outstanding request
+    /// matches it, to be acked or rejected.
+    pub fn complete_delivery(&self, delivery: Delivery) -> Option<Delivery> {
+        let Some(correlation_id) = delivery.properties.correlation_id().clone() else {
+            self.lock_inner().metrics.unmatched += 1;
+            return Some(delivery);
+        };
+        self.complete(correlation_id.as_str(), delivery).err()
+    }
+}
+
+impl<T> Clone for CorrelationMap<T> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            capacity: self.capacity,
+            ttl: self.ttl,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for CorrelationMap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CorrelationMap")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("metrics", &lock(&self.inner).metrics)
+            .finish()
+    }
+}
+
+/// The response to a request registered in a [`CorrelationMap`]
+///
+/// Resolves to `None` if the response didn't arrive before the `ttl` of the map, or if the map
+/// got cleared. Dropping it gives up on the request.
+pub struct PendingResponse<T: 'static = Delivery> {
+    correlation_id: ShortString,
+    response: RecvFut<'static, T>,
+    receiver: Receiver<T>,
+    timeout: Timeout,
+    inner: Arc<Mutex<Inner<T>>>,
+    done: bool,
+}
+
+impl<T: 'static> PendingResponse<T> {
+    pub fn correlation_id(&self) -> &ShortString {
+        &self.correlation_id
+    }
+
+    /* Forget about the request, unless its response arrived in the meantime */
+    fn give_up(&mut self) -> bool {
+        self.done = true;
+        let mut inner = lock(&self.inner);
+        let pending = inner.pending.remove(&self.correlation_id).is_some();
+        inner.metrics.outstanding = inner.pending.len();
+        pending
+    }
+}
+
+impl<T: 'static> Future for PendingResponse<T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(response) = Pin::new(&mut self.response).poll(cx) {
+            self.done = true;
+            return Poll::Ready(response.ok());
+        }
+        if self.timeout.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if self.give_up() {
+            trace!(correlation_id=%self.correlation_id, "request expired");
+            lock(&self.inner).metrics.expired += 1;
+            return Poll::Ready(None);
+        }
+        // The response arrived along with the timeout
+        Poll::Ready(self.receiver.try_recv().ok())
+    }
+}
+
+impl<T: 'static> Drop for PendingResponse<T> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.give_up();
+        }
+    }
+}
+
+impl<T: 'static> fmt::Debug for PendingResponse<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingResponse")
+            .field("correlation_id", &self.correlation_id)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+fn lock<T>(inner: &Mutex<Inner<T>>) -> MutexGuard<'_, Inner<T>> {
+    inner.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::{BasicGetOptions, BasicPublishOptions, QueueDeclareOptions},
+        testing::MockBroker,
+        types::FieldTable,
+        BasicProperties, ConnectionProperties,
+    };
+
+    #[test]
+    fn requests() {
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "replies",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let requests = CorrelationMap::new(&channel, 2, Duration::from_millis(50));
+            let answered = requests.register("req-1".into())?;
+            let expiring = requests.register("req-2".into())?;
+            assert_eq!(
+                requests.register("req-1".into()).unwrap_err(),
+                ErrorKind::DuplicateCorrelationId("req-1".into()).into()
+            );
+            assert_eq!(
+                requests.register("req-3".into()).unwrap_err(),
+                ErrorKind::PendingRequestsLimitReached(2).into()
+            );
+
+            for correlation_id in ["req-1", "unknown"] {
+                channel
+                    .basic_publish(
+                        "",
+                        "replies",
+                        BasicPublishOptions::default(),
+                        b"pong",
+                        BasicProperties::default().with_correlation_id(correlation_id.into()),
+                    )
+                    .await?;
+                let reply = channel
+                    .basic_get("replies", BasicGetOptions::default())
+                    .await?
+                    .unwrap();
+                let unmatched = requests.complete_delivery(reply.delivery);
+                assert_eq!(unmatched.is_some(), correlation_id == "unknown");
+            }
+            assert_eq!(&answered.await.unwrap().data[..], b"pong");
+            assert!(expiring.await.is_none());
+
+            // Abandoned requests don't linger either
+            drop(requests.register("req-4".into())?);
+            assert_eq!(
+                requests.metrics(),
+                CorrelationMetrics {
+                    outstanding: 0,
+                    completed: 1,
+                    expired: 1,
+                    unmatched: 1,
+                }
+            );
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
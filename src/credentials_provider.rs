@@ -0,0 +1,132 @@
+//! Fetch the credentials each time a connection is opened, instead of baking them in the uri.
+//!
+//! The provider given to [`ConnectionProperties::with_credentials_provider`] is queried before
+//! the handshake of every connection made with these properties, which includes the reconnections
+//! of a [`Supervisor`]. Rotated passwords, such as the dynamic secrets of Vault, are thus picked
+//! up on the next reconnection.
+//!
+//! ```rust,no_run
+//! use lapin::{
+//!     credentials_provider::FileCredentials, supervisor::Supervisor, ConnectionProperties,
+//! };
+//!
+//! // Kept up to date by a Vault agent
+//! let properties = ConnectionProperties::default()
+//!     .with_credentials_provider(FileCredentials::new("/run/secrets/rabbitmq"));
+//! let supervisor = Supervisor::new("amqp://rabbitmq:5672/%2f", properties);
+//! ```
+//!
+//! [`ConnectionProperties::with_credentials_provider`]: ../struct.ConnectionProperties.html#method.with_credentials_provider
+//! [`Supervisor`]: ../supervisor/struct.Supervisor.html
+
+use crate::{uri::AMQPUserInfo, Result};
+use async_trait::async_trait;
+use std::{future::Future, io, path::PathBuf};
+use zeroize::Zeroizing;
+
+/// A source of credentials, queried before each connection attempt
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    /// The username and password to log in with
+    async fn credentials(&self) -> Result<AMQPUserInfo>;
+}
+
+#[async_trait]
+impl<F, Fut> CredentialsProvider for F
+where
+    F: Fn() -> Fut + Send + Sync,
+    Fut: Future<Output = Result<AMQPUserInfo>> + Send,
+{
+    async fn credentials(&self) -> Result<AMQPUserInfo> {
+        self().await
+    }
+}
+
+/// Read the credentials from a file containing `username:password`, each time they're needed.
+///
+/// Only the first line of the file is used. It is read synchronously, as it is expected to be
+/// small and on a local filesystem.
+#[derive(Clone, Debug)]
+pub struct FileCredentials {
+    path: PathBuf,
+}
+
+impl FileCredentials {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for FileCredentials {
+    async fn credentials(&self) -> Result<AMQPUserInfo> {
+        let content = Zeroizing::new(std::fs::read_to_string(&self.path)?);
+        let (username, password) = content
+            .lines()
+            .next()
+            .and_then(|line| line.split_once(':'))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected username:password in {}", self.path.display()),
+                )
+            })?;
+        Ok(AMQPUserInfo {
+            username: username.into(),
+            password: password.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::MockBroker, ConnectionProperties};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn queried_on_each_connection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let calls = Arc::new(AtomicUsize::new(0));
+            let properties = ConnectionProperties::default().with_credentials_provider({
+                let calls = calls.clone();
+                move || {
+                    let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                    async move {
+                        Ok(AMQPUserInfo {
+                            username: format!("user-{}", call),
+                            password: "secret".into(),
+                        })
+                    }
+                }
+            });
+            for call in 1..=2 {
+                let connection = broker.connect(properties.clone()).await?;
+                assert_eq!(connection.status().username(), format!("user-{}", call));
+                connection.close(0, "").await?;
+            }
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn file_credentials() {
+        let path = std::env::temp_dir().join(format!("lapin-credentials-{}", std::process::id()));
+        let provider = FileCredentials::new(&path);
+        std::fs::write(&path, "rotated:p4ss:word\n").unwrap();
+        let credentials = async_global_executor::block_on(provider.credentials()).unwrap();
+        assert_eq!(credentials.username, "rotated");
+        assert_eq!(credentials.password, "p4ss:word");
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(async_global_executor::block_on(provider.credentials()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{
+    message::Delivery,
+    options::{
+        BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicGetOptions,
+        BasicNackOptions, BasicPublishOptions, BasicQosOptions,
+    },
+    publisher_confirm::Confirmation,
+    types::{AMQPValue, FieldTable, ShortString, ShortUInt},
+    Channel, Consumer, FieldTableExt, Result,
+};
+use tracing::debug;
+
+/* Set on replayed messages, to know how many times they went through the dead letter queue */
+const REPLAY_COUNT_HEADER: &str = "x-replay-count";
+const REPLAYED_FROM_HEADER: &str = "x-replayed-from";
+
+/// How [`DeadLetterConsumers`] consume a queue and its dead letter queue.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeadLetterOptions {
+    /// The dead letter queue, `<queue>.dlq` by default
+    pub dead_letter_queue: Option<ShortString>,
+    /// The prefetch count of both consumers
+    pub prefetch_count: Option<ShortUInt>,
+    pub consume_options: BasicConsumeOptions,
+    pub arguments: FieldTable,
+}
+
+impl DeadLetterOptions {
+    #[must_use]
+    pub fn with_dead_letter_queue(mut self, dead_letter_queue: &str) -> Self {
+        self.dead_letter_queue = Some(dead_letter_queue.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_prefetch_count(mut self, prefetch_count: ShortUInt) -> Self {
+        self.prefetch_count = Some(prefetch_count);
+        self
+    }
+
+    #[must_use]
+    pub fn with_consume_options(mut self, consume_options: BasicConsumeOptions) -> Self {
+        self.consume_options = consume_options;
+        self
+    }
+
+    #[must_use]
+    pub fn with_arguments(mut self, arguments: FieldTable) -> Self {
+        self.arguments = arguments;
+        self
+    }
+}
+
+/// The consumers of a queue and of the queue its rejected or expired messages are dead
+/// lettered to, through the `x-dead-letter-exchange` argument of the queue.
+///
+/// Both consumers share the same channel, and thus the same prefetch count, and are canceled
+/// together. Dead lettered messages can be sent back to the queue they came from with
+/// [`replay`] once the issue which made them fail got fixed.
+///
+/// ```rust,no_run
+/// use futures_lite::StreamExt;
+/// use lapin::{
+///     dead_letter::{DeadLetterConsumers, DeadLetterOptions},
+///     options::BasicAckOptions,
+///     Connection, ConnectionProperties,
+/// };
+///
+/// # async_global_executor::block_on(async {
+/// let uri = "amqp://127.0.0.1:5672/%2f";
+/// let connection = Connection::connect(uri, ConnectionProperties::default()).await?;
+/// let channel = connection.create_channel().await?;
+/// let mut consumers = DeadLetterConsumers::start(
+///     &channel,
+///     "jobs",
+///     DeadLetterOptions::default().with_prefetch_count(10),
+/// )
+/// .await?;
+/// // Once the jobs can be handled again
+/// let replayed = consumers.replay_pending(100).await?;
+/// while let Some(delivery) = consumers.main.next().await {
+///     delivery?.ack(BasicAckOptions::default()).await?;
+/// }
+/// # Ok::<(), lapin::Error>(())
+/// # });
+/// ```
+///
+/// [`replay`]: #method.replay
+#[derive(Clone, Debug)]
+pub struct DeadLetterConsumers {
+    pub main: Consumer,
+    pub dead_letters: Consumer,
+    channel: Channel,
+    queue: ShortString,
+    dead_letter_queue: ShortString,
+}
+
+impl DeadLetterConsumers {
+    /// Start consuming `queue` and its dead letter queue, which must both already exist
+    pub async fn start(channel: &Channel, queue: &str, options: DeadLetterOptions) -> Result<Self> {
+        let dead_letter_queue = options
+            .dead_letter_queue
+            .unwrap_or_else(|| format!("{}.dlq", queue).into());
+        if let Some(prefetch_count) = options.prefetch_count {
+            channel
+                .basic_qos(prefetch_count, BasicQosOptions::default())
+                .await?;
+        }
+        let main = channel
+            .basic_consume(
+                queue,
+                "",
+                options.consume_options,
+                options.arguments.clone(),
+            )
+            .await?;
+        let dead_letters = match channel
+            .basic_consume(
+                dead_letter_queue.as_str(),
+                "",
+                options.consume_options,
+                options.arguments,
+            )
+            .await
+        {
+            Ok(dead_letters) => dead_letters,
+            Err(err) => {
+                channel
+                    .basic_cancel(main.tag().as_str(), BasicCancelOptions::default())
+                    .await?;
+                return Err(err);
+            }
+        };
+        Ok(Self {
+            main,
+            dead_letters,
+            channel: channel.clone(),
+            queue: queue.into(),
+            dead_letter_queue,
+        })
+    }
+
+    pub fn queue(&self) -> &ShortString {
+        &self.queue
+    }
+
+    pub fn dead_letter_queue(&self) -> &ShortString {
+        &self.dead_letter_queue
+    }
+
+    /// Cancel both consumers
+    pub async fn cancel(&self) -> Result<()> {
+        let main = self
+            .channel
+            .basic_cancel(self.main.tag().as_str(), BasicCancelOptions::default())
+            .await;
+        self.channel
+            .basic_cancel(
+                self.dead_letters.tag().as_str(),
+                BasicCancelOptions::default(),
+            )
+            .await?;
+        main
+    }
+
+    /// Publish a dead lettered message back to the queue it was dead lettered from
+    ///
+    /// See [`replay`](./fn.replay.html).
+    pub async fn replay(&self, delivery: &Delivery) -> Result<Confirmation> {
+        replay(
+            &self.channel,
+            delivery,
+            self.dead_letter_queue.as_str(),
+            self.queue.as_str(),
+        )
+        .await
+    }
+
+    /// Replay at most `limit` of the messages waiting in the dead letter queue, returning how
+    /// many were replayed
+    ///
+    /// This stops at the first message the broker refused, which is left in the dead letter
+    /// queue.
+    pub async fn replay_pending(&self, limit: usize) -> Result<usize> {
+        let mut replayed = 0;
+        while replayed < limit {
+            let Some(message) = self
+                .channel
+                .basic_get(self.dead_letter_queue.as_str(), BasicGetOptions::default())
+                .await?
+            else {
+                break;
+            };
+            if self.replay(&message.delivery).await?.is_nack() {
+                break;
+            }
+            replayed += 1;
+        }
+        debug!(queue=%self.queue, replayed, "replayed dead lettered messages");
+        Ok(replayed)
+    }
+}
+
+/// Publish a message received from `dead_letter_queue` back to the queue it was dead lettered
+/// from, and ack it once done
+///
+/// The original queue is the one of the latest `x-death` entry of the message, or
+/// `default_queue` if it has none. The `x-replay-count` header of the message is incremented
+/// and its `x-replayed-from` header is set to the dead letter queue.
+///
+/// When the channel is in confirm mode, the message is only acked once the broker confirmed
+/// the replayed one, and is requeued if it was refused.
+pub async fn replay(
+    channel: &Channel,
+    delivery: &Delivery,
+    dead_letter_queue: &str,
+    default_queue: &str,
+) -> Result<Confirmation> {
+    let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+    let queue = original_queue(&headers).unwrap_or(default_queue).to_owned();
+    headers
+        .entry(REPLAY_COUNT_HEADER)
+        .and_modify(|count| *count += 1)
+        .or_insert(AMQPValue::LongLongInt(1));
+    headers.set_str(REPLAYED_FROM_HEADER, dead_letter_queue);
+    let confirmation = channel
+        .basic_publish(
+            "",
+            &queue,
+            BasicPublishOptions::default(),
+            &delivery.data,
+            delivery.properties.clone().with_headers(headers),
+        )
+        .await?
+        .await?;
+    if confirmation.is_nack() {
+        delivery
+            .nack(BasicNackOptions {
+                requeue: true,
+                ..BasicNackOptions::default()
+            })
+            .await?;
+    } else {
+        delivery.ack(BasicAckOptions::default()).await?;
+    }
+    Ok(confirmation)
+}
+
+/* The broker puts the latest death first */
+fn original_queue(headers: &FieldTable) -> Option<&str> {
+    headers.get_tables("x-death").next()?.get_str("queue")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldArray, BasicProperties, ConnectionProperties,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn paired_consumers() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            for queue in ["jobs", "jobs.dlq", "other"] {
+                channel
+                    .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+                    .await?;
+            }
+            let mut consumers = DeadLetterConsumers::start(
+                &channel,
+                "jobs",
+                DeadLetterOptions::default().with_prefetch_count(5),
+            )
+            .await?;
+            assert_eq!(channel.status().prefetch_count(), Some(5));
+            channel
+                .basic_publish(
+                    "",
+                    "jobs.dlq",
+                    BasicPublishOptions::default(),
+                    b"first",
+                    BasicProperties::default(),
+                )
+                .await?
+                .await?;
+            let delivery = consumers.dead_letters.next().await.unwrap()?;
+            assert!(consumers.replay(&delivery).await?.is_ack());
+            let delivery = consumers.main.next().await.unwrap()?;
+            assert_eq!(&delivery.data[..], b"first");
+            let mut headers = delivery.properties.headers().clone().unwrap();
+            assert_eq!(headers.get_i64(REPLAY_COUNT_HEADER), Some(1));
+            assert_eq!(headers.get_str(REPLAYED_FROM_HEADER), Some("jobs.dlq"));
+
+            consumers.cancel().await?;
+            let mut death = FieldTable::default();
+            death
+                .set_str("queue", "other")
+                .set_str("reason", "rejected");
+            headers.insert(
+                "x-death".into(),
+                AMQPValue::FieldArray(FieldArray::from(vec![AMQPValue::FieldTable(death)])),
+            );
+            channel
+                .basic_publish(
+                    "",
+                    "jobs.dlq",
+                    BasicPublishOptions::default(),
+                    b"second",
+                    BasicProperties::default().with_headers(headers),
+                )
+                .await?
+                .await?;
+            assert_eq!(consumers.replay_pending(10).await?, 1);
+            let message = channel
+                .basic_get("other", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(&message.delivery.data[..], b"second");
+            let headers = message.delivery.properties.headers().clone().unwrap();
+            assert_eq!(headers.get_i64(REPLAY_COUNT_HEADER), Some(2));
+            assert!(channel
+                .basic_get("jobs.dlq", BasicGetOptions::default())
+                .await?
+                .is_none());
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+use crate::types::{AMQPValue, DecimalValue};
+use std::{fmt, str::FromStr};
+
+/// An AMQP decimal: an unsigned mantissa and the number of its digits after the decimal point
+///
+/// The scale is part of the value: `1.5` and `1.50` are different decimals until they're
+/// rescaled to the same scale with [`rescale`].
+///
+/// ```rust
+/// use lapin::Decimal;
+///
+/// let price: Decimal = "12.50".parse().unwrap();
+/// assert_eq!((price.mantissa(), price.scale()), (1250, 2));
+/// assert_eq!(price.to_f64(), 12.5);
+/// assert_eq!(price.checked_add("0.005".parse().unwrap()).unwrap().to_string(), "12.505");
+/// ```
+///
+/// [`rescale`]: #method.rescale
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    mantissa: u32,
+    scale: u8,
+}
+
+impl Decimal {
+    /// `mantissa / 10^scale`
+    pub const fn new(mantissa: u32, scale: u8) -> Self {
+        Self { mantissa, scale }
+    }
+
+    pub fn mantissa(&self) -> u32 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    pub fn to_f64(self) -> f64 {
+        f64::from(self.mantissa) / 10f64.powi(self.scale.into())
+    }
+
+    /// Round a float to `scale` digits after the decimal point
+    ///
+    /// Returns `None` for negative, non finite or too large values.
+    pub fn from_f64(value: f64, scale: u8) -> Option<Self> {
+        let mantissa = (value * 10f64.powi(scale.into())).round();
+        if !mantissa.is_finite() || mantissa < 0.0 || mantissa > f64::from(u32::MAX) {
+            return None;
+        }
+        Some(Self::new(mantissa as u32, scale))
+    }
+
+    /// Change the number of digits after the decimal point
+    ///
+    /// Returns `None` if the mantissa would overflow or if digits would be lost.
+    pub fn rescale(self, scale: u8) -> Option<Self> {
+        let mantissa = if scale >= self.scale {
+            self.mantissa
+                .checked_mul(10u32.checked_pow((scale - self.scale).into())?)?
+        } else {
+            match 10u32.checked_pow((self.scale - scale).into()) {
+                Some(divisor) if self.mantissa % divisor == 0 => self.mantissa / divisor,
+                None if self.mantissa == 0 => 0,
+                _ => return None,
+            }
+        };
+        Some(Self::new(mantissa, scale))
+    }
+
+    /// Add two decimals, using the largest of their scales
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        let scale = self.scale.max(other.scale);
+        let mantissa = self
+            .rescale(scale)?
+            .mantissa
+            .checked_add(other.rescale(scale)?.mantissa)?;
+        Some(Self::new(mantissa, scale))
+    }
+}
+
+impl From<DecimalValue> for Decimal {
+    fn from(decimal: DecimalValue) -> Self {
+        Self::new(decimal.value, decimal.scale)
+    }
+}
+
+impl From<Decimal> for DecimalValue {
+    fn from(decimal: Decimal) -> Self {
+        Self {
+            scale: decimal.scale,
+            value: decimal.mantissa,
+        }
+    }
+}
+
+impl From<Decimal> for AMQPValue {
+    fn from(decimal: Decimal) -> Self {
+        AMQPValue::DecimalValue(decimal.into())
+    }
+}
+
+/// Fails if the scale is above the 28 digits supported by `rust_decimal`
+#[cfg(feature = "rust_decimal")]
+impl TryFrom<Decimal> for rust_decimal::Decimal {
+    type Error = rust_decimal::Error;
+
+    fn try_from(decimal: Decimal) -> Result<Self, Self::Error> {
+        Self::try_new(decimal.mantissa.into(), decimal.scale.into())
+    }
+}
+
+/// Keeps the scale, failing for negative values or if the mantissa doesn't fit in a u32
+#[cfg(feature = "rust_decimal")]
+impl TryFrom<rust_decimal::Decimal> for Decimal {
+    type Error = DecimalOutOfRange;
+
+    fn try_from(decimal: rust_decimal::Decimal) -> Result<Self, Self::Error> {
+        if decimal.is_sign_negative() && !decimal.is_zero() {
+            return Err(DecimalOutOfRange);
+        }
+        let mantissa =
+            u32::try_from(decimal.mantissa().unsigned_abs()).map_err(|_| DecimalOutOfRange)?;
+        let scale = u8::try_from(decimal.scale()).map_err(|_| DecimalOutOfRange)?;
+        Ok(Self::new(mantissa, scale))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.mantissa.to_string();
+        let scale = usize::from(self.scale);
+        if scale == 0 {
+            return f.write_str(&digits);
+        }
+        if digits.len() > scale {
+            let (integer, fraction) = digits.split_at(digits.len() - scale);
+            write!(f, "{}.{}", integer, fraction)
+        } else {
+            write!(f, "0.{:0>width$}", digits, width = scale)
+        }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    /// Parse a non negative decimal number, keeping its number of digits after the decimal point
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if integer.is_empty()
+            || !integer.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(ParseDecimalError);
+        }
+        let scale = u8::try_from(fraction.len()).map_err(|_| ParseDecimalError)?;
+        let mantissa = format!("{}{}", integer, fraction)
+            .parse()
+            .map_err(|_| ParseDecimalError)?;
+        Ok(Self::new(mantissa, scale))
+    }
+}
+
+/// The error returned when a string isn't a valid [`Decimal`]
+///
+/// [`Decimal`]: ./struct.Decimal.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseDecimalError;
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid decimal: expected digits, optionally followed by a dot and digits, fitting in a u32")
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+/// The error returned when a `rust_decimal::Decimal` can't be represented as an AMQP [`Decimal`]
+///
+/// [`Decimal`]: ./struct.Decimal.html
+#[cfg(feature = "rust_decimal")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecimalOutOfRange;
+
+#[cfg(feature = "rust_decimal")]
+impl fmt::Display for DecimalOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "decimal out of range: AMQP decimals are non negative with a mantissa fitting in a u32",
+        )
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl std::error::Error for DecimalOutOfRange {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Decimal::new(5, 3).to_string(), "0.005");
+        assert_eq!(Decimal::new(150, 2).to_string(), "1.50");
+        assert_eq!(Decimal::new(42, 0).to_string(), "42");
+        assert_eq!("0.005".parse(), Ok(Decimal::new(5, 3)));
+        assert_eq!("7".parse(), Ok(Decimal::new(7, 0)));
+        for invalid in ["", ".5", "-1", "1.2.3", "1e3", "4294967296"] {
+            assert_eq!(invalid.parse::<Decimal>(), Err(ParseDecimalError));
+        }
+        assert_eq!(Decimal::from_f64(1.005, 2), Some(Decimal::new(100, 2)));
+        assert_eq!(Decimal::from_f64(2.5, 1), Some(Decimal::new(25, 1)));
+        assert_eq!(Decimal::from_f64(-1.0, 0), None);
+        assert_eq!(Decimal::from_f64(f64::NAN, 0), None);
+        assert_eq!(Decimal::from_f64(1e10, 0), None);
+        assert_eq!(
+            DecimalValue::from(Decimal::new(150, 2)),
+            DecimalValue {
+                scale: 2,
+                value: 150
+            }
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(Decimal::new(15, 1).rescale(3), Some(Decimal::new(1500, 3)));
+        assert_eq!(Decimal::new(1500, 3).rescale(1), Some(Decimal::new(15, 1)));
+        assert_eq!(Decimal::new(1505, 3).rescale(1), None);
+        assert_eq!(Decimal::new(u32::MAX, 0).rescale(1), None);
+        assert_eq!(Decimal::new(1, 0).rescale(20), None);
+        assert_eq!(Decimal::new(0, 20).rescale(0), Some(Decimal::new(0, 0)));
+        assert_eq!(Decimal::new(u32::MAX, 20).rescale(0), None);
+        assert_eq!(
+            Decimal::new(15, 1).checked_add(Decimal::new(25, 2)),
+            Some(Decimal::new(175, 2))
+        );
+        assert_eq!(
+            Decimal::new(u32::MAX, 0).checked_add(Decimal::new(1, 0)),
+            None
+        );
+    }
+
+    #[cfg(feature = "rust_decimal")]
+    #[test]
+    fn rust_decimal() {
+        let price = rust_decimal::Decimal::try_from(Decimal::new(1250, 2)).unwrap();
+        assert_eq!(price, rust_decimal::Decimal::new(1250, 2));
+        assert_eq!(price.to_string(), "12.50");
+        assert_eq!(Decimal::try_from(price), Ok(Decimal::new(1250, 2)));
+        assert!(rust_decimal::Decimal::try_from(Decimal::new(1, 29)).is_err());
+        assert_eq!(
+            Decimal::try_from(rust_decimal::Decimal::new(-1, 0)),
+            Err(DecimalOutOfRange)
+        );
+        assert_eq!(
+            Decimal::try_from(rust_decimal::Decimal::new(i64::from(u32::MAX) + 1, 0)),
+            Err(DecimalOutOfRange)
+        );
+        let mut negative_zero = rust_decimal::Decimal::new(0, 3);
+        negative_zero.set_sign_negative(true);
+        assert_eq!(Decimal::try_from(negative_zero), Ok(Decimal::new(0, 3)));
+    }
+}
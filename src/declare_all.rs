@@ -0,0 +1,284 @@
+use crate::{
+    options::{ExchangeBindOptions, QueueBindOptions},
+    queue::Queue,
+    topology::{
+        BindingDefinition, Declaration, DeclareFailure, DeclareReport, ExchangeDefinition,
+        QueueDefinition, TopologyDefinition,
+    },
+    Channel, Connection, Pipeline, Result,
+};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+use tracing::{debug, trace};
+
+/* One step of the declaration, each one depending on the previous ones */
+type Step<'a> = Vec<DeclareOp<'a>>;
+
+enum DeclareOp<'a> {
+    Exchange(&'a ExchangeDefinition),
+    ExchangeBinding(&'a ExchangeDefinition, &'a BindingDefinition),
+    Queue(&'a QueueDefinition),
+    QueueBinding(&'a QueueDefinition, &'a BindingDefinition),
+}
+
+impl DeclareOp<'_> {
+    fn declaration(&self) -> Declaration {
+        match self {
+            DeclareOp::Exchange(ex) => Declaration::Exchange(ex.name.clone()),
+            DeclareOp::ExchangeBinding(ex, binding) => Declaration::ExchangeBinding {
+                destination: ex.name.clone(),
+                source: binding.source.clone(),
+                routing_key: binding.routing_key.clone(),
+            },
+            DeclareOp::Queue(queue) => Declaration::Queue(queue.name.clone()),
+            DeclareOp::QueueBinding(queue, binding) => Declaration::QueueBinding {
+                queue: queue.name.clone(),
+                exchange: binding.source.clone(),
+                routing_key: binding.routing_key.clone(),
+            },
+        }
+    }
+
+    async fn run(&self, channel: &Channel) -> Result<Option<Queue>> {
+        match self {
+            DeclareOp::Exchange(ex) => channel
+                .exchange_declare(
+                    ex.name.as_str(),
+                    ex.kind.clone().unwrap_or_default(),
+                    ex.options.unwrap_or_default(),
+                    ex.arguments.clone().unwrap_or_default(),
+                )
+                .await
+                .map(|()| None),
+            DeclareOp::ExchangeBinding(ex, binding) => channel
+                .exchange_bind(
+                    ex.name.as_str(),
+                    binding.source.as_str(),
+                    binding.routing_key.as_str(),
+                    ExchangeBindOptions::default(),
+                    binding.arguments.clone(),
+                )
+                .await
+                .map(|()| None),
+            DeclareOp::Queue(queue) => channel
+                .queue_declare(
+                    queue.name.as_str(),
+                    queue.options.unwrap_or_default(),
+                    queue.arguments.clone().unwrap_or_default(),
+                )
+                .await
+                .map(Some),
+            DeclareOp::QueueBinding(queue, binding) => channel
+                .queue_bind(
+                    queue.name.as_str(),
+                    binding.source.as_str(),
+                    binding.routing_key.as_str(),
+                    QueueBindOptions::default(),
+                    binding.arguments.clone(),
+                )
+                .await
+                .map(|()| None),
+        }
+    }
+}
+
+pub(crate) async fn declare_all(
+    connection: &Connection,
+    topology: &TopologyDefinition,
+    parallelism: usize,
+) -> Result<DeclareReport> {
+    // Exchanges only known through their bindings are expected to already exist
+    let steps: [Step<'_>; 4] = [
+        topology
+            .exchanges
+            .iter()
+            .filter(|ex| ex.kind.is_some())
+            .map(DeclareOp::Exchange)
+            .collect(),
+        topology
+            .exchanges
+            .iter()
+            .flat_map(|ex| {
+                ex.bindings
+                    .iter()
+                    .map(move |binding| DeclareOp::ExchangeBinding(ex, binding))
+            })
+            .collect(),
+        topology.queues.iter().map(DeclareOp::Queue).collect(),
+        topology
+            .queues
+            .iter()
+            .flat_map(|queue| {
+                queue
+                    .bindings
+                    .iter()
+                    .map(move |binding| DeclareOp::QueueBinding(queue, binding))
+            })
+            .collect(),
+    ];
+    let mut channels = vec![None; parallelism.max(1)];
+    let mut report = DeclareReport::default();
+    let mut res = Ok(());
+    for step in &steps {
+        if let Err(err) = run_step(connection, step, &mut channels, &mut report).await {
+            res = Err(err);
+            break;
+        }
+    }
+    for channel in channels.into_iter().flatten() {
+        if channel.status().connected() {
+            let _ = channel.close(200, "OK").await;
+        }
+    }
+    res.map(|()| report)
+}
+
+async fn run_step(
+    connection: &Connection,
+    step: &[DeclareOp<'_>],
+    channels: &mut [Option<Channel>],
+    report: &mut DeclareReport,
+) -> Result<()> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..step.len()).map(|_| None).collect::<Vec<_>>());
+    channels
+        .iter_mut()
+        .map(|channel| worker(connection, step, channel, &next, &results))
+        .collect::<Pipeline<'_, _>>()
+        .await?;
+    let results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    for (op, res) in step.iter().zip(results) {
+        match res {
+            Some(Ok(queue)) => {
+                report.declared.push(op.declaration());
+                report.queues.extend(queue);
+            }
+            Some(Err(error)) => {
+                debug!(declaration=?op.declaration(), %error, "declaration failed");
+                report.failures.push(DeclareFailure {
+                    declaration: op.declaration(),
+                    error,
+                });
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/* Runs the declarations one at a time on its own channel, replacing it when one fails */
+async fn worker(
+    connection: &Connection,
+    step: &[DeclareOp<'_>],
+    channel: &mut Option<Channel>,
+    next: &AtomicUsize,
+    results: &Mutex<Vec<Option<Result<Option<Queue>>>>>,
+) -> Result<()> {
+    loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let Some(op) = step.get(index) else {
+            return Ok(());
+        };
+        let current = match channel.as_ref().filter(|c| c.status().connected()) {
+            Some(current) => current.clone(),
+            None => {
+                trace!("opening a channel to declare the topology");
+                let current = connection.create_channel().await?;
+                *channel = Some(current.clone());
+                current
+            }
+        };
+        let res = op.run(&current).await;
+        results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::BasicPublishOptions, testing::MockBroker, types::ShortString, BasicProperties,
+        ConnectionProperties, ExchangeKind,
+    };
+
+    fn binding(source: &str) -> BindingDefinition {
+        BindingDefinition {
+            source: source.into(),
+            routing_key: ShortString::default(),
+            arguments: Default::default(),
+        }
+    }
+
+    #[test]
+    fn declare_topology() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let exchange = |name: &str| ExchangeDefinition {
+                name: name.into(),
+                kind: Some(ExchangeKind::Fanout),
+                ..ExchangeDefinition::default()
+            };
+            let topology = TopologyDefinition {
+                exchanges: vec![exchange("events"), exchange("amq.reserved")],
+                queues: (0..20)
+                    .map(|i| QueueDefinition {
+                        name: format!("queue-{i}").into(),
+                        bindings: if i == 0 {
+                            vec![binding("missing"), binding("events")]
+                        } else {
+                            vec![binding("events")]
+                        },
+                        ..QueueDefinition::default()
+                    })
+                    .collect(),
+                channels: Vec::new(),
+            };
+
+            let report = connection.declare_all(topology, 4).await?;
+            assert!(!report.is_success());
+            assert_eq!(
+                report
+                    .failures()
+                    .iter()
+                    .map(|failure| failure.declaration.clone())
+                    .collect::<Vec<_>>(),
+                vec![
+                    Declaration::Exchange("amq.reserved".into()),
+                    Declaration::QueueBinding {
+                        queue: "queue-0".into(),
+                        exchange: "missing".into(),
+                        routing_key: ShortString::default(),
+                    },
+                ]
+            );
+            assert!(report.failures()[1].error.is_amqp_soft_error());
+            assert_eq!(report.declared().len(), 41);
+            assert_eq!(report.declared()[0], Declaration::Exchange("events".into()));
+            assert_eq!(report.queues().len(), 20);
+            assert_eq!(report.queues()[3].name().as_str(), "queue-3");
+
+            // Bindings declared after the failed one on the same channel went through
+            let channel = connection.create_channel().await?;
+            channel
+                .basic_publish(
+                    "events",
+                    "",
+                    BasicPublishOptions::default(),
+                    b"event",
+                    BasicProperties::default(),
+                )
+                .await?
+                .await?;
+            for i in 0..20 {
+                assert_eq!(broker.message_count(&format!("queue-{i}")), Some(1));
+            }
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    options::{ExchangeDeclareOptions, QueueDeclareOptions},
+    queue::Queue,
+    types::{FieldTable, ShortString},
+    ExchangeKind,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/* The declares already sent on a channel, to skip identical ones */
+#[derive(Clone, Default)]
+pub(crate) struct DeclareCache(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    enabled: bool,
+    queues: HashMap<ShortString, (QueueDeclareOptions, FieldTable, Queue)>,
+    exchanges: HashMap<ShortString, (ExchangeKind, ExchangeDeclareOptions, FieldTable)>,
+}
+
+impl DeclareCache {
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        let mut inner = self.lock_inner();
+        inner.enabled = enabled;
+        if !enabled {
+            inner.queues.clear();
+            inner.exchanges.clear();
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.lock_inner().enabled
+    }
+
+    pub(crate) fn queue(
+        &self,
+        queue: &str,
+        options: &QueueDeclareOptions,
+        arguments: &FieldTable,
+    ) -> Option<Queue> {
+        let inner = self.lock_inner();
+        inner
+            .queues
+            .get(queue)
+            .filter(|(cached_options, cached_arguments, _)| {
+                cached_options == options && cached_arguments == arguments
+            })
+            .map(|(_, _, queue)| queue.clone())
+    }
+
+    pub(crate) fn register_queue(
+        &self,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+        queue: Queue,
+    ) {
+        let mut inner = self.lock_inner();
+        if inner.enabled {
+            inner
+                .queues
+                .insert(queue.name().clone(), (options, arguments, queue));
+        }
+    }
+
+    pub(crate) fn exchange(
+        &self,
+        exchange: &str,
+        kind: &ExchangeKind,
+        options: &ExchangeDeclareOptions,
+        arguments: &FieldTable,
+    ) -> bool {
+        self.lock_inner().exchanges.get(exchange).is_some_and(
+            |(cached_kind, cached_options, cached_arguments)| {
+                cached_kind == kind && cached_options == options && cached_arguments == arguments
+            },
+        )
+    }
+
+    pub(crate) fn register_exchange(
+        &self,
+        exchange: &str,
+        kind: ExchangeKind,
+        options: ExchangeDeclareOptions,
+        arguments: FieldTable,
+    ) {
+        let mut inner = self.lock_inner();
+        if inner.enabled {
+            inner
+                .exchanges
+                .insert(exchange.into(), (kind, options, arguments));
+        }
+    }
+
+    pub(crate) fn forget_queue(&self, queue: &str) {
+        self.lock_inner().queues.remove(queue);
+    }
+
+    pub(crate) fn forget_exchange(&self, exchange: &str) {
+        self.lock_inner().exchanges.remove(exchange);
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut inner = self.lock_inner();
+        inner.queues.clear();
+        inner.exchanges.clear();
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for DeclareCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("DeclareCache");
+        if let Ok(inner) = self.0.try_lock() {
+            debug
+                .field("enabled", &inner.enabled)
+                .field("queues", &inner.queues.keys())
+                .field("exchanges", &inner.exchanges.keys());
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, ChannelOptions, ConnectionProperties,
+        ExchangeKind,
+    };
+
+    #[test]
+    fn skips_identical_declares() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let cached = connection
+                .create_channel_with(ChannelOptions::default().with_declare_cache())
+                .await?;
+            let other = connection.create_channel().await?;
+            let options = QueueDeclareOptions {
+                durable: true,
+                ..QueueDeclareOptions::default()
+            };
+
+            cached
+                .queue_declare("jobs", options, FieldTable::default())
+                .await?;
+            other
+                .queue_delete("jobs", QueueDeleteOptions::default())
+                .await?;
+            // Identical, so not sent again
+            let queue = cached
+                .queue_declare("jobs", options, FieldTable::default())
+                .await?;
+            assert_eq!(queue.name().as_str(), "jobs");
+            assert!(!broker.queue_exists("jobs"));
+            // Different options are sent
+            cached
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            assert!(broker.queue_exists("jobs"));
+            other
+                .queue_delete("jobs", QueueDeleteOptions::default())
+                .await?;
+            // Forced declares are always sent
+            cached
+                .queue_declare_forced("jobs", options, FieldTable::default())
+                .await?;
+            assert!(broker.queue_exists("jobs"));
+            // Deleting through the channel forgets the declare
+            cached
+                .queue_delete("jobs", QueueDeleteOptions::default())
+                .await?;
+            cached
+                .queue_declare("jobs", options, FieldTable::default())
+                .await?;
+            assert!(broker.queue_exists("jobs"));
+
+            cached
+                .exchange_declare(
+                    "events",
+                    ExchangeKind::Topic,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            other
+                .exchange_delete("events", ExchangeDeleteOptions::default())
+                .await?;
+            cached
+                .exchange_declare(
+                    "events",
+                    ExchangeKind::Topic,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            assert!(!broker.exchange_exists("events"));
+            cached.set_declare_cache(false);
+            cached
+                .exchange_declare(
+                    "events",
+                    ExchangeKind::Topic,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            assert!(broker.exchange_exists("events"));
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Where a consumer remembers the ids of the messages it already handled, set with
+/// [`Consumer::set_deduplication`].
+///
+/// [`DedupWindow`] keeps them in memory, a shared store such as a database lets several
+/// consumers deduplicate the messages together.
+///
+/// [`Consumer::set_deduplication`]: ./struct.Consumer.html#method.set_deduplication
+pub trait DedupStore: Send + Sync {
+    /// Whether the message with this id was already handled
+    fn contains(&self, message_id: &str) -> bool;
+
+    /// Remember that the message with this id has been handled
+    fn insert(&self, message_id: &str);
+}
+
+impl<S: DedupStore + ?Sized> DedupStore for Arc<S> {
+    fn contains(&self, message_id: &str) -> bool {
+        (**self).contains(message_id)
+    }
+
+    fn insert(&self, message_id: &str) {
+        (**self).insert(message_id)
+    }
+}
+
+/// An in-memory [`DedupStore`] remembering at most `capacity` message ids, for at most `ttl`
+///
+/// The least recently handled ids are forgotten first.
+pub struct DedupWindow {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /* When each id was last handled */
+    handled: HashMap<String, Instant>,
+    /* The ids in the order they were handled, including the stale entries of re-handled ones */
+    order: VecDeque<(String, Instant)>,
+}
+
+impl DedupWindow {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// How many message ids are currently remembered
+    pub fn len(&self) -> usize {
+        let mut inner = self.lock_inner();
+        inner.expire(self.ttl);
+        inner.handled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl DedupStore for DedupWindow {
+    fn contains(&self, message_id: &str) -> bool {
+        let mut inner = self.lock_inner();
+        inner.expire(self.ttl);
+        inner.handled.contains_key(message_id)
+    }
+
+    fn insert(&self, message_id: &str) {
+        let mut inner = self.lock_inner();
+        inner.expire(self.ttl);
+        let now = Instant::now();
+        inner.handled.insert(message_id.to_owned(), now);
+        inner.order.push_back((message_id.to_owned(), now));
+        while inner.handled.len() > self.capacity {
+            inner.pop_oldest();
+        }
+        // Don't let the ids handled over and over pile up
+        if inner.order.len() > 2 * self.capacity {
+            let Inner { handled, order } = &mut *inner;
+            order.retain(|(id, at)| handled.get(id) == Some(at));
+        }
+    }
+}
+
+impl Inner {
+    fn expire(&mut self, ttl: Duration) {
+        while self
+            .order
+            .front()
+            .is_some_and(|(_, handled)| handled.elapsed() >= ttl)
+        {
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((id, handled)) = self.order.pop_front() {
+            // Only forget it if it wasn't handled again since
+            if self.handled.get(&id) == Some(&handled) {
+                self.handled.remove(&id);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DedupWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DedupWindow")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+/* Remembers the message id of a delivery when it gets acked */
+#[derive(Clone)]
+pub(crate) struct DedupMark {
+    store: Arc<dyn DedupStore>,
+    message_id: String,
+}
+
+impl DedupMark {
+    pub(crate) fn new(store: Arc<dyn DedupStore>, message_id: String) -> Self {
+        Self { store, message_id }
+    }
+
+    pub(crate) fn mark(&self) {
+        self.store.insert(&self.message_id);
+    }
+}
+
+impl fmt::Debug for DedupMark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DedupMark").field(&self.message_id).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window() {
+        let window = DedupWindow::new(2, Duration::from_millis(50));
+        assert!(!window.contains("a"));
+        window.insert("a");
+        window.insert("b");
+        assert!(window.contains("a"));
+        // Handling "a" again keeps it around longer than "b"
+        window.insert("a");
+        window.insert("c");
+        assert!(window.contains("a"));
+        assert!(!window.contains("b"));
+        assert!(window.contains("c"));
+        assert_eq!(window.len(), 2);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!window.contains("a"));
+        assert!(window.is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{
+    message::Delivery,
+    options::{
+        BasicAckOptions, BasicNackOptions, BasicPublishOptions, ExchangeDeclareOptions,
+        QueueBindOptions, QueueDeclareOptions,
+    },
+    publisher_confirm::Confirmation,
+    types::{AMQPValue, FieldTable, ShortString},
+    Backoff, Channel, ExchangeKind, FieldTableExt, Result,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tracing::debug;
+
+/* Incremented each time a message is delayed */
+const RETRY_COUNT_HEADER: &str = "x-retry-count";
+const DEFAULT_PREFIX: &str = "lapin.delay";
+
+/// How many times a message went through [`DelayedRetry`], from its `x-retry-count` header.
+///
+/// [`DelayedRetry`]: ./struct.DelayedRetry.html
+pub fn retry_count(delivery: &Delivery) -> u32 {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.get_i64(RETRY_COUNT_HEADER))
+        .and_then(|count| u32::try_from(count).ok())
+        .unwrap_or(0)
+}
+
+/// Send messages back to a queue after a delay, instead of sleeping before nacking them and
+/// holding a prefetch slot meanwhile.
+///
+/// A delayed message is republished to a wait queue whose `x-message-ttl` is the delay, and
+/// which dead letters expired messages to the default exchange. The message keeps the consumed
+/// queue as routing key, and thus goes back to it once the delay elapsed. The original delivery
+/// is then acked, or requeued if the broker refused the delayed message when the channel is in
+/// confirm mode.
+///
+/// There is one wait queue per delay, named `lapin.delay.<milliseconds>`, with a fanout exchange
+/// of the same name in front of it. They are declared the first time a delay is used, so the
+/// delays should come from a small set, which is what [`nack_with_backoff`] does.
+///
+/// ```rust,no_run
+/// use futures_lite::stream::StreamExt;
+/// use lapin::{
+///     delayed_retry::DelayedRetry, options::BasicConsumeOptions, types::FieldTable, Backoff,
+///     Connection, ConnectionProperties,
+/// };
+/// use std::time::Duration;
+///
+/// # async_global_executor::block_on(async {
+/// let uri = "amqp://127.0.0.1:5672/%2f";
+/// let connection = Connection::connect(uri, ConnectionProperties::default()).await?;
+/// let channel = connection.create_channel().await?;
+/// let retry = DelayedRetry::new(channel.clone(), "jobs")
+///     .with_backoff(Backoff::new(Duration::from_secs(1), Duration::from_secs(60)));
+/// let mut consumer = channel
+///     .basic_consume("jobs", "worker", BasicConsumeOptions::default(), FieldTable::default())
+///     .await?;
+/// while let Some(delivery) = consumer.next().await {
+///     let delivery = delivery?;
+///     // Processing failed: try again later
+///     retry.nack_with_backoff(&delivery).await?;
+/// }
+/// # Ok::<(), lapin::Error>(())
+/// # });
+/// ```
+///
+/// [`nack_with_backoff`]: ./struct.DelayedRetry.html#method.nack_with_backoff
+#[derive(Clone, Debug)]
+pub struct DelayedRetry {
+    channel: Channel,
+    queue: ShortString,
+    prefix: ShortString,
+    backoff: Backoff,
+    declared: Arc<Mutex<HashSet<u64>>>,
+}
+
+impl DelayedRetry {
+    /// Delay the messages consumed from `queue` through `channel`
+    pub fn new(channel: Channel, queue: &str) -> Self {
+        Self {
+            channel,
+            queue: queue.into(),
+            prefix: DEFAULT_PREFIX.into(),
+            backoff: Backoff::default(),
+            declared: Arc::default(),
+        }
+    }
+
+    /// The policy used by [`nack_with_backoff`], 100ms doubling up to 30s by default
+    ///
+    /// [`nack_with_backoff`]: ./struct.DelayedRetry.html#method.nack_with_backoff
+    #[must_use]
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Name the wait queues and exchanges `<prefix>.<milliseconds>` instead of
+    /// `lapin.delay.<milliseconds>`
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn queue(&self) -> &ShortString {
+        &self.queue
+    }
+
+    /// Send the message back to the queue once `delay` elapsed, and ack it
+    ///
+    /// Its `x-retry-count` header gets incremented. A zero delay republishes it to the queue
+    /// straight away.
+    pub async fn nack_with_delay(
+        &self,
+        delivery: &Delivery,
+        delay: Duration,
+    ) -> Result<Confirmation> {
+        let millis = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+        let exchange = if millis == 0 {
+            ShortString::from("")
+        } else {
+            self.declare(millis).await?
+        };
+        let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+        headers
+            .entry(RETRY_COUNT_HEADER)
+            .and_modify(|count| *count += 1)
+            .or_insert(AMQPValue::LongLongInt(1));
+        let confirmation = self
+            .channel
+            .basic_publish(
+                exchange.as_str(),
+                self.queue.as_str(),
+                BasicPublishOptions::default(),
+                &delivery.data,
+                delivery.properties.clone().with_headers(headers),
+            )
+            .await?
+            .await?;
+        if confirmation.is_nack() {
+            delivery
+                .nack(BasicNackOptions {
+                    requeue: true,
+                    ..BasicNackOptions::default()
+                })
+                .await?;
+        } else {
+            delivery.ack(BasicAckOptions::default()).await?;
+        }
+        Ok(confirmation)
+    }
+
+    /// Send the message back to the queue after the delay the backoff policy gives for its
+    /// [`retry_count`], and ack it
+    ///
+    /// [`retry_count`]: ./fn.retry_count.html
+    pub async fn nack_with_backoff(&self, delivery: &Delivery) -> Result<Confirmation> {
+        let delay = self.backoff.delay(retry_count(delivery));
+        self.nack_with_delay(delivery, delay).await
+    }
+
+    async fn declare(&self, millis: u64) -> Result<ShortString> {
+        let name = ShortString::from(format!("{}.{}", self.prefix, millis));
+        if self.lock_declared().contains(&millis) {
+            return Ok(name);
+        }
+        self.channel
+            .exchange_declare(
+                name.as_str(),
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        let mut arguments = FieldTable::default();
+        arguments
+            .set_i64("x-message-ttl", i64::try_from(millis).unwrap_or(i64::MAX))
+            .set_str("x-dead-letter-exchange", "");
+        self.channel
+            .queue_declare(
+                name.as_str(),
+                QueueDeclareOptions {
+                    durable: true,
+                    ..QueueDeclareOptions::default()
+                },
+                arguments,
+            )
+            .await?;
+        self.channel
+            .queue_bind(
+                name.as_str(),
+                name.as_str(),
+                "",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        debug!(wait_queue=%name, "declared delayed retry topology");
+        self.lock_declared().insert(millis);
+        Ok(name)
+    }
+
+    fn lock_declared(&self) -> MutexGuard<'_, HashSet<u64>> {
+        self.declared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::*, testing::MockBroker, BasicProperties, ConnectionProperties};
+
+    #[test]
+    fn delay_through_wait_queue() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default().with_app_id("shop".into()),
+                )
+                .await?;
+            let retry = DelayedRetry::new(channel.clone(), "jobs")
+                .with_backoff(Backoff::new(Duration::from_secs(1), Duration::from_secs(4)));
+
+            let message = channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(retry_count(&message.delivery), 0);
+            assert!(!retry.nack_with_backoff(&message.delivery).await?.is_nack());
+
+            let delayed = channel
+                .basic_get("lapin.delay.1000", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(delayed.delivery.routing_key.as_str(), "jobs");
+            assert_eq!(&delayed.delivery.data[..], b"job");
+            assert_eq!(delayed.delivery.properties.app_id(), &Some("shop".into()));
+            assert_eq!(retry_count(&delayed.delivery), 1);
+            // MockBroker doesn't expire messages: send it back to the queue straight away
+            assert!(!retry
+                .nack_with_delay(&delayed.delivery, Duration::ZERO)
+                .await?
+                .is_nack());
+
+            let message = channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(retry_count(&message.delivery), 2);
+            retry.nack_with_backoff(&message.delivery).await?;
+            let delayed = channel
+                .basic_get("lapin.delay.4000", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(retry_count(&delayed.delivery), 3);
+            assert_eq!(retry.lock_declared().len(), 2);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    acker::Acker,
+    consumer_status::ConsumerStatus,
+    error_handler::ErrorHandler,
+    internal_rpc::InternalRPCHandle,
+    message::DeliveryResult,
+    types::{ChannelId, DeliveryTag, ShortString},
+    ErrorKind,
+};
+use std::{
+    any::Any,
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+};
+use tracing::error;
+
+type DelegateFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// What happens when the delegate of a [`Consumer`] panics, set with
+/// [`Consumer::set_panic_policy`].
+///
+/// Unless the panic is propagated, it gets reported through [`Channel::on_error`] as an
+/// [`ErrorKind::DelegatePanicked`].
+///
+/// [`Consumer`]: ./struct.Consumer.html
+/// [`Consumer::set_panic_policy`]: ./struct.Consumer.html#method.set_panic_policy
+/// [`Channel::on_error`]: ./struct.Channel.html#method.on_error
+/// [`ErrorKind::DelegatePanicked`]: ./enum.ErrorKind.html#variant.DelegatePanicked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Let the panic unwind into the executor running the delegate
+    Propagate,
+    /// Reject the delivery, requeuing it if `requeue` is set, and keep consuming
+    Reject { requeue: bool },
+    /// Reject the delivery, requeuing it if `requeue` is set, and cancel the consumer
+    Cancel { requeue: bool },
+}
+
+impl Default for PanicPolicy {
+    fn default() -> Self {
+        Self::Reject { requeue: true }
+    }
+}
+
+/// A panic of the delegate of a consumer, along with what identifies the delivery it was
+/// handling
+///
+/// The delivery related fields are empty when the delegate panicked while being notified of
+/// the cancellation or an error of its consumer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DelegatePanic {
+    pub consumer_tag: ShortString,
+    pub delivery_tag: Option<DeliveryTag>,
+    pub exchange: Option<ShortString>,
+    pub routing_key: Option<ShortString>,
+    pub message_id: Option<ShortString>,
+    pub correlation_id: Option<ShortString>,
+    /// The message the delegate panicked with
+    pub message: String,
+}
+
+impl fmt::Display for DelegatePanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "delegate of consumer {} panicked", self.consumer_tag)?;
+        if let Some(delivery_tag) = self.delivery_tag {
+            write!(f, " handling delivery {}", delivery_tag)?;
+        }
+        if let Some(message_id) = self.message_id.as_ref() {
+            write!(f, " (message id {})", message_id)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/* How a consumer reacts to the panics of its delegate, shared by all its handles */
+#[derive(Clone, Default)]
+pub(crate) struct PanicGuard(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    policy: PanicPolicy,
+    channel: Option<ChannelContext>,
+}
+
+#[derive(Clone)]
+struct ChannelContext {
+    id: ChannelId,
+    internal_rpc: InternalRPCHandle,
+    error_handler: ErrorHandler,
+}
+
+/* Everything needed to handle a panic once the delivery has been handed to the delegate */
+struct Handling {
+    policy: PanicPolicy,
+    channel: Option<ChannelContext>,
+    status: ConsumerStatus,
+    acker: Option<Acker>,
+    report: DelegatePanic,
+}
+
+impl PanicGuard {
+    pub(crate) fn set_policy(&self, policy: PanicPolicy) {
+        self.lock_inner().policy = policy;
+    }
+
+    pub(crate) fn policy(&self) -> PanicPolicy {
+        self.lock_inner().policy
+    }
+
+    pub(crate) fn set_channel(
+        &self,
+        id: ChannelId,
+        internal_rpc: InternalRPCHandle,
+        error_handler: ErrorHandler,
+    ) {
+        self.lock_inner().channel = Some(ChannelContext {
+            id,
+            internal_rpc,
+            error_handler,
+        });
+    }
+
+    /* Get the future handling this delivery, catching its panics unless they propagate */
+    pub(crate) fn guard<F: FnOnce(DeliveryResult) -> DelegateFuture>(
+        &self,
+        consumer_tag: &ShortString,
+        status: &ConsumerStatus,
+        no_ack: bool,
+        delivery: DeliveryResult,
+        handle: F,
+    ) -> DelegateFuture {
+        let (policy, channel) = {
+            let inner = self.lock_inner();
+            (inner.policy, inner.channel.clone())
+        };
+        if policy == PanicPolicy::Propagate {
+            return handle(delivery);
+        }
+        let delivery_ref = delivery.as_ref().ok().and_then(Option::as_ref);
+        let mut handling = Handling {
+            policy,
+            channel,
+            status: status.clone(),
+            acker: delivery_ref
+                .filter(|_| !no_ack)
+                .map(|delivery| delivery.acker.clone()),
+            report: DelegatePanic {
+                consumer_tag: consumer_tag.clone(),
+                delivery_tag: delivery_ref.map(|delivery| delivery.delivery_tag),
+                exchange: delivery_ref.map(|delivery| delivery.exchange.clone()),
+                routing_key: delivery_ref.map(|delivery| delivery.routing_key.clone()),
+                message_id: delivery_ref
+                    .and_then(|delivery| delivery.properties.message_id().clone()),
+                correlation_id: delivery_ref
+                    .and_then(|delivery| delivery.properties.correlation_id().clone()),
+                message: String::new(),
+            },
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| handle(delivery))) {
+            Ok(future) => Box::pin(CatchPanic {
+                future,
+                handling: Some(handling),
+            }),
+            Err(payload) => {
+                handling.report.message = panic_message(&*payload);
+                handling.run();
+                Box::pin(async {})
+            }
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Handling {
+    fn run(self) {
+        let Handling {
+            policy,
+            channel,
+            status,
+            acker,
+            report,
+        } = self;
+        error!(
+            consumer_tag=%report.consumer_tag, delivery_tag=?report.delivery_tag,
+            message_id=?report.message_id, message=%report.message,
+            "Consumer delegate panicked"
+        );
+        let (requeue, cancel) = match policy {
+            PanicPolicy::Propagate => return,
+            PanicPolicy::Reject { requeue } => (requeue, false),
+            PanicPolicy::Cancel { requeue } => (requeue, true),
+        };
+        if let Some(acker) = acker {
+            // Does nothing if the delegate already acked it before panicking
+            acker.reject_detached(requeue);
+        }
+        let Some(channel) = channel else {
+            return;
+        };
+        // The notifications of the cancellation hold the status, and it's canceled already
+        if cancel && report.delivery_tag.is_some() && status.state().is_active() {
+            channel.internal_rpc.cancel_consumer(
+                channel.id,
+                report.consumer_tag.to_string(),
+                status,
+            );
+        }
+        channel
+            .error_handler
+            .on_error(ErrorKind::DelegatePanicked(Box::new(report)).into());
+    }
+}
+
+struct CatchPanic {
+    future: DelegateFuture,
+    handling: Option<Handling>,
+}
+
+impl Future for CatchPanic {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| this.future.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                if let Some(mut handling) = this.handling.take() {
+                    handling.report.message = panic_message(&*payload);
+                    handling.run();
+                }
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| (*message).to_owned())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_owned())
+}
@@ -0,0 +1,349 @@
+use crate::{
+    timestamp,
+    types::{AMQPValue, ShortString},
+    BasicProperties,
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/* Set by RabbitMQ's message timestamp plugin when the message reaches the broker */
+const BROKER_TIMESTAMP_HEADER: &str = "timestamp_in_ms";
+
+/* Upper bounds of the histogram buckets, in milliseconds */
+const BUCKETS_MS: [u64; 14] = [
+    1, 2, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000,
+];
+
+/// How long a message took to reach the consumer
+///
+/// The latencies are measured when the properties of the message are received, using the
+/// clock of the consumer: they're `None` when the message doesn't carry the needed time, or
+/// when that time is in the future because of a clock skew.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeliveryLatency {
+    /// Since the message was published, according to the header configured with
+    /// [`ConnectionProperties::with_latency_header`], or to its `timestamp` property
+    ///
+    /// [`ConnectionProperties::with_latency_header`]: ./struct.ConnectionProperties.html#method.with_latency_header
+    pub publish_to_consume: Option<Duration>,
+    /// Since the message reached the broker, according to the `timestamp_in_ms` header set by
+    /// RabbitMQ's message timestamp plugin
+    pub broker_to_consume: Option<Duration>,
+}
+
+/// The distribution of latencies, in buckets going from 1ms to 30s
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; BUCKETS_MS.len() + 1],
+    sum: Duration,
+    max: Duration,
+}
+
+/// The main percentiles of a [`LatencyHistogram`]
+///
+/// [`LatencyHistogram`]: ./struct.LatencyHistogram.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| latency <= Duration::from_millis(bound))
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+
+    /// The highest latency recorded
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The latency below which `percentile` percents of the recorded ones are, `None` if
+    /// nothing was recorded
+    ///
+    /// It is the upper bound of the bucket the percentile falls in, or the highest recorded
+    /// latency if it is lower.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets()
+            .find(|(_, bucket_count)| {
+                seen += bucket_count;
+                seen >= rank
+            })
+            .map(|(bound, _)| bound.map_or(self.max, |bound| bound.min(self.max)))
+    }
+
+    /// The median, 95th and 99th percentiles and the maximum, `None` if nothing was recorded
+    pub fn summary(&self) -> Option<LatencySummary> {
+        Some(LatencySummary {
+            count: self.count(),
+            p50: self.percentile(50.0)?,
+            p95: self.percentile(95.0)?,
+            p99: self.percentile(99.0)?,
+            max: self.max,
+        })
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// The number of latencies in each bucket, with the bucket's upper bound
+    ///
+    /// The last bucket has no upper bound.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        BUCKETS_MS
+            .iter()
+            .map(|&bound| Some(Duration::from_millis(bound)))
+            .chain([None])
+            .zip(self.counts.iter().copied())
+    }
+}
+
+/// The latencies of all the messages received and confirmed on a connection
+///
+/// Get them with [`Connection::delivery_latency`].
+///
+/// [`Connection::delivery_latency`]: ./struct.Connection.html#method.delivery_latency
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyMetrics {
+    pub publish_to_consume: LatencyHistogram,
+    pub broker_to_consume: LatencyHistogram,
+    /// Between publishing a message and receiving its confirm, on the channels in confirm mode
+    pub publish_to_confirm: LatencyHistogram,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LatencyRecorder {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    header: Option<ShortString>,
+    metrics: LatencyMetrics,
+}
+
+impl LatencyRecorder {
+    pub(crate) fn set_header(&self, header: Option<ShortString>) {
+        self.lock_inner().header = header;
+    }
+
+    pub(crate) fn metrics(&self) -> LatencyMetrics {
+        self.lock_inner().metrics.clone()
+    }
+
+    /// Measure the latencies of a message received now, and record them
+    pub(crate) fn measure(&self, properties: &BasicProperties) -> DeliveryLatency {
+        let now = SystemTime::now();
+        let mut inner = self.lock_inner();
+        let headers = properties.headers().as_ref();
+        let published = inner
+            .header
+            .as_ref()
+            .and_then(|header| headers?.inner().get(header))
+            .and_then(millis_since_epoch)
+            .or_else(|| (*properties.timestamp()).map(timestamp::to_system_time));
+        let reached_broker = headers
+            .and_then(|headers| headers.inner().get(BROKER_TIMESTAMP_HEADER))
+            .and_then(millis_since_epoch);
+        let latency = DeliveryLatency {
+            publish_to_consume: published.and_then(|time| now.duration_since(time).ok()),
+            broker_to_consume: reached_broker.and_then(|time| now.duration_since(time).ok()),
+        };
+        if let Some(latency) = latency.publish_to_consume {
+            inner.metrics.publish_to_consume.record(latency);
+        }
+        if let Some(latency) = latency.broker_to_consume {
+            inner.metrics.broker_to_consume.record(latency);
+        }
+        latency
+    }
+
+    pub(crate) fn record_confirm(&self, latency: Duration) {
+        self.lock_inner().metrics.publish_to_confirm.record(latency);
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/* A number of milliseconds, stored as a timestamp or any integer type */
+fn millis_since_epoch(value: &AMQPValue) -> Option<SystemTime> {
+    timestamp::from_value(value).map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fields, options::*, testing::MockBroker, types::FieldTable, ConnectionProperties};
+
+    fn millis_ago(millis: u64) -> AMQPValue {
+        let time = SystemTime::now() - Duration::from_millis(millis);
+        AMQPValue::Timestamp(time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64)
+    }
+
+    #[test]
+    fn histogram() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_millis(1));
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_secs(60));
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.sum(), Duration::from_millis(60_004));
+        let buckets = histogram.buckets().collect::<Vec<_>>();
+        assert_eq!(buckets[0], (Some(Duration::from_millis(1)), 1));
+        assert_eq!(buckets[2], (Some(Duration::from_millis(5)), 1));
+        assert_eq!(buckets.last(), Some(&(None, 1)));
+        assert_eq!(histogram.max(), Duration::from_secs(60));
+        assert_eq!(histogram.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.percentile(50.0), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.percentile(99.0), Some(Duration::from_secs(60)));
+        assert_eq!(LatencyHistogram::default().summary(), None);
+
+        let mut histogram = LatencyHistogram::default();
+        for millis in 1..=100 {
+            histogram.record(Duration::from_micros(millis * 100));
+        }
+        assert_eq!(
+            histogram.summary(),
+            Some(LatencySummary {
+                count: 100,
+                p50: Duration::from_millis(5),
+                p95: Duration::from_millis(10),
+                p99: Duration::from_millis(10),
+                max: Duration::from_millis(10),
+            })
+        );
+    }
+
+    #[test]
+    fn measure() {
+        let recorder = LatencyRecorder::default();
+        let latency = recorder.measure(
+            &BasicProperties::default()
+                .with_timestamp(timestamp::now() - 2)
+                .with_headers(fields! { "timestamp_in_ms" => millis_ago(500) }),
+        );
+        assert!(latency.publish_to_consume.unwrap() >= Duration::from_secs(1));
+        assert!(latency.broker_to_consume.unwrap() >= Duration::from_millis(500));
+
+        recorder.set_header(Some("x-published-at".into()));
+        let latency = recorder.measure(
+            &BasicProperties::default()
+                .with_timestamp(timestamp::now() - 60)
+                .with_headers(fields! { "x-published-at" => millis_ago(100) }),
+        );
+        let publish_to_consume = latency.publish_to_consume.unwrap();
+        assert!(publish_to_consume >= Duration::from_millis(100));
+        assert!(publish_to_consume < Duration::from_secs(60));
+        assert_eq!(latency.broker_to_consume, None);
+        assert_eq!(
+            recorder.measure(&BasicProperties::default()),
+            DeliveryLatency::default()
+        );
+
+        let metrics = recorder.metrics();
+        assert_eq!(metrics.publish_to_consume.count(), 2);
+        assert_eq!(metrics.broker_to_consume.count(), 1);
+    }
+
+    #[test]
+    fn delivery() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker
+                .connect(ConnectionProperties::default().with_latency_header("x-published-at"))
+                .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default()
+                        .with_headers(fields! { "x-published-at" => millis_ago(50) }),
+                )
+                .await?;
+            let message = channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            let latency = message.delivery.latency.publish_to_consume.unwrap();
+            assert!(latency >= Duration::from_millis(50));
+            assert_eq!(message.delivery.latency.broker_to_consume, None);
+            let metrics = connection.delivery_latency();
+            assert_eq!(metrics.publish_to_consume.count(), 1);
+            assert_eq!(metrics.publish_to_consume.sum(), latency);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn confirm() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            assert_eq!(channel.confirm_latency().summary(), None);
+            for _ in 0..3 {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        b"job",
+                        BasicProperties::default(),
+                    )
+                    .await?
+                    .await?;
+            }
+            let summary = channel.confirm_latency().summary().unwrap();
+            assert_eq!(summary.count, 3);
+            assert!(summary.p50 <= summary.p99 && summary.p99 <= summary.max);
+            assert_eq!(
+                connection.delivery_latency().publish_to_confirm,
+                channel.confirm_latency()
+            );
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
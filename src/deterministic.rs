@@ -0,0 +1,458 @@
+use async_trait::async_trait;
+use executor_trait::{BlockingExecutor, Executor, FullExecutor, LocalExecutorError, Task};
+use futures_core::Stream;
+use reactor_trait::{AsyncIOHandle, IOHandle, Reactor, TimeReactor};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    future::Future,
+    io,
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+/// A clock which only moves forward when told to
+///
+/// It implements the timer part of a reactor, so that timeouts and heartbeats can be tested
+/// without actually sleeping. Registering sockets isn't supported, use it with
+/// [`Connection::connector_with_stream`].
+///
+/// [`Connection::connector_with_stream`]: ../struct.Connection.html#method.connector_with_stream
+#[derive(Clone)]
+pub struct ManualClock(Arc<Mutex<Clock>>);
+
+struct Clock {
+    start: Instant,
+    elapsed: Duration,
+    next_id: u64,
+    timers: BTreeMap<(Duration, u64), Option<Waker>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Clock {
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            next_id: 0,
+            timers: BTreeMap::default(),
+        })))
+    }
+}
+
+impl ManualClock {
+    /// Time elapsed since the creation of the clock
+    pub fn elapsed(&self) -> Duration {
+        self.lock_inner().elapsed
+    }
+
+    /// Move the clock forward, waking up the timers which expire
+    pub fn advance(&self, dur: Duration) {
+        let deadline = self.lock_inner().elapsed + dur;
+        self.advance_to(deadline);
+    }
+
+    /// Move the clock forward to the next timer deadline, if there is one
+    pub fn advance_to_next_timer(&self) -> bool {
+        let next = self
+            .lock_inner()
+            .timers
+            .keys()
+            .next()
+            .map(|(deadline, _)| *deadline);
+        if let Some(deadline) = next {
+            self.advance_to(deadline);
+        }
+        next.is_some()
+    }
+
+    fn advance_to(&self, deadline: Duration) {
+        let wakers = {
+            let mut inner = self.lock_inner();
+            inner.elapsed = inner.elapsed.max(deadline);
+            let elapsed = inner.elapsed;
+            let pending = inner.timers.split_off(&(elapsed, u64::MAX));
+            std::mem::replace(&mut inner.timers, pending)
+        };
+        for waker in wakers.into_values().flatten() {
+            waker.wake();
+        }
+    }
+
+    fn timer(&self, dur: Duration) -> Timer {
+        let mut inner = self.lock_inner();
+        inner.next_id += 1;
+        Timer {
+            clock: self.clone(),
+            deadline: inner.elapsed + dur,
+            id: inner.next_id,
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Clock> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualClock")
+            .field("elapsed", &self.elapsed())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl TimeReactor for ManualClock {
+    async fn sleep(&self, dur: Duration) {
+        self.timer(dur).await;
+    }
+
+    fn interval(&self, dur: Duration) -> Box<dyn Stream<Item = Instant>> {
+        Box::new(Interval {
+            timer: self.timer(dur),
+            period: dur,
+        })
+    }
+}
+
+impl Reactor for ManualClock {
+    fn register(&self, _socket: IOHandle) -> io::Result<Box<dyn AsyncIOHandle + Send>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the manual clock cannot register sockets",
+        ))
+    }
+}
+
+struct Timer {
+    clock: ManualClock,
+    deadline: Duration,
+    id: u64,
+}
+
+impl Future for Timer {
+    type Output = Instant;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.clock.lock_inner();
+        if inner.elapsed >= self.deadline {
+            inner.timers.remove(&(self.deadline, self.id));
+            return Poll::Ready(inner.start + self.deadline);
+        }
+        inner
+            .timers
+            .insert((self.deadline, self.id), Some(cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.clock
+            .lock_inner()
+            .timers
+            .remove(&(self.deadline, self.id));
+    }
+}
+
+struct Interval {
+    timer: Timer,
+    period: Duration,
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let instant = futures_core::ready!(Pin::new(&mut self.timer).poll(cx));
+        let next = self.timer.deadline + self.period;
+        self.timer.deadline = next;
+        Poll::Ready(Some(instant))
+    }
+}
+
+/// A single-threaded executor running its tasks in a reproducible order
+///
+/// Tasks only run from [`block_on`] or [`run_until_stalled`], in the order they've been woken
+/// up. Blocking tasks run inline. When given a [`ManualClock`], the clock automatically moves
+/// forward to the next timer whenever all the tasks are waiting, so that timeouts fire without
+/// any actual sleep.
+///
+/// As the io loop of the connection would otherwise run in its own thread, use it along with
+/// [`ConnectionProperties::with_manual_io_loop`] and spawn [`Connection::drive`] on it.
+///
+/// [`block_on`]: #method.block_on
+/// [`run_until_stalled`]: #method.run_until_stalled
+/// [`ConnectionProperties::with_manual_io_loop`]: ../struct.ConnectionProperties.html#method.with_manual_io_loop
+/// [`Connection::drive`]: ../struct.Connection.html#method.drive
+#[derive(Clone, Default)]
+pub struct DeterministicExecutor {
+    inner: Arc<Mutex<Tasks>>,
+    clock: Option<ManualClock>,
+}
+
+type BoxedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+#[derive(Default)]
+struct Tasks {
+    next_id: u64,
+    tasks: HashMap<u64, Arc<TaskState>>,
+    ready: VecDeque<u64>,
+}
+
+#[derive(Default)]
+struct TaskState {
+    future: Mutex<Option<BoxedFuture>>,
+    finished: AtomicBool,
+    join_waker: Mutex<Option<Waker>>,
+}
+
+struct TaskWaker {
+    id: u64,
+    tasks: Arc<Mutex<Tasks>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+        if !tasks.ready.contains(&self.id) {
+            tasks.ready.push_back(self.id);
+        }
+    }
+}
+
+struct MainWaker(AtomicBool);
+
+impl Wake for MainWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+impl DeterministicExecutor {
+    /// Create an executor automatically moving the given clock forward when stalled
+    pub fn new(clock: ManualClock) -> Self {
+        Self {
+            inner: Arc::default(),
+            clock: Some(clock),
+        }
+    }
+
+    /// Run the ready tasks, in order, until all of them are waiting.
+    ///
+    /// Returns whether any task has been polled.
+    pub fn run_until_stalled(&self) -> bool {
+        let mut progress = false;
+        loop {
+            let next = {
+                let mut inner = self.lock_inner();
+                inner
+                    .ready
+                    .pop_front()
+                    .map(|id| (id, inner.tasks.get(&id).cloned()))
+            };
+            let Some((id, task)) = next else {
+                return progress;
+            };
+            let Some(task) = task else {
+                continue;
+            };
+            progress = true;
+            let waker = Waker::from(Arc::new(TaskWaker {
+                id,
+                tasks: self.inner.clone(),
+            }));
+            let mut future = task.future.lock().unwrap_or_else(|e| e.into_inner());
+            let finished = match future.as_mut() {
+                Some(fut) => fut
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready(),
+                None => true,
+            };
+            if finished {
+                *future = None;
+                drop(future);
+                self.lock_inner().tasks.remove(&id);
+                task.finished.store(true, Ordering::SeqCst);
+                if let Some(waker) = task
+                    .join_waker
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take()
+                {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    /// Run the given future to completion along with the spawned tasks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the future cannot complete: all the tasks are waiting and there is no timer
+    /// left to fire.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let main = Arc::new(MainWaker(AtomicBool::new(true)));
+        let waker = Waker::from(main.clone());
+        loop {
+            if main.0.swap(false, Ordering::SeqCst) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker))
+                {
+                    return output;
+                }
+            }
+            if self.run_until_stalled() || main.0.load(Ordering::SeqCst) {
+                continue;
+            }
+            if !self
+                .clock
+                .as_ref()
+                .is_some_and(ManualClock::advance_to_next_timer)
+            {
+                panic!("deterministic executor stalled: no task can make progress");
+            }
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Tasks> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for DeterministicExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeterministicExecutor")
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+impl FullExecutor for DeterministicExecutor {}
+
+impl Executor for DeterministicExecutor {
+    fn block_on(&self, f: Pin<Box<dyn Future<Output = ()>>>) {
+        DeterministicExecutor::block_on(self, f);
+    }
+
+    fn spawn(&self, f: Pin<Box<dyn Future<Output = ()> + Send>>) -> Box<dyn Task> {
+        let task = Arc::new(TaskState {
+            future: Mutex::new(Some(f)),
+            ..TaskState::default()
+        });
+        let mut inner = self.lock_inner();
+        inner.next_id += 1;
+        let id = inner.next_id;
+        inner.tasks.insert(id, task.clone());
+        inner.ready.push_back(id);
+        Box::new(JoinHandle(task))
+    }
+
+    fn spawn_local(
+        &self,
+        f: Pin<Box<dyn Future<Output = ()>>>,
+    ) -> Result<Box<dyn Task>, LocalExecutorError> {
+        Err(LocalExecutorError(f))
+    }
+}
+
+#[async_trait]
+impl BlockingExecutor for DeterministicExecutor {
+    async fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        f();
+    }
+}
+
+struct JoinHandle(Arc<TaskState>);
+
+#[async_trait(?Send)]
+impl Task for JoinHandle {
+    async fn cancel(self: Box<Self>) -> Option<()> {
+        let future = self
+            .0
+            .future
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        drop(future);
+        self.0.finished.load(Ordering::SeqCst).then_some(())
+    }
+}
+
+impl Future for JoinHandle {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        *self.0.join_waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(cx.waker().clone());
+        if self.0.finished.load(Ordering::SeqCst) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::MockBroker, Connection, ConnectionProperties};
+
+    #[test]
+    fn reproducible_order_and_timers() {
+        let clock = ManualClock::default();
+        let executor = DeterministicExecutor::new(clock.clone());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        for (name, delay) in [("slow", 30), ("fast", 10), ("instant", 0)] {
+            let events = events.clone();
+            let clock = clock.clone();
+            executor.spawn(Box::pin(async move {
+                clock.sleep(Duration::from_secs(delay)).await;
+                events.lock().unwrap().push(name);
+            }));
+        }
+        executor.block_on(clock.sleep(Duration::from_secs(60)));
+        assert_eq!(*events.lock().unwrap(), ["instant", "fast", "slow"]);
+        assert_eq!(clock.elapsed(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn connection() {
+        let clock = ManualClock::default();
+        let executor = DeterministicExecutor::new(clock.clone());
+        let broker = MockBroker::default();
+        let options = ConnectionProperties::default()
+            .with_executor(executor.clone())
+            .with_reactor(clock.clone())
+            .with_manual_io_loop();
+        executor.block_on(async {
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                broker.stream(),
+                options,
+            )
+            .await
+            .unwrap();
+            let connection = Arc::new(connection);
+            executor.spawn(Box::pin({
+                let connection = connection.clone();
+                async move {
+                    let _ = connection.drive().await;
+                }
+            }));
+            let channel = connection.create_channel().await.unwrap();
+            assert!(channel.status().connected());
+            connection.close(200, "OK").await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{channels::Channels, socket_state::SocketStateHandle, Result};
+use amq_protocol::frame::AMQPFrame;
+use std::{
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::Builder as ThreadBuilder,
+};
+use tracing::{trace, Span};
+
+/// Handles the frames received on the channels on worker threads instead of the io loop.
+///
+/// The frames of a channel always go to the same worker so that they're handled in order. The
+/// frames of channel 0 are still handled by the io loop, once the workers are done with the
+/// frames received before them, so that connection events don't overtake the ones of channels.
+/// The workers stop once the pool gets dropped along with the io loop.
+pub(crate) struct DispatchPool {
+    workers: Vec<flume::Sender<AMQPFrame>>,
+    pending: Pending,
+}
+
+/* The number of frames dispatched to the workers and not handled yet */
+#[derive(Clone, Default)]
+struct Pending(Arc<(Mutex<usize>, Condvar)>);
+
+impl DispatchPool {
+    pub(crate) fn new(
+        size: usize,
+        channels: Channels,
+        waker: SocketStateHandle,
+        span: Span,
+    ) -> Result<Self> {
+        let pending = Pending::default();
+        let workers = (0..size)
+            .map(|id| {
+                let (sender, receiver) = flume::unbounded::<AMQPFrame>();
+                let channels = channels.clone();
+                let waker = waker.clone();
+                let pending = pending.clone();
+                let span = span.clone();
+                ThreadBuilder::new()
+                    .name(format!("lapin-dispatch-{}", id))
+                    .spawn(move || {
+                        let _span = span.entered();
+                        for frame in receiver.iter() {
+                            if channels.handle_frame(frame).is_err() {
+                                // Let the io loop notice the connection error
+                                waker.wake();
+                            }
+                            pending.done();
+                        }
+                        trace!(worker=%id, "dispatch worker stopped");
+                    })?;
+                Ok(sender)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { workers, pending })
+    }
+
+    /// Hand the frame over to the worker of its channel, or give it back if the io loop has to
+    /// handle it itself
+    pub(crate) fn dispatch(&self, frame: AMQPFrame) -> Option<AMQPFrame> {
+        let channel_id = match &frame {
+            AMQPFrame::Method(id, _) | AMQPFrame::Header(id, _, _) | AMQPFrame::Body(id, _) => *id,
+            AMQPFrame::ProtocolHeader(_) | AMQPFrame::Heartbeat(_) => return Some(frame),
+        };
+        if channel_id == 0 {
+            // Handle connection methods after everything received before them
+            if matches!(frame, AMQPFrame::Method(..)) {
+                self.wait_idle();
+            }
+            return Some(frame);
+        }
+        let worker = &self.workers[usize::from(channel_id) % self.workers.len()];
+        self.pending.add();
+        match worker.send(frame) {
+            Ok(()) => None,
+            Err(flume::SendError(frame)) => {
+                self.pending.done();
+                Some(frame)
+            }
+        }
+    }
+
+    /// Wait for the workers to handle all the frames dispatched to them
+    pub(crate) fn wait_idle(&self) {
+        self.pending.wait();
+    }
+}
+
+impl Pending {
+    fn add(&self) {
+        *self.lock() += 1;
+    }
+
+    fn done(&self) {
+        let mut pending = self.lock();
+        *pending = pending.saturating_sub(1);
+        if *pending == 0 {
+            self.0 .1.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut pending = self.lock();
+        while *pending > 0 {
+            pending = self.0 .1.wait(pending).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.0 .0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn deliveries_stay_in_order() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker
+                .connect(ConnectionProperties::default().with_dispatch_workers(2))
+                .await?;
+            let mut consumers = Vec::new();
+            for queue in ["first", "second", "third"] {
+                let channel = connection.create_channel().await?;
+                channel
+                    .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+                    .await?;
+                for i in 0..20u8 {
+                    channel
+                        .basic_publish(
+                            "",
+                            queue,
+                            BasicPublishOptions::default(),
+                            &[i],
+                            BasicProperties::default(),
+                        )
+                        .await?;
+                }
+                let consumer = channel
+                    .basic_consume(
+                        queue,
+                        "",
+                        BasicConsumeOptions {
+                            no_ack: true,
+                            ..BasicConsumeOptions::default()
+                        },
+                        FieldTable::default(),
+                    )
+                    .await?;
+                consumers.push(consumer);
+            }
+            for consumer in consumers.iter_mut() {
+                for i in 0..20u8 {
+                    let delivery = consumer.next().await.unwrap()?;
+                    assert_eq!(delivery.data, vec![i]);
+                }
+            }
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{options::BasicPublishOptions, types::ShortString, BasicProperties};
+
+/// Everything needed to publish a message: where to, how, and what
+///
+/// Publish it with [`Channel::publish`], or hand it over to the publishers built on top of a
+/// channel, such as the [`OutboxPublisher`].
+///
+/// ```rust
+/// use lapin::{BasicProperties, Envelope};
+///
+/// let envelope = Envelope::new("orders", "order.created", b"{}".to_vec())
+///     .with_mandatory()
+///     .with_properties(BasicProperties::default().with_content_type("application/json".into()));
+/// assert!(envelope.options.mandatory);
+/// assert_eq!(envelope.routing_key.as_str(), "order.created");
+/// ```
+///
+/// [`Channel::publish`]: ./struct.Channel.html#method.publish
+/// [`OutboxPublisher`]: ./outbox/struct.OutboxPublisher.html
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Envelope {
+    pub exchange: ShortString,
+    pub routing_key: ShortString,
+    pub options: BasicPublishOptions,
+    pub properties: BasicProperties,
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn new(exchange: &str, routing_key: &str, payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            payload: payload.into(),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn with_options(mut self, options: BasicPublishOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Have the broker return the message if it cannot be routed to any queue
+    #[must_use]
+    pub fn with_mandatory(mut self) -> Self {
+        self.options.mandatory = true;
+        self
+    }
+
+    #[must_use]
+    pub fn with_properties(mut self, properties: BasicProperties) -> Self {
+        self.properties = properties;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::*, testing::MockBroker, types::FieldTable, ConnectionProperties};
+
+    #[test]
+    fn publish() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "orders",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let envelope = Envelope::new("", "orders", b"order".to_vec())
+                .with_properties(BasicProperties::default().with_app_id("shop".into()));
+            channel.publish(&envelope).await?;
+            let message = channel
+                .basic_get("orders", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(&message.delivery.data[..], b"order");
+            assert_eq!(message.delivery.properties.app_id(), &Some("shop".into()));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -1,6 +1,11 @@
 use crate::{
-    channel_status::ChannelState, connection_status::ConnectionState, notifier::Notifier,
-    protocol::AMQPError, types::ChannelId,
+    channel_id_allocation::OpenChannel,
+    channel_status::ChannelState,
+    connection_status::ConnectionState,
+    delegate_panic::DelegatePanic,
+    notifier::Notifier,
+    protocol::AMQPError,
+    types::{ChannelId, DeliveryTag, ShortString},
 };
 use amq_protocol::{
     frame::{GenError, ParserError, ProtocolVersion},
@@ -25,12 +30,25 @@ pub struct Error {
 #[derive(Clone, Debug)]
 #[non_exhaustive]
 pub enum ErrorKind {
-    ChannelsLimitReached,
+    /// No channel id is available anymore, because of these open channels
+    ChannelsLimitReached(Vec<OpenChannel>),
     InvalidProtocolVersion(ProtocolVersion),
 
     InvalidChannel(ChannelId),
     InvalidChannelState(ChannelState),
     InvalidConnectionState(ConnectionState),
+    StaleDeliveryTag(DeliveryTag),
+    RecoveryFailed(Box<Error>),
+    /// A message property has an invalid value
+    InvalidProperty(&'static str, String),
+    /// The payload of a delivery couldn't be decoded
+    InvalidPayload(String),
+    /// The delegate of a consumer panicked
+    DelegatePanicked(Box<DelegatePanic>),
+    /// A request with this correlation id is already waiting for its response
+    DuplicateCorrelationId(ShortString),
+    /// This many requests are already waiting for their response
+    PendingRequestsLimitReached(usize),
 
     IOError(Arc<io::Error>),
     ParsingError(ParserError),
@@ -38,6 +56,8 @@ pub enum ErrorKind {
     SerialisationError(Arc<GenError>),
 
     MissingHeartbeatError,
+    /// The server doesn't advertise a capability required by the client
+    MissingCapability(ShortString),
 
     NoConfiguredExecutor,
     NoConfiguredReactor,
@@ -102,10 +122,16 @@ impl Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.kind() {
-            ErrorKind::ChannelsLimitReached => write!(
-                f,
-                "the maximum number of channels for this connection has been reached"
-            ),
+            ErrorKind::ChannelsLimitReached(open_channels) => {
+                write!(
+                    f,
+                    "the maximum number of channels for this connection has been reached, open channels:"
+                )?;
+                for channel in open_channels {
+                    write!(f, " {}", channel)?;
+                }
+                Ok(())
+            }
             ErrorKind::InvalidProtocolVersion(version) => {
                 write!(f, "the server only supports AMQP {}", version)
             }
@@ -117,6 +143,27 @@ impl fmt::Display for Error {
             ErrorKind::InvalidConnectionState(state) => {
                 write!(f, "invalid connection state: {:?}", state)
             }
+            ErrorKind::StaleDeliveryTag(delivery_tag) => write!(
+                f,
+                "stale delivery tag {}: the message was received before the channel recovered",
+                delivery_tag
+            ),
+            ErrorKind::RecoveryFailed(e) => write!(f, "gave up on channel recovery: {}", e),
+            ErrorKind::InvalidProperty(property, reason) => {
+                write!(f, "invalid {} property: {}", property, reason)
+            }
+            ErrorKind::InvalidPayload(reason) => write!(f, "invalid payload: {}", reason),
+            ErrorKind::DelegatePanicked(panic) => write!(f, "{}", panic),
+            ErrorKind::DuplicateCorrelationId(correlation_id) => write!(
+                f,
+                "a request with correlation id {} is already pending",
+                correlation_id
+            ),
+            ErrorKind::PendingRequestsLimitReached(limit) => write!(
+                f,
+                "the maximum number of pending requests ({}) has been reached",
+                limit
+            ),
 
             ErrorKind::IOError(e) => write!(f, "IO error: {}", e),
             ErrorKind::ParsingError(e) => write!(f, "failed to parse: {}", e),
@@ -126,6 +173,13 @@ impl fmt::Display for Error {
             ErrorKind::MissingHeartbeatError => {
                 write!(f, "no heartbeat received from server for too long")
             }
+            ErrorKind::MissingCapability(capability) => {
+                write!(
+                    f,
+                    "the server doesn't support the {} capability",
+                    capability
+                )
+            }
 
             ErrorKind::NoConfiguredExecutor => {
                 write!(
@@ -150,6 +204,7 @@ impl error::Error for Error {
             ErrorKind::ParsingError(e) => Some(e),
             ErrorKind::ProtocolError(e) => Some(e),
             ErrorKind::SerialisationError(e) => Some(&**e),
+            ErrorKind::RecoveryFailed(e) => Some(&**e),
             _ => None,
         }
     }
@@ -176,7 +231,7 @@ impl PartialEq for Error {
         use ErrorKind::*;
 
         match (self.kind(), other.kind()) {
-            (ChannelsLimitReached, ChannelsLimitReached) => true,
+            (ChannelsLimitReached(_), ChannelsLimitReached(_)) => true,
             (InvalidProtocolVersion(left_inner), InvalidProtocolVersion(right_version)) => {
                 left_inner == right_version
             }
@@ -188,6 +243,24 @@ impl PartialEq for Error {
             (InvalidConnectionState(left_inner), InvalidConnectionState(right_inner)) => {
                 left_inner == right_inner
             }
+            (StaleDeliveryTag(left_inner), StaleDeliveryTag(right_inner)) => {
+                left_inner == right_inner
+            }
+            (RecoveryFailed(left_inner), RecoveryFailed(right_inner)) => left_inner == right_inner,
+            (
+                InvalidProperty(left_property, left_reason),
+                InvalidProperty(right_property, right_reason),
+            ) => left_property == right_property && left_reason == right_reason,
+            (InvalidPayload(left_inner), InvalidPayload(right_inner)) => left_inner == right_inner,
+            (DelegatePanicked(left_inner), DelegatePanicked(right_inner)) => {
+                left_inner == right_inner
+            }
+            (DuplicateCorrelationId(left_inner), DuplicateCorrelationId(right_inner)) => {
+                left_inner == right_inner
+            }
+            (PendingRequestsLimitReached(left_inner), PendingRequestsLimitReached(right_inner)) => {
+                left_inner == right_inner
+            }
 
             (IOError(_), IOError(_)) => {
                 error!("Unable to compare lapin::ErrorKind::IOError");
@@ -195,6 +268,9 @@ impl PartialEq for Error {
             }
             (ParsingError(left_inner), ParsingError(right_inner)) => left_inner == right_inner,
             (ProtocolError(left_inner), ProtocolError(right_inner)) => left_inner == right_inner,
+            (MissingCapability(left_inner), MissingCapability(right_inner)) => {
+                left_inner == right_inner
+            }
             (SerialisationError(_), SerialisationError(_)) => {
                 error!("Unable to compare lapin::ErrorKind::SerialisationError");
                 false
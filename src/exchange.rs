@@ -1,20 +1,15 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
 pub enum ExchangeKind {
     Custom(String),
+    #[default]
     Direct,
     Fanout,
     Headers,
     Topic,
 }
 
-impl Default for ExchangeKind {
-    fn default() -> Self {
-        Self::Direct
-    }
-}
-
 impl ExchangeKind {
     pub(crate) fn kind(&self) -> &str {
         match self {
@@ -0,0 +1,488 @@
+use crate::{
+    protocol::{channel, AMQPClass},
+    recording::frame_size,
+    types::{ChannelId, Identifier, ReplyCode},
+};
+use amq_protocol::frame::{gen_frame, parse_frame, AMQPFrame, WriteContext};
+use futures_io::{AsyncRead, AsyncWrite};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+use tracing::trace;
+
+/// The kind of frames a fault applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Heartbeat,
+    /// A method frame, identified by its class and method ids (e.g. 60, 60 for basic.deliver),
+    /// along with its content if it carries some
+    Method(Identifier, Identifier),
+}
+
+impl FrameKind {
+    fn matches(&self, frame: &AMQPFrame) -> bool {
+        match (self, frame) {
+            (FrameKind::Heartbeat, AMQPFrame::Heartbeat(_)) => true,
+            (FrameKind::Method(class_id, method_id), AMQPFrame::Method(_, method)) => {
+                method.get_amqp_class_id() == *class_id && method.get_amqp_method_id() == *method_id
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A handle to inject faults in a [`FaultyStream`] while the connection is running
+///
+/// Faults apply to the frames the client receives from the broker, unless stated otherwise.
+#[derive(Clone, Default)]
+pub struct FaultInjector(Arc<Mutex<Faults>>);
+
+#[derive(Default)]
+struct Faults {
+    disconnected: bool,
+    hold: Vec<FrameKind>,
+    held: VecDeque<Vec<u8>>,
+    duplicate: Vec<(FrameKind, usize)>,
+    corrupt_heartbeats: bool,
+    injected: VecDeque<Vec<u8>>,
+    failed_channels: HashSet<ChannelId>,
+    waker: Option<Waker>,
+}
+
+impl FaultInjector {
+    /// Simulate a dropped socket: all subsequent reads and writes fail
+    pub fn disconnect(&self) {
+        self.update(|faults| faults.disconnected = true);
+    }
+
+    /// Hold back the frames of the given kind until [`release_frames`] is called
+    ///
+    /// [`release_frames`]: #method.release_frames
+    pub fn hold_frames(&self, kind: FrameKind) {
+        self.update(|faults| faults.hold.push(kind));
+    }
+
+    /// Stop holding frames back and deliver the ones that have been held, in order
+    pub fn release_frames(&self) {
+        self.update(|faults| {
+            faults.hold.clear();
+            let held = std::mem::take(&mut faults.held);
+            faults.injected.extend(held);
+        });
+    }
+
+    /// Deliver the next `count` frames of the given kind twice
+    pub fn duplicate_frames(&self, kind: FrameKind, count: usize) {
+        self.update(|faults| faults.duplicate.push((kind, count)));
+    }
+
+    /// Corrupt all the subsequent heartbeats, which should make the connection fail
+    pub fn corrupt_heartbeats(&self) {
+        self.update(|faults| faults.corrupt_heartbeats = true);
+    }
+
+    /// Make the broker close the given channel with an error
+    ///
+    /// The client receives a channel.close with the given reply, and the broker side of the
+    /// channel gets closed normally.
+    pub fn fail_channel(&self, channel_id: ChannelId, reply_code: ReplyCode, reply_text: &str) {
+        let close = serialize(&AMQPFrame::Method(
+            channel_id,
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code,
+                reply_text: reply_text.into(),
+                class_id: 0,
+                method_id: 0,
+            })),
+        ));
+        self.update(|faults| {
+            faults.failed_channels.insert(channel_id);
+            faults.injected.push_back(close);
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Faults)) {
+        let waker = {
+            let mut faults = self.lock_inner();
+            f(&mut faults);
+            faults.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Faults> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjector").finish()
+    }
+}
+
+fn serialize(frame: &AMQPFrame) -> Vec<u8> {
+    gen_frame(frame)(WriteContext::from(Vec::new()))
+        .map(|ctx| ctx.write)
+        .expect("failed to serialize frame")
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "injected disconnection")
+}
+
+/* A method frame along with its content frames, if any */
+struct Unit {
+    kind: AMQPFrame,
+    bytes: Vec<u8>,
+    remaining: Option<u64>,
+}
+
+/// A stream wrapper injecting faults in a live connection, controlled by a [`FaultInjector`]
+///
+/// Use it with [`Connection::connector_with_stream`], wrapping a [`MockBroker`] stream or any
+/// other one.
+///
+/// [`Connection::connector_with_stream`]: ../struct.Connection.html#method.connector_with_stream
+/// [`MockBroker`]: struct.MockBroker.html
+pub struct FaultyStream<S> {
+    inner: S,
+    faults: FaultInjector,
+    input: Vec<u8>,
+    units: HashMap<ChannelId, Unit>,
+    output: VecDeque<u8>,
+    sent: Vec<u8>,
+    pending: VecDeque<u8>,
+}
+
+impl<S> FaultyStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            faults: FaultInjector::default(),
+            input: Vec::new(),
+            units: HashMap::default(),
+            output: VecDeque::new(),
+            sent: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Get a handle to inject faults in this stream
+    pub fn injector(&self) -> FaultInjector {
+        self.faults.clone()
+    }
+
+    fn receive_frames(&mut self) -> io::Result<()> {
+        while let Some(size) = frame_size(&self.input) {
+            let bytes = self.input.drain(..size).collect::<Vec<_>>();
+            let frame = parse_frame(&bytes[..])
+                .map(|(_, frame)| frame)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)))?;
+            match frame {
+                AMQPFrame::Method(channel_id, ref method) => {
+                    let has_content = matches!(
+                        (method.get_amqp_class_id(), method.get_amqp_method_id()),
+                        (60, 50) | (60, 60) | (60, 71)
+                    );
+                    let unit = Unit {
+                        kind: frame.clone(),
+                        bytes,
+                        remaining: None,
+                    };
+                    if has_content {
+                        self.units.insert(channel_id, unit);
+                    } else {
+                        self.dispatch(unit);
+                    }
+                }
+                AMQPFrame::Header(channel_id, _, ref header) => {
+                    if let Some(mut unit) = self.units.remove(&channel_id) {
+                        unit.bytes.extend(bytes);
+                        unit.remaining = Some(header.body_size);
+                        if header.body_size == 0 {
+                            self.dispatch(unit);
+                        } else {
+                            self.units.insert(channel_id, unit);
+                        }
+                    }
+                }
+                AMQPFrame::Body(channel_id, ref payload) => {
+                    if let Some(mut unit) = self.units.remove(&channel_id) {
+                        unit.bytes.extend(bytes.iter());
+                        let remaining = unit
+                            .remaining
+                            .unwrap_or_default()
+                            .saturating_sub(payload.len() as u64);
+                        unit.remaining = Some(remaining);
+                        if remaining == 0 {
+                            self.dispatch(unit);
+                        } else {
+                            self.units.insert(channel_id, unit);
+                        }
+                    }
+                }
+                _ => self.dispatch(Unit {
+                    kind: frame,
+                    bytes,
+                    remaining: None,
+                }),
+            }
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, mut unit: Unit) {
+        let mut faults = self.faults.lock_inner();
+        if let AMQPFrame::Method(channel_id, ref method) = unit.kind {
+            if faults.failed_channels.contains(&channel_id) {
+                // The channel is closed from the client's point of view, but the broker still
+                // answers what was sent before it handled our channel.close
+                if let AMQPClass::Channel(channel::AMQPMethod::CloseOk(_)) = method {
+                    faults.failed_channels.remove(&channel_id);
+                }
+                return;
+            }
+        }
+        if let AMQPFrame::Heartbeat(_) = unit.kind {
+            if faults.corrupt_heartbeats {
+                trace!("corrupting heartbeat");
+                if let Some(frame_end) = unit.bytes.last_mut() {
+                    *frame_end = 0;
+                }
+            }
+        }
+        if faults.hold.iter().any(|kind| kind.matches(&unit.kind)) {
+            trace!(frame = %unit.kind, "holding frame back");
+            faults.held.push_back(unit.bytes);
+            return;
+        }
+        let mut duplicate = false;
+        for (kind, count) in faults.duplicate.iter_mut() {
+            if *count > 0 && kind.matches(&unit.kind) {
+                *count -= 1;
+                duplicate = true;
+                break;
+            }
+        }
+        if duplicate {
+            trace!(frame = %unit.kind, "duplicating frame");
+            self.output.extend(unit.bytes.iter());
+        }
+        self.output.extend(unit.bytes);
+    }
+
+    fn send_frames(&mut self) {
+        while let Some(size) = frame_size(&self.sent) {
+            let bytes = self.sent.drain(..size).collect::<Vec<_>>();
+            if let Ok((
+                _,
+                AMQPFrame::Method(channel_id, AMQPClass::Channel(channel::AMQPMethod::CloseOk(_))),
+            )) = parse_frame(&bytes[..])
+            {
+                if self
+                    .faults
+                    .lock_inner()
+                    .failed_channels
+                    .contains(&channel_id)
+                {
+                    // The broker still considers the channel open, close it for real
+                    self.pending.extend(serialize(&AMQPFrame::Method(
+                        channel_id,
+                        AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                            reply_code: 200,
+                            reply_text: "OK".into(),
+                            class_id: 0,
+                            method_id: 0,
+                        })),
+                    )));
+                    continue;
+                }
+            }
+            self.pending.extend(bytes);
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> FaultyStream<S> {
+    fn poll_send_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let (buf, _) = self.pending.as_slices();
+            let len = futures_core::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+            if len == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..len);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FaultyStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            {
+                let mut faults = this.faults.lock_inner();
+                if faults.disconnected {
+                    return Poll::Ready(Err(disconnected()));
+                }
+                let injected = std::mem::take(&mut faults.injected);
+                this.output.extend(injected.into_iter().flatten());
+                faults.waker = Some(cx.waker().clone());
+            }
+            if !this.output.is_empty() {
+                let len = buf.len().min(this.output.len());
+                for (dst, src) in buf.iter_mut().zip(this.output.drain(..len)) {
+                    *dst = src;
+                }
+                return Poll::Ready(Ok(len));
+            }
+            let mut chunk = [0; 8192];
+            let len = futures_core::ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if len == 0 {
+                return Poll::Ready(Ok(0));
+            }
+            this.input.extend_from_slice(&chunk[..len]);
+            this.receive_frames()?;
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FaultyStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.faults.lock_inner().disconnected {
+            return Poll::Ready(Err(disconnected()));
+        }
+        if !this.pending.is_empty() {
+            futures_core::ready!(this.poll_send_pending(cx))?;
+        }
+        this.sent.extend_from_slice(buf);
+        this.send_frames();
+        // Errors will be reported by the next write or flush
+        let _ = this.poll_send_pending(cx);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.faults.lock_inner().disconnected {
+            return Poll::Ready(Err(disconnected()));
+        }
+        futures_core::ready!(this.poll_send_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let _ = futures_core::ready!(this.poll_send_pending(cx));
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+impl<S> fmt::Debug for FaultyStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultyStream").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, Connection,
+        ConnectionProperties,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn inject_faults() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default(),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "chaos",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut consumer = channel
+                .basic_consume(
+                    "chaos",
+                    "",
+                    BasicConsumeOptions {
+                        no_ack: true,
+                        ..BasicConsumeOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+
+            injector.duplicate_frames(FrameKind::Method(60, 60), 1);
+            channel
+                .basic_publish(
+                    "",
+                    "chaos",
+                    BasicPublishOptions::default(),
+                    b"twice",
+                    BasicProperties::default(),
+                )
+                .await?;
+            let first = consumer.next().await.expect("delivery")?;
+            let second = consumer.next().await.expect("delivery")?;
+            assert_eq!(first.delivery_tag, second.delivery_tag);
+            assert_eq!(&second.data[..], b"twice");
+
+            injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+            assert!(channel
+                .queue_declare(
+                    "chaos",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default()
+                )
+                .await
+                .is_err());
+
+            let channel = connection.create_channel().await?;
+            assert_eq!(broker.consumer_count("chaos"), Some(0));
+            injector.disconnect();
+            assert!(channel
+                .queue_declare(
+                    "chaos",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default()
+                )
+                .await
+                .is_err());
+            assert!(!connection.status().connected());
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,491 @@
+use crate::{
+    decimal::Decimal,
+    timestamp,
+    types::{AMQPValue, ByteArray, FieldArray, FieldTable, LongString, ShortString},
+};
+use std::{fmt, time::SystemTime};
+
+/* How many bytes of a binary value are shown by PrettyFields */
+const BINARY_PREVIEW_LEN: usize = 16;
+
+/// Build a [`FieldTable`] from `key => value` pairs
+///
+/// The values are converted with [`IntoAMQPValue`], which picks the AMQP type matching their
+/// Rust type: a plain integer literal is an `i32`, and thus a long int.
+///
+/// ```rust
+/// use lapin::{fields, types::AMQPValue};
+///
+/// let arguments = fields! {
+///     "x-message-ttl" => 60_000,
+///     "x-queue-type" => "quorum",
+///     "x-single-active-consumer" => true,
+/// };
+/// assert_eq!(
+///     arguments.inner().get("x-message-ttl"),
+///     Some(&AMQPValue::LongInt(60_000))
+/// );
+/// ```
+///
+/// [`FieldTable`]: ./types/struct.FieldTable.html
+/// [`IntoAMQPValue`]: ./trait.IntoAMQPValue.html
+#[macro_export]
+macro_rules! fields {
+    ($($key:expr => $value:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut table = $crate::types::FieldTable::default();
+        $(
+            table.insert(
+                $key.into(),
+                $crate::IntoAMQPValue::into_amqp_value($value),
+            );
+        )*
+        table
+    }};
+}
+
+/// Convert a Rust value to the [`AMQPValue`] of the matching type
+///
+/// `u64` isn't supported, as AMQP only has unsigned 64 bits timestamps: use an `i64`, or an
+/// explicit `AMQPValue::Timestamp`.
+///
+/// [`AMQPValue`]: ./types/enum.AMQPValue.html
+pub trait IntoAMQPValue {
+    fn into_amqp_value(self) -> AMQPValue;
+}
+
+macro_rules! into_amqp_value {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl IntoAMQPValue for $ty {
+                fn into_amqp_value(self) -> AMQPValue {
+                    AMQPValue::$variant(self.into())
+                }
+            }
+        )*
+    };
+}
+
+into_amqp_value! {
+    bool => Boolean,
+    i8 => ShortShortInt,
+    u8 => ShortShortUInt,
+    i16 => ShortInt,
+    u16 => ShortUInt,
+    i32 => LongInt,
+    u32 => LongUInt,
+    i64 => LongLongInt,
+    f32 => Float,
+    f64 => Double,
+    &str => LongString,
+    String => LongString,
+    LongString => LongString,
+    ShortString => ShortString,
+    FieldTable => FieldTable,
+    FieldArray => FieldArray,
+    ByteArray => ByteArray,
+    &[u8] => ByteArray,
+    Decimal => DecimalValue,
+}
+
+impl IntoAMQPValue for AMQPValue {
+    fn into_amqp_value(self) -> AMQPValue {
+        self
+    }
+}
+
+/// Stored as a timestamp, truncated to the second
+impl IntoAMQPValue for SystemTime {
+    fn into_amqp_value(self) -> AMQPValue {
+        AMQPValue::Timestamp(timestamp::from_system_time(self))
+    }
+}
+
+impl<T: IntoAMQPValue> IntoAMQPValue for Vec<T> {
+    fn into_amqp_value(self) -> AMQPValue {
+        AMQPValue::FieldArray(FieldArray::from(
+            self.into_iter()
+                .map(IntoAMQPValue::into_amqp_value)
+                .collect::<Vec<_>>(),
+        ))
+    }
+}
+
+/// Typed accessors for the values of a [`FieldTable`], such as message headers or queue
+/// arguments.
+///
+/// The getters return `None` when the key is missing or when its value cannot be read as the
+/// requested type:
+/// - strings are read from short and long strings, the latter only when they're valid UTF-8
+/// - integers are read from any integer type or timestamp, as long as they fit in an `i64`, but
+///   never from floats or decimals
+/// - booleans, decimals, tables and arrays are only read from values of the same type
+/// - bytes are read from byte arrays and long strings
+///
+/// ```rust
+/// use lapin::{types::FieldTable, FieldTableExt};
+///
+/// let mut headers = FieldTable::default();
+/// headers.set_str("origin", "billing").set_i64("attempt", 1);
+/// headers.entry("attempt").and_modify(|attempt| *attempt += 1);
+/// assert_eq!(headers.get_str("origin"), Some("billing"));
+/// assert_eq!(headers.get_i64("attempt"), Some(2));
+/// headers.set_bytes("signature", &[0xca, 0xfe]);
+/// assert_eq!(
+///     format!("{:?}", headers.pretty()),
+///     r#"{"attempt": 2, "origin": "billing", "signature": <2 bytes: ca fe>}"#
+/// );
+/// ```
+///
+/// [`FieldTable`]: ./types/struct.FieldTable.html
+pub trait FieldTableExt {
+    fn get_str(&self, key: &str) -> Option<&str>;
+    fn get_i64(&self, key: &str) -> Option<i64>;
+    fn get_bool(&self, key: &str) -> Option<bool>;
+    fn get_table(&self, key: &str) -> Option<&FieldTable>;
+    fn get_array(&self, key: &str) -> Option<&[AMQPValue]>;
+    fn get_bytes(&self, key: &str) -> Option<&[u8]>;
+    /// Iterate over the tables of an array, such as the `x-death` header, skipping other values
+    fn get_tables(&self, key: &str) -> impl Iterator<Item = &FieldTable>;
+    fn get_decimal(&self, key: &str) -> Option<Decimal>;
+    /// Read a timestamp, or a non negative integer number of seconds since the UNIX epoch
+    fn get_timestamp(&self, key: &str) -> Option<SystemTime>;
+
+    /// Insert a long string
+    fn set_str(&mut self, key: &str, value: &str) -> &mut Self;
+    /// Insert a long long int
+    fn set_i64(&mut self, key: &str, value: i64) -> &mut Self;
+    fn set_bool(&mut self, key: &str, value: bool) -> &mut Self;
+    fn set_table(&mut self, key: &str, value: FieldTable) -> &mut Self;
+    fn set_array(&mut self, key: &str, values: Vec<AMQPValue>) -> &mut Self;
+    /// Insert a byte array
+    fn set_bytes(&mut self, key: &str, value: &[u8]) -> &mut Self;
+
+    /// Read or update the value of a key, like `BTreeMap::entry`
+    fn entry(&mut self, key: &str) -> FieldTableEntry<'_>;
+
+    /// Format the table for humans, with plain values and binaries truncated to their first bytes
+    fn pretty(&self) -> PrettyFields<'_>;
+}
+
+impl FieldTableExt for FieldTable {
+    fn get_str(&self, key: &str) -> Option<&str> {
+        self.inner().get(key).and_then(value_str)
+    }
+
+    fn get_i64(&self, key: &str) -> Option<i64> {
+        self.inner().get(key).and_then(value_i64)
+    }
+
+    fn get_bool(&self, key: &str) -> Option<bool> {
+        self.inner().get(key)?.as_bool()
+    }
+
+    fn get_table(&self, key: &str) -> Option<&FieldTable> {
+        self.inner().get(key)?.as_field_table()
+    }
+
+    fn get_array(&self, key: &str) -> Option<&[AMQPValue]> {
+        self.inner().get(key)?.as_array().map(FieldArray::as_slice)
+    }
+
+    fn get_bytes(&self, key: &str) -> Option<&[u8]> {
+        match self.inner().get(key)? {
+            AMQPValue::ByteArray(bytes) => Some(bytes.as_slice()),
+            AMQPValue::LongString(value) => Some(value.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn get_tables(&self, key: &str) -> impl Iterator<Item = &FieldTable> {
+        self.get_array(key)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(AMQPValue::as_field_table)
+    }
+
+    fn get_decimal(&self, key: &str) -> Option<Decimal> {
+        self.inner().get(key)?.as_decimal_value().map(Decimal::from)
+    }
+
+    fn get_timestamp(&self, key: &str) -> Option<SystemTime> {
+        timestamp::from_value(self.inner().get(key)?).map(timestamp::to_system_time)
+    }
+
+    fn set_str(&mut self, key: &str, value: &str) -> &mut Self {
+        self.insert(key.into(), AMQPValue::LongString(value.into()))
+    }
+
+    fn set_i64(&mut self, key: &str, value: i64) -> &mut Self {
+        self.insert(key.into(), AMQPValue::LongLongInt(value))
+    }
+
+    fn set_bool(&mut self, key: &str, value: bool) -> &mut Self {
+        self.insert(key.into(), AMQPValue::Boolean(value))
+    }
+
+    fn set_table(&mut self, key: &str, value: FieldTable) -> &mut Self {
+        self.insert(key.into(), AMQPValue::FieldTable(value))
+    }
+
+    fn set_array(&mut self, key: &str, values: Vec<AMQPValue>) -> &mut Self {
+        self.insert(key.into(), AMQPValue::FieldArray(values.into()))
+    }
+
+    fn set_bytes(&mut self, key: &str, value: &[u8]) -> &mut Self {
+        self.insert(key.into(), AMQPValue::ByteArray(value.into()))
+    }
+
+    fn entry(&mut self, key: &str) -> FieldTableEntry<'_> {
+        FieldTableEntry {
+            table: self,
+            key: key.into(),
+        }
+    }
+
+    fn pretty(&self) -> PrettyFields<'_> {
+        PrettyFields(self)
+    }
+}
+
+/// The human readable `Debug` formatting of a [`FieldTable`]
+///
+/// Created with [`FieldTableExt::pretty`]. Values are shown without their AMQP type, and
+/// binaries, which are byte arrays and long strings that aren't valid UTF-8, are shown as their
+/// length and first bytes.
+///
+/// [`FieldTable`]: ./types/struct.FieldTable.html
+/// [`FieldTableExt::pretty`]: ./trait.FieldTableExt.html#tymethod.pretty
+#[derive(Clone, Copy)]
+pub struct PrettyFields<'a>(&'a FieldTable);
+
+impl fmt::Debug for PrettyFields<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.0
+                    .into_iter()
+                    .map(|(key, value)| (key.as_str(), PrettyValue(value))),
+            )
+            .finish()
+    }
+}
+
+struct PrettyValue<'a>(&'a AMQPValue);
+
+impl fmt::Debug for PrettyValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            AMQPValue::Boolean(value) => value.fmt(f),
+            AMQPValue::ShortShortInt(value) => value.fmt(f),
+            AMQPValue::ShortShortUInt(value) => value.fmt(f),
+            AMQPValue::ShortInt(value) => value.fmt(f),
+            AMQPValue::ShortUInt(value) => value.fmt(f),
+            AMQPValue::LongInt(value) => value.fmt(f),
+            AMQPValue::LongUInt(value) => value.fmt(f),
+            AMQPValue::LongLongInt(value) => value.fmt(f),
+            AMQPValue::Float(value) => value.fmt(f),
+            AMQPValue::Double(value) => value.fmt(f),
+            AMQPValue::DecimalValue(decimal) => fmt::Display::fmt(&Decimal::from(*decimal), f),
+            AMQPValue::ShortString(value) => value.as_str().fmt(f),
+            AMQPValue::LongString(value) => match std::str::from_utf8(value.as_bytes()) {
+                Ok(value) => value.fmt(f),
+                Err(_) => fmt_binary(value.as_bytes(), f),
+            },
+            AMQPValue::FieldArray(values) => f
+                .debug_list()
+                .entries(values.as_slice().iter().map(PrettyValue))
+                .finish(),
+            AMQPValue::Timestamp(value) => write!(f, "Timestamp({})", value),
+            AMQPValue::FieldTable(table) => PrettyFields(table).fmt(f),
+            AMQPValue::ByteArray(bytes) => fmt_binary(bytes.as_slice(), f),
+            AMQPValue::Void => f.write_str("Void"),
+        }
+    }
+}
+
+fn fmt_binary(bytes: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "<{} bytes:", bytes.len())?;
+    for byte in bytes.iter().take(BINARY_PREVIEW_LEN) {
+        write!(f, " {:02x}", byte)?;
+    }
+    if bytes.len() > BINARY_PREVIEW_LEN {
+        f.write_str(" ...")?;
+    }
+    f.write_str(">")
+}
+
+/// A key of a [`FieldTable`], which may or may not have a value
+///
+/// Created with [`FieldTableExt::entry`].
+///
+/// [`FieldTable`]: ./types/struct.FieldTable.html
+/// [`FieldTableExt::entry`]: ./trait.FieldTableExt.html#tymethod.entry
+#[derive(Debug)]
+pub struct FieldTableEntry<'a> {
+    table: &'a mut FieldTable,
+    key: ShortString,
+}
+
+impl<'a> FieldTableEntry<'a> {
+    pub fn get(&self) -> Option<&AMQPValue> {
+        self.table.inner().get(&self.key)
+    }
+
+    /// Insert `default` if the key has no value, and get its value
+    pub fn or_insert(self, default: AMQPValue) -> &'a AMQPValue {
+        if !self.table.contains_key(self.key.as_str()) {
+            self.table.insert(self.key.clone(), default);
+        }
+        let table: &'a FieldTable = self.table;
+        &table.inner()[&self.key]
+    }
+
+    /// Update the value of the key as an integer, if it has one which can be read as such
+    ///
+    /// The updated value is stored as a long long int.
+    pub fn and_modify<F: FnOnce(&mut i64)>(self, f: F) -> Self {
+        if let Some(mut value) = self.get().and_then(value_i64) {
+            f(&mut value);
+            self.table
+                .insert(self.key.clone(), AMQPValue::LongLongInt(value));
+        }
+        self
+    }
+}
+
+fn value_str(value: &AMQPValue) -> Option<&str> {
+    match value {
+        AMQPValue::ShortString(value) => Some(value.as_str()),
+        AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok(),
+        _ => None,
+    }
+}
+
+fn value_i64(value: &AMQPValue) -> Option<i64> {
+    match *value {
+        AMQPValue::ShortShortInt(value) => Some(value.into()),
+        AMQPValue::ShortShortUInt(value) => Some(value.into()),
+        AMQPValue::ShortInt(value) => Some(value.into()),
+        AMQPValue::ShortUInt(value) => Some(value.into()),
+        AMQPValue::LongInt(value) => Some(value.into()),
+        AMQPValue::LongUInt(value) => Some(value.into()),
+        AMQPValue::LongLongInt(value) => Some(value),
+        AMQPValue::Timestamp(value) => value.try_into().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coercions() {
+        let mut death = FieldTable::default();
+        death
+            .set_str("queue", "jobs")
+            .insert("count".into(), AMQPValue::LongUInt(3));
+        let mut headers = FieldTable::default();
+        headers
+            .insert(
+                "x-death".into(),
+                AMQPValue::FieldArray(vec![AMQPValue::FieldTable(death)].into()),
+            )
+            .insert("kind".into(), AMQPValue::ShortString("retry".into()))
+            .insert("binary".into(), AMQPValue::LongString(vec![0xff].into()))
+            .insert("ratio".into(), AMQPValue::Double(0.5))
+            .insert("late".into(), AMQPValue::Timestamp(u64::MAX))
+            .set_bool("urgent", true);
+
+        let count = headers
+            .get_array("x-death")
+            .and_then(|deaths| deaths.first()?.as_field_table())
+            .and_then(|death| death.get_i64("count"));
+        assert_eq!(count, Some(3));
+        assert_eq!(headers.get_str("kind"), Some("retry"));
+        assert_eq!(headers.get_str("binary"), None);
+        assert_eq!(headers.get_i64("ratio"), None);
+        assert_eq!(headers.get_i64("late"), None);
+        assert_eq!(headers.get_i64("kind"), None);
+        assert_eq!(headers.get_bool("urgent"), Some(true));
+        assert_eq!(headers.get_table("missing"), None);
+        assert_eq!(headers.get_bytes("binary"), Some(&[0xff][..]));
+        assert_eq!(
+            headers
+                .get_tables("x-death")
+                .filter_map(|death| death.get_str("queue"))
+                .collect::<Vec<_>>(),
+            vec!["jobs"]
+        );
+        assert_eq!(headers.get_tables("missing").count(), 0);
+    }
+
+    #[test]
+    fn pretty() {
+        let mut headers = fields! {
+            "payload" => vec![0u8; 100].as_slice(),
+            "nested" => fields! {
+                "list" => vec![AMQPValue::Void, AMQPValue::Timestamp(1)],
+                "price" => Decimal::new(150, 2),
+            },
+        };
+        headers.set_array("ids", vec![AMQPValue::ShortString("a".into())]);
+        assert_eq!(
+            format!("{:?}", headers.pretty()),
+            concat!(
+                r#"{"ids": ["a"], "nested": {"list": [Void, Timestamp(1)], "price": 1.50}, "#,
+                r#""payload": <100 bytes: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 ...>}"#
+            )
+        );
+    }
+
+    #[test]
+    fn fields_macro() {
+        let table = fields! {
+            "x-max-length" => 10u16,
+            "x-dead-letter-exchange" => "dlx".to_string(),
+            "x-routing-keys" => vec!["a", "b"],
+            "x-nested" => fields! { "ok" => true },
+            "x-void" => AMQPValue::Void,
+        };
+        let mut expected = FieldTable::default();
+        expected
+            .insert("x-max-length".into(), AMQPValue::ShortUInt(10))
+            .set_str("x-dead-letter-exchange", "dlx")
+            .insert(
+                "x-routing-keys".into(),
+                AMQPValue::FieldArray(
+                    vec![
+                        AMQPValue::LongString("a".into()),
+                        AMQPValue::LongString("b".into()),
+                    ]
+                    .into(),
+                ),
+            )
+            .insert(
+                "x-nested".into(),
+                AMQPValue::FieldTable(fields! { "ok" => true }),
+            )
+            .insert("x-void".into(), AMQPValue::Void);
+        assert_eq!(table, expected);
+        assert_eq!(fields! {}, FieldTable::default());
+    }
+
+    #[test]
+    fn entry() {
+        let mut headers = FieldTable::default();
+        let retries = headers
+            .entry("retries")
+            .and_modify(|retries| *retries += 1)
+            .or_insert(AMQPValue::LongLongInt(0));
+        assert_eq!(retries, &AMQPValue::LongLongInt(0));
+        headers.insert("retries".into(), AMQPValue::ShortShortUInt(4));
+        headers.entry("retries").and_modify(|retries| *retries += 1);
+        assert_eq!(headers.get_i64("retries"), Some(5));
+        assert_eq!(
+            headers.inner().get("retries"),
+            Some(&AMQPValue::LongLongInt(5))
+        );
+    }
+}
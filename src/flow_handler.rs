@@ -0,0 +1,38 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+type FlowFn = Box<dyn FnMut(bool) + Send + 'static>;
+type Inner = Option<FlowFn>;
+
+#[derive(Clone)]
+pub(crate) struct FlowHandler(Arc<Mutex<Inner>>);
+
+impl FlowHandler {
+    pub(crate) fn set_handler<F: FnMut(bool) + Send + 'static>(&self, handler: F) {
+        *self.lock_inner() = Some(Box::new(handler));
+    }
+
+    pub(crate) fn on_flow(&self, active: bool) {
+        if let Some(handler) = self.lock_inner().as_mut() {
+            handler(active)
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for FlowHandler {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(None)))
+    }
+}
+
+impl fmt::Debug for FlowHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FlowHandler").finish()
+    }
+}
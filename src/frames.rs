@@ -1,5 +1,6 @@
 use crate::{
-    channel::Reply, promise::Cancelable, types::ChannelId, Error, Promise, PromiseResolver,
+    buffer_pool::BufferPool, channel::Reply, promise::Cancelable, secrets::RedactedFrame,
+    types::ChannelId, wakers::Wakers, Error, Promise, PromiseResolver,
 };
 use amq_protocol::{
     frame::AMQPFrame,
@@ -9,9 +10,92 @@ use std::{
     collections::{HashMap, VecDeque},
     fmt,
     sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
 };
 use tracing::{level_enabled, trace, Level};
 
+/// Number of content frames of a publish sent in a row before letting a frame from another
+/// channel through, so that a large publish doesn't delay RPCs, acks and heartbeats.
+const PUBLISH_FRAMES_QUOTA: usize = 16;
+
+type QueuedFrame = (OutgoingFrame, Option<PromiseResolver<()>>);
+
+/// A frame waiting to be written to the socket
+#[derive(Debug, PartialEq)]
+pub(crate) enum OutgoingFrame {
+    Frame(AMQPFrame),
+    /// The already serialized method and header frames of a publish
+    Serialized(ChannelId, Vec<u8>),
+}
+
+impl OutgoingFrame {
+    fn channel_id(&self) -> Option<ChannelId> {
+        match self {
+            OutgoingFrame::Frame(AMQPFrame::ProtocolHeader(_)) => None,
+            OutgoingFrame::Frame(
+                AMQPFrame::Method(id, _)
+                | AMQPFrame::Header(id, _, _)
+                | AMQPFrame::Body(id, _)
+                | AMQPFrame::Heartbeat(id),
+            )
+            | OutgoingFrame::Serialized(id, _) => Some(*id),
+        }
+    }
+
+    /* Heartbeats and settlements of deliveries, which would time out if delayed by publishes */
+    fn is_control(&self) -> bool {
+        matches!(
+            self,
+            OutgoingFrame::Frame(
+                AMQPFrame::Heartbeat(_)
+                    | AMQPFrame::Method(
+                        _,
+                        AMQPClass::Basic(
+                            AMQPMethod::Ack(_) | AMQPMethod::Nack(_) | AMQPMethod::Reject(_)
+                        )
+                    )
+            )
+        )
+    }
+
+    fn is_header(&self) -> bool {
+        matches!(self, OutgoingFrame::Frame(frame) if frame.is_header())
+    }
+
+    fn is_content(&self) -> bool {
+        matches!(
+            self,
+            OutgoingFrame::Frame(AMQPFrame::Header(..) | AMQPFrame::Body(..))
+        )
+    }
+
+    fn body_size(&self) -> usize {
+        match self {
+            OutgoingFrame::Frame(AMQPFrame::Body(_, payload)) => payload.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl From<AMQPFrame> for OutgoingFrame {
+    fn from(frame: AMQPFrame) -> Self {
+        OutgoingFrame::Frame(frame)
+    }
+}
+
+impl fmt::Display for OutgoingFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutgoingFrame::Frame(frame) => RedactedFrame(frame).fmt(f),
+            OutgoingFrame::Serialized(channel_id, bytes) => f.write_fmt(format_args!(
+                "OutgoingFrame::Serialized({}, {} bytes)",
+                channel_id,
+                bytes.len()
+            )),
+        }
+    }
+}
+
 pub(crate) struct ExpectedReply(pub(crate) Reply, pub(crate) Box<dyn Cancelable + Send>);
 
 impl fmt::Debug for ExpectedReply {
@@ -21,9 +105,38 @@ impl fmt::Debug for ExpectedReply {
 }
 
 #[derive(Clone, Default)]
-pub(crate) struct Frames(Arc<Mutex<Inner>>);
+pub(crate) struct Frames {
+    inner: Arc<Mutex<Inner>>,
+    buffers: BufferPool,
+}
 
 impl Frames {
+    /// Limit the number of bytes of publish payloads waiting to be written to the socket, keep
+    /// up to buffer_pool_size buffers around for the body frames, and send heartbeats and acks
+    /// ahead of the other frames if prioritize_control_frames is set
+    pub(crate) fn new(
+        capacity: Option<usize>,
+        buffer_pool_size: usize,
+        prioritize_control_frames: bool,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                capacity,
+                prioritize_control_frames,
+                ..Default::default()
+            })),
+            buffers: BufferPool::new(buffer_pool_size),
+        }
+    }
+
+    pub(crate) fn body_buffer(&self, data: &[u8]) -> Vec<u8> {
+        self.buffers.get(data)
+    }
+
+    pub(crate) fn recycle_body_buffer(&self, buffer: Vec<u8>) {
+        self.buffers.recycle(buffer);
+    }
+
     pub(crate) fn push(
         &self,
         channel_id: ChannelId,
@@ -35,15 +148,22 @@ impl Frames {
             .push(channel_id, frame, resolver, expected_reply);
     }
 
-    pub(crate) fn push_frames(&self, frames: Vec<AMQPFrame>) -> Promise<()> {
+    /// Queue the frames of a publish at once, which then get sent without any other frame of
+    /// their channel in between
+    pub(crate) fn push_frames(&self, frames: Vec<OutgoingFrame>) -> Promise<()> {
         self.lock_inner().push_frames(frames)
     }
 
-    pub(crate) fn retry(&self, frame: (AMQPFrame, Option<PromiseResolver<()>>)) {
+    /// Wait for enough capacity to queue a publish with a payload of the given size, and reserve it
+    pub(crate) fn poll_reserve(&self, size: usize, cx: &mut Context<'_>) -> Poll<()> {
+        self.lock_inner().poll_reserve(size, cx)
+    }
+
+    pub(crate) fn retry(&self, frame: QueuedFrame) {
         self.lock_inner().retry_frames.push_back(frame);
     }
 
-    pub(crate) fn pop(&self, flow: bool) -> Option<(AMQPFrame, Option<PromiseResolver<()>>)> {
+    pub(crate) fn pop(&self, flow: bool) -> Option<QueuedFrame> {
         self.lock_inner().pop(flow)
     }
 
@@ -107,7 +227,7 @@ impl Frames {
     }
 
     fn lock_inner(&self) -> MutexGuard<'_, Inner> {
-        self.0.lock().unwrap_or_else(|e| e.into_inner())
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
     }
 }
 
@@ -115,10 +235,21 @@ impl Frames {
 struct Inner {
     /* Header frames must follow basic.publish frames directly, otherwise RabbitMQ-server send us an UNEXPECTED_FRAME */
     /* After sending the Header frame, we need to send the associated Body frames before anything else for the same reason */
-    publish_frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-    retry_frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-    frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-    low_prio_frames: VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
+    publish_frames: VecDeque<QueuedFrame>,
+    /* Number of publish_frames sent since we last let another channel's frame through */
+    publish_frames_sent: usize,
+    retry_frames: VecDeque<QueuedFrame>,
+    /* Heartbeats, acks, nacks and rejects, sent before anything but the frames queued earlier for their channel */
+    prioritize_control_frames: bool,
+    control_frames: VecDeque<QueuedFrame>,
+    frames: VecDeque<QueuedFrame>,
+    /* Publishes are queued per channel and sent in a round-robin fashion between channels */
+    low_prio_frames: HashMap<ChannelId, VecDeque<QueuedFrame>>,
+    low_prio_channels: VecDeque<ChannelId>,
+    /* Bytes of publish payloads queued, bounded by capacity if any */
+    capacity: Option<usize>,
+    queued_bytes: usize,
+    capacity_wakers: Wakers,
     expected_replies: HashMap<ChannelId, VecDeque<ExpectedReply>>,
     poison: Option<Error>,
 }
@@ -126,7 +257,7 @@ struct Inner {
 impl fmt::Debug for Frames {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut debug = f.debug_struct("Frames");
-        if let Ok(inner) = self.0.try_lock() {
+        if let Ok(inner) = self.inner.try_lock() {
             debug.field("expected_replies", &inner.expected_replies);
         }
         debug.finish()
@@ -145,7 +276,12 @@ impl Inner {
             return;
         }
 
-        self.frames.push_back((frame, Some(resolver)));
+        let frame = OutgoingFrame::from(frame);
+        if self.prioritize_control_frames && frame.is_control() {
+            self.control_frames.push_back((frame, Some(resolver)));
+        } else {
+            self.frames.push_back((frame, Some(resolver)));
+        }
         if let Some(reply) = expected_reply {
             trace!(
                 channel=%channel_id,
@@ -159,7 +295,7 @@ impl Inner {
         }
     }
 
-    fn push_frames(&mut self, mut frames: Vec<AMQPFrame>) -> Promise<()> {
+    fn push_frames(&mut self, mut frames: Vec<OutgoingFrame>) -> Promise<()> {
         let (promise, resolver) = Promise::new();
         let last_frame = frames.pop();
 
@@ -171,17 +307,53 @@ impl Inner {
             promise.set_marker("Frames".into());
         }
 
-        for frame in frames {
-            self.low_prio_frames.push_back((frame, None));
-        }
-        if let Some(last_frame) = last_frame {
-            self.low_prio_frames.push_back((last_frame, Some(resolver)));
-        } else {
+        let Some(last_frame) = last_frame else {
             resolver.resolve(());
+            return promise;
+        };
+        let channel_id = last_frame.channel_id().unwrap_or_default();
+        let queue = self.low_prio_frames.entry(channel_id).or_default();
+        if queue.is_empty() {
+            self.low_prio_channels.push_back(channel_id);
         }
+        queue.extend(frames.into_iter().map(|frame| (frame, None)));
+        queue.push_back((last_frame, Some(resolver)));
         promise
     }
 
+    fn poll_reserve(&mut self, size: usize, cx: &mut Context<'_>) -> Poll<()> {
+        let available = match self.capacity {
+            // Always accept a publish when nothing is queued, even if it's bigger than the capacity
+            Some(capacity) => self.queued_bytes == 0 || self.queued_bytes + size <= capacity,
+            None => true,
+        };
+        if available || self.poison.is_some() {
+            self.queued_bytes += size;
+            Poll::Ready(())
+        } else {
+            self.capacity_wakers.register(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    fn release(&mut self, frame: &QueuedFrame) {
+        let size = frame.0.body_size();
+        if size > 0 {
+            self.queued_bytes = self.queued_bytes.saturating_sub(size);
+            self.capacity_wakers.wake();
+        }
+    }
+
+    fn reset_queued_bytes(&mut self) {
+        self.queued_bytes = self
+            .publish_frames
+            .iter()
+            .chain(self.low_prio_frames.values().flatten())
+            .map(|(frame, _)| frame.body_size())
+            .sum();
+        self.capacity_wakers.wake();
+    }
+
     fn check_poison(&self, resolver: &PromiseResolver<()>) -> bool {
         if let Some(error) = self.poison.clone() {
             resolver.reject(error);
@@ -190,78 +362,131 @@ impl Inner {
         false
     }
 
-    fn pop(&mut self, flow: bool) -> Option<(AMQPFrame, Option<PromiseResolver<()>>)> {
-        if let Some(frame) = self
-            .retry_frames
-            .pop_front()
-            .or_else(|| self.publish_frames.pop_front())
-            .or_else(|| self.frames.pop_front())
-        {
+    fn pop(&mut self, flow: bool) -> Option<QueuedFrame> {
+        if let Some(frame) = self.retry_frames.pop_front() {
             return Some(frame);
         }
-        if flow {
-            if let Some(frame) = self.low_prio_frames.pop_front() {
-                // If the next frame is a header, that means we're a basic.publish
-                // Header frame needs to follow directly the basic.publish frame, and Body frames
-                // need to be sent just after those or the AMQP server will close the connection.
-                // Push the header into publish_frames which is there to handle just that.
-                if self
-                    .low_prio_frames
-                    .front()
-                    .map(|(frame, _)| frame.is_header())
-                    .unwrap_or(false)
+        let publishing = self
+            .publish_frames
+            .front()
+            .map(|(frame, _)| frame.channel_id());
+        if let Some(frame) = self.pop_control(publishing) {
+            return Some(frame);
+        }
+        if let Some(publishing) = self
+            .publish_frames
+            .front()
+            .map(|(frame, _)| frame.channel_id())
+        {
+            // Frames for the publishing channel have to wait for the end of the publish, but other
+            // channels can be interleaved.
+            if self.publish_frames_sent >= PUBLISH_FRAMES_QUOTA {
+                if let Some(idx) = self
+                    .frames
+                    .iter()
+                    .position(|(frame, _)| frame.channel_id() != publishing)
                 {
-                    // Yes, this will always be Some() with a Header frame, but let's keep our unwrap() count low
-                    if let Some(next_frame) = self.low_prio_frames.pop_front() {
-                        self.publish_frames.push_back(next_frame);
-                    }
-                    while let Some(next_frame) = self.low_prio_frames.pop_front() {
-                        match next_frame.0 {
-                            AMQPFrame::Body(..) => {
-                                self.publish_frames.push_back(next_frame);
-                            }
-                            _ => {
-                                // We've exhausted Body frames for this publish, push back the next one and exit
-                                self.low_prio_frames.push_front(next_frame);
-                                break;
-                            }
-                        }
-                    }
+                    self.publish_frames_sent = 0;
+                    return self.frames.remove(idx);
                 }
-                return Some(frame);
             }
+            self.publish_frames_sent += 1;
+            let frame = self.publish_frames.pop_front();
+            if let Some(frame) = frame.as_ref() {
+                self.release(frame);
+            }
+            return frame;
+        }
+        self.publish_frames_sent = 0;
+        if let Some(frame) = self.frames.pop_front() {
+            return Some(frame);
+        }
+        if flow {
+            return self.pop_low_prio();
         }
         None
     }
 
+    /* The first control frame which can be sent without reordering the frames of its channel */
+    fn pop_control(&mut self, publishing: Option<Option<ChannelId>>) -> Option<QueuedFrame> {
+        let idx = self.control_frames.iter().position(|(frame, _)| {
+            let channel_id = frame.channel_id();
+            publishing != Some(channel_id)
+                && !self
+                    .frames
+                    .iter()
+                    .any(|(frame, _)| frame.channel_id() == channel_id)
+        })?;
+        self.control_frames.remove(idx)
+    }
+
+    fn pop_low_prio(&mut self) -> Option<QueuedFrame> {
+        let channel_id = self.low_prio_channels.pop_front()?;
+        let queue = self.low_prio_frames.get_mut(&channel_id)?;
+        let frame = queue.pop_front()?;
+        // If the next frame is a header, that means we're a basic.publish (unless the header was
+        // already serialized along with the method).
+        // Header frame needs to follow directly the basic.publish frame, and Body frames
+        // need to be sent just after those or the AMQP server will close the connection.
+        // Push them into publish_frames which is there to handle just that.
+        if matches!(frame.0, OutgoingFrame::Serialized(..))
+            || queue
+                .front()
+                .map(|(frame, _)| frame.is_header())
+                .unwrap_or(false)
+        {
+            while queue
+                .front()
+                .map(|(frame, _)| frame.is_content())
+                .unwrap_or(false)
+            {
+                // Yes, this will always be Some(), but let's keep our unwrap() count low
+                if let Some(next_frame) = queue.pop_front() {
+                    self.publish_frames.push_back(next_frame);
+                }
+            }
+        }
+        if queue.is_empty() {
+            self.low_prio_frames.remove(&channel_id);
+        } else {
+            // Give the other channels a chance to publish before our next message
+            self.low_prio_channels.push_back(channel_id);
+        }
+        Some(frame)
+    }
+
     fn has_pending(&self) -> bool {
         !(self.retry_frames.is_empty()
             && self.publish_frames.is_empty()
+            && self.control_frames.is_empty()
             && self.frames.is_empty()
-            && self.low_prio_frames.is_empty())
+            && self.low_prio_channels.is_empty())
     }
 
     fn drop_pending(&mut self, error: Error) {
         Self::drop_pending_frames(&mut self.retry_frames, error.clone());
         Self::drop_pending_frames(&mut self.publish_frames, error.clone());
+        Self::drop_pending_frames(&mut self.control_frames, error.clone());
         Self::drop_pending_frames(&mut self.frames, error.clone());
-        Self::drop_pending_frames(&mut self.low_prio_frames, error.clone());
+        self.low_prio_channels.clear();
+        for (_, mut frames) in self.low_prio_frames.drain() {
+            Self::drop_pending_frames(&mut frames, error.clone());
+        }
         for (_, replies) in self.expected_replies.drain() {
             Self::cancel_expected_replies(replies, error.clone());
         }
         self.poison = Some(error);
+        self.reset_queued_bytes();
     }
 
-    fn drop_pending_frames(
-        frames: &mut VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
-        error: Error,
-    ) {
+    fn drop_pending_frames(frames: &mut VecDeque<QueuedFrame>, error: Error) {
         for (frame, resolver) in std::mem::take(frames) {
             if let Some(resolver) = resolver {
                 match frame {
-                    AMQPFrame::Method(_, AMQPClass::Basic(AMQPMethod::Cancel(_))) => {
-                        resolver.resolve(())
-                    }
+                    OutgoingFrame::Frame(AMQPFrame::Method(
+                        _,
+                        AMQPClass::Basic(AMQPMethod::Cancel(_)),
+                    )) => resolver.resolve(()),
                     _ => resolver.reject(error.clone()),
                 }
             }
@@ -271,25 +496,29 @@ impl Inner {
     fn drop_frames_for_channel(&mut self, channel_id: ChannelId, error: Error) {
         Self::drop_pending_frames_for_channel(channel_id, &mut self.retry_frames, error.clone());
         Self::drop_pending_frames_for_channel(channel_id, &mut self.publish_frames, error.clone());
+        Self::drop_pending_frames_for_channel(channel_id, &mut self.control_frames, error.clone());
         Self::drop_pending_frames_for_channel(channel_id, &mut self.frames, error.clone());
-        Self::drop_pending_frames_for_channel(channel_id, &mut self.low_prio_frames, error);
+        self.low_prio_channels.retain(|id| *id != channel_id);
+        if let Some(mut frames) = self.low_prio_frames.remove(&channel_id) {
+            Self::drop_pending_frames_for_channel(channel_id, &mut frames, error);
+        }
+        self.reset_queued_bytes();
     }
 
     fn drop_pending_frames_for_channel(
         channel_id: ChannelId,
-        frames: &mut VecDeque<(AMQPFrame, Option<PromiseResolver<()>>)>,
+        frames: &mut VecDeque<QueuedFrame>,
         error: Error,
     ) {
-        use AMQPFrame::*;
-
-        frames.retain(|(f, r)| match f {
-            Method(id, _) | Header(id, _, _) | Body(id, _) | Heartbeat(id) if *id == channel_id => {
+        frames.retain(|(f, r)| {
+            if f.channel_id() == Some(channel_id) {
                 if let Some(r) = r {
                     r.reject(error.clone());
                 }
                 false
+            } else {
+                true
             }
-            _ => true,
         })
     }
 
@@ -318,3 +547,205 @@ impl Inner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amq_protocol::{
+        frame::AMQPContentHeader,
+        protocol::{basic, channel},
+    };
+
+    fn publish(channel_id: ChannelId, bodies: usize) -> Vec<OutgoingFrame> {
+        let mut frames = vec![
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Publish(basic::Publish {
+                    exchange: "".into(),
+                    routing_key: "".into(),
+                    mandatory: false,
+                    immediate: false,
+                })),
+            ),
+            AMQPFrame::Header(
+                channel_id,
+                60,
+                Box::new(AMQPContentHeader {
+                    class_id: 60,
+                    body_size: 0,
+                    properties: Default::default(),
+                }),
+            ),
+        ];
+        frames.extend((0..bodies).map(|_| AMQPFrame::Body(channel_id, vec![0; 10])));
+        frames.into_iter().map(OutgoingFrame::from).collect()
+    }
+
+    fn flow_ok(channel_id: ChannelId) -> AMQPFrame {
+        AMQPFrame::Method(
+            channel_id,
+            AMQPClass::Channel(channel::AMQPMethod::FlowOk(channel::FlowOk {
+                active: true,
+            })),
+        )
+    }
+
+    fn pop_all(frames: &Frames) -> Vec<OutgoingFrame> {
+        std::iter::from_fn(|| frames.pop(true))
+            .map(|(frame, _)| frame)
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_between_channels() {
+        let frames = Frames::default();
+        drop(frames.push_frames(publish(1, 1)));
+        drop(frames.push_frames(publish(1, 1)));
+        drop(frames.push_frames(publish(2, 1)));
+        let channels = pop_all(&frames)
+            .iter()
+            .filter(|frame| matches!(frame, OutgoingFrame::Frame(AMQPFrame::Method(..))))
+            .filter_map(OutgoingFrame::channel_id)
+            .collect::<Vec<_>>();
+        assert_eq!(channels, vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn large_publish_does_not_starve_other_channels() {
+        let frames = Frames::default();
+        drop(frames.push_frames(publish(1, 100)));
+        // Start the publish
+        frames.pop(true);
+        frames.push(1, flow_ok(1), Promise::new().1, None);
+        frames.push(2, flow_ok(2), Promise::new().1, None);
+        let sent = pop_all(&frames);
+        let position = |channel_id| {
+            sent.iter()
+                .position(|frame| *frame == flow_ok(channel_id).into())
+                .unwrap()
+        };
+        assert_eq!(position(2), PUBLISH_FRAMES_QUOTA);
+        // Frames for the publishing channel wait for the end of the publish
+        assert_eq!(position(1), sent.len() - 1);
+    }
+
+    #[test]
+    fn control_frames_skip_the_queue() {
+        let ack = |channel_id| {
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag: 1,
+                    multiple: false,
+                })),
+            )
+        };
+        let frames = Frames::new(None, 0, true);
+        drop(frames.push_frames(publish(1, 100)));
+        // Start the publish
+        frames.pop(true);
+        frames.push(2, flow_ok(2), Promise::new().1, None);
+        frames.push(3, flow_ok(3), Promise::new().1, None);
+        frames.push(1, ack(1), Promise::new().1, None);
+        frames.push(3, ack(3), Promise::new().1, None);
+        frames.push(2, ack(2), Promise::new().1, None);
+        frames.push(0, AMQPFrame::Heartbeat(0), Promise::new().1, None);
+        let sent = pop_all(&frames);
+        let position = |frame: AMQPFrame| {
+            sent.iter()
+                .position(|sent| *sent == frame.clone().into())
+                .unwrap()
+        };
+        assert_eq!(position(AMQPFrame::Heartbeat(0)), 0);
+        // Frames queued earlier for the same channel are still sent first
+        assert_eq!(position(flow_ok(2)), PUBLISH_FRAMES_QUOTA + 1);
+        assert_eq!(position(ack(2)), PUBLISH_FRAMES_QUOTA + 2);
+        assert_eq!(position(flow_ok(3)), 2 * PUBLISH_FRAMES_QUOTA + 3);
+        assert_eq!(position(ack(3)), 2 * PUBLISH_FRAMES_QUOTA + 4);
+        // The publishing channel waits for the end of the publish
+        assert_eq!(position(ack(1)), sent.len() - 1);
+    }
+
+    #[test]
+    fn bounded_publish_buffer() {
+        use futures_lite::future::{block_on, poll_fn, poll_once};
+
+        let frames = Frames::new(Some(25), 0, false);
+        let reserve = |size| block_on(poll_once(poll_fn(|cx| frames.poll_reserve(size, cx))));
+        assert!(reserve(20).is_some());
+        drop(frames.push_frames(publish(1, 2)));
+        assert!(reserve(10).is_none());
+        // Send the method, header and first body frames
+        frames.pop(true);
+        frames.pop(true);
+        frames.pop(true);
+        assert!(reserve(10).is_some());
+    }
+
+    #[test]
+    fn publishes_are_never_interleaved() {
+        const BODIES: usize = 40;
+
+        let ack = |channel_id| {
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                    delivery_tag: 1,
+                    multiple: false,
+                })),
+            )
+        };
+        let frames = Frames::new(None, 0, true);
+        drop(frames.push_frames(publish(1, BODIES)));
+        drop(frames.push_frames(publish(2, BODIES)));
+        let mut pushed = 2;
+        let mut sent = Vec::new();
+        let mut popped = 0;
+        while let Some((frame, resolver)) = frames.pop(true) {
+            popped += 1;
+            // The socket buffer being full, the frame gets written later
+            if popped % 7 == 0 {
+                frames.retry((frame, resolver));
+                continue;
+            }
+            sent.push(frame);
+            // Other tasks keep on using the channels meanwhile
+            if popped < 500 {
+                match popped % 5 {
+                    0 => {
+                        pushed += 1;
+                        drop(frames.push_frames(publish(1, BODIES)));
+                    }
+                    1 => frames.push(1, ack(1), Promise::new().1, None),
+                    2 => frames.push(2, flow_ok(2), Promise::new().1, None),
+                    3 => frames.push(0, AMQPFrame::Heartbeat(0), Promise::new().1, None),
+                    _ => frames.push(1, flow_ok(1), Promise::new().1, None),
+                }
+            }
+        }
+
+        let mut remaining = HashMap::<Option<ChannelId>, usize>::new();
+        let mut publishes = 0;
+        for frame in &sent {
+            let remaining = remaining.entry(frame.channel_id()).or_default();
+            if frame.is_content() {
+                assert!(*remaining > 0, "{} outside of a publish", frame);
+                *remaining -= 1;
+            } else {
+                assert_eq!(*remaining, 0, "{} in the middle of a publish", frame);
+                if matches!(
+                    frame,
+                    OutgoingFrame::Frame(AMQPFrame::Method(
+                        _,
+                        AMQPClass::Basic(basic::AMQPMethod::Publish(_))
+                    ))
+                ) {
+                    publishes += 1;
+                    *remaining = BODIES + 1;
+                }
+            }
+        }
+        assert!(remaining.values().all(|remaining| *remaining == 0));
+        assert_eq!(publishes, pushed);
+    }
+}
@@ -191,7 +191,7 @@ use options::*;
 #[derive(Debug)]
 #[allow(clippy::enum_variant_names)]
 pub(crate) enum Reply {
-    BasicQosOk(PromiseResolver<()>),
+    BasicQosOk(PromiseResolver<()>, ShortUInt, BasicQosOptions),
     BasicConsumeOk(
         PromiseResolver<Consumer>,
         Option<Arc<ChannelCloser>>,
@@ -405,7 +405,7 @@ impl Channel {
             method,
             send_resolver,
             Some(ExpectedReply(
-                Reply::BasicQosOk(resolver.clone()),
+                Reply::BasicQosOk(resolver.clone(), prefetch_count, options),
                 Box::new(resolver),
             )),
         );
@@ -421,8 +421,8 @@ impl Channel {
             .frames
             .find_expected_reply(self.id, |reply| matches!(&reply.0, Reply::BasicQosOk(..)))
         {
-            Some(Reply::BasicQosOk(resolver)) => {
-                let res = Ok(());
+            Some(Reply::BasicQosOk(resolver, prefetch_count, options)) => {
+                let res = self.on_basic_qos_ok_received(prefetch_count, options);
                 resolver.complete(res.clone());
                 res
             }
@@ -629,10 +629,12 @@ impl Channel {
         payload: &[u8],
         properties: BasicProperties,
     ) -> Result<PublisherConfirm> {
-        if !self.status.connected() {
+        if !self.status.connected_or_recovering() {
             return Err(self.status.state_error());
         }
 
+        self.throttle_basic_publish(payload).await?;
+        let (options, properties) = self.prepare_basic_publish(options, properties);
         let start_hook_res = self.before_basic_publish();
         let BasicPublishOptions {
             mandatory,
@@ -777,6 +779,7 @@ impl Channel {
             promise.set_marker("basic.reject".into());
         }
         self.send_method_frame(method, send_resolver, None);
+        self.on_basic_reject_sent(delivery_tag);
         promise.await
     }
     pub async fn basic_recover_async(&self, options: BasicRecoverAsyncOptions) -> Result<()> {
@@ -849,7 +852,7 @@ impl Channel {
             ),
         }
     }
-    pub async fn basic_nack(
+    async fn do_basic_nack(
         &self,
         delivery_tag: LongLongUInt,
         options: BasicNackOptions,
@@ -1664,6 +1667,7 @@ impl Channel {
             return Err(self.status.state_error());
         }
 
+        self.require_capability("exchange_exchange_bindings")?;
         let creation_arguments = arguments.clone();
         let ExchangeBindOptions { nowait } = options;
         let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Bind(
@@ -1750,6 +1754,7 @@ impl Channel {
             return Err(self.status.state_error());
         }
 
+        self.require_capability("exchange_exchange_bindings")?;
         let creation_arguments = arguments.clone();
         let ExchangeUnbindOptions { nowait } = options;
         let method = AMQPClass::Exchange(protocol::exchange::AMQPMethod::Unbind(
@@ -1824,7 +1829,7 @@ impl Channel {
             ),
         }
     }
-    pub async fn queue_declare(
+    async fn do_queue_declare(
         &self,
         queue: &str,
         options: QueueDeclareOptions,
@@ -2346,6 +2351,7 @@ impl Channel {
             return Err(self.status.state_error());
         }
 
+        self.require_capability("publisher_confirms")?;
         let ConfirmSelectOptions { nowait } = options;
         let method = AMQPClass::Confirm(protocol::confirm::AMQPMethod::Select(
             protocol::confirm::Select { nowait },
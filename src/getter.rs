@@ -0,0 +1,112 @@
+use crate::{
+    backoff::Backoff, message::BasicGetMessage, options::BasicGetOptions, types::ShortString,
+    Channel, Result,
+};
+use futures_core::stream::Stream;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tracing::trace;
+
+type NextMessage = Pin<Box<dyn Future<Output = Result<BasicGetMessage>> + Send>>;
+
+/// Stream of messages retrieved from a queue by polling it with
+/// [basic.get](https://www.rabbitmq.com/amqp-0-9-1-quickref.html#basic.get).
+///
+/// This is a lightweight alternative to a [`Consumer`] for low-volume queues.
+/// While the queue is empty, the next poll is delayed according to the configured [`Backoff`].
+///
+/// The stream ends after yielding an error (e.g. if the channel gets closed).
+///
+/// A getter is obtained by calling [`Channel::getter`].
+///
+/// [`Consumer`]: ./struct.Consumer.html
+/// [`Backoff`]: ./struct.Backoff.html
+/// [`Channel::getter`]: ./struct.Channel.html#method.getter
+pub struct Getter {
+    channel: Channel,
+    queue: ShortString,
+    options: BasicGetOptions,
+    backoff: Backoff,
+    next: Option<NextMessage>,
+    done: bool,
+}
+
+impl Getter {
+    pub(crate) fn new(
+        channel: Channel,
+        queue: ShortString,
+        options: BasicGetOptions,
+        backoff: Backoff,
+    ) -> Self {
+        Self {
+            channel,
+            queue,
+            options,
+            backoff,
+            next: None,
+            done: false,
+        }
+    }
+
+    /// Get the name of the queue we're polling
+    pub fn queue(&self) -> ShortString {
+        self.queue.clone()
+    }
+
+    fn next_message(&self) -> NextMessage {
+        let channel = self.channel.clone();
+        let queue = self.queue.clone();
+        let options = self.options;
+        let backoff = self.backoff;
+        Box::pin(async move {
+            let mut attempt = 0;
+            loop {
+                if let Some(message) = channel.basic_get(queue.as_str(), options).await? {
+                    return Ok(message);
+                }
+                let delay = backoff.delay(attempt);
+                trace!(%queue, ?delay, "queue is empty, waiting before polling again");
+                channel.sleep(delay).await;
+                attempt = attempt.saturating_add(1);
+            }
+        })
+    }
+}
+
+impl fmt::Debug for Getter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Getter")
+            .field("queue", &self.queue)
+            .field("options", &self.options)
+            .field("backoff", &self.backoff)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl Stream for Getter {
+    type Item = Result<BasicGetMessage>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let mut next = self.next.take().unwrap_or_else(|| self.next_message());
+        match next.as_mut().poll(cx) {
+            Poll::Pending => {
+                self.next = Some(next);
+                Poll::Pending
+            }
+            Poll::Ready(res) => {
+                if res.is_err() {
+                    self.done = true;
+                }
+                Poll::Ready(Some(res))
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{Connection, ConnectionState, Error};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// How thoroughly [`Connection::health_check`] checks the connection
+///
+/// [`Connection::health_check`]: ../struct.Connection.html#method.health_check
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HealthCheck {
+    /// Only look at the current state of the connection, without any network round trip
+    #[default]
+    Passive,
+    /// Open then close a scratch channel, checking that the broker actually answers
+    ScratchChannel,
+}
+
+/// The result of a health check
+#[derive(Clone, Debug)]
+pub struct HealthStatus {
+    /// State of the connection
+    pub state: ConnectionState,
+    /// Whether the broker blocked publishing on the connection (e.g. because of a memory alarm)
+    pub blocked: bool,
+    /// Time taken by the round trip to the broker, for [`HealthCheck::ScratchChannel`]
+    pub latency: Option<Duration>,
+    /// The error which made the round trip to the broker fail
+    pub error: Option<Error>,
+}
+
+impl HealthStatus {
+    /// Whether the connection can be used to communicate with the broker
+    ///
+    /// A blocked connection is still considered healthy as it can consume messages.
+    pub fn healthy(&self) -> bool {
+        self.state == ConnectionState::Connected && self.error.is_none()
+    }
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.state)?;
+        if self.blocked {
+            write!(f, ", blocked")?;
+        }
+        if let Some(latency) = self.latency {
+            write!(f, ", latency: {:?}", latency)?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ", error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Aggregated health of several named connections, to back a readiness endpoint
+///
+/// ```rust,no_run
+/// use lapin::{health::{HealthCheck, Healthz}, Connection, ConnectionProperties};
+/// use std::sync::Arc;
+///
+/// # async_global_executor::block_on(async {
+/// let connection = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+/// let healthz = Healthz::default();
+/// healthz.register("orders", Arc::new(connection));
+/// // In the readiness handler
+/// let report = healthz.check(HealthCheck::Passive).await;
+/// let http_status = if report.healthy() { 200 } else { 503 };
+/// println!("{} {}", http_status, report);
+/// # Ok::<(), lapin::Error>(())
+/// # });
+/// ```
+#[derive(Clone, Default)]
+pub struct Healthz(Arc<Mutex<Connections>>);
+
+type Connections = Vec<(String, Arc<Connection>)>;
+
+impl Healthz {
+    /// Register a connection to be checked, replacing any connection with the same name.
+    pub fn register(&self, name: &str, connection: Arc<Connection>) {
+        let mut connections = self.lock_inner();
+        connections.retain(|(n, _)| n != name);
+        connections.push((name.into(), connection));
+    }
+
+    pub fn unregister(&self, name: &str) {
+        self.lock_inner().retain(|(n, _)| n != name);
+    }
+
+    /// Check all the registered connections.
+    pub async fn check(&self, check: HealthCheck) -> HealthReport {
+        let connections = self.lock_inner().clone();
+        let mut report = HealthReport::default();
+        for (name, connection) in connections {
+            let status = connection.health_check(check).await;
+            report.connections.push((name, status));
+        }
+        report
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Connections> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Healthz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.lock_inner().iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+/// The health of all the connections registered in a [`Healthz`]
+#[derive(Clone, Debug, Default)]
+pub struct HealthReport {
+    pub connections: Vec<(String, HealthStatus)>,
+}
+
+impl HealthReport {
+    /// Whether all the connections are healthy
+    pub fn healthy(&self) -> bool {
+        self.connections.iter().all(|(_, status)| status.healthy())
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, status) in &self.connections {
+            writeln!(
+                f,
+                "{}: {} ({})",
+                name,
+                if status.healthy() { "ok" } else { "ko" },
+                status
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::MockBroker, ConnectionProperties};
+
+    #[test]
+    fn check_connections() {
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = Arc::new(broker.connect(ConnectionProperties::default()).await?);
+            let healthz = Healthz::default();
+            healthz.register("main", connection.clone());
+
+            let report = healthz.check(HealthCheck::ScratchChannel).await;
+            assert!(report.healthy());
+            assert!(report.connections[0].1.latency.is_some());
+
+            connection.close(200, "OK").await?;
+            let report = healthz.check(HealthCheck::ScratchChannel).await;
+            assert!(!report.healthy());
+            assert_ne!(report.connections[0].1.state, ConnectionState::Connected);
+            Ok::<(), Error>(())
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{
+    options::BasicPublishOptions,
+    ordered_publisher::OrderedPublisher,
+    publisher_confirm::PublisherConfirm,
+    types::{AMQPValue, FieldTable, ShortString},
+    BasicProperties, Channel, FieldTableExt, Result,
+};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Header holding the id of the producer which published the message.
+pub const PRODUCER_ID_HEADER: &str = "x-producer-id";
+/// Header holding the sequence number of the message for its producer and routing key.
+pub const SEQUENCE_HEADER: &str = "x-sequence";
+
+/// Persistence for the last sequence number used by a producer for a routing key.
+///
+/// This allows an [`IdempotentPublisher`] to carry on its sequences across restarts.
+pub trait SequenceStore {
+    /// Get the last sequence number used, if any.
+    fn load(&self, producer_id: &str, routing_key: &str) -> io::Result<Option<u64>>;
+    /// Persist the last sequence number used.
+    fn save(&self, producer_id: &str, routing_key: &str, sequence: u64) -> io::Result<()>;
+}
+
+/// A [`SequenceStore`] keeping the sequences in memory, which are thus lost on restart.
+#[derive(Clone, Debug, Default)]
+pub struct InMemorySequenceStore(Arc<Mutex<HashMap<(String, String), u64>>>);
+
+impl SequenceStore for InMemorySequenceStore {
+    fn load(&self, producer_id: &str, routing_key: &str) -> io::Result<Option<u64>> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(producer_id.into(), routing_key.into()))
+            .copied())
+    }
+
+    fn save(&self, producer_id: &str, routing_key: &str, sequence: u64) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((producer_id.into(), routing_key.into()), sequence);
+        Ok(())
+    }
+}
+
+/// Publisher attaching a producer id and a per routing key, monotonically increasing,
+/// sequence number to every message, in the [`PRODUCER_ID_HEADER`] and [`SEQUENCE_HEADER`]
+/// headers.
+///
+/// Consumers (or a broker side plugin) can then use those to discard duplicates, e.g. when
+/// a message is published again after a missing confirmation.
+///
+/// The sequence is persisted in the [`SequenceStore`] before the message is sent, so that
+/// a sequence number is never reused, at the cost of possible gaps. Messages sharing a routing
+/// key are sent in the order of their sequence numbers, even when published concurrently.
+#[derive(Clone)]
+pub struct IdempotentPublisher {
+    channel: Channel,
+    producer_id: ShortString,
+    store: Arc<dyn SequenceStore + Send + Sync>,
+    sequences: Arc<Mutex<HashMap<ShortString, u64>>>,
+    ordering: OrderedPublisher,
+}
+
+impl IdempotentPublisher {
+    pub fn new<S: SequenceStore + Send + Sync + 'static>(
+        channel: Channel,
+        producer_id: ShortString,
+        store: S,
+    ) -> Self {
+        Self {
+            ordering: OrderedPublisher::new(channel.clone()),
+            channel,
+            producer_id,
+            store: Arc::new(store),
+            sequences: Arc::default(),
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    pub fn producer_id(&self) -> &ShortString {
+        &self.producer_id
+    }
+
+    /// Publish a message tagged with the next sequence number for its routing key.
+    pub async fn basic_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        // Until its frames are queued, so that the next sequence number can't get sent first
+        let _turn = self.ordering.wait_turn(routing_key).await;
+        let sequence = self.next_sequence(routing_key)?;
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        headers.insert(
+            PRODUCER_ID_HEADER.into(),
+            AMQPValue::LongString(self.producer_id.as_str().into()),
+        );
+        headers.insert(
+            SEQUENCE_HEADER.into(),
+            AMQPValue::LongLongInt(sequence as i64),
+        );
+        self.channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                options,
+                payload,
+                properties.with_headers(headers),
+            )
+            .await
+    }
+
+    fn next_sequence(&self, routing_key: &str) -> Result<u64> {
+        let mut sequences = self.lock_sequences();
+        let last = match sequences.get(routing_key) {
+            Some(last) => Some(*last),
+            None => self.store.load(self.producer_id.as_str(), routing_key)?,
+        };
+        let sequence = last.map_or(0, |last| last + 1);
+        self.store
+            .save(self.producer_id.as_str(), routing_key, sequence)?;
+        sequences.insert(routing_key.into(), sequence);
+        Ok(sequence)
+    }
+
+    fn lock_sequences(&self) -> MutexGuard<'_, HashMap<ShortString, u64>> {
+        self.sequences.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for IdempotentPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdempotentPublisher")
+            .field("channel", &self.channel)
+            .field("producer_id", &self.producer_id)
+            .finish()
+    }
+}
+
+/// Extract the producer id and sequence number set by an [`IdempotentPublisher`].
+pub fn sequence_headers(headers: &FieldTable) -> Option<(&str, u64)> {
+    let producer_id = headers.get_str(PRODUCER_ID_HEADER)?;
+    let sequence = headers.get_i64(SEQUENCE_HEADER)?;
+    Some((producer_id, u64::try_from(sequence).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::{BasicGetOptions, QueueDeclareOptions},
+        testing::MockBroker,
+        ConnectionProperties,
+    };
+    use futures_lite::future;
+
+    #[test]
+    fn concurrent_publishes_stay_ordered() {
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "events",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let store = InMemorySequenceStore::default();
+            let publisher =
+                IdempotentPublisher::new(channel.clone(), "producer".into(), store.clone());
+            let publishes = (0..50)
+                .map(|_| {
+                    let publisher = publisher.clone();
+                    async_global_executor::spawn(async move {
+                        publisher
+                            .basic_publish(
+                                "",
+                                "events",
+                                BasicPublishOptions::default(),
+                                b"event",
+                                BasicProperties::default(),
+                            )
+                            .await
+                    })
+                })
+                .collect::<Vec<_>>();
+            for publish in publishes {
+                publish.await?;
+            }
+
+            // While the server paused the publishes, the later one can't overtake the first one
+            channel.status().set_send_flow(false);
+            let publish = || {
+                Box::pin(publisher.basic_publish(
+                    "",
+                    "events",
+                    BasicPublishOptions::default(),
+                    b"event",
+                    BasicProperties::default(),
+                ))
+            };
+            let mut first = publish();
+            let mut second = publish();
+            assert!(future::poll_once(&mut first).await.is_none());
+            assert!(future::poll_once(&mut second).await.is_none());
+            channel.status().set_send_flow(true);
+            assert!(future::poll_once(&mut second).await.is_none());
+            first.await?;
+            second.await?;
+
+            let mut sequences = Vec::new();
+            while let Some(message) = channel
+                .basic_get("events", BasicGetOptions::default())
+                .await?
+            {
+                let headers = message.properties.headers().clone().unwrap();
+                let (producer_id, sequence) = sequence_headers(&headers).unwrap();
+                assert_eq!(producer_id, "producer");
+                sequences.push(sequence);
+            }
+            assert_eq!(sequences, (0..52).collect::<Vec<_>>());
+
+            // The sequence carries on from the store
+            let publisher = IdempotentPublisher::new(channel.clone(), "producer".into(), store);
+            assert_eq!(publisher.next_sequence("events")?, 52);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
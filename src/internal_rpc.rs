@@ -34,6 +34,7 @@ impl InternalRPCHandle {
         options: BasicAckOptions,
         resolver: PromiseResolver<()>,
         error: Option<ErrorHolder>,
+        channel_killswitch: Option<KillSwitch>,
     ) {
         self.send(InternalCommand::BasicAck(
             channel_id,
@@ -41,6 +42,7 @@ impl InternalRPCHandle {
             options,
             resolver,
             error,
+            channel_killswitch,
         ));
     }
 
@@ -51,6 +53,7 @@ impl InternalRPCHandle {
         options: BasicNackOptions,
         resolver: PromiseResolver<()>,
         error: Option<ErrorHolder>,
+        channel_killswitch: Option<KillSwitch>,
     ) {
         self.send(InternalCommand::BasicNack(
             channel_id,
@@ -58,6 +61,7 @@ impl InternalRPCHandle {
             options,
             resolver,
             error,
+            channel_killswitch,
         ));
     }
 
@@ -68,6 +72,7 @@ impl InternalRPCHandle {
         options: BasicRejectOptions,
         resolver: PromiseResolver<()>,
         error: Option<ErrorHolder>,
+        channel_killswitch: Option<KillSwitch>,
     ) {
         self.send(InternalCommand::BasicReject(
             channel_id,
@@ -75,6 +80,7 @@ impl InternalRPCHandle {
             options,
             resolver,
             error,
+            channel_killswitch,
         ));
     }
 
@@ -139,6 +145,10 @@ impl InternalRPCHandle {
         self.send(InternalCommand::SetConnectionError(error));
     }
 
+    pub(crate) fn set_connection_failed(&self, error: Error) {
+        self.send(InternalCommand::SetConnectionFailed(error));
+    }
+
     pub(crate) fn stop(&self) {
         trace!("Stopping internal RPC command");
         let _ = self.sender.send(None);
@@ -189,6 +199,7 @@ enum InternalCommand {
         BasicAckOptions,
         PromiseResolver<()>,
         Option<ErrorHolder>,
+        Option<KillSwitch>,
     ),
     BasicNack(
         ChannelId,
@@ -196,6 +207,7 @@ enum InternalCommand {
         BasicNackOptions,
         PromiseResolver<()>,
         Option<ErrorHolder>,
+        Option<KillSwitch>,
     ),
     BasicReject(
         ChannelId,
@@ -203,6 +215,7 @@ enum InternalCommand {
         BasicRejectOptions,
         PromiseResolver<()>,
         Option<ErrorHolder>,
+        Option<KillSwitch>,
     ),
     CancelConsumer(ChannelId, String, ConsumerStatus),
     CloseChannel(ChannelId, ReplyCode, String),
@@ -213,6 +226,7 @@ enum InternalCommand {
     SetConnectionClosing,
     SetConnectionClosed(Error),
     SetConnectionError(Error),
+    SetConnectionFailed(Error),
 }
 
 impl InternalRPC {
@@ -240,7 +254,7 @@ impl InternalRPC {
     fn channel_ok(&self, chan: ChannelId) -> bool {
         self.channels_status
             .get(&chan)
-            .map_or(false, |killswitch| !killswitch.killed())
+            .is_some_and(|killswitch| !killswitch.killed())
     }
 
     pub(crate) async fn run(mut self, channels: Channels) {
@@ -257,7 +271,14 @@ impl InternalRPC {
         while let Ok(Some(command)) = rpc.recv_async().await {
             trace!(?command, "Handling internal RPC command");
             match command {
-                BasicAck(channel_id, delivery_tag, options, resolver, error) => {
+                BasicAck(
+                    channel_id,
+                    delivery_tag,
+                    options,
+                    resolver,
+                    error,
+                    channel_killswitch,
+                ) => {
                     if !self.channel_ok(channel_id) {
                         continue;
                     }
@@ -267,12 +288,20 @@ impl InternalRPC {
                             if let Some(error) = error {
                                 error.check()?;
                             }
-                            channel?.basic_ack(delivery_tag, options).await
+                            check_delivery_generation(channel_killswitch, delivery_tag)?;
+                            channel?.ack_delivery(delivery_tag, options).await
                         },
                         resolver,
                     )
                 }
-                BasicNack(channel_id, delivery_tag, options, resolver, error) => {
+                BasicNack(
+                    channel_id,
+                    delivery_tag,
+                    options,
+                    resolver,
+                    error,
+                    channel_killswitch,
+                ) => {
                     if !self.channel_ok(channel_id) {
                         continue;
                     }
@@ -282,12 +311,20 @@ impl InternalRPC {
                             if let Some(error) = error {
                                 error.check()?;
                             }
+                            check_delivery_generation(channel_killswitch, delivery_tag)?;
                             channel?.basic_nack(delivery_tag, options).await
                         },
                         resolver,
                     )
                 }
-                BasicReject(channel_id, delivery_tag, options, resolver, error) => {
+                BasicReject(
+                    channel_id,
+                    delivery_tag,
+                    options,
+                    resolver,
+                    error,
+                    channel_killswitch,
+                ) => {
                     if !self.channel_ok(channel_id) {
                         continue;
                     }
@@ -297,6 +334,7 @@ impl InternalRPC {
                             if let Some(error) = error {
                                 error.check()?;
                             }
+                            check_delivery_generation(channel_killswitch, delivery_tag)?;
                             channel?.basic_reject(delivery_tag, options).await
                         },
                         resolver,
@@ -324,7 +362,12 @@ impl InternalRPC {
                     }
                     let channel = get_channel(channel_id);
                     handle.register_internal_future(async move {
-                        channel?.close(reply_code, &reply_text).await
+                        let channel = channel?;
+                        // The connection is being closed, which closes the channel as well
+                        if channel.status().closing() {
+                            return Ok(());
+                        }
+                        channel.close(reply_code, &reply_text).await
                     })
                 }
                 CloseConnection(reply_code, reply_text, class_id, method_id) => {
@@ -355,9 +398,22 @@ impl InternalRPC {
                 SetConnectionClosing => channels.set_connection_closing(),
                 SetConnectionClosed(error) => channels.set_connection_closed(error),
                 SetConnectionError(error) => channels.set_connection_error(error),
+                SetConnectionFailed(error) => channels.set_connection_failed(error),
             }
             handle.waker.wake();
         }
         trace!("InternalRPC stopped");
     }
 }
+
+/// Fail acknowledgements of deliveries received before the channel started recovering, as their
+/// delivery tags are meaningless to the server now.
+fn check_delivery_generation(
+    channel_killswitch: Option<KillSwitch>,
+    delivery_tag: DeliveryTag,
+) -> Result<()> {
+    if channel_killswitch.is_some_and(|killswitch| killswitch.killed()) {
+        return Err(ErrorKind::StaleDeliveryTag(delivery_tag).into());
+    }
+    Ok(())
+}
@@ -2,30 +2,34 @@ use crate::{
     buffer::Buffer,
     channels::Channels,
     connection_status::ConnectionState,
-    frames::Frames,
+    dispatch_pool::DispatchPool,
+    frames::{Frames, OutgoingFrame},
     heartbeat::Heartbeat,
     internal_rpc::InternalRPCHandle,
     killswitch::KillSwitch,
-    protocol::{self, AMQPError, AMQPHardError},
+    protocol::{self, constants::FRAME_METHOD, AMQPError, AMQPHardError},
+    raw_method::{RawMethod, RAW_METHOD_HEADER_SIZE},
     socket_state::{SocketEvent, SocketState},
     thread::ThreadHandle,
-    types::FrameSize,
+    types::{FrameSize, Identifier},
     Configuration, ConnectionStatus, Error, ErrorKind, PromiseResolver, Result,
 };
 use amq_protocol::frame::{gen_frame, parse_frame, AMQPFrame, GenError};
 use reactor_trait::AsyncIOHandle;
 use std::{
     collections::VecDeque,
-    io,
+    io::{self, Write},
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex, MutexGuard},
     task::{Context, Poll, Waker},
     thread::Builder as ThreadBuilder,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tracing::{error, trace};
 
-const FRAMES_STORAGE: usize = 32;
+/* A frame starts with its type (1 byte), channel id (2 bytes) and size (4 bytes), and ends with 1 byte */
+const FRAME_HEADER_SIZE: usize = 7;
+const FRAME_END_SIZE: usize = 1;
 
 #[derive(Debug, PartialEq)]
 enum Status {
@@ -48,11 +52,18 @@ pub struct IoLoop {
     killswitch: KillSwitch,
     frame_size: FrameSize,
     receive_buffer: Buffer,
+    /* Size of the frame being received, once we've read its header */
+    next_frame_size: Option<usize>,
     send_buffer: Buffer,
     serialized_frames: VecDeque<(FrameSize, Option<PromiseResolver<()>>)>,
+    write_coalescing: Option<Duration>,
+    coalescing_since: Option<Instant>,
+    io_buffer_frames: usize,
+    dispatch_pool: Option<DispatchPool>,
 }
 
 impl IoLoop {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) async fn new(
         connection_status: ConnectionStatus,
         configuration: Configuration,
@@ -63,7 +74,21 @@ impl IoLoop {
         connection_io_loop_handle: ThreadHandle,
         stream: Pin<Box<dyn AsyncIOHandle + Send>>,
         heartbeat: Heartbeat,
+        write_coalescing: Option<Duration>,
+        io_buffer_frames: usize,
+        dispatch_workers: usize,
     ) -> Result<Self> {
+        let io_buffer_frames = io_buffer_frames.max(1);
+        let dispatch_pool = if dispatch_workers > 0 {
+            Some(DispatchPool::new(
+                dispatch_workers,
+                channels.clone(),
+                socket_state.handle(),
+                connection_status.span(),
+            )?)
+        } else {
+            None
+        };
         let frame_size = std::cmp::max(
             protocol::constants::FRAME_MIN_SIZE,
             configuration.frame_max(),
@@ -83,9 +108,14 @@ impl IoLoop {
             status: Status::Initial,
             killswitch,
             frame_size,
-            receive_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size as usize),
-            send_buffer: Buffer::with_capacity(FRAMES_STORAGE * frame_size as usize),
+            receive_buffer: Buffer::with_capacity(io_buffer_frames * frame_size as usize),
+            next_frame_size: None,
+            send_buffer: Buffer::with_capacity(io_buffer_frames * frame_size as usize),
             serialized_frames: VecDeque::default(),
+            write_coalescing,
+            coalescing_since: None,
+            io_buffer_frames,
+            dispatch_pool,
         })
     }
 
@@ -104,9 +134,9 @@ impl IoLoop {
             let frame_max = self.configuration.frame_max();
             self.frame_size = std::cmp::max(self.frame_size, frame_max);
             self.receive_buffer
-                .grow(FRAMES_STORAGE * self.frame_size as usize);
+                .grow(self.io_buffer_frames * self.frame_size as usize);
             self.send_buffer
-                .grow(FRAMES_STORAGE * self.frame_size as usize);
+                .grow(self.io_buffer_frames * self.frame_size as usize);
             let heartbeat = self.configuration.heartbeat();
             if heartbeat != 0 {
                 let heartbeat = Duration::from_millis(u64::from(heartbeat) * 500); // * 1000 (ms) / 2 (half the negotiated timeout)
@@ -133,7 +163,28 @@ impl IoLoop {
     }
 
     fn can_write(&mut self) -> bool {
-        self.socket_state.writable() && self.has_data() && !self.connection_status.blocked()
+        self.socket_state.writable()
+            && self.has_data()
+            && !self.connection_status.blocked()
+            && self.coalescing_delay().is_none()
+    }
+
+    // When write coalescing is enabled, hold off small writes to wait for more frames to be
+    // queued. Returns how long we still have to wait.
+    fn coalescing_delay(&mut self) -> Option<Duration> {
+        let since = self.coalescing_since?;
+        let remaining = self
+            .write_coalescing?
+            .checked_sub(since.elapsed())
+            .filter(|remaining| !remaining.is_zero());
+        if remaining.is_none()
+            || self.status != Status::Connected
+            || self.send_buffer.available_data() >= self.frame_size as usize
+        {
+            self.coalescing_since = None;
+            return None;
+        }
+        remaining
     }
 
     fn can_read(&mut self) -> bool {
@@ -163,37 +214,46 @@ impl IoLoop {
             ThreadBuilder::new()
                 .name("lapin-io-loop".to_owned())
                 .spawn(move || {
+                    let _span = self.connection_status.span().entered();
                     let readable_waker = self.readable_waker();
                     let mut readable_context = Context::from_waker(&readable_waker);
                     let writable_waker = self.writable_waker();
                     let mut writable_context = Context::from_waker(&writable_waker);
                     let mut res = Ok(());
                     while self.should_continue() {
-                        if let Err(err) = self.run(&mut readable_context, &mut writable_context) {
+                        if let Poll::Ready(Err(err)) =
+                            self.run(&mut readable_context, &mut writable_context, None)
+                        {
                             res = self.critical_error(err);
                         }
                     }
-                    self.heartbeat.cancel();
-                    self.clear_serialized_frames(self.frames.poison().unwrap_or(
-                        ErrorKind::InvalidConnectionState(ConnectionState::Closed).into(),
-                    ));
-                    let internal_rpc = self.internal_rpc.clone();
-                    if self.killswitch.killed() {
-                        internal_rpc.register_internal_future(std::future::poll_fn(move |cx| {
-                            self.stream
-                                .as_mut()
-                                .poll_close(cx)
-                                .map(|res| res.map_err(From::from))
-                        }));
-                    }
-                    internal_rpc.stop();
-                    res
+                    self.finish(res)
                 })?,
         );
         waker.wake();
         Ok(())
     }
 
+    fn finish(mut self, res: Result<()>) -> Result<()> {
+        self.heartbeat.cancel();
+        self.clear_serialized_frames(
+            self.frames
+                .poison()
+                .unwrap_or(ErrorKind::InvalidConnectionState(ConnectionState::Closed).into()),
+        );
+        let internal_rpc = self.internal_rpc.clone();
+        if self.killswitch.killed() {
+            internal_rpc.register_internal_future(std::future::poll_fn(move |cx| {
+                self.stream
+                    .as_mut()
+                    .poll_close(cx)
+                    .map(|res| res.map_err(From::from))
+            }));
+        }
+        internal_rpc.stop();
+        res
+    }
+
     fn stop(&mut self) {
         self.status = Status::Stop;
         self.heartbeat.cancel();
@@ -209,15 +269,18 @@ impl IoLoop {
         }
     }
 
+    // Run one iteration of the loop. Without a task to wake up, block the current thread
+    // until there is something to do, otherwise return Pending.
     fn run(
         &mut self,
         readable_context: &mut Context<'_>,
         writable_context: &mut Context<'_>,
-    ) -> Result<()> {
+        task: Option<&mut Context<'_>>,
+    ) -> Poll<Result<()>> {
         trace!("io_loop run");
         self.poll_socket_events();
         if !self.ensure_setup()? {
-            return Ok(());
+            return Poll::Ready(Ok(()));
         }
         self.check_connection_state();
         trace!(
@@ -227,7 +290,15 @@ impl IoLoop {
             "io_loop do_run",
         );
         if !self.can_read() && !self.can_write() && self.should_continue() {
-            self.socket_state.wait();
+            if let Some(task) = task {
+                if self.socket_state.poll_wait(task).is_pending() {
+                    return Poll::Pending;
+                }
+            } else if let Some(delay) = self.coalescing_delay() {
+                self.socket_state.wait_timeout(delay);
+            } else {
+                self.socket_state.wait();
+            }
         }
         self.poll_socket_events();
         self.attempt_flush(writable_context)?;
@@ -245,7 +316,7 @@ impl IoLoop {
             status=?self.status,
             "io_loop do_run done",
         );
-        Ok(())
+        Poll::Ready(Ok(()))
     }
 
     fn critical_error(&mut self, error: Error) -> Result<()> {
@@ -287,6 +358,10 @@ impl IoLoop {
     }
 
     fn write(&mut self, writable_context: &mut Context<'_>) -> Result<()> {
+        if self.write_coalescing.is_some() {
+            // Serialize the pending frames to know if we have enough data to write right away
+            self.serialize()?;
+        }
         while self.can_write() {
             let res = self.write_to_stream(writable_context);
             self.handle_io_result(res)?;
@@ -357,8 +432,8 @@ impl IoLoop {
     fn read_from_stream(&mut self, readable_context: &mut Context<'_>) -> Result<()> {
         match self.connection_status.state() {
             ConnectionState::Closed => Ok(()),
-            ConnectionState::Error => {
-                Err(ErrorKind::InvalidConnectionState(ConnectionState::Error).into())
+            state @ (ConnectionState::Error | ConnectionState::Failed) => {
+                Err(ErrorKind::InvalidConnectionState(state).into())
             }
             _ => {
                 let res = self
@@ -385,14 +460,37 @@ impl IoLoop {
     }
 
     fn serialize(&mut self) -> Result<()> {
+        let was_empty = self.send_buffer.available_data() == 0;
         while let Some((next_msg, resolver)) = self.frames.pop(self.channels.flow()) {
             trace!(%next_msg, "will write to buffer");
             let checkpoint = self.send_buffer.checkpoint();
-            let res = gen_frame(&next_msg)((&mut self.send_buffer).into());
-            match res.map(|w| w.into_inner().1) {
-                Ok(sz) => self
-                    .serialized_frames
-                    .push_back((sz as FrameSize, resolver)),
+            let res = match &next_msg {
+                OutgoingFrame::Frame(frame) => {
+                    gen_frame(frame)((&mut self.send_buffer).into()).map(|w| w.into_inner().1)
+                }
+                OutgoingFrame::Serialized(_, bytes) => {
+                    if self.send_buffer.available_space() < bytes.len() {
+                        Err(GenError::BufferTooSmall(
+                            bytes.len() - self.send_buffer.available_space(),
+                        ))
+                    } else {
+                        (&mut self.send_buffer)
+                            .write_all(bytes)
+                            .map(|()| bytes.len() as u64)
+                            .map_err(GenError::IoError)
+                    }
+                }
+            };
+            match res {
+                Ok(sz) => {
+                    self.serialized_frames
+                        .push_back((sz as FrameSize, resolver));
+                    if let OutgoingFrame::Frame(AMQPFrame::Body(_, buffer))
+                    | OutgoingFrame::Serialized(_, buffer) = next_msg
+                    {
+                        self.frames.recycle_body_buffer(buffer);
+                    }
+                }
                 Err(e) => {
                     self.send_buffer.rollback(checkpoint);
                     match e {
@@ -409,13 +507,26 @@ impl IoLoop {
                 }
             }
         }
+        if self.write_coalescing.is_some() && was_empty && self.send_buffer.available_data() > 0 {
+            // We queued new data, start a new coalescing window
+            self.coalescing_since = Some(Instant::now());
+        }
         Ok(())
     }
 
     fn handle_frames(&mut self) -> Result<()> {
         while self.can_parse() {
+            if self.handle_raw_method()? {
+                continue;
+            }
             if let Some(frame) = self.parse()? {
-                self.channels.handle_frame(frame)?;
+                let frame = match self.dispatch_pool.as_ref() {
+                    Some(dispatch_pool) => dispatch_pool.dispatch(frame),
+                    None => Some(frame),
+                };
+                if let Some(frame) = frame {
+                    self.channels.handle_frame(frame)?;
+                }
             } else {
                 break;
             }
@@ -423,25 +534,96 @@ impl IoLoop {
         Ok(())
     }
 
+    // Wait for a whole frame to be buffered before parsing it, instead of parsing it again
+    // each time we receive a part of it.
+    fn frame_available(&mut self) -> Result<bool> {
+        let frame_size = match self.next_frame_size {
+            Some(frame_size) => frame_size,
+            None => {
+                let Some(header) = self.receive_buffer.peek::<FRAME_HEADER_SIZE>() else {
+                    return Ok(false);
+                };
+                if header[0] == b'A' {
+                    // Protocol header sent by the server on version mismatch, let the parser handle it
+                    return Ok(true);
+                }
+                let size = u32::from_be_bytes([header[3], header[4], header[5], header[6]]);
+                let frame_size = FRAME_HEADER_SIZE + size as usize + FRAME_END_SIZE;
+                self.check_frame_size(frame_size)?;
+                self.next_frame_size = Some(frame_size);
+                frame_size
+            }
+        };
+        Ok(self.receive_buffer.available_data() >= frame_size)
+    }
+
+    fn check_frame_size(&mut self, frame_size: usize) -> Result<()> {
+        let frame_max = self.configuration.frame_max() as usize;
+        if frame_max > 0 && frame_size > frame_max {
+            error!(bytes = frame_size, "received large frame");
+            let error = AMQPError::new(
+                AMQPHardError::FRAMEERROR.into(),
+                format!("frame too large: {} bytes", frame_size).into(),
+            );
+            self.internal_rpc.close_connection(
+                error.get_id(),
+                error.get_message().to_string(),
+                0,
+                0,
+            );
+            self.critical_error(ErrorKind::ProtocolError(error).into())?;
+        }
+        Ok(())
+    }
+
+    /// Hand the method frames registered through `Connection::on_raw_method` to their handler
+    /// instead of parsing them, as they're unknown to the protocol layer
+    fn handle_raw_method(&mut self) -> Result<bool> {
+        if !self.frame_available()? {
+            return Ok(false);
+        }
+        let (Some(header), Some(frame_size)) = (
+            self.receive_buffer.peek::<RAW_METHOD_HEADER_SIZE>(),
+            self.next_frame_size,
+        ) else {
+            return Ok(false);
+        };
+        if header[0] != FRAME_METHOD
+            || !self.channels.raw_method_handlers().handles(
+                Identifier::from_be_bytes([header[7], header[8]]),
+                Identifier::from_be_bytes([header[9], header[10]]),
+            )
+        {
+            return Ok(false);
+        }
+        let Some(method) = self
+            .receive_buffer
+            .peek_vec(frame_size)
+            .as_deref()
+            .and_then(RawMethod::parse)
+        else {
+            // Let the parser report the malformed frame
+            return Ok(false);
+        };
+        trace!(channel=%method.channel_id, class=%method.class_id, method=%method.method_id, "received raw method");
+        self.receive_buffer.consume(frame_size);
+        self.next_frame_size = None;
+        if let Some(dispatch_pool) = self.dispatch_pool.as_ref() {
+            // Keep the raw methods in order with the frames handled by the workers
+            dispatch_pool.wait_idle();
+        }
+        self.channels.raw_method_handlers().handle(method);
+        Ok(true)
+    }
+
     fn parse(&mut self) -> Result<Option<AMQPFrame>> {
+        if !self.frame_available()? {
+            return Ok(None);
+        }
         match parse_frame(self.receive_buffer.parsing_context()) {
             Ok((i, f)) => {
                 let consumed = self.receive_buffer.offset(i);
-                let frame_max = self.configuration.frame_max() as usize;
-                if frame_max > 0 && consumed > frame_max {
-                    error!(bytes = consumed, "received large frame");
-                    let error = AMQPError::new(
-                        AMQPHardError::FRAMEERROR.into(),
-                        format!("frame too large: {} bytes", consumed).into(),
-                    );
-                    self.internal_rpc.close_connection(
-                        error.get_id(),
-                        error.get_message().to_string(),
-                        0,
-                        0,
-                    );
-                    self.critical_error(ErrorKind::ProtocolError(error).into())?;
-                }
+                self.next_frame_size = None;
                 self.receive_buffer.consume(consumed);
                 Ok(Some(f))
             }
@@ -455,3 +637,61 @@ impl IoLoop {
         }
     }
 }
+
+/* How many iterations of the io loop to run before yielding back to the executor */
+const DRIVE_BUDGET: usize = 32;
+
+/// Holds the io loop when it's driven manually by the caller instead of running in its own
+/// thread.
+#[derive(Clone, Default)]
+pub(crate) struct IoLoopDriver(Arc<Mutex<Option<DrivenIoLoop>>>);
+
+struct DrivenIoLoop {
+    io_loop: IoLoop,
+    readable_waker: Waker,
+    writable_waker: Waker,
+    res: Result<()>,
+}
+
+impl IoLoopDriver {
+    pub(crate) fn set(&self, io_loop: IoLoop) {
+        *self.lock_inner() = Some(DrivenIoLoop {
+            readable_waker: io_loop.readable_waker(),
+            writable_waker: io_loop.writable_waker(),
+            io_loop,
+            res: Ok(()),
+        });
+    }
+
+    /// Run the io loop until it needs to wait for the socket, and resolve once it's done.
+    pub(crate) fn poll_drive(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut inner = self.lock_inner();
+        let Some(driven) = inner.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let _span = driven.io_loop.connection_status.span().entered();
+        let mut readable_context = Context::from_waker(&driven.readable_waker);
+        let mut writable_context = Context::from_waker(&driven.writable_waker);
+        for _ in 0..DRIVE_BUDGET {
+            if !driven.io_loop.should_continue() {
+                let driven = inner.take().expect("io loop should be there");
+                return Poll::Ready(driven.io_loop.finish(driven.res));
+            }
+            match driven
+                .io_loop
+                .run(&mut readable_context, &mut writable_context, Some(cx))
+            {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(err)) => driven.res = driven.io_loop.critical_error(err),
+                Poll::Ready(Ok(())) => {}
+            }
+        }
+        // Let the other tasks run
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Option<DrivenIoLoop>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,515 @@
+use crate::thread::ThreadHandle;
+use async_trait::async_trait;
+use futures_core::Stream;
+use futures_io::{AsyncRead, AsyncWrite};
+use io_uring::{opcode, squeue, types, IoUring};
+use reactor_trait::{AsyncIOHandle, IOHandle, Reactor, TimeReactor};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io::{self, IoSlice, IoSliceMut, Read, Write},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread::Builder as ThreadBuilder,
+    time::{Duration, Instant},
+};
+use tracing::{error, trace};
+
+const RING_ENTRIES: u32 = 256;
+/* user_data of the poll on the notifier socket, operations start at 1 */
+const NOTIFY_TOKEN: u64 = 0;
+
+/// A reactor driving IO readiness and timers through io_uring (linux only).
+///
+/// A single thread owns the ring. Readiness polls and timers requested by the connections
+/// are batched in the submission queue and submitted along with the wait for completions,
+/// which saves the epoll_ctl/epoll_wait round trips on busy connections. The actual reads
+/// and writes still go through the registered handle, so that TLS streams work the same.
+///
+/// ```rust,no_run
+/// use lapin::{ConnectionProperties, IoUringReactor};
+///
+/// let properties = ConnectionProperties::default()
+///     .with_reactor(IoUringReactor::new().expect("io_uring unavailable"));
+/// ```
+#[derive(Clone)]
+pub struct IoUringReactor {
+    driver: Arc<Driver>,
+}
+
+impl IoUringReactor {
+    /// Setup a new ring and spawn the thread driving it.
+    pub fn new() -> io::Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+        let (notifier, notified) = UnixStream::pair()?;
+        notifier.set_nonblocking(true)?;
+        notified.set_nonblocking(true)?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            notifier,
+        });
+        let thread = ThreadHandle::default();
+        let driver_shared = shared.clone();
+        thread.register(
+            ThreadBuilder::new()
+                .name("lapin-io-uring".into())
+                .spawn(move || driver_shared.run(ring, notified))?,
+        );
+        Ok(Self {
+            driver: Arc::new(Driver { shared, thread }),
+        })
+    }
+}
+
+impl fmt::Debug for IoUringReactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IoUringReactor").finish()
+    }
+}
+
+impl Reactor for IoUringReactor {
+    fn register(&self, socket: IOHandle) -> io::Result<Box<dyn AsyncIOHandle + Send>> {
+        let fd = socket.as_raw_fd();
+        set_nonblocking(fd)?;
+        Ok(Box::new(UringIO {
+            socket,
+            fd,
+            driver: self.driver.clone(),
+            read: None,
+            write: None,
+        }))
+    }
+}
+
+#[async_trait]
+impl TimeReactor for IoUringReactor {
+    async fn sleep(&self, dur: Duration) {
+        self.driver.timeout(dur).await;
+    }
+
+    fn interval(&self, dur: Duration) -> Box<dyn Stream<Item = Instant>> {
+        Box::new(Interval {
+            driver: self.driver.clone(),
+            period: dur,
+            next: Instant::now() + dur,
+            timeout: None,
+        })
+    }
+}
+
+struct Driver {
+    shared: Arc<Shared>,
+    thread: ThreadHandle,
+}
+
+impl Driver {
+    fn poll_ready(&self, fd: RawFd, events: libc::c_short) -> Completion {
+        self.shared.submit(
+            opcode::PollAdd::new(types::Fd(fd), events as u32).build(),
+            None,
+        )
+    }
+
+    fn timeout(&self, dur: Duration) -> Completion {
+        let timespec = Box::new(
+            types::Timespec::new()
+                .sec(dur.as_secs())
+                .nsec(dur.subsec_nanos()),
+        );
+        let entry = opcode::Timeout::new(&*timespec).build();
+        self.shared.submit(entry, Some(timespec))
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .shutdown = true;
+        self.shared.notify();
+        if let Err(err) = self.thread.wait("io_uring") {
+            error!(?err, "io_uring thread failed");
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    notifier: UnixStream,
+}
+
+#[derive(Default)]
+struct State {
+    next_token: u64,
+    pending: Vec<squeue::Entry>,
+    operations: HashMap<u64, Operation>,
+    waiting: bool,
+    shutdown: bool,
+}
+
+struct Operation {
+    status: OperationStatus,
+    /* The kernel may read the timespec until the timeout is completed */
+    _timespec: Option<Box<types::Timespec>>,
+}
+
+enum OperationStatus {
+    Pending(Option<Waker>),
+    Completed(i32),
+    Cancelled,
+}
+
+impl Shared {
+    fn submit(
+        self: &Arc<Self>,
+        entry: squeue::Entry,
+        timespec: Option<Box<types::Timespec>>,
+    ) -> Completion {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.next_token += 1;
+        let token = state.next_token;
+        state.operations.insert(
+            token,
+            Operation {
+                status: OperationStatus::Pending(None),
+                _timespec: timespec,
+            },
+        );
+        self.push(&mut state, entry.user_data(token));
+        Completion {
+            shared: self.clone(),
+            token,
+        }
+    }
+
+    fn push(&self, state: &mut State, entry: squeue::Entry) {
+        state.pending.push(entry);
+        // Only wake the ring thread up if it's blocked waiting for completions, otherwise it
+        // will pick the entry up on its next iteration.
+        if state.waiting {
+            state.waiting = false;
+            self.notify();
+        }
+    }
+
+    fn notify(&self) {
+        if let Err(err) = (&self.notifier).write(&[1]) {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                error!(?err, "failed to wake io_uring thread up");
+            }
+        }
+    }
+
+    fn poll_completion(&self, token: u64, cx: &mut Context<'_>) -> Poll<i32> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let operation = state
+            .operations
+            .get_mut(&token)
+            .expect("io_uring operation polled after completion");
+        match &mut operation.status {
+            OperationStatus::Completed(res) => {
+                let res = *res;
+                state.operations.remove(&token);
+                Poll::Ready(res)
+            }
+            OperationStatus::Pending(waker) => {
+                *waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            OperationStatus::Cancelled => unreachable!(),
+        }
+    }
+
+    fn cancel(&self, token: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(operation) = state.operations.get_mut(&token) else {
+            return;
+        };
+        if let OperationStatus::Completed(_) = operation.status {
+            state.operations.remove(&token);
+        } else {
+            // Keep the operation around until the kernel is done with it
+            operation.status = OperationStatus::Cancelled;
+            self.push(
+                &mut state,
+                opcode::AsyncCancel::new(token).build().user_data(u64::MAX),
+            );
+        }
+    }
+
+    fn complete(&self, token: u64, res: i32) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(operation) = state.operations.get_mut(&token) else {
+            return;
+        };
+        match std::mem::replace(&mut operation.status, OperationStatus::Completed(res)) {
+            OperationStatus::Pending(waker) => {
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+            OperationStatus::Cancelled => {
+                state.operations.remove(&token);
+            }
+            OperationStatus::Completed(_) => {}
+        }
+    }
+
+    fn run(&self, mut ring: IoUring, mut notified: UnixStream) -> crate::Result<()> {
+        let notify_poll =
+            opcode::PollAdd::new(types::Fd(notified.as_raw_fd()), libc::POLLIN as u32)
+                .build()
+                .user_data(NOTIFY_TOKEN);
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pending
+            .push(notify_poll.clone());
+        loop {
+            let pending = {
+                let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                if state.shutdown {
+                    trace!("io_uring thread stopping");
+                    return Ok(());
+                }
+                state.waiting = true;
+                std::mem::take(&mut state.pending)
+            };
+            for entry in pending {
+                // SAFETY: the buffers referenced by the entries (timespecs) are kept alive in
+                // the operations map until their completion.
+                while unsafe { ring.submission().push(&entry) }.is_err() {
+                    ring.submit()?;
+                }
+            }
+            match ring.submit_and_wait(1) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+            self.state.lock().unwrap_or_else(|e| e.into_inner()).waiting = false;
+            for cqe in ring.completion() {
+                match cqe.user_data() {
+                    NOTIFY_TOKEN => {
+                        let mut buf = [0; 64];
+                        while let Ok(1..) = notified.read(&mut buf) {}
+                        self.state
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .pending
+                            .push(notify_poll.clone());
+                    }
+                    u64::MAX => {}
+                    token => self.complete(token, cqe.result()),
+                }
+            }
+        }
+    }
+}
+
+struct Completion {
+    shared: Arc<Shared>,
+    token: u64,
+}
+
+impl Future for Completion {
+    type Output = i32;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let res = self.shared.poll_completion(self.token, cx);
+        if res.is_ready() {
+            // Don't try to cancel it on drop
+            self.get_mut().token = NOTIFY_TOKEN;
+        }
+        res
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        if self.token != NOTIFY_TOKEN {
+            self.shared.cancel(self.token);
+        }
+    }
+}
+
+struct Interval {
+    driver: Arc<Driver>,
+    period: Duration,
+    next: Instant,
+    timeout: Option<Completion>,
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(timeout) = this.timeout.as_mut() {
+                if Pin::new(timeout).poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.timeout = None;
+            }
+            let now = Instant::now();
+            if now >= this.next {
+                let tick = this.next;
+                this.next += this.period;
+                return Poll::Ready(Some(tick));
+            }
+            this.timeout = Some(this.driver.timeout(this.next - now));
+        }
+    }
+}
+
+struct UringIO {
+    socket: IOHandle,
+    fd: RawFd,
+    driver: Arc<Driver>,
+    read: Option<Completion>,
+    write: Option<Completion>,
+}
+
+impl UringIO {
+    fn poll_io<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        write: bool,
+        mut op: impl FnMut(&mut IOHandle) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        loop {
+            let readiness = if write {
+                &mut self.write
+            } else {
+                &mut self.read
+            };
+            if let Some(completion) = readiness.as_mut() {
+                let res = match Pin::new(completion).poll(cx) {
+                    Poll::Ready(res) => res,
+                    Poll::Pending => return Poll::Pending,
+                };
+                *readiness = None;
+                if res < 0 {
+                    return Poll::Ready(Err(io::Error::from_raw_os_error(-res)));
+                }
+            }
+            match op(&mut self.socket) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    let events = if write { libc::POLLOUT } else { libc::POLLIN };
+                    let completion = self.driver.poll_ready(self.fd, events);
+                    if write {
+                        self.write = Some(completion);
+                    } else {
+                        self.read = Some(completion);
+                    }
+                }
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+}
+
+impl AsyncRead for UringIO {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_io(cx, false, |socket| socket.read(&mut *buf))
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_io(cx, false, |socket| socket.read_vectored(&mut *bufs))
+    }
+}
+
+impl AsyncWrite for UringIO {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_io(cx, true, |socket| socket.write(buf))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_io(cx, true, |socket| socket.write_vectored(bufs))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_io(cx, true, |socket| socket.flush())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    // SAFETY: fd belongs to the IOHandle we were given, which stays open
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_and_readiness() {
+        let Ok(reactor) = IoUringReactor::new() else {
+            // io_uring may be disabled on this kernel
+            return;
+        };
+        futures_lite::future::block_on(async {
+            let start = Instant::now();
+            reactor.sleep(Duration::from_millis(20)).await;
+            assert!(start.elapsed() >= Duration::from_millis(20));
+
+            let (a, b) = UnixStream::pair().unwrap();
+            let mut a = Box::into_pin(reactor.register(IOHandle::new(a)).unwrap());
+            let mut b = Box::into_pin(reactor.register(IOHandle::new(b)).unwrap());
+            let reader = async {
+                let mut buf = [0; 5];
+                futures_lite::AsyncReadExt::read_exact(&mut b, &mut buf)
+                    .await
+                    .unwrap();
+                buf
+            };
+            let writer = async {
+                reactor.sleep(Duration::from_millis(10)).await;
+                futures_lite::AsyncWriteExt::write_all(&mut a, b"hello")
+                    .await
+                    .unwrap();
+            };
+            let (buf, ()) = futures_lite::future::zip(reader, writer).await;
+            assert_eq!(&buf, b"hello");
+        });
+    }
+}
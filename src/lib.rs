@@ -10,6 +10,13 @@
 //!
 //! ## Feature switches
 //!
+//! * `amqp1`: enable the `amqp1` module, a connection speaking AMQP 1.0 and `AnyConnection` to pick
+//!   the protocol from the uri or the properties
+//! * `tokio`: enable the `tokio` module to run lapin on a tokio runtime
+//! * `io-uring`: enable `IoUringReactor`, a reactor backed by io_uring (linux only)
+//! * `testing`: enable the `testing` module, an in-memory broker for unit tests
+//! * `management`: enable the `management` module, a client for the RabbitMQ management HTTP API
+//! * `streams`: enable the `streams` module, a client for the native RabbitMQ Streams protocol
 //! * `codegen`: generate code instead of using pregenerated one
 //! * `native-tls`: enable amqps support through native-tls (preferred over rustls when set)
 //! * `openssl`: enable amqps support through openssl (preferred over rustls when set)
@@ -104,64 +111,170 @@ pub use amq_protocol::{
     tcp::{self, TcpStream},
     types, uri,
 };
+pub use bytes::Bytes;
 
+pub use backoff::Backoff;
+pub use basic_properties_builder::BasicPropertiesBuilder;
+pub use broker_profile::BrokerProfile;
+pub use capabilities::Capabilities;
 pub use channel::{options, Channel};
+pub use channel_id_allocation::{ChannelIdAllocation, OpenChannel};
+pub use channel_options::ChannelOptions;
 pub use channel_status::{ChannelState, ChannelStatus};
 pub use configuration::Configuration;
+pub use confirm_throttle::ConfirmThrottle;
 pub use connection::{Connect, Connection};
 pub use connection_properties::ConnectionProperties;
 pub use connection_status::{ConnectionState, ConnectionStatus};
-pub use consumer::{Consumer, ConsumerDelegate};
+pub use consumer::{Consumer, ConsumerDelegate, ConsumerEvent, DelegateExecutor};
+pub use consumer_ordering::ConsumerOrdering;
 pub use consumer_status::ConsumerState;
+pub use consumer_tag::ConsumerTagStrategy;
+#[cfg(feature = "rust_decimal")]
+pub use decimal::DecimalOutOfRange;
+pub use decimal::{Decimal, ParseDecimalError};
+pub use dedup::{DedupStore, DedupWindow};
+pub use delegate_panic::{DelegatePanic, PanicPolicy};
+pub use delivery_latency::{DeliveryLatency, LatencyHistogram, LatencyMetrics, LatencySummary};
+pub use envelope::Envelope;
 pub use error::{Error, ErrorKind, Result};
 pub use exchange::ExchangeKind;
+pub use field_table_ext::{FieldTableEntry, FieldTableExt, IntoAMQPValue, PrettyFields};
+pub use getter::Getter;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use io_uring_reactor::IoUringReactor;
+pub use notifier::{Notifier, RecoveryEvent, RecoveryEvents, RecoveryOutcome};
+pub use pipeline::Pipeline;
+pub use publish_defaults::PublishDefaults;
+pub use publish_events::{PublishEvent, PublishEvents, PublishStage};
+pub use publish_template::PublishTemplate;
 pub use queue::Queue;
-pub use recovery_config::RecoveryConfig;
+pub use queue_depth::{QueueDepth, QueueDepthWatcher};
+pub use rate_limit::RateLimit;
+pub use raw_method::RawMethod;
+pub use recovery_config::{PublishBufferOverflow, RecoveryConfig};
+pub use sender_selected_distribution::SenderSelectedDistribution;
+pub use shutdown_signal::ShutdownSignal;
+pub use slow_consumer::SlowConsumer;
 
 pub mod acker;
+#[cfg(feature = "amqp1")]
+pub mod amqp1;
+pub mod autoscaler;
+pub mod blocking;
+pub mod consumer_group;
+pub mod consumer_stream;
+pub mod correlation;
+pub mod credentials_provider;
+pub mod dead_letter;
+pub mod delayed_retry;
+pub mod health;
 pub mod heartbeat;
+pub mod idempotent_publisher;
+#[cfg(feature = "management")]
+pub mod management;
 pub mod message;
+pub mod ordered_publisher;
+pub mod outbox;
+pub mod plain_fields;
 pub mod publisher_confirm;
+pub mod publisher_handle;
+pub mod retrying_channel;
+pub mod sharded_publisher;
+pub mod shovel;
 pub mod socket_state;
+#[cfg(feature = "streams")]
+pub mod streams;
+pub mod super_stream;
+pub mod supervisor;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timestamp;
+#[cfg(feature = "tokio")]
+pub mod tokio;
 pub mod topology;
+pub mod windowed_publisher;
 
 use promise::{Promise, PromiseResolver};
 
+mod ack_coalescer;
 mod acknowledgement;
+mod backoff;
 mod basic_get_delivery;
+mod basic_properties_builder;
+mod broker_profile;
 mod buffer;
+mod buffer_pool;
+mod capabilities;
 mod channel;
 mod channel_closer;
+mod channel_id_allocation;
+mod channel_options;
 mod channel_receiver_state;
 mod channel_recovery_context;
 mod channel_status;
 mod channels;
 mod configuration;
+mod confirm_throttle;
 mod connection;
 mod connection_closer;
 mod connection_properties;
 mod connection_status;
 mod consumer;
 mod consumer_canceler;
+mod consumer_ordering;
 mod consumer_status;
+mod consumer_tag;
 mod consumers;
+mod decimal;
+mod declare_all;
+mod declare_cache;
+mod dedup;
+mod delegate_panic;
+mod delivery_latency;
+#[cfg(any(test, feature = "testing"))]
+mod deterministic;
+mod dispatch_pool;
+mod envelope;
 mod error;
 mod error_handler;
 mod error_holder;
 mod exchange;
+#[cfg(any(test, feature = "testing"))]
+mod fault_injection;
+mod field_table_ext;
+mod flow_handler;
 mod frames;
+mod getter;
 mod id_sequence;
 mod internal_rpc;
 mod io_loop;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod io_uring_reactor;
 mod killswitch;
 mod notifier;
 mod parsing;
+mod pipeline;
 mod promise;
+mod publish_defaults;
+mod publish_events;
+mod publish_template;
 mod queue;
+mod queue_depth;
+mod rate_limit;
+mod raw_method;
 mod reactor;
+#[cfg(any(test, feature = "testing"))]
+mod recording;
 mod recovery_config;
 mod registry;
 mod returned_messages;
+mod secrets;
+mod sender_selected_distribution;
+mod shutdown_signal;
+mod slow_consumer;
+#[cfg(any(feature = "amqp1", feature = "management", feature = "streams"))]
+mod socket;
 mod thread;
 mod topology_internal;
 mod wakers;
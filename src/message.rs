@@ -1,14 +1,23 @@
 use crate::{
     acker::Acker,
+    delivery_latency::DeliveryLatency,
     error_holder::ErrorHolder,
+    field_table_ext::FieldTableExt,
     internal_rpc::InternalRPCHandle,
     killswitch::KillSwitch,
     protocol::AMQPError,
-    types::ShortString,
-    types::{ChannelId, DeliveryTag, MessageCount, ReplyCode},
+    timestamp,
+    types::{
+        ChannelId, DeliveryTag, FieldTable, MessageCount, PayloadSize, ReplyCode, ShortString,
+    },
     BasicProperties, Result,
 };
-use std::ops::{Deref, DerefMut};
+use bytes::Bytes;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    time::{Duration, SystemTime},
+};
 
 /// Type wrapping the output of a consumer
 ///
@@ -17,6 +26,15 @@ use std::ops::{Deref, DerefMut};
 /// - Err(error) carries the error and is always followed by Ok(None)
 pub type DeliveryResult = Result<Option<Delivery>>;
 
+/* Set by quorum queues to the number of previous deliveries of the message */
+const DELIVERY_COUNT_HEADER: &str = "x-delivery-count";
+/* Set by the broker when dead lettering a message */
+const DEATH_HEADER: &str = "x-death";
+/* Set by Channel::forward to where the message was first published, and how many times it got forwarded */
+const FORWARDED_EXCHANGE_HEADER: &str = "x-forwarded-exchange";
+const FORWARDED_ROUTING_KEY_HEADER: &str = "x-forwarded-routing-key";
+const HOP_COUNT_HEADER: &str = "x-hop-count";
+
 /// A received AMQP message.
 ///
 /// The message has to be acknowledged after processing by calling
@@ -48,10 +66,15 @@ pub struct Delivery {
     pub properties: BasicProperties,
 
     /// The payload of the message in binary format.
-    pub data: Vec<u8>,
+    ///
+    /// It can be cloned cheaply to be shared with other tasks.
+    pub data: Bytes,
 
     /// The acker used to ack/nack the message
     pub acker: Acker,
+
+    /// How long the message took to reach us
+    pub latency: DeliveryLatency,
 }
 
 impl Delivery {
@@ -71,16 +94,189 @@ impl Delivery {
             routing_key,
             redelivered,
             properties: BasicProperties::default(),
-            data: Vec::default(),
-            acker: Acker::new(channel_id, delivery_tag, internal_rpc, error, killswitch),
+            data: Bytes::new(),
+            acker: Acker::new(
+                channel_id,
+                delivery_tag,
+                delivery_count(redelivered, None),
+                internal_rpc,
+                error,
+                killswitch,
+            ),
+            latency: DeliveryLatency::default(),
+        }
+    }
+
+    /// How many times the message was delivered, including this one
+    ///
+    /// The previous deliveries are counted from the `x-delivery-count` header set by quorum
+    /// queues, or from the `redelivered` flag otherwise, in which case at most one is known.
+    /// The rejections recorded in the `x-death` header are added, so that messages going
+    /// through a dead letter exchange to be retried keep their count.
+    pub fn delivery_count(&self) -> u64 {
+        delivery_count(self.redelivered, self.properties.headers().as_ref())
+    }
+
+    /// How many times the message got forwarded with [`Channel::forward`] before reaching us
+    ///
+    /// [`Channel::forward`]: ../struct.Channel.html#method.forward
+    pub fn hop_count(&self) -> u64 {
+        self.headers()
+            .and_then(|headers| headers.get_i64(HOP_COUNT_HEADER))
+            .map_or(0, |count| count.max(0) as u64)
+    }
+
+    /// The exchange and routing key the message was first published with, before getting
+    /// forwarded with [`Channel::forward`]
+    ///
+    /// [`Channel::forward`]: ../struct.Channel.html#method.forward
+    pub fn origin(&self) -> (&str, &str) {
+        let headers = self.headers();
+        (
+            headers
+                .and_then(|headers| headers.get_str(FORWARDED_EXCHANGE_HEADER))
+                .unwrap_or(self.exchange()),
+            headers
+                .and_then(|headers| headers.get_str(FORWARDED_ROUTING_KEY_HEADER))
+                .unwrap_or(self.routing_key()),
+        )
+    }
+
+    /* The properties of the message, along with its provenance headers, to forward it */
+    pub(crate) fn forwarded_properties(&self) -> BasicProperties {
+        let (exchange, routing_key) = self.origin();
+        let mut headers = self.headers().cloned().unwrap_or_default();
+        headers
+            .set_str(FORWARDED_EXCHANGE_HEADER, exchange)
+            .set_str(FORWARDED_ROUTING_KEY_HEADER, routing_key)
+            .set_i64(HOP_COUNT_HEADER, (self.hop_count() + 1) as i64);
+        self.properties.clone().with_headers(headers)
+    }
+
+    pub fn exchange(&self) -> &str {
+        self.exchange.as_str()
+    }
+
+    pub fn routing_key(&self) -> &str {
+        self.routing_key.as_str()
+    }
+
+    pub fn properties(&self) -> &BasicProperties {
+        &self.properties
+    }
+
+    pub fn headers(&self) -> Option<&FieldTable> {
+        self.properties.headers().as_ref()
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// A snapshot of what identifies this delivery, cheap enough to be logged for each of them
+    pub fn metadata(&self) -> DeliveryMetadata {
+        DeliveryMetadata {
+            delivery_tag: self.delivery_tag,
+            exchange: self.exchange.clone(),
+            routing_key: self.routing_key.clone(),
+            redelivered: self.redelivered,
+            delivery_count: self.delivery_count(),
+            message_id: self.properties.message_id().clone(),
+            correlation_id: self.properties.correlation_id().clone(),
+            payload_size: self.data.len(),
         }
     }
 
-    pub(crate) fn receive_content(&mut self, data: Vec<u8>) {
-        self.data.extend(data);
+    pub(crate) fn set_properties(&mut self, properties: BasicProperties) {
+        self.acker.set_delivery_count(delivery_count(
+            self.redelivered,
+            properties.headers().as_ref(),
+        ));
+        self.properties = properties;
+    }
+
+    /// How long ago the message was published, according to its timestamp property
+    ///
+    /// This is `None` if the message has no timestamp or if it's in the future, e.g. because
+    /// of a clock skew between the publisher and the consumer. The timestamp only has a
+    /// precision of one second.
+    pub fn age(&self) -> Option<Duration> {
+        let published = timestamp::to_system_time((*self.properties.timestamp())?);
+        SystemTime::now().duration_since(published).ok()
+    }
+
+    pub(crate) fn receive_content(&mut self, data: Vec<u8>, remaining_size: PayloadSize) {
+        if self.data.is_empty() {
+            // Reuse the frame's buffer instead of copying it for the first (and often only) body frame
+            // and make room for the remaining ones at once
+            let mut data = data;
+            data.reserve_exact(remaining_size as usize);
+            self.data = data.into();
+        } else {
+            // Converting back and forth doesn't copy as long as nobody else holds the payload
+            let mut buffer = Vec::from(std::mem::take(&mut self.data));
+            buffer.extend(data);
+            self.data = buffer.into();
+        }
     }
 }
 
+/// What identifies a [`Delivery`], obtained with [`Delivery::metadata`]
+///
+/// It doesn't hold on to the payload, and can outlive the delivery.
+///
+/// [`Delivery`]: ./struct.Delivery.html
+/// [`Delivery::metadata`]: ./struct.Delivery.html#method.metadata
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeliveryMetadata {
+    pub delivery_tag: DeliveryTag,
+    pub exchange: ShortString,
+    pub routing_key: ShortString,
+    pub redelivered: bool,
+    pub delivery_count: u64,
+    pub message_id: Option<ShortString>,
+    pub correlation_id: Option<ShortString>,
+    pub payload_size: usize,
+}
+
+impl fmt::Display for DeliveryMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "delivery {} from exchange '{}' with routing key '{}'",
+            self.delivery_tag, self.exchange, self.routing_key
+        )?;
+        if let Some(message_id) = self.message_id.as_ref() {
+            write!(f, ", message id {}", message_id)?;
+        }
+        if let Some(correlation_id) = self.correlation_id.as_ref() {
+            write!(f, ", correlation id {}", correlation_id)?;
+        }
+        write!(
+            f,
+            ", {} bytes, delivered {} times",
+            self.payload_size, self.delivery_count
+        )
+    }
+}
+
+/* The number of deliveries of a message, including the current one */
+fn delivery_count(redelivered: bool, headers: Option<&FieldTable>) -> u64 {
+    let Some(headers) = headers else {
+        return 1 + u64::from(redelivered);
+    };
+    let redeliveries = headers
+        .get_i64(DELIVERY_COUNT_HEADER)
+        .map_or(u64::from(redelivered), |count| count.max(0) as u64);
+    let rejections = headers
+        .get_tables(DEATH_HEADER)
+        .filter(|death| death.get_str("reason") == Some("rejected"))
+        .filter_map(|death| death.get_i64("count"))
+        .map(|count| count.max(0) as u64)
+        .sum::<u64>();
+    1 + redeliveries + rejections
+}
+
 impl Deref for Delivery {
     type Target = Acker;
 
@@ -178,3 +374,181 @@ impl DerefMut for BasicReturnMessage {
         &mut self.delivery
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*,
+        testing::MockBroker,
+        types::{AMQPValue, FieldArray},
+        ConnectionProperties,
+    };
+
+    #[test]
+    fn delivery_counts() {
+        assert_eq!(delivery_count(false, None), 1);
+        assert_eq!(delivery_count(true, None), 2);
+        let mut headers = FieldTable::default();
+        headers.set_i64(DELIVERY_COUNT_HEADER, 3);
+        assert_eq!(delivery_count(true, Some(&headers)), 4);
+        let deaths = [("rejected", 2), ("expired", 2)]
+            .into_iter()
+            .map(|(reason, count)| {
+                let mut death = FieldTable::default();
+                death.set_str("reason", reason).set_i64("count", count);
+                AMQPValue::FieldTable(death)
+            })
+            .collect::<Vec<_>>();
+        headers.insert(
+            DEATH_HEADER.into(),
+            AMQPValue::FieldArray(FieldArray::from(deaths)),
+        );
+        assert_eq!(delivery_count(true, Some(&headers)), 6);
+    }
+
+    #[test]
+    fn body_frames() {
+        let mut delivery = Delivery::new(0, 1, "".into(), "".into(), false, None, None, None);
+        let first = vec![1, 2, 3];
+        let buffer = first.as_ptr();
+        // The first body frame's buffer is kept, with room for the other ones
+        delivery.receive_content(first, 4);
+        assert_eq!(delivery.data.as_ptr(), buffer);
+        delivery.receive_content(vec![4, 5], 2);
+        delivery.receive_content(vec![6, 7], 0);
+        assert_eq!(&delivery.data[..], &[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(delivery.data.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn requeue_budget() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default(),
+                )
+                .await?;
+            for delivery_count in 1..=2 {
+                let message = channel
+                    .basic_get("jobs", BasicGetOptions::default())
+                    .await?
+                    .unwrap();
+                assert_eq!(message.delivery.delivery_count(), delivery_count);
+                assert!(message.reject_or_dead_letter(1).await?);
+            }
+            assert_eq!(broker.message_count("jobs"), Some(0));
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn forward() {
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            for queue in ["inbox", "routed", "archived"] {
+                channel
+                    .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+                    .await?;
+            }
+            channel
+                .basic_publish(
+                    "",
+                    "inbox",
+                    BasicPublishOptions::default(),
+                    b"order",
+                    BasicProperties::default().with_message_id("order-1".into()),
+                )
+                .await?
+                .await?;
+
+            for (from, to) in [("inbox", "routed"), ("routed", "archived")] {
+                let message = channel
+                    .basic_get(from, BasicGetOptions::default())
+                    .await?
+                    .unwrap();
+                assert!(channel.forward(&message, "", to).await?.await?.is_ack());
+                message.ack(BasicAckOptions::default()).await?;
+            }
+
+            let message = channel
+                .basic_get("archived", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(message.hop_count(), 2);
+            assert_eq!(message.origin(), ("", "inbox"));
+            assert_eq!(message.routing_key(), "archived");
+            assert_eq!(&message.data[..], b"order");
+            assert_eq!(
+                message
+                    .properties
+                    .message_id()
+                    .as_ref()
+                    .map(ShortString::as_str),
+                Some("order-1")
+            );
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn metadata() {
+        let mut delivery = Delivery::new(
+            1,
+            42,
+            "events".into(),
+            "orders.created".into(),
+            true,
+            None,
+            None,
+            None,
+        );
+        let mut headers = FieldTable::default();
+        headers.set_str("tenant", "acme");
+        delivery.set_properties(
+            BasicProperties::default()
+                .with_message_id("order-7".into())
+                .with_headers(headers),
+        );
+        delivery.receive_content(b"{}".to_vec(), 0);
+
+        assert_eq!(delivery.exchange(), "events");
+        assert_eq!(delivery.routing_key(), "orders.created");
+        assert_eq!(delivery.payload(), b"{}");
+        assert_eq!(
+            delivery
+                .headers()
+                .and_then(|headers| headers.get_str("tenant")),
+            Some("acme")
+        );
+        let metadata = delivery.metadata();
+        drop(delivery);
+        assert_eq!(
+            metadata.to_string(),
+            "delivery 42 from exchange 'events' with routing key 'orders.created', message id order-7, 2 bytes, delivered 2 times"
+        );
+    }
+}
@@ -1,39 +1,79 @@
-use crate::wakers::Wakers;
+use crate::{reactor::FullReactor, types::ChannelId, wakers::Wakers, Error};
 
+use flume::{r#async::RecvStream, Sender};
+use futures_core::stream::Stream;
 use std::{
     fmt,
-    future::Future,
+    future::{self, Future},
     pin::Pin,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
+    sync::{Arc, Mutex, MutexGuard},
     task::{Context, Poll},
+    time::Duration,
 };
 
-#[derive(Default, Clone)]
+/// How the recovery of a channel ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecoveryOutcome {
+    /// The channel has been reopened and can be used again
+    Recovered,
+    /// The channel won't be recovered, because of the given error
+    PermanentlyFailed(Error),
+}
+
+/// Resolves with the [`RecoveryOutcome`] once the recovery of a channel ended
+///
+/// It is obtained through [`Error::notifier`] when an operation failed because its channel
+/// was recovering.
+///
+/// [`Error::notifier`]: ./struct.Error.html#method.notifier
+#[derive(Clone)]
 pub struct Notifier {
-    done: Arc<AtomicBool>,
+    outcome: Arc<Mutex<Option<RecoveryOutcome>>>,
     wakers: Arc<Wakers>,
+    reactor: Arc<dyn FullReactor + Send + Sync>,
 }
 
 impl Notifier {
-    pub(crate) fn notify_all(&self) {
-        self.done.store(true, Ordering::Release);
+    pub(crate) fn new(reactor: Arc<dyn FullReactor + Send + Sync>) -> Self {
+        Self {
+            outcome: Arc::default(),
+            wakers: Arc::default(),
+            reactor,
+        }
+    }
+
+    pub(crate) fn notify_all(&self, outcome: RecoveryOutcome) {
+        self.lock_outcome().get_or_insert(outcome);
         self.wakers.wake();
     }
 
-    fn ready(&self) -> bool {
-        self.done.load(Ordering::Acquire)
+    /// Wait for the outcome of the recovery, giving up after `timeout`
+    ///
+    /// Returns `None` if the recovery was still ongoing once the timeout expired.
+    pub async fn timeout(mut self, timeout: Duration) -> Option<RecoveryOutcome> {
+        let reactor = self.reactor.clone();
+        let mut sleep = reactor.sleep(timeout);
+        future::poll_fn(|cx| {
+            if let Poll::Ready(outcome) = Pin::new(&mut self).poll(cx) {
+                return Poll::Ready(Some(outcome));
+            }
+            sleep.as_mut().poll(cx).map(|()| None)
+        })
+        .await
+    }
+
+    fn lock_outcome(&self) -> MutexGuard<'_, Option<RecoveryOutcome>> {
+        self.outcome.lock().unwrap_or_else(|e| e.into_inner())
     }
 }
 
 impl Future for Notifier {
-    type Output = ();
+    type Output = RecoveryOutcome;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self.ready() {
-            Poll::Ready(())
+        let outcome = self.lock_outcome();
+        if let Some(outcome) = outcome.as_ref() {
+            Poll::Ready(outcome.clone())
         } else {
             self.wakers.register(cx.waker());
             Poll::Pending
@@ -46,3 +86,76 @@ impl fmt::Debug for Notifier {
         f.debug_tuple("Notifier").finish()
     }
 }
+
+/// Something that happened to a connection or to one of its channels, yielded by
+/// [`RecoveryEvents`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecoveryEvent {
+    /// The given channel has been reopened and can be used again
+    ChannelRecovered(ChannelId),
+    /// The given channel won't be recovered, because of the given error
+    ChannelFailed(ChannelId, Error),
+    /// The connection closed or failed, because of the given error: nothing else will happen
+    ConnectionUnusable(Error),
+}
+
+/// The stream of the [`RecoveryEvent`]s of a connection, obtained through
+/// [`Connection::recovery_events`]
+///
+/// Unlike a [`Notifier`], which only tells about the recovery of the channel an error came
+/// from, this tells about all the channels of the connection, whether they were in use or
+/// not. It only yields the events which happened after its creation, and ends after
+/// [`RecoveryEvent::ConnectionUnusable`].
+///
+/// [`Connection::recovery_events`]: ./struct.Connection.html#method.recovery_events
+pub struct RecoveryEvents(RecvStream<'static, RecoveryEvent>);
+
+impl Stream for RecoveryEvents {
+    type Item = RecoveryEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+impl fmt::Debug for RecoveryEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RecoveryEvents").finish()
+    }
+}
+
+/* Dispatches the RecoveryEvents of a connection to all of its subscribers */
+#[derive(Default)]
+pub(crate) struct NotifierRegistry {
+    subscribers: Vec<Sender<RecoveryEvent>>,
+    unusable: Option<Error>,
+}
+
+impl NotifierRegistry {
+    pub(crate) fn subscribe(&mut self) -> RecoveryEvents {
+        let (sender, receiver) = flume::unbounded();
+        match self.unusable.as_ref() {
+            // Nothing else will happen, let the stream end right away
+            Some(error) => {
+                let _ = sender.send(RecoveryEvent::ConnectionUnusable(error.clone()));
+            }
+            None => self.subscribers.push(sender),
+        }
+        RecoveryEvents(receiver.into_stream())
+    }
+
+    pub(crate) fn notify(&mut self, event: RecoveryEvent) {
+        if let RecoveryEvent::ConnectionUnusable(error) = &event {
+            if self.unusable.is_some() {
+                return;
+            }
+            self.unusable = Some(error.clone());
+        }
+        // Forget about the dropped streams along the way
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        if self.unusable.is_some() {
+            self.subscribers.clear();
+        }
+    }
+}
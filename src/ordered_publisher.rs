@@ -0,0 +1,232 @@
+use crate::{
+    options::BasicPublishOptions, publisher_confirm::PublisherConfirm, types::ShortString,
+    BasicProperties, Channel, Result,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, future,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Poll, Waker},
+};
+
+/// Publisher serializing publishes sharing the same partition key.
+///
+/// Messages published with the same partition key (the routing key by default) are sent in the
+/// order in which [`basic_publish`] was called, even when called concurrently from several tasks,
+/// while messages with different partition keys are published in parallel.
+///
+/// [`basic_publish`]: #method.basic_publish
+#[derive(Clone)]
+pub struct OrderedPublisher {
+    channel: Channel,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl OrderedPublisher {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            inner: Arc::default(),
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// Publish a message, ordered with the other ones sharing the same routing key.
+    pub async fn basic_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        self.basic_publish_partitioned(
+            routing_key,
+            exchange,
+            routing_key,
+            options,
+            payload,
+            properties,
+        )
+        .await
+    }
+
+    /// Publish a message, ordered with the other ones sharing the same partition key.
+    pub async fn basic_publish_partitioned(
+        &self,
+        partition_key: &str,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        let _turn = self.wait_turn(partition_key).await;
+        self.channel
+            .basic_publish(exchange, routing_key, options, payload, properties)
+            .await
+    }
+
+    /* Wait for the publishes which came before with the same partition key to be sent */
+    pub(crate) async fn wait_turn(&self, partition_key: &str) -> Turn {
+        let turn = self.take_turn(partition_key);
+        future::poll_fn(|cx| {
+            if self.lock_inner().is_current(&turn, cx.waker()) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        turn
+    }
+
+    fn take_turn(&self, partition_key: &str) -> Turn {
+        let mut inner = self.lock_inner();
+        let id = inner.next_id;
+        inner.next_id = inner.next_id.wrapping_add(1);
+        inner
+            .partitions
+            .entry(partition_key.into())
+            .or_default()
+            .push_back((id, None));
+        Turn {
+            inner: self.inner.clone(),
+            partition_key: partition_key.into(),
+            id,
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for OrderedPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrderedPublisher")
+            .field("channel", &self.channel)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    partitions: HashMap<ShortString, VecDeque<(u64, Option<Waker>)>>,
+}
+
+impl Inner {
+    fn is_current(&mut self, turn: &Turn, waker: &Waker) -> bool {
+        let Some(queue) = self.partitions.get_mut(&turn.partition_key) else {
+            return false;
+        };
+        if queue.front().is_some_and(|(id, _)| *id == turn.id) {
+            return true;
+        }
+        if let Some((_, slot)) = queue.iter_mut().find(|(id, _)| *id == turn.id) {
+            *slot = Some(waker.clone());
+        }
+        false
+    }
+}
+
+/// Position of a publish in its partition queue, released when the publish completes or is
+/// cancelled.
+pub(crate) struct Turn {
+    inner: Arc<Mutex<Inner>>,
+    partition_key: ShortString,
+    id: u64,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(queue) = inner.partitions.get_mut(&self.partition_key) else {
+            return;
+        };
+        queue.retain(|(id, _)| *id != self.id);
+        if let Some((_, waker)) = queue.front_mut() {
+            if let Some(waker) = waker.take() {
+                waker.wake();
+            }
+        } else {
+            inner.partitions.remove(&self.partition_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::{BasicGetOptions, ConfirmSelectOptions, QueueDeclareOptions},
+        testing::MockBroker,
+        types::FieldTable,
+        ConnectionProperties,
+    };
+    use futures_lite::future;
+
+    #[test]
+    fn publish_order() {
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .queue_declare(
+                    "events",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let publisher = OrderedPublisher::new(channel.clone());
+            let publish = |payload: &'static [u8]| {
+                Box::pin(publisher.basic_publish(
+                    "",
+                    "events",
+                    BasicPublishOptions::default(),
+                    payload,
+                    BasicProperties::default(),
+                ))
+            };
+
+            // Take the turns while the server paused the publishes, then let them go in reverse
+            channel.status().set_send_flow(false);
+            let mut first = publish(b"first");
+            let mut cancelled = publish(b"cancelled");
+            let mut second = publish(b"second");
+            for publish in [&mut first, &mut cancelled, &mut second] {
+                assert!(future::poll_once(publish).await.is_none());
+            }
+            drop(cancelled);
+            channel.status().set_send_flow(true);
+            let (second, first) = future::zip(second, first).await;
+            let (second, mut first) = (second?, first?);
+
+            // The confirms resolve in publish order too
+            assert!(second.await?.is_ack());
+            assert!(future::poll_once(&mut first).await.unwrap()?.is_ack());
+
+            for payload in [&b"first"[..], b"second"] {
+                let message = channel
+                    .basic_get("events", BasicGetOptions::default())
+                    .await?
+                    .unwrap();
+                assert_eq!(&message.data[..], payload);
+            }
+            assert!(channel
+                .basic_get("events", BasicGetOptions::default())
+                .await?
+                .is_none());
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,633 @@
+use crate::{
+    options::BasicPublishOptions,
+    protocol::{basic, AMQPClass},
+    publisher_confirm::Confirmation,
+    types::ShortString,
+    BasicProperties, Channel, Envelope, Promise, RecoveryEvent, Result,
+};
+use amq_protocol::frame::{gen_frame, parse_frame, AMQPContentHeader, AMQPFrame, WriteContext};
+use futures_core::Stream;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    future::poll_fn,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+use tracing::{debug, trace, warn};
+
+/// A message waiting in an outbox [`Journal`] for its publisher confirmation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub exchange: ShortString,
+    pub routing_key: ShortString,
+    pub options: BasicPublishOptions,
+    pub payload: Vec<u8>,
+    pub properties: BasicProperties,
+}
+
+/// Storage for the messages which have not been confirmed by the server yet.
+///
+/// The [`OutboxPublisher`] calls it from the blocking pool of the executor, so it can block on
+/// disk or network I/O.
+pub trait Journal {
+    /// Record a message before it gets published.
+    fn append(&self, entry: &OutboxEntry) -> io::Result<()>;
+    /// Forget about a message once it has been confirmed.
+    fn remove(&self, id: u64) -> io::Result<()>;
+    /// Get all the unconfirmed messages, ordered by id.
+    fn pending(&self) -> io::Result<Vec<OutboxEntry>>;
+}
+
+/// A [`Journal`] keeping the messages in memory.
+///
+/// This only protects against connection failures, not against the process crashing.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryJournal(Arc<Mutex<BTreeMap<u64, OutboxEntry>>>);
+
+impl Journal for InMemoryJournal {
+    fn append(&self, entry: &OutboxEntry) -> io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(entry.id, entry.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: u64) -> io::Result<()> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+        Ok(())
+    }
+
+    fn pending(&self) -> io::Result<Vec<OutboxEntry>> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect())
+    }
+}
+
+/// A [`Journal`] storing each message in its own file inside a directory.
+///
+/// Messages are encoded as the AMQP frames used to publish them.
+#[derive(Clone, Debug)]
+pub struct FileJournal {
+    directory: PathBuf,
+}
+
+impl FileJournal {
+    /// Use the given directory as a journal, creating it if needed.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, id: u64, extension: &str) -> PathBuf {
+        self.directory.join(format!("{id:020}.{extension}"))
+    }
+
+    #[cfg(unix)]
+    fn sync_directory(&self) -> io::Result<()> {
+        fs::File::open(&self.directory)?.sync_all()
+    }
+
+    /* Directories cannot be opened as files there */
+    #[cfg(not(unix))]
+    fn sync_directory(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Journal for FileJournal {
+    fn append(&self, entry: &OutboxEntry) -> io::Result<()> {
+        let tmp = self.path(entry.id, "tmp");
+        let file = fs::File::create(&tmp)?;
+        io::Write::write_all(&mut &file, &encode(entry)?)?;
+        file.sync_all()?;
+        fs::rename(tmp, self.path(entry.id, "msg"))?;
+        // The rename only survives a crash once the directory itself is synced
+        self.sync_directory()
+    }
+
+    fn remove(&self, id: u64) -> io::Result<()> {
+        match fs::remove_file(self.path(id, "msg")) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn pending(&self) -> io::Result<Vec<OutboxEntry>> {
+        let mut entries = Vec::new();
+        for file in fs::read_dir(&self.directory)? {
+            let path = file?.path();
+            if !path.extension().is_some_and(|ext| ext == "msg") {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            else {
+                continue;
+            };
+            entries.push(decode(id, &fs::read(&path)?)?);
+        }
+        entries.sort_by_key(|entry| entry.id);
+        Ok(entries)
+    }
+}
+
+fn encode(entry: &OutboxEntry) -> io::Result<Vec<u8>> {
+    let frames = [
+        AMQPFrame::Method(
+            0,
+            AMQPClass::Basic(basic::AMQPMethod::Publish(basic::Publish {
+                exchange: entry.exchange.clone(),
+                routing_key: entry.routing_key.clone(),
+                mandatory: entry.options.mandatory,
+                immediate: entry.options.immediate,
+            })),
+        ),
+        AMQPFrame::Header(
+            0,
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                body_size: entry.payload.len() as u64,
+                properties: entry.properties.clone(),
+            }),
+        ),
+        AMQPFrame::Body(0, entry.payload.clone()),
+    ];
+    frames
+        .iter()
+        .try_fold(WriteContext::from(Vec::new()), |ctx, frame| {
+            gen_frame(frame)(ctx)
+        })
+        .map(|ctx| ctx.write)
+        .map_err(io::Error::other)
+}
+
+fn decode(id: u64, mut input: &[u8]) -> io::Result<OutboxEntry> {
+    let mut next_frame = || {
+        let (rest, frame) = parse_frame(input).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "invalid outbox journal entry")
+        })?;
+        input = rest;
+        Ok::<_, io::Error>(frame)
+    };
+    match (next_frame()?, next_frame()?, next_frame()?) {
+        (
+            AMQPFrame::Method(_, AMQPClass::Basic(basic::AMQPMethod::Publish(publish))),
+            AMQPFrame::Header(_, _, header),
+            AMQPFrame::Body(_, payload),
+        ) => Ok(OutboxEntry {
+            id,
+            exchange: publish.exchange,
+            routing_key: publish.routing_key,
+            options: BasicPublishOptions {
+                mandatory: publish.mandatory,
+                immediate: publish.immediate,
+            },
+            payload,
+            properties: header.properties,
+        }),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected frames in outbox journal entry",
+        )),
+    }
+}
+
+/// Publisher providing at-least-once delivery by going through an outbox [`Journal`].
+///
+/// Each message is written to the journal before being published and is only removed from it
+/// once the server has acked it. Unconfirmed messages are published again when the publisher
+/// gets created and each time its channel gets recovered, and can be replayed at any time
+/// using [`OutboxPublisher::replay`]. Replays skip the messages still waiting for their
+/// confirmation.
+///
+/// Consumers may thus receive some messages several times.
+#[derive(Clone)]
+pub struct OutboxPublisher(Arc<Inner>);
+
+struct Inner {
+    channel: Channel,
+    journal: Arc<dyn Journal + Send + Sync>,
+    next_id: AtomicU64,
+    /* The ids of the entries being published, that replays must leave alone */
+    in_flight: Mutex<HashSet<u64>>,
+}
+
+impl OutboxPublisher {
+    /// Create a publisher, enabling publisher confirms on the channel and replaying the
+    /// messages left unconfirmed in the journal.
+    pub async fn new<J: Journal + Send + Sync + 'static>(
+        channel: Channel,
+        journal: J,
+    ) -> Result<Self> {
+        if !channel.status().confirm() {
+            channel.confirm_select(Default::default()).await?;
+        }
+        let journal: Arc<dyn Journal + Send + Sync> = Arc::new(journal);
+        let pending = blocking(&channel, &journal, |journal| journal.pending()).await?;
+        let next_id = pending.last().map_or(0, |entry| entry.id + 1);
+        let publisher = Self(Arc::new(Inner {
+            channel,
+            journal,
+            next_id: AtomicU64::new(next_id),
+            in_flight: Mutex::default(),
+        }));
+        publisher.replay_on_recovery();
+        publisher.replay_entries(pending).await?;
+        Ok(publisher)
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.0.channel
+    }
+
+    /// Journal then publish a message, waiting for its confirmation.
+    ///
+    /// The message only gets removed from the journal if the server acked it.
+    pub async fn basic_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<Confirmation> {
+        self.append_then_publish(OutboxEntry {
+            id: self.0.next_id.fetch_add(1, Ordering::SeqCst),
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            options,
+            payload: payload.to_vec(),
+            properties,
+        })
+        .await
+    }
+
+    /// Journal then publish a message described by an [`Envelope`], waiting for its
+    /// confirmation.
+    ///
+    /// [`Envelope`]: ../struct.Envelope.html
+    pub async fn publish(&self, envelope: Envelope) -> Result<Confirmation> {
+        self.append_then_publish(OutboxEntry {
+            id: self.0.next_id.fetch_add(1, Ordering::SeqCst),
+            exchange: envelope.exchange,
+            routing_key: envelope.routing_key,
+            options: envelope.options,
+            payload: envelope.payload,
+            properties: envelope.properties,
+        })
+        .await
+    }
+
+    /// Publish again all the messages which are still in the journal, except for the ones
+    /// waiting for their confirmation.
+    ///
+    /// Returns the number of messages which were acked.
+    pub async fn replay(&self) -> Result<usize> {
+        let pending = blocking(&self.0.channel, &self.0.journal, |journal| {
+            journal.pending()
+        })
+        .await?;
+        self.replay_entries(pending).await
+    }
+
+    async fn replay_entries(&self, entries: Vec<OutboxEntry>) -> Result<usize> {
+        let mut acked = 0;
+        for entry in entries {
+            let Some(in_flight) = InFlight::claim(&self.0, entry.id) else {
+                trace!(
+                    id = entry.id,
+                    "outbox entry already in flight, not replaying it"
+                );
+                continue;
+            };
+            debug!(id = entry.id, "replaying unconfirmed outbox entry");
+            if self.publish_entry(&entry, in_flight).await?.is_ack() {
+                acked += 1;
+            }
+        }
+        Ok(acked)
+    }
+
+    /* Replay the journal each time the channel comes back, for as long as the publisher lives */
+    fn replay_on_recovery(&self) {
+        let channel_id = self.0.channel.id();
+        let mut events = self.0.channel.recovery_events();
+        let publisher = Arc::downgrade(&self.0);
+        self.0.channel.spawn(async move {
+            while let Some(event) = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await {
+                if !matches!(event, RecoveryEvent::ChannelRecovered(id) if id == channel_id) {
+                    continue;
+                }
+                let Some(inner) = Weak::upgrade(&publisher) else {
+                    break;
+                };
+                match Self(inner).replay().await {
+                    Ok(acked) => {
+                        debug!(channel = %channel_id, acked, "outbox replayed after recovery");
+                    }
+                    Err(error) => {
+                        warn!(channel = %channel_id, %error, "outbox replay after recovery failed");
+                    }
+                }
+            }
+        });
+    }
+
+    async fn append_then_publish(&self, entry: OutboxEntry) -> Result<Confirmation> {
+        // Claimed before it lands in the journal so that no replay picks it up meanwhile
+        let in_flight = InFlight::claim(&self.0, entry.id).expect("fresh outbox entry id");
+        let appended = entry.clone();
+        blocking(&self.0.channel, &self.0.journal, move |journal| {
+            journal.append(&appended)
+        })
+        .await?;
+        self.publish_entry(&entry, in_flight).await
+    }
+
+    async fn publish_entry(
+        &self,
+        entry: &OutboxEntry,
+        _in_flight: InFlight<'_>,
+    ) -> Result<Confirmation> {
+        let confirmation = self
+            .0
+            .channel
+            .basic_publish(
+                entry.exchange.as_str(),
+                entry.routing_key.as_str(),
+                entry.options,
+                &entry.payload,
+                entry.properties.clone(),
+            )
+            .await?
+            .await?;
+        if confirmation.is_ack() {
+            trace!(id = entry.id, "outbox entry confirmed");
+            let id = entry.id;
+            blocking(&self.0.channel, &self.0.journal, move |journal| {
+                journal.remove(id)
+            })
+            .await?;
+        }
+        Ok(confirmation)
+    }
+}
+
+impl fmt::Debug for OutboxPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OutboxPublisher")
+            .field("channel", &self.0.channel)
+            .field("next_id", &self.0.next_id)
+            .finish()
+    }
+}
+
+/* Marks an outbox entry as being published until dropped, whether it got confirmed or not */
+struct InFlight<'a> {
+    inner: &'a Inner,
+    id: u64,
+}
+
+impl<'a> InFlight<'a> {
+    fn claim(inner: &'a Inner, id: u64) -> Option<Self> {
+        let claimed = inner
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id);
+        claimed.then_some(Self { inner, id })
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.inner
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+/* Journals may hit the disk, so run their operations on the blocking pool of the executor */
+async fn blocking<T: Send + 'static>(
+    channel: &Channel,
+    journal: &Arc<dyn Journal + Send + Sync>,
+    f: impl FnOnce(&dyn Journal) -> io::Result<T> + Send + 'static,
+) -> Result<T> {
+    let journal = journal.clone();
+    let (promise, resolver) = Promise::new();
+    channel
+        .spawn_blocking(move || resolver.complete(f(&*journal).map_err(Into::into)))
+        .await;
+    promise.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::QueueDeclareOptions,
+        testing::{FaultyStream, MockBroker},
+        types::FieldTable,
+        Connection, ConnectionProperties, RecoveryConfig,
+    };
+    use std::time::Duration;
+
+    fn entry(id: u64, payload: &[u8]) -> OutboxEntry {
+        OutboxEntry {
+            id,
+            exchange: "".into(),
+            routing_key: "jobs".into(),
+            options: BasicPublishOptions::default(),
+            payload: payload.to_vec(),
+            properties: BasicProperties::default(),
+        }
+    }
+
+    async fn jobs_channel(connection: &Connection) -> Result<Channel> {
+        let channel = connection.create_channel().await?;
+        channel
+            .queue_declare(
+                "jobs",
+                QueueDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+        Ok(channel)
+    }
+
+    #[test]
+    fn confirmed_messages_leave_the_journal() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let journal = InMemoryJournal::default();
+            let publisher =
+                OutboxPublisher::new(jobs_channel(&connection).await?, journal.clone()).await?;
+            let confirmation = publisher
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"first",
+                    BasicProperties::default(),
+                )
+                .await?;
+            assert!(confirmation.is_ack());
+            assert!(journal.pending()?.is_empty());
+            assert_eq!(broker.messages("jobs"), vec![b"first".to_vec()]);
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn replay_pending_entries() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let journal = InMemoryJournal::default();
+            journal.append(&entry(3, b"left over"))?;
+            let publisher =
+                OutboxPublisher::new(jobs_channel(&connection).await?, journal.clone()).await?;
+            assert!(journal.pending()?.is_empty());
+            assert_eq!(broker.messages("jobs"), vec![b"left over".to_vec()]);
+
+            // Ids keep going after the replayed ones
+            journal.append(&entry(4, b"added"))?;
+            assert_eq!(publisher.replay().await?, 1);
+            publisher
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"published",
+                    BasicProperties::default(),
+                )
+                .await?;
+            assert_eq!(broker.message_count("jobs"), Some(3));
+            assert_eq!(publisher.replay().await?, 0);
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn replay_skips_in_flight_entries() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let journal = InMemoryJournal::default();
+            let publisher =
+                OutboxPublisher::new(jobs_channel(&connection).await?, journal.clone()).await?;
+            broker.pause();
+            let publishing = async_global_executor::spawn({
+                let publisher = publisher.clone();
+                async move {
+                    publisher
+                        .basic_publish(
+                            "",
+                            "jobs",
+                            BasicPublishOptions::default(),
+                            b"in flight",
+                            BasicProperties::default(),
+                        )
+                        .await
+                }
+            });
+            while journal.pending()?.is_empty() {
+                futures_lite::future::yield_now().await;
+            }
+            assert_eq!(publisher.replay().await?, 0);
+            broker.resume();
+            assert!(publishing.await?.is_ack());
+            assert!(journal.pending()?.is_empty());
+            assert_eq!(broker.message_count("jobs"), Some(1));
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn replay_on_channel_recovery() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let journal = InMemoryJournal::default();
+            let publisher =
+                OutboxPublisher::new(jobs_channel(&connection).await?, journal.clone()).await?;
+            // Left behind by a publish whose confirmation got lost with the channel
+            journal.append(&entry(0, b"unconfirmed"))?;
+            injector.fail_channel(publisher.channel().id(), 406, "PRECONDITION_FAILED - chaos");
+            for _ in 0..1000 {
+                if journal.pending()?.is_empty() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert!(journal.pending()?.is_empty());
+            assert_eq!(broker.messages("jobs"), vec![b"unconfirmed".to_vec()]);
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn file_journal_roundtrip() {
+        let directory = std::env::temp_dir().join(format!("lapin-outbox-{}", std::process::id()));
+        let journal = FileJournal::new(&directory).unwrap();
+        let entry = OutboxEntry {
+            id: 42,
+            exchange: "exchange".into(),
+            routing_key: "key".into(),
+            options: BasicPublishOptions {
+                mandatory: true,
+                immediate: false,
+            },
+            payload: b"payload".to_vec(),
+            properties: BasicProperties::default().with_app_id("app".into()),
+        };
+        journal.append(&entry).unwrap();
+        assert_eq!(journal.pending().unwrap(), vec![entry]);
+        journal.remove(42).unwrap();
+        assert!(journal.pending().unwrap().is_empty());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use crate::Result;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+type PipelinedFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Several requests in flight at once, resolving to their results in order.
+///
+/// The requests of a [`Channel`] are sent as soon as their future is first polled, and the
+/// broker answers those of a channel in order, so there's no need to wait for the reply to a
+/// request before sending the next one. Awaiting a pipeline polls all of its futures, in the
+/// order they were pushed, turning what would be one round trip per request into a single one,
+/// which is what bulk topology setup needs. The futures can belong to different channels.
+///
+/// The pipeline fails with the error of the first failed request, in push order. A failed
+/// request closes its channel, making the ones sent after it on that channel fail too.
+///
+/// ```rust,no_run
+/// use lapin::{options::*, types::FieldTable, Channel, Pipeline, Result};
+///
+/// async fn declare_all(channel: &Channel) -> Result<()> {
+///     let names = (0..500).map(|i| format!("queue-{i}")).collect::<Vec<_>>();
+///     let queues = names
+///         .iter()
+///         .map(|name| {
+///             channel.queue_declare(name, QueueDeclareOptions::default(), FieldTable::default())
+///         })
+///         .collect::<Pipeline<'_, _>>()
+///         .await?;
+///     assert_eq!(queues.len(), 500);
+///     Ok(())
+/// }
+/// ```
+///
+/// [`Channel`]: ./struct.Channel.html
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Pipeline<'a, T> {
+    slots: Vec<Slot<'a, T>>,
+}
+
+enum Slot<'a, T> {
+    Pending(PipelinedFuture<'a, T>),
+    Done(Result<T>),
+    Taken,
+}
+
+impl<'a, T> Pipeline<'a, T> {
+    /// An empty pipeline, resolving to an empty list
+    pub fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+
+    /// Add a request to the pipeline
+    pub fn push<F: Future<Output = Result<T>> + Send + 'a>(&mut self, future: F) {
+        self.slots.push(Slot::Pending(Box::pin(future)));
+    }
+
+    /// Add a request to the pipeline
+    pub fn with<F: Future<Output = Result<T>> + Send + 'a>(mut self, future: F) -> Self {
+        self.push(future);
+        self
+    }
+
+    /// The number of requests in the pipeline
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Whether the pipeline holds no request
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+impl<T> Default for Pipeline<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T, F: Future<Output = Result<T>> + Send + 'a> Extend<F> for Pipeline<'a, T> {
+    fn extend<I: IntoIterator<Item = F>>(&mut self, futures: I) {
+        for future in futures {
+            self.push(future);
+        }
+    }
+}
+
+impl<'a, T, F: Future<Output = Result<T>> + Send + 'a> FromIterator<F> for Pipeline<'a, T> {
+    fn from_iter<I: IntoIterator<Item = F>>(futures: I) -> Self {
+        let mut pipeline = Self::new();
+        pipeline.extend(futures);
+        pipeline
+    }
+}
+
+/* The futures are boxed and the results never pinned */
+impl<T> Unpin for Pipeline<'_, T> {}
+
+impl<T> Future for Pipeline<'_, T> {
+    type Output = Result<Vec<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut done = true;
+        for slot in self.slots.iter_mut() {
+            if let Slot::Pending(future) = slot {
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(res) => *slot = Slot::Done(res),
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+        if !done {
+            return Poll::Pending;
+        }
+        Poll::Ready(
+            self.slots
+                .iter_mut()
+                .map(|slot| match std::mem::replace(slot, Slot::Taken) {
+                    Slot::Done(res) => res,
+                    _ => unreachable!("pipeline polled after completion"),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl<T> fmt::Debug for Pipeline<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("len", &self.slots.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+        ExchangeKind,
+    };
+
+    #[test]
+    fn bulk_topology_setup() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            let other = connection.create_channel().await?;
+            let names = (0..500).map(|i| format!("queue-{i}")).collect::<Vec<_>>();
+
+            let queues = names
+                .iter()
+                .map(|name| {
+                    channel.queue_declare(
+                        name,
+                        QueueDeclareOptions::default(),
+                        FieldTable::default(),
+                    )
+                })
+                .collect::<Pipeline<'_, _>>()
+                .await?;
+            assert_eq!(
+                queues
+                    .iter()
+                    .map(|queue| queue.name().as_str())
+                    .collect::<Vec<_>>(),
+                names
+            );
+
+            // Independent requests on several channels
+            Pipeline::new()
+                .with(channel.exchange_declare(
+                    "events",
+                    ExchangeKind::Fanout,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                ))
+                .with(other.queue_bind(
+                    "queue-0",
+                    "amq.fanout",
+                    "",
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                ))
+                .with(other.queue_bind(
+                    "queue-1",
+                    "amq.fanout",
+                    "",
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                ))
+                .await?;
+            assert!(broker.exchange_exists("events"));
+            channel
+                .basic_publish(
+                    "amq.fanout",
+                    "",
+                    BasicPublishOptions::default(),
+                    b"hello",
+                    BasicProperties::default(),
+                )
+                .await?
+                .await?;
+            assert_eq!(broker.message_count("queue-0"), Some(1));
+            assert_eq!(broker.message_count("queue-1"), Some(1));
+            assert_eq!(broker.message_count("queue-2"), Some(0));
+
+            assert!(Pipeline::<()>::new().await?.is_empty());
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn first_error_wins() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            let declare = |queue, passive| {
+                channel.queue_declare(
+                    queue,
+                    QueueDeclareOptions {
+                        passive,
+                        ..QueueDeclareOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+            };
+
+            let error = Pipeline::new()
+                .with(declare("before", false))
+                .with(declare("missing", true))
+                .with(declare("after", false))
+                .await
+                .unwrap_err();
+            assert!(error.is_amqp_soft_error());
+            assert!(error.to_string().contains("NOT_FOUND"), "{error}");
+            // The requests sent before the failed one went through, the ones after it didn't
+            assert!(broker.queue_exists("before"));
+            assert!(!broker.queue_exists("after"));
+            assert!(!channel.status().connected());
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+//! Serialize [`FieldTable`] and [`AMQPValue`] as plain data instead of their tagged
+//! representation, to log headers as JSON or to read arguments from configuration files.
+//!
+//! Values are mapped as follows:
+//! - booleans, integers, timestamps, floats and tables to their natural counterpart
+//! - decimals to floats
+//! - strings to strings, long strings which aren't valid UTF-8 to bytes
+//! - arrays and byte arrays to sequences
+//! - void to unit (`null` in JSON)
+//!
+//! When deserializing, integers become long long ints, unsigned ones which don't fit become
+//! timestamps, floats become doubles, strings become long strings, sequences become arrays and
+//! maps become tables.
+//!
+//! ```rust
+//! use lapin::{plain_fields::Plain, types::FieldTable, FieldTableExt};
+//!
+//! let arguments: Plain<FieldTable> =
+//!     serde_json::from_str(r#"{"x-message-ttl": 60000, "x-queue-type": "quorum"}"#).unwrap();
+//! assert_eq!(arguments.0.get_i64("x-message-ttl"), Some(60000));
+//! assert_eq!(
+//!     serde_json::to_string(&Plain(&arguments.0)).unwrap(),
+//!     r#"{"x-message-ttl":60000,"x-queue-type":"quorum"}"#
+//! );
+//! ```
+//!
+//! [`FieldTable`]: ../types/struct.FieldTable.html
+//! [`AMQPValue`]: ../types/enum.AMQPValue.html
+
+use crate::{
+    types::{AMQPValue, FieldArray, FieldTable, ShortString},
+    Decimal,
+};
+use serde::{
+    de::{self, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq, Serializer},
+    Deserialize, Serialize,
+};
+use std::{collections::BTreeMap, fmt};
+
+/// Wrap a [`FieldTable`] or an [`AMQPValue`], or a reference to one, to serialize it as plain
+/// data
+///
+/// [`FieldTable`]: ../types/struct.FieldTable.html
+/// [`AMQPValue`]: ../types/enum.AMQPValue.html
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plain<T>(pub T);
+
+/// Serialize a [`FieldTable`] as plain data, for `#[serde(serialize_with = "...")]`
+///
+/// [`FieldTable`]: ../types/struct.FieldTable.html
+pub fn serialize<S: Serializer>(table: &FieldTable, serializer: S) -> Result<S::Ok, S::Error> {
+    Plain(table).serialize(serializer)
+}
+
+/// Deserialize a [`FieldTable`] from plain data, for `#[serde(deserialize_with = "...")]`
+///
+/// [`FieldTable`]: ../types/struct.FieldTable.html
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FieldTable, D::Error> {
+    Plain::<FieldTable>::deserialize(deserializer).map(|table| table.0)
+}
+
+impl Serialize for Plain<&FieldTable> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.inner().len()))?;
+        for (key, value) in self.0 {
+            map.serialize_entry(key.as_str(), &Plain(value))?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Plain<FieldTable> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Plain(&self.0).serialize(serializer)
+    }
+}
+
+impl Serialize for Plain<&AMQPValue> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            AMQPValue::Boolean(value) => serializer.serialize_bool(*value),
+            AMQPValue::ShortShortInt(value) => serializer.serialize_i8(*value),
+            AMQPValue::ShortShortUInt(value) => serializer.serialize_u8(*value),
+            AMQPValue::ShortInt(value) => serializer.serialize_i16(*value),
+            AMQPValue::ShortUInt(value) => serializer.serialize_u16(*value),
+            AMQPValue::LongInt(value) => serializer.serialize_i32(*value),
+            AMQPValue::LongUInt(value) => serializer.serialize_u32(*value),
+            AMQPValue::LongLongInt(value) => serializer.serialize_i64(*value),
+            AMQPValue::Float(value) => serializer.serialize_f32(*value),
+            AMQPValue::Double(value) => serializer.serialize_f64(*value),
+            AMQPValue::DecimalValue(decimal) => {
+                serializer.serialize_f64(Decimal::from(*decimal).to_f64())
+            }
+            AMQPValue::ShortString(value) => serializer.serialize_str(value.as_str()),
+            AMQPValue::LongString(value) => match std::str::from_utf8(value.as_bytes()) {
+                Ok(value) => serializer.serialize_str(value),
+                Err(_) => serializer.serialize_bytes(value.as_bytes()),
+            },
+            AMQPValue::FieldArray(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.as_slice().len()))?;
+                for value in values.as_slice() {
+                    seq.serialize_element(&Plain(value))?;
+                }
+                seq.end()
+            }
+            AMQPValue::Timestamp(value) => serializer.serialize_u64(*value),
+            AMQPValue::FieldTable(table) => Plain(table).serialize(serializer),
+            AMQPValue::ByteArray(bytes) => serializer.serialize_bytes(bytes.as_slice()),
+            AMQPValue::Void => serializer.serialize_unit(),
+        }
+    }
+}
+
+impl Serialize for Plain<AMQPValue> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Plain(&self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Plain<FieldTable> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Plain::<AMQPValue>::deserialize(deserializer)?.0 {
+            AMQPValue::FieldTable(table) => Ok(Plain(table)),
+            _ => Err(de::Error::custom("expected a map")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Plain<AMQPValue> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor).map(Plain)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = AMQPValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an AMQP value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Boolean(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::LongLongInt(value))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<AMQPValue, E> {
+        Ok(i64::try_from(value)
+            .map(AMQPValue::LongLongInt)
+            .unwrap_or(AMQPValue::Timestamp(value)))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Double(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::LongString(value.into()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::ByteArray(value.into()))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Void)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<AMQPValue, E> {
+        Ok(AMQPValue::Void)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<AMQPValue, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<AMQPValue, A::Error> {
+        let mut values = Vec::new();
+        while let Some(Plain(value)) = seq.next_element::<Plain<AMQPValue>>()? {
+            values.push(value);
+        }
+        Ok(AMQPValue::FieldArray(FieldArray::from(values)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<AMQPValue, A::Error> {
+        let mut table = BTreeMap::new();
+        while let Some((key, Plain(value))) = map.next_entry::<String, Plain<AMQPValue>>()? {
+            table.insert(ShortString::from(key), value);
+        }
+        Ok(AMQPValue::FieldTable(table.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DecimalValue, LongString};
+
+    #[test]
+    fn json_mapping() {
+        let mut nested = FieldTable::default();
+        nested.insert("ok".into(), AMQPValue::Boolean(true));
+        let mut table = FieldTable::default();
+        table
+            .insert("byte".into(), AMQPValue::ShortShortUInt(7))
+            .insert(
+                "decimal".into(),
+                AMQPValue::DecimalValue(DecimalValue {
+                    scale: 2,
+                    value: 150,
+                }),
+            )
+            .insert("short".into(), AMQPValue::ShortString("s".into()))
+            .insert(
+                "binary".into(),
+                AMQPValue::LongString(LongString::from(vec![0xff])),
+            )
+            .insert(
+                "list".into(),
+                AMQPValue::FieldArray(vec![AMQPValue::Void, AMQPValue::Double(0.5)].into()),
+            )
+            .insert("nested".into(), AMQPValue::FieldTable(nested.clone()))
+            .insert("late".into(), AMQPValue::Timestamp(u64::MAX));
+        let json = serde_json::to_value(Plain(&table)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "binary": [255],
+                "byte": 7,
+                "decimal": 1.5,
+                "late": u64::MAX,
+                "list": [null, 0.5],
+                "nested": {"ok": true},
+                "short": "s",
+            })
+        );
+
+        let Plain(parsed) = serde_json::from_value::<Plain<FieldTable>>(json).unwrap();
+        let mut expected = FieldTable::default();
+        expected
+            .insert(
+                "binary".into(),
+                AMQPValue::FieldArray(vec![AMQPValue::LongLongInt(255)].into()),
+            )
+            .insert("byte".into(), AMQPValue::LongLongInt(7))
+            .insert("decimal".into(), AMQPValue::Double(1.5))
+            .insert("late".into(), AMQPValue::Timestamp(u64::MAX))
+            .insert(
+                "list".into(),
+                AMQPValue::FieldArray(vec![AMQPValue::Void, AMQPValue::Double(0.5)].into()),
+            )
+            .insert("nested".into(), AMQPValue::FieldTable(nested))
+            .insert("short".into(), AMQPValue::LongString("s".into()));
+        assert_eq!(parsed, expected);
+        assert!(serde_json::from_str::<Plain<FieldTable>>("[1]").is_err());
+    }
+
+    #[test]
+    fn with_attribute() {
+        #[derive(Deserialize, Serialize)]
+        struct Queue {
+            name: String,
+            #[serde(with = "crate::plain_fields")]
+            arguments: FieldTable,
+        }
+
+        let queue: Queue =
+            serde_json::from_str(r#"{"name": "jobs", "arguments": {"x-max-length": 10}}"#).unwrap();
+        assert_eq!(
+            queue.arguments.inner().get("x-max-length"),
+            Some(&AMQPValue::LongLongInt(10))
+        );
+        assert_eq!(
+            serde_json::to_string(&queue).unwrap(),
+            r#"{"name":"jobs","arguments":{"x-max-length":10}}"#
+        );
+    }
+}
@@ -108,6 +108,23 @@ impl<T> PromiseResolver<T> {
         self.complete(Err(error))
     }
 
+    /* Like resolve, but hands the data back if the promise has vanished */
+    pub(crate) fn try_resolve(&self, data: T) -> std::result::Result<(), T> {
+        trace!(
+            promise = %self.marker(),
+            "Resolving promise.",
+        );
+        match self.send.send(Ok(data)) {
+            Err(flume::SendError(Ok(data))) => Err(data),
+            _ => Ok(()),
+        }
+    }
+
+    /* Whether the promise was dropped, nobody waiting for its result anymore */
+    pub(crate) fn is_abandoned(&self) -> bool {
+        self.send.is_disconnected()
+    }
+
     pub(crate) fn complete(&self, res: Result<T>) {
         trace!(
             promise = %self.marker(),
@@ -0,0 +1,199 @@
+use crate::{
+    options::BasicPublishOptions,
+    timestamp,
+    types::{ShortShortUInt, ShortString},
+    BasicProperties,
+};
+use uuid::Uuid;
+
+/// Defaults applied by a [`Channel`] to every message it publishes.
+///
+/// Properties are only filled in when they were not explicitly set on the published message.
+/// The `mandatory` flag is enabled if either the defaults or the publish options request it.
+///
+/// [`Channel`]: ./struct.Channel.html
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PublishDefaults {
+    pub app_id: Option<ShortString>,
+    pub content_type: Option<ShortString>,
+    pub delivery_mode: Option<ShortShortUInt>,
+    pub mandatory: bool,
+    /// Set the message_id property to a random UUID
+    pub message_id: bool,
+    /// Set the timestamp property to the current time
+    pub timestamp: bool,
+}
+
+impl PublishDefaults {
+    #[must_use]
+    pub fn with_app_id(mut self, app_id: ShortString) -> Self {
+        self.app_id = Some(app_id);
+        self
+    }
+
+    #[must_use]
+    pub fn with_content_type(mut self, content_type: ShortString) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    #[must_use]
+    pub fn with_delivery_mode(mut self, delivery_mode: ShortShortUInt) -> Self {
+        self.delivery_mode = Some(delivery_mode);
+        self
+    }
+
+    #[must_use]
+    pub fn with_mandatory(mut self, mandatory: bool) -> Self {
+        self.mandatory = mandatory;
+        self
+    }
+
+    #[must_use]
+    pub fn with_message_id(mut self, message_id: bool) -> Self {
+        self.message_id = message_id;
+        self
+    }
+
+    #[must_use]
+    pub fn with_timestamp(mut self, timestamp: bool) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub(crate) fn apply(
+        &self,
+        options: BasicPublishOptions,
+        properties: BasicProperties,
+    ) -> (BasicPublishOptions, BasicProperties) {
+        let (options, properties) = self.apply_shared(options, properties);
+        (options, self.stamp(properties))
+    }
+
+    /* The defaults which are the same for every message */
+    pub(crate) fn apply_shared(
+        &self,
+        mut options: BasicPublishOptions,
+        mut properties: BasicProperties,
+    ) -> (BasicPublishOptions, BasicProperties) {
+        options.mandatory |= self.mandatory;
+        if let (None, Some(app_id)) = (properties.app_id(), self.app_id.as_ref()) {
+            properties = properties.with_app_id(app_id.clone());
+        }
+        if let (None, Some(content_type)) = (properties.content_type(), self.content_type.as_ref())
+        {
+            properties = properties.with_content_type(content_type.clone());
+        }
+        if let (None, Some(delivery_mode)) = (properties.delivery_mode(), self.delivery_mode) {
+            properties = properties.with_delivery_mode(delivery_mode);
+        }
+        (options, properties)
+    }
+
+    /* Whether the message gets properties of its own, which are different for every message */
+    pub(crate) fn stamps(&self, properties: &BasicProperties) -> bool {
+        (self.message_id && properties.message_id().is_none())
+            || (self.timestamp && properties.timestamp().is_none())
+    }
+
+    /* The defaults which are different for every message */
+    pub(crate) fn stamp(&self, mut properties: BasicProperties) -> BasicProperties {
+        if self.message_id && properties.message_id().is_none() {
+            properties = properties.with_message_id(Uuid::new_v4().to_string().into());
+        }
+        if self.timestamp && properties.timestamp().is_none() {
+            properties = properties.with_timestamp(timestamp::now());
+        }
+        properties
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_properties_win() {
+        let defaults = PublishDefaults::default()
+            .with_app_id("default-app".into())
+            .with_content_type("application/json".into())
+            .with_delivery_mode(2)
+            .with_mandatory(true)
+            .with_message_id(true)
+            .with_timestamp(true);
+        let (options, properties) = defaults.apply(
+            BasicPublishOptions::default(),
+            BasicProperties::default()
+                .with_app_id("my-app".into())
+                .with_timestamp(42),
+        );
+        assert!(options.mandatory);
+        assert_eq!(properties.app_id(), &Some("my-app".into()));
+        assert_eq!(properties.content_type(), &Some("application/json".into()));
+        assert_eq!(properties.delivery_mode(), &Some(2));
+        assert_eq!(properties.timestamp(), &Some(42));
+        assert!(properties.message_id().is_some());
+    }
+
+    #[test]
+    fn stamped_message_ids() {
+        use crate::{
+            options::{BasicGetOptions, BasicPublishOptions, QueueDeclareOptions},
+            testing::MockBroker,
+            types::FieldTable,
+            ConnectionProperties,
+        };
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel.set_publish_defaults(PublishDefaults::default().with_message_id(true));
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for message_id in [None, None, Some("explicit")] {
+                let mut properties = BasicProperties::default();
+                if let Some(message_id) = message_id {
+                    properties = properties.with_message_id(message_id.into());
+                }
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        b"job",
+                        properties,
+                    )
+                    .await?;
+            }
+
+            let mut message_ids = Vec::new();
+            while let Some(message) = channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+            {
+                message_ids.push(message.properties.message_id().clone().unwrap());
+            }
+            assert_eq!(message_ids.len(), 3);
+            assert_ne!(message_ids[0], message_ids[1]);
+            assert!(Uuid::parse_str(message_ids[0].as_str()).is_ok());
+            assert!(Uuid::parse_str(message_ids[1].as_str()).is_ok());
+            assert_eq!(message_ids[2].as_str(), "explicit");
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn empty_defaults() {
+        let (options, properties) = PublishDefaults::default()
+            .apply(BasicPublishOptions::default(), BasicProperties::default());
+        assert_eq!(options, BasicPublishOptions::default());
+        assert_eq!(properties, BasicProperties::default());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{
+    message::BasicReturnMessage,
+    publisher_confirm::{Confirmation, PublisherConfirm},
+    Promise, Result,
+};
+use futures_core::stream::Stream;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// How far a message published with [`Channel::basic_publish_with_events`] went.
+///
+/// [`Channel::basic_publish_with_events`]: ./struct.Channel.html#method.basic_publish_with_events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishStage {
+    /// The frames of the message are queued, waiting to be written to the socket
+    Enqueued,
+    /// The frames of the message were written to the socket
+    Written,
+    /// The broker returned the message as it couldn't route it, before confirming it
+    Returned,
+    /// The broker confirmed the message
+    Acked,
+    /// The broker refused the message
+    Nacked,
+}
+
+/// A step in the life of a published message.
+#[derive(Debug, PartialEq)]
+pub struct PublishEvent {
+    pub stage: PublishStage,
+    /// The time elapsed since the publish started
+    pub elapsed: Duration,
+    /// The message, when it got returned
+    pub returned_message: Option<Box<BasicReturnMessage>>,
+}
+
+/// The stream of the [`PublishEvent`]s of a message published with
+/// [`Channel::basic_publish_with_events`].
+///
+/// It yields [`Enqueued`] right away, then [`Written`], then, if the channel is in confirm mode,
+/// [`Returned`] if the message couldn't be routed, and finally either [`Acked`] or [`Nacked`].
+/// An error stops it.
+///
+/// Dropping it before the message got confirmed keeps the confirmation around for
+/// [`Channel::wait_for_confirms`], like dropping a [`PublisherConfirm`] does.
+///
+/// [`PublishEvent`]: ./struct.PublishEvent.html
+/// [`Channel::basic_publish_with_events`]: ./struct.Channel.html#method.basic_publish_with_events
+/// [`Channel::wait_for_confirms`]: ./struct.Channel.html#method.wait_for_confirms
+/// [`PublisherConfirm`]: ./publisher_confirm/struct.PublisherConfirm.html
+/// [`Enqueued`]: ./enum.PublishStage.html#variant.Enqueued
+/// [`Written`]: ./enum.PublishStage.html#variant.Written
+/// [`Returned`]: ./enum.PublishStage.html#variant.Returned
+/// [`Acked`]: ./enum.PublishStage.html#variant.Acked
+/// [`Nacked`]: ./enum.PublishStage.html#variant.Nacked
+pub struct PublishEvents {
+    start: Instant,
+    enqueued: Option<Duration>,
+    written: Option<Promise<()>>,
+    confirm: Option<PublisherConfirm>,
+    pending: Option<PublishEvent>,
+    stage: Option<PublishStage>,
+}
+
+impl PublishEvents {
+    pub(crate) fn new(start: Instant, written: Promise<()>, confirm: PublisherConfirm) -> Self {
+        Self {
+            enqueued: Some(start.elapsed()),
+            start,
+            written: Some(written),
+            confirm: Some(confirm),
+            pending: None,
+            stage: None,
+        }
+    }
+
+    /// The last stage yielded by the stream, if any
+    pub fn stage(&self) -> Option<PublishStage> {
+        self.stage
+    }
+
+    /// Whether the message got written to the socket, but not confirmed yet
+    pub fn is_unconfirmed(&self) -> bool {
+        matches!(
+            self.stage,
+            Some(PublishStage::Written | PublishStage::Returned)
+        ) && self.confirm.is_some()
+    }
+
+    fn event(&mut self, stage: PublishStage) -> PublishEvent {
+        self.stage = Some(stage);
+        PublishEvent {
+            stage,
+            elapsed: self.start.elapsed(),
+            returned_message: None,
+        }
+    }
+}
+
+impl Stream for PublishEvents {
+    type Item = Result<PublishEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+        if let Some(elapsed) = this.enqueued.take() {
+            this.stage = Some(PublishStage::Enqueued);
+            return Poll::Ready(Some(Ok(PublishEvent {
+                stage: PublishStage::Enqueued,
+                elapsed,
+                returned_message: None,
+            })));
+        }
+        if let Some(written) = this.written.as_mut() {
+            let res = match Pin::new(written).poll(cx) {
+                Poll::Ready(res) => res,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.written = None;
+            return Poll::Ready(Some(match res {
+                Ok(()) => Ok(this.event(PublishStage::Written)),
+                Err(error) => {
+                    this.confirm = None;
+                    Err(error)
+                }
+            }));
+        }
+        if let Some(event) = this.pending.take() {
+            this.stage = Some(event.stage);
+            return Poll::Ready(Some(Ok(event)));
+        }
+        let Some(confirm) = this.confirm.as_mut() else {
+            return Poll::Ready(None);
+        };
+        let res = match Pin::new(confirm).poll(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.confirm = None;
+        let (stage, returned_message) = match res {
+            Ok(Confirmation::Ack(message)) => (PublishStage::Acked, message),
+            Ok(Confirmation::Nack(message)) => (PublishStage::Nacked, message),
+            Ok(Confirmation::NotRequested) => return Poll::Ready(None),
+            Err(error) => return Poll::Ready(Some(Err(error))),
+        };
+        Poll::Ready(Some(Ok(match returned_message {
+            Some(message) => {
+                let returned = PublishEvent {
+                    returned_message: Some(message),
+                    ..this.event(PublishStage::Returned)
+                };
+                this.pending = Some(this.event(stage));
+                this.stage = Some(PublishStage::Returned);
+                returned
+            }
+            None => this.event(stage),
+        })))
+    }
+}
+
+impl fmt::Debug for PublishEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PublishEvents")
+            .field("stage", &self.stage)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn lifecycle() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            let stages = |events: Vec<Result<PublishEvent>>| {
+                events
+                    .into_iter()
+                    .map(|event| event.map(|event| event.stage))
+                    .collect::<Result<Vec<_>>>()
+            };
+            let events = channel
+                .basic_publish_with_events(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default(),
+                )
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+            assert_eq!(
+                stages(events)?,
+                [PublishStage::Enqueued, PublishStage::Written]
+            );
+
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            let mut events = channel
+                .basic_publish_with_events(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default(),
+                )
+                .await?;
+            assert_eq!(events.next().await.unwrap()?.stage, PublishStage::Enqueued);
+            assert_eq!(events.next().await.unwrap()?.stage, PublishStage::Written);
+            assert!(events.is_unconfirmed());
+            let acked = events.next().await.unwrap()?;
+            assert_eq!(acked.stage, PublishStage::Acked);
+            assert!(!events.is_unconfirmed());
+            assert!(events.next().await.is_none());
+
+            let events = channel
+                .basic_publish_with_events(
+                    "",
+                    "unroutable",
+                    BasicPublishOptions {
+                        mandatory: true,
+                        ..BasicPublishOptions::default()
+                    },
+                    b"lost",
+                    BasicProperties::default(),
+                )
+                .await?
+                .collect::<Vec<_>>()
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(
+                events.iter().map(|event| event.stage).collect::<Vec<_>>(),
+                [
+                    PublishStage::Enqueued,
+                    PublishStage::Written,
+                    PublishStage::Returned,
+                    PublishStage::Acked
+                ]
+            );
+            assert_eq!(
+                &events[2].returned_message.as_ref().unwrap().delivery.data[..],
+                b"lost"
+            );
+            assert!(events
+                .windows(2)
+                .all(|events| events[0].elapsed <= events[1].elapsed));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
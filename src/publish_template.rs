@@ -0,0 +1,162 @@
+use crate::{
+    options::BasicPublishOptions,
+    protocol::{basic, AMQPClass},
+    publish_defaults::PublishDefaults,
+    types::{ChannelId, Identifier, PayloadSize},
+    BasicProperties, ErrorKind, Result,
+};
+use amq_protocol::frame::{gen_frame, AMQPContentHeader, AMQPFrame, WriteContext};
+use std::{borrow::Cow, sync::Arc};
+
+/* A frame starts with its type (1 byte), channel id (2 bytes) and size (4 bytes) */
+const CHANNEL_ID_OFFSET: usize = 1;
+/* The content header payload starts with the class id (2 bytes) and weight (2 bytes) */
+const BODY_SIZE_OFFSET: usize = 7 + 4;
+
+/// The pre-serialized basic.publish method and content header frames for messages sharing the
+/// same exchange, routing key, options and properties.
+///
+/// Created with [`Channel::publish_template`] and used with
+/// [`Channel::basic_publish_with_template`], so that only the body of each message needs to be
+/// serialized. When the publish defaults of the channel give each message its own message_id or
+/// timestamp, the content header frame is serialized again for each message too.
+///
+/// [`Channel::publish_template`]: ./struct.Channel.html#method.publish_template
+/// [`Channel::basic_publish_with_template`]: ./struct.Channel.html#method.basic_publish_with_template
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublishTemplate {
+    frames: Vec<u8>,
+    header_offset: usize,
+    stamps: Option<Stamps>,
+}
+
+/* What's needed to serialize the content header frame with the properties of each message */
+#[derive(Clone, Debug, PartialEq)]
+struct Stamps {
+    defaults: PublishDefaults,
+    class_id: Identifier,
+    properties: BasicProperties,
+}
+
+impl PublishTemplate {
+    pub(crate) fn new(
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        properties: BasicProperties,
+        defaults: &PublishDefaults,
+    ) -> Result<Self> {
+        let (options, properties) = defaults.apply_shared(options, properties);
+        let method = AMQPClass::Basic(basic::AMQPMethod::Publish(basic::Publish {
+            exchange: exchange.into(),
+            routing_key: routing_key.into(),
+            mandatory: options.mandatory,
+            immediate: options.immediate,
+        }));
+        let class_id = method.get_amqp_class_id();
+        let method = gen_frame(&AMQPFrame::Method(0, method))(WriteContext::from(Vec::new()))
+            .map_err(|e| ErrorKind::SerialisationError(Arc::new(e)))?
+            .write;
+        let header_offset = method.len();
+        let stamps = defaults.stamps(&properties).then(|| Stamps {
+            defaults: defaults.clone(),
+            class_id,
+            properties: properties.clone(),
+        });
+        let frames = header_frame(method, class_id, properties)?;
+        Ok(Self {
+            frames,
+            header_offset,
+            stamps,
+        })
+    }
+
+    /// The frames of the next message, with its own message_id and timestamp if needed
+    pub(crate) fn frames(&self) -> Result<Cow<'_, [u8]>> {
+        let Some(stamps) = self.stamps.as_ref() else {
+            return Ok(Cow::Borrowed(&self.frames));
+        };
+        let method = self.frames[..self.header_offset].to_vec();
+        let properties = stamps.defaults.stamp(stamps.properties.clone());
+        header_frame(method, stamps.class_id, properties).map(Cow::Owned)
+    }
+
+    /// Set the channel id and body size in a copy of the template's frames
+    pub(crate) fn patch(&self, frames: &mut [u8], channel_id: ChannelId, body_size: usize) {
+        let channel_id = channel_id.to_be_bytes();
+        let header = self.header_offset;
+        frames[CHANNEL_ID_OFFSET..CHANNEL_ID_OFFSET + 2].copy_from_slice(&channel_id);
+        frames[header + CHANNEL_ID_OFFSET..header + CHANNEL_ID_OFFSET + 2]
+            .copy_from_slice(&channel_id);
+        frames[header + BODY_SIZE_OFFSET..header + BODY_SIZE_OFFSET + 8]
+            .copy_from_slice(&(body_size as PayloadSize).to_be_bytes());
+    }
+}
+
+/* Serialize the content header frame after the method one */
+fn header_frame(
+    method: Vec<u8>,
+    class_id: Identifier,
+    properties: BasicProperties,
+) -> Result<Vec<u8>> {
+    let header = AMQPFrame::Header(
+        0,
+        class_id,
+        Box::new(AMQPContentHeader {
+            class_id,
+            body_size: 0,
+            properties,
+        }),
+    );
+    let frames = gen_frame(&header)(WriteContext::from(method))
+        .map_err(|e| ErrorKind::SerialisationError(Arc::new(e)))?
+        .write;
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_regular_frames() {
+        let properties = BasicProperties::default().with_content_type("text/plain".into());
+        let template = PublishTemplate::new(
+            "logs",
+            "app.info",
+            BasicPublishOptions::default(),
+            properties.clone(),
+            &PublishDefaults::default(),
+        )
+        .unwrap();
+        let mut frames = template.frames().unwrap().to_vec();
+        template.patch(&mut frames, 42, 1337);
+        let expected = [
+            AMQPFrame::Method(
+                42,
+                AMQPClass::Basic(basic::AMQPMethod::Publish(basic::Publish {
+                    exchange: "logs".into(),
+                    routing_key: "app.info".into(),
+                    mandatory: false,
+                    immediate: false,
+                })),
+            ),
+            AMQPFrame::Header(
+                42,
+                60,
+                Box::new(AMQPContentHeader {
+                    class_id: 60,
+                    body_size: 1337,
+                    properties,
+                }),
+            ),
+        ]
+        .iter()
+        .try_fold(WriteContext::from(Vec::new()), |ctx, frame| {
+            gen_frame(frame)(ctx)
+        })
+        .unwrap()
+        .write;
+        assert_eq!(frames, expected);
+    }
+}
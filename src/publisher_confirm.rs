@@ -1,4 +1,5 @@
 use crate::{message::BasicReturnMessage, returned_messages::ReturnedMessages, Promise, Result};
+use futures_core::stream::Stream;
 use std::{
     fmt,
     future::Future,
@@ -7,6 +8,59 @@ use std::{
 };
 use tracing::trace;
 
+/// What happens to the outcome of a [`PublisherConfirm`] dropped without being awaited.
+///
+/// [`PublisherConfirm`]: ./struct.PublisherConfirm.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DroppedConfirmPolicy {
+    /// Keep it for [`Channel::wait_for_confirms`], which returns the messages which got
+    /// returned, but not the nacks
+    ///
+    /// [`Channel::wait_for_confirms`]: ../struct.Channel.html#method.wait_for_confirms
+    #[default]
+    Track,
+    /// Discard it
+    Ignore,
+    /// Log a warning, and keep it like [`Track`] does
+    ///
+    /// [`Track`]: #variant.Track
+    Warn,
+    /// Send the nacks and returned messages to [`Channel::unhandled_confirms`]
+    ///
+    /// [`Channel::unhandled_confirms`]: ../struct.Channel.html#method.unhandled_confirms
+    Forward,
+    /// Panic in debug builds, and behave like [`Track`] in release ones
+    ///
+    /// [`Track`]: #variant.Track
+    Panic,
+}
+
+/// The stream of the nacks and returned messages of the [`PublisherConfirm`]s dropped without
+/// being awaited, when using [`DroppedConfirmPolicy::Forward`].
+///
+/// It never ends, and all the streams of a channel share the same confirmations.
+///
+/// [`PublisherConfirm`]: ./struct.PublisherConfirm.html
+/// [`DroppedConfirmPolicy::Forward`]: ./enum.DroppedConfirmPolicy.html#variant.Forward
+#[derive(Debug)]
+pub struct UnhandledConfirms {
+    returned_messages: ReturnedMessages,
+}
+
+impl UnhandledConfirms {
+    pub(crate) fn new(returned_messages: ReturnedMessages) -> Self {
+        Self { returned_messages }
+    }
+}
+
+impl Stream for UnhandledConfirms {
+    type Item = Confirmation;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.returned_messages.poll_unhandled_confirm(cx).map(Some)
+    }
+}
+
 pub struct PublisherConfirm {
     inner: Option<Promise<Confirmation>>,
     returned_messages: ReturnedMessages,
@@ -81,8 +135,81 @@ impl Future for PublisherConfirm {
 impl Drop for PublisherConfirm {
     fn drop(&mut self) {
         if let Some(promise) = self.inner.take() {
-            trace!("PublisherConfirm dropped without use, registering it for wait_for_confirms");
+            trace!("PublisherConfirm dropped without use, applying the dropped confirm policy");
             self.returned_messages.register_dropped_confirm(promise);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ChannelOptions,
+        ConnectionProperties,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn forward_dropped_confirms() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection
+                .create_channel_with(
+                    ChannelOptions::default()
+                        .with_dropped_confirm_policy(DroppedConfirmPolicy::Forward),
+                )
+                .await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut unhandled = channel.unhandled_confirms();
+
+            drop(
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        b"job",
+                        BasicProperties::default(),
+                    )
+                    .await?,
+            );
+            drop(
+                channel
+                    .basic_publish(
+                        "",
+                        "unroutable",
+                        BasicPublishOptions {
+                            mandatory: true,
+                            ..BasicPublishOptions::default()
+                        },
+                        b"lost",
+                        BasicProperties::default(),
+                    )
+                    .await?,
+            );
+
+            // Only the returned message needs handling
+            let confirmation = unhandled.next().await.unwrap();
+            assert_eq!(
+                &confirmation.take_message().unwrap().delivery.data[..],
+                b"lost"
+            );
+            assert!(channel.wait_for_confirms().await?.is_empty());
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+//! Publish on one channel from several threads or tasks.
+//!
+//! A [`PublisherHandle`], obtained with [`Channel::publisher_handle`], is `Send + Sync + Clone`
+//! and only lets its holder publish and wait for confirms. All the handles of a channel share
+//! its confirm window: each publish gets its delivery tag when its frames are queued, in the
+//! order in which the broker receives them, so the confirms are matched to the right messages
+//! however the publishes of the different threads interleave. Worker threads thus don't need a
+//! channel each just to publish safely.
+//!
+//! ```rust,no_run
+//! use lapin::{options::*, BasicProperties, Connection, ConnectionProperties};
+//!
+//! async_global_executor::block_on(async {
+//!     let connection =
+//!         Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())
+//!             .await?;
+//!     let channel = connection.create_channel().await?;
+//!     channel
+//!         .confirm_select(ConfirmSelectOptions::default())
+//!         .await?;
+//!     let workers = (0..4)
+//!         .map(|worker| {
+//!             let publisher = channel.publisher_handle();
+//!             std::thread::spawn(move || {
+//!                 async_global_executor::block_on(async {
+//!                     publisher
+//!                         .basic_publish(
+//!                             "",
+//!                             "jobs",
+//!                             BasicPublishOptions::default(),
+//!                             format!("job from worker {}", worker).as_bytes(),
+//!                             BasicProperties::default(),
+//!                         )
+//!                         .await?
+//!                         .await
+//!                 })
+//!             })
+//!         })
+//!         .collect::<Vec<_>>();
+//!     for worker in workers {
+//!         assert!(worker.join().unwrap()?.is_ack());
+//!     }
+//!     Ok::<(), lapin::Error>(())
+//! })
+//! .unwrap();
+//! ```
+//!
+//! [`PublisherHandle`]: ./struct.PublisherHandle.html
+//! [`Channel::publisher_handle`]: ../struct.Channel.html#method.publisher_handle
+
+use crate::{
+    message::BasicReturnMessage, options::BasicPublishOptions, publisher_confirm::PublisherConfirm,
+    types::ChannelId, BasicProperties, Channel, Envelope, PublishTemplate, Result,
+};
+use std::fmt;
+
+/// A handle publishing on a channel, which can be shared between threads.
+///
+/// It keeps the channel open as long as it lives, like a clone of the [`Channel`] would, but
+/// doesn't give access to the rest of its API.
+///
+/// [`Channel`]: ../struct.Channel.html
+#[derive(Clone)]
+pub struct PublisherHandle {
+    channel: Channel,
+}
+
+impl PublisherHandle {
+    pub(crate) fn new(channel: Channel) -> Self {
+        Self { channel }
+    }
+
+    pub fn channel_id(&self) -> ChannelId {
+        self.channel.id()
+    }
+
+    /// Publish a message, like [`Channel::basic_publish`] would.
+    ///
+    /// [`Channel::basic_publish`]: ../struct.Channel.html#method.basic_publish
+    pub async fn basic_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        self.channel
+            .basic_publish(exchange, routing_key, options, payload, properties)
+            .await
+    }
+
+    /// Publish a message described by an [`Envelope`].
+    ///
+    /// [`Envelope`]: ../struct.Envelope.html
+    pub async fn publish(&self, envelope: &Envelope) -> Result<PublisherConfirm> {
+        self.channel.publish(envelope).await
+    }
+
+    /// Publish a message using frames pre-serialized with [`Channel::publish_template`].
+    ///
+    /// [`Channel::publish_template`]: ../struct.Channel.html#method.publish_template
+    pub async fn basic_publish_with_template(
+        &self,
+        template: &PublishTemplate,
+        payload: &[u8],
+    ) -> Result<PublisherConfirm> {
+        self.channel
+            .basic_publish_with_template(template, payload)
+            .await
+    }
+
+    /// Wait for the confirms of all the messages published on the channel so far, by any of
+    /// its handles.
+    pub async fn wait_for_confirms(&self) -> Result<Vec<BasicReturnMessage>> {
+        self.channel.wait_for_confirms().await
+    }
+}
+
+impl fmt::Debug for PublisherHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PublisherHandle")
+            .field("channel", &self.channel.id())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+        RateLimit,
+    };
+    use futures_lite::future::poll_once;
+
+    async fn publish(publisher: &PublisherHandle, payload: &[u8]) -> Result<PublisherConfirm> {
+        publisher
+            .basic_publish(
+                "",
+                "jobs",
+                BasicPublishOptions::default(),
+                payload,
+                BasicProperties::default(),
+            )
+            .await
+    }
+
+    #[test]
+    fn publish_from_threads() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let workers = (0..4)
+                .map(|_| {
+                    let publisher = channel.publisher_handle();
+                    std::thread::spawn(move || {
+                        async_global_executor::block_on(async {
+                            let mut confirms = Vec::new();
+                            for _ in 0..25 {
+                                confirms.push(
+                                    publisher
+                                        .basic_publish(
+                                            "",
+                                            "jobs",
+                                            BasicPublishOptions::default(),
+                                            b"job",
+                                            BasicProperties::default(),
+                                        )
+                                        .await?,
+                                );
+                            }
+                            for confirm in confirms {
+                                assert!(confirm.await?.is_ack());
+                            }
+                            Ok::<(), crate::Error>(())
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            for worker in workers {
+                worker.join().unwrap()?;
+            }
+            channel.publisher_handle().wait_for_confirms().await?;
+            assert_eq!(broker.message_count("jobs"), Some(100));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn cancelled_publishes_are_never_interleaved() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker
+                .connect(
+                    ConnectionProperties::default()
+                        .with_publish_buffer_size(1)
+                        .with_io_buffer_frames(2),
+                )
+                .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let publisher = channel.publisher_handle();
+            // Larger than the io buffer, so that it only gets partially written while paused
+            let large = vec![1; 1_000_000];
+
+            broker.pause();
+            // Dropped once queued: all of its frames still get sent
+            let mut queued = Box::pin(publish(&publisher, &large));
+            assert!(poll_once(&mut queued).await.is_none());
+            // Dropped while waiting for room in the publish buffer: none of them get sent
+            let mut waiting = Box::pin(publish(&publisher, b"waiting"));
+            assert!(poll_once(&mut waiting).await.is_none());
+            drop(waiting);
+            drop(queued);
+            // Let the io loop start writing it before queuing an RPC on the same channel
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            let mut rpc = Box::pin(channel.queue_declare(
+                "jobs",
+                QueueDeclareOptions {
+                    passive: true,
+                    ..QueueDeclareOptions::default()
+                },
+                FieldTable::default(),
+            ));
+            assert!(poll_once(&mut rpc).await.is_none());
+            let concurrent = (0..4u8)
+                .map(|i| {
+                    let publisher = publisher.clone();
+                    async_global_executor::spawn(async move {
+                        publish(&publisher, &vec![i; 200_000]).await?.await
+                    })
+                })
+                .collect::<Vec<_>>();
+            broker.resume();
+            for publish in concurrent {
+                assert!(publish.await?.is_ack());
+            }
+            rpc.await?;
+
+            // Dropped while throttled: none of its frames get sent
+            channel.set_rate_limit(Some(RateLimit::default().with_messages_per_second(1)));
+            publish(&publisher, b"first").await?.await?;
+            let mut throttled = Box::pin(publish(&publisher, b"throttled"));
+            assert!(poll_once(&mut throttled).await.is_none());
+            drop(throttled);
+            channel.set_rate_limit(None);
+
+            // Dropped while waiting for its confirm
+            drop(publish(&publisher, b"unconfirmed").await?);
+            publisher.wait_for_confirms().await?;
+
+            // The broker would have closed the connection on an interleaved frame
+            assert!(connection.status().connected());
+            let messages = broker.messages("jobs");
+            assert_eq!(messages.len(), 7);
+            assert!(messages.contains(&large));
+            assert!(!messages.contains(&b"waiting".to_vec()));
+            assert!(!messages.contains(&b"throttled".to_vec()));
+            assert!(messages.contains(&b"unconfirmed".to_vec()));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
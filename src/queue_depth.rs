@@ -0,0 +1,187 @@
+use crate::{
+    queue::Queue,
+    types::{ConsumerCount, MessageCount, ShortString},
+    Channel, Result,
+};
+use futures_core::stream::Stream;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+type NextDepth = Pin<Box<dyn Future<Output = Result<QueueDepth>> + Send>>;
+
+/// The number of messages ready in a queue and of consumers attached to it, as reported by a
+/// passive queue.declare.
+///
+/// Obtained by calling [`Channel::queue_depth`].
+///
+/// [`Channel::queue_depth`]: ./struct.Channel.html#method.queue_depth
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueDepth {
+    message_count: MessageCount,
+    consumer_count: ConsumerCount,
+}
+
+impl QueueDepth {
+    pub fn message_count(&self) -> MessageCount {
+        self.message_count
+    }
+
+    pub fn consumer_count(&self) -> ConsumerCount {
+        self.consumer_count
+    }
+}
+
+impl From<&Queue> for QueueDepth {
+    fn from(queue: &Queue) -> Self {
+        Self {
+            message_count: queue.message_count(),
+            consumer_count: queue.consumer_count(),
+        }
+    }
+}
+
+/// Stream of the depth of a queue, polled with a passive queue.declare at a fixed interval.
+///
+/// The first depth is yielded right away, each following one after waiting for the interval.
+/// This is enough for simple backlog-based autoscaling, without requiring the management API.
+///
+/// The stream ends after yielding an error. Beware that the server closes the channel if the
+/// queue doesn't exist, so polling should use a dedicated channel.
+///
+/// A watcher is obtained by calling [`Channel::watch_queue_depth`].
+///
+/// [`Channel::watch_queue_depth`]: ./struct.Channel.html#method.watch_queue_depth
+pub struct QueueDepthWatcher {
+    channel: Channel,
+    queue: ShortString,
+    interval: Duration,
+    next: Option<NextDepth>,
+    polled: bool,
+    done: bool,
+}
+
+impl QueueDepthWatcher {
+    pub(crate) fn new(channel: Channel, queue: ShortString, interval: Duration) -> Self {
+        Self {
+            channel,
+            queue,
+            interval,
+            next: None,
+            polled: false,
+            done: false,
+        }
+    }
+
+    /// Get the name of the queue we're watching
+    pub fn queue(&self) -> ShortString {
+        self.queue.clone()
+    }
+
+    fn next_depth(&self) -> NextDepth {
+        let channel = self.channel.clone();
+        let queue = self.queue.clone();
+        let delay = self.polled.then_some(self.interval);
+        Box::pin(async move {
+            if let Some(delay) = delay {
+                channel.sleep(delay).await;
+            }
+            channel.queue_depth(queue.as_str()).await
+        })
+    }
+}
+
+impl fmt::Debug for QueueDepthWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueDepthWatcher")
+            .field("queue", &self.queue)
+            .field("interval", &self.interval)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl Stream for QueueDepthWatcher {
+    type Item = Result<QueueDepth>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let mut next = self.next.take().unwrap_or_else(|| self.next_depth());
+        match next.as_mut().poll(cx) {
+            Poll::Pending => {
+                self.next = Some(next);
+                Poll::Pending
+            }
+            Poll::Ready(res) => {
+                self.polled = true;
+                if res.is_err() {
+                    self.done = true;
+                }
+                Poll::Ready(Some(res))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::{BasicPublishOptions, QueueDeclareOptions},
+        testing::MockBroker,
+        types::FieldTable,
+        BasicProperties, ConnectionProperties, ErrorKind,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn watch_depth() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let watcher = connection.create_channel().await?;
+            let mut depths = watcher.watch_queue_depth("jobs", Duration::from_millis(10));
+            assert_eq!(depths.next().await.unwrap()?.message_count(), 0);
+            for payload in [b"a", b"b"] {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        payload,
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let depth = depths.next().await.unwrap()?;
+            assert_eq!(depth.message_count(), 2);
+            assert_eq!(depth.consumer_count(), 0);
+            assert_eq!(channel.queue_depth("jobs").await?, depth);
+
+            let mut missing = watcher.watch_queue_depth("missing", Duration::from_millis(10));
+            assert!(matches!(
+                missing.next().await.unwrap().unwrap_err().kind(),
+                ErrorKind::ProtocolError(_)
+            ));
+            assert!(missing.next().await.is_none());
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+}
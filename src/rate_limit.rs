@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+/// Limits applied to the messages published on a [`Channel`].
+///
+/// Each limit is enforced using a token bucket allowing bursts of up to one second worth of
+/// messages or bytes. Publishers exceeding the limit are delayed.
+///
+/// [`Channel`]: ./struct.Channel.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    pub messages_per_second: Option<u32>,
+    pub bytes_per_second: Option<u64>,
+}
+
+impl RateLimit {
+    #[must_use]
+    pub fn with_messages_per_second(mut self, messages_per_second: u32) -> Self {
+        self.messages_per_second = Some(messages_per_second);
+        self
+    }
+
+    #[must_use]
+    pub fn with_bytes_per_second(mut self, bytes_per_second: u64) -> Self {
+        self.bytes_per_second = Some(bytes_per_second);
+        self
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    messages: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        let now = Instant::now();
+        Self {
+            limit,
+            messages: limit
+                .messages_per_second
+                .map(|rate| TokenBucket::new(rate.into(), now)),
+            bytes: limit
+                .bytes_per_second
+                .map(|rate| TokenBucket::new(rate as f64, now)),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Account for a message of the given size, returning how long to wait before sending it.
+    pub(crate) fn reserve(&mut self, size: usize, now: Instant) -> Duration {
+        let messages = self
+            .messages
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.reserve(1.0, now));
+        let bytes = self
+            .bytes
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.reserve(size as f64, now));
+        messages.max(bytes)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            last: now,
+        }
+    }
+
+    fn reserve(&mut self, tokens: f64, now: Instant) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - tokens;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_limit() {
+        let mut limiter = RateLimiter::new(RateLimit::default().with_messages_per_second(2));
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(10, now), Duration::ZERO);
+        assert_eq!(limiter.reserve(10, now), Duration::ZERO);
+        assert_eq!(limiter.reserve(10, now), Duration::from_millis(500));
+        assert_eq!(limiter.reserve(10, now), Duration::from_secs(1));
+        assert_eq!(
+            limiter.reserve(10, now + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn bytes_limit() {
+        let mut limiter = RateLimiter::new(RateLimit::default().with_bytes_per_second(100));
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(100, now), Duration::ZERO);
+        assert_eq!(limiter.reserve(50, now), Duration::from_millis(500));
+    }
+}
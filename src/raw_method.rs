@@ -0,0 +1,171 @@
+use crate::{
+    protocol::constants::{FRAME_END, FRAME_METHOD},
+    types::{ChannelId, Identifier},
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/* A method frame payload starts with its class id (2 bytes) and method id (2 bytes) */
+pub(crate) const RAW_METHOD_HEADER_SIZE: usize = 7 + 4;
+
+/// A method frame lapin doesn't know how to parse, sent or received for a protocol extension
+/// implemented by a broker plugin
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawMethod {
+    pub channel_id: ChannelId,
+    pub class_id: Identifier,
+    pub method_id: Identifier,
+    /// The serialized arguments of the method, encoded as specified by the extension
+    pub arguments: Vec<u8>,
+}
+
+impl RawMethod {
+    /// Parse a whole method frame, returning `None` if it's malformed
+    pub(crate) fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.len() < RAW_METHOD_HEADER_SIZE + 1
+            || frame[0] != FRAME_METHOD
+            || frame[frame.len() - 1] != FRAME_END
+        {
+            return None;
+        }
+        Some(Self {
+            channel_id: ChannelId::from_be_bytes([frame[1], frame[2]]),
+            class_id: Identifier::from_be_bytes([frame[7], frame[8]]),
+            method_id: Identifier::from_be_bytes([frame[9], frame[10]]),
+            arguments: frame[RAW_METHOD_HEADER_SIZE..frame.len() - 1].to_vec(),
+        })
+    }
+
+    #[cfg(any(feature = "unstable", test))]
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let size = (self.arguments.len() + 4) as u32;
+        let mut frame = Vec::with_capacity(RAW_METHOD_HEADER_SIZE + self.arguments.len() + 1);
+        frame.push(FRAME_METHOD);
+        frame.extend_from_slice(&self.channel_id.to_be_bytes());
+        frame.extend_from_slice(&size.to_be_bytes());
+        frame.extend_from_slice(&self.class_id.to_be_bytes());
+        frame.extend_from_slice(&self.method_id.to_be_bytes());
+        frame.extend_from_slice(&self.arguments);
+        frame.push(FRAME_END);
+        frame
+    }
+}
+
+type RawMethodFn = Box<dyn FnMut(RawMethod) + Send + 'static>;
+type Inner = HashMap<(Identifier, Identifier), RawMethodFn>;
+
+/// The handlers of the method frames registered through `Connection::on_raw_method`
+#[derive(Clone, Default)]
+pub(crate) struct RawMethodHandlers(Arc<Mutex<Inner>>);
+
+impl RawMethodHandlers {
+    #[cfg(feature = "unstable")]
+    pub(crate) fn set_handler<H: FnMut(RawMethod) + Send + 'static>(
+        &self,
+        class_id: Identifier,
+        method_id: Identifier,
+        handler: H,
+    ) {
+        self.lock_inner()
+            .insert((class_id, method_id), Box::new(handler));
+    }
+
+    pub(crate) fn handles(&self, class_id: Identifier, method_id: Identifier) -> bool {
+        self.lock_inner().contains_key(&(class_id, method_id))
+    }
+
+    pub(crate) fn handle(&self, method: RawMethod) {
+        if let Some(handler) = self
+            .lock_inner()
+            .get_mut(&(method.class_id, method.method_id))
+        {
+            handler(method);
+        }
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for RawMethodHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RawMethodHandlers")
+            .field(&self.lock_inner().keys())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{basic, AMQPClass};
+    use amq_protocol::frame::{gen_frame, AMQPFrame, WriteContext};
+
+    #[test]
+    fn roundtrip() {
+        let method = RawMethod {
+            channel_id: 3,
+            class_id: 60,
+            method_id: 10,
+            arguments: vec![0, 0, 0, 0, 0, 42, 0],
+        };
+        let expected = gen_frame(&AMQPFrame::Method(
+            3,
+            AMQPClass::Basic(basic::AMQPMethod::Qos(basic::Qos {
+                prefetch_count: 42,
+                global: false,
+            })),
+        ))(WriteContext::from(Vec::new()))
+        .unwrap()
+        .write;
+        let frame = method.serialize();
+        assert_eq!(frame, expected);
+        assert_eq!(RawMethod::parse(&frame), Some(method));
+        assert_eq!(RawMethod::parse(&frame[..frame.len() - 1]), None);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn send_and_receive() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = crate::testing::MockBroker::default();
+            let connection = broker
+                .connect(crate::ConnectionProperties::default())
+                .await?;
+            let channel = connection.create_channel().await?;
+            let (sender, receiver) = flume::unbounded();
+            connection.on_raw_method(4242, 10, move |method| {
+                let _ = sender.send(method);
+            });
+            // The mock broker echoes the methods it doesn't know about
+            channel.send_raw_method(4242, 10, b"ping").await?;
+            let method = receiver.recv_async().await.unwrap();
+            assert_eq!(
+                method,
+                RawMethod {
+                    channel_id: channel.id(),
+                    class_id: 4242,
+                    method_id: 10,
+                    arguments: b"ping".to_vec(),
+                }
+            );
+            // The connection is still usable afterwards
+            channel
+                .queue_declare(
+                    "after-raw",
+                    crate::options::QueueDeclareOptions::default(),
+                    crate::types::FieldTable::default(),
+                )
+                .await?;
+            assert!(broker.queue_exists("after-raw"));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
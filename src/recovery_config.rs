@@ -1,6 +1,38 @@
-#[derive(Default, Clone)]
+use std::time::Duration;
+
+#[derive(Clone)]
 pub struct RecoveryConfig {
     pub(crate) auto_recover_channels: bool,
+    pub(crate) publish_buffer_capacity: usize,
+    pub(crate) publish_buffer_overflow: PublishBufferOverflow,
+    pub(crate) recover_exchanges: bool,
+    pub(crate) recover_queues: bool,
+    pub(crate) rename_server_named_queues: bool,
+    pub(crate) recover_bindings: bool,
+    pub(crate) recover_consumers: bool,
+    pub(crate) recover_confirm_mode: bool,
+    pub(crate) recover_qos: bool,
+    pub(crate) max_recovery_attempts: Option<u32>,
+    pub(crate) max_recovery_time: Option<Duration>,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        Self {
+            auto_recover_channels: false,
+            publish_buffer_capacity: 0,
+            publish_buffer_overflow: PublishBufferOverflow::default(),
+            recover_exchanges: false,
+            recover_queues: false,
+            rename_server_named_queues: false,
+            recover_bindings: false,
+            recover_consumers: false,
+            recover_confirm_mode: true,
+            recover_qos: true,
+            max_recovery_attempts: None,
+            max_recovery_time: None,
+        }
+    }
 }
 
 impl RecoveryConfig {
@@ -9,4 +41,723 @@ impl RecoveryConfig {
         self.auto_recover_channels = true;
         self
     }
+
+    /// Hold back up to `capacity` publishes issued on a channel while it is recovering, and send
+    /// them once it has recovered, instead of failing them right away.
+    ///
+    /// `overflow` decides which publish fails once the buffer is full. A buffered publish fails
+    /// if the channel cannot be recovered.
+    #[cfg(feature = "unstable")]
+    pub fn buffer_publishes(mut self, capacity: usize, overflow: PublishBufferOverflow) -> Self {
+        self.publish_buffer_capacity = capacity;
+        self.publish_buffer_overflow = overflow;
+        self
+    }
+
+    /// Enable the recovery of exchanges, queues, bindings and consumers once a channel has been
+    /// reopened
+    ///
+    /// Server-named queues get re-declared with new names.
+    #[cfg(feature = "unstable")]
+    pub fn recover_topology(self) -> Self {
+        self.recover_exchanges(true)
+            .recover_queues(true)
+            .rename_server_named_queues(true)
+            .recover_bindings(true)
+            .recover_consumers(true)
+    }
+
+    /// Re-declare the exchanges declared on the connection
+    #[cfg(feature = "unstable")]
+    pub fn recover_exchanges(mut self, enabled: bool) -> Self {
+        self.recover_exchanges = enabled;
+        self
+    }
+
+    /// Re-declare the queues declared on the channel
+    #[cfg(feature = "unstable")]
+    pub fn recover_queues(mut self, enabled: bool) -> Self {
+        self.recover_queues = enabled;
+        self
+    }
+
+    /// Re-declare server-named queues, which get new names from the server
+    ///
+    /// Server-named queues cannot be re-declared with the name they were given, so they are
+    /// otherwise left aside when recovering queues.
+    #[cfg(feature = "unstable")]
+    pub fn rename_server_named_queues(mut self, enabled: bool) -> Self {
+        self.rename_server_named_queues = enabled;
+        self
+    }
+
+    /// Re-create the bindings of the exchanges declared on the connection and of the queues
+    /// declared on the channel
+    #[cfg(feature = "unstable")]
+    pub fn recover_bindings(mut self, enabled: bool) -> Self {
+        self.recover_bindings = enabled;
+        self
+    }
+
+    /// Restart the consumers of the channel, instead of failing them
+    #[cfg(feature = "unstable")]
+    pub fn recover_consumers(mut self, enabled: bool) -> Self {
+        self.recover_consumers = enabled;
+        self
+    }
+
+    /// Re-enable publisher confirms if they were enabled on the channel (enabled by default)
+    #[cfg(feature = "unstable")]
+    pub fn recover_confirm_mode(mut self, enabled: bool) -> Self {
+        self.recover_confirm_mode = enabled;
+        self
+    }
+
+    /// Restore the prefetch counts set with basic.qos on the channel (enabled by default)
+    #[cfg(feature = "unstable")]
+    pub fn recover_qos(mut self, enabled: bool) -> Self {
+        self.recover_qos = enabled;
+        self
+    }
+
+    /// Give up once a channel failed to recover this many times in a row
+    ///
+    /// The connection then ends up in the terminal [`ConnectionState::Failed`] state and every
+    /// pending operation fails with [`ErrorKind::RecoveryFailed`], which is also passed to the
+    /// [`Connection::on_error`] handler.
+    ///
+    /// [`ConnectionState::Failed`]: ./enum.ConnectionState.html#variant.Failed
+    /// [`ErrorKind::RecoveryFailed`]: ./enum.ErrorKind.html#variant.RecoveryFailed
+    /// [`Connection::on_error`]: ./struct.Connection.html#method.on_error
+    #[cfg(feature = "unstable")]
+    pub fn max_recovery_attempts(mut self, attempts: u32) -> Self {
+        self.max_recovery_attempts = Some(attempts);
+        self
+    }
+
+    /// Give up once a channel has been recovering for this long, the same way as
+    /// [`max_recovery_attempts`] does
+    ///
+    /// [`max_recovery_attempts`]: #method.max_recovery_attempts
+    #[cfg(feature = "unstable")]
+    pub fn max_recovery_time(mut self, time: Duration) -> Self {
+        self.max_recovery_time = Some(time);
+        self
+    }
+}
+
+/// Which publish fails when publishing on a recovering channel whose publish buffer is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PublishBufferOverflow {
+    /// Fail the new publish
+    #[default]
+    RejectNew,
+    /// Fail the oldest buffered publish to make room for the new one
+    DropOldest,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*,
+        testing::{FaultyStream, FrameKind, MockBroker},
+        types::FieldTable,
+        BasicProperties, Connection, ConnectionProperties, RecoveryEvent, RecoveryOutcome,
+    };
+    use futures_lite::{future, StreamExt};
+
+    #[test]
+    fn buffer_publishes_during_recovery() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                publish_buffer_capacity: 1,
+                publish_buffer_overflow: PublishBufferOverflow::DropOldest,
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "buffered",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            // Keep the channel recovering until we release channel.open-ok
+            injector.hold_frames(FrameKind::Method(20, 11));
+            injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+            while !channel.status().reconnecting() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            let publish = |payload: &'static [u8]| {
+                Box::pin(channel.basic_publish(
+                    "",
+                    "buffered",
+                    BasicPublishOptions::default(),
+                    payload,
+                    BasicProperties::default(),
+                ))
+            };
+            let mut first = publish(b"first");
+            assert!(future::poll_once(&mut first).await.is_none());
+            let mut second = publish(b"second");
+            assert!(future::poll_once(&mut second).await.is_none());
+            // The buffer only holds one publish
+            assert!(first.await.is_err());
+
+            injector.release_frames();
+            second.await?.await?;
+            assert!(channel.status().connected());
+            let queue = channel
+                .queue_declare(
+                    "buffered",
+                    QueueDeclareOptions {
+                        passive: true,
+                        ..QueueDeclareOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+            assert_eq!(queue.message_count(), 1);
+            assert_eq!(broker.messages("buffered"), vec![b"second".to_vec()]);
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn cancelled_publishes_free_their_buffer_slot() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                publish_buffer_capacity: 1,
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "buffered",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            injector.hold_frames(FrameKind::Method(20, 11));
+            injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+            while !channel.status().reconnecting() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            let publish = |payload: &'static [u8]| {
+                Box::pin(channel.basic_publish(
+                    "",
+                    "buffered",
+                    BasicPublishOptions::default(),
+                    payload,
+                    BasicProperties::default(),
+                ))
+            };
+            let mut cancelled = publish(b"cancelled");
+            assert!(future::poll_once(&mut cancelled).await.is_none());
+            drop(cancelled);
+            // The cancelled publish gave its slot back
+            let mut second = publish(b"second");
+            assert!(future::poll_once(&mut second).await.is_none());
+
+            injector.release_frames();
+            second.await?.await?;
+            assert!(channel.status().connected());
+            assert_eq!(broker.messages("buffered"), vec![b"second".to_vec()]);
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn recovery_generations() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "generations",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "generations",
+                    BasicPublishOptions::default(),
+                    b"payload",
+                    BasicProperties::default(),
+                )
+                .await?;
+            let message = channel
+                .basic_get("generations", BasicGetOptions::default())
+                .await?
+                .expect("message");
+            assert_eq!(channel.status().recovery_generation(), 0);
+            assert!(!connection.status().is_recovering());
+
+            injector.hold_frames(FrameKind::Method(20, 11));
+            injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+            while !channel.status().is_recovering() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert!(connection.status().is_recovering());
+            assert_eq!(channel.status().recovery_generation(), 1);
+            assert_eq!(connection.status().recovery_generation(), 1);
+
+            injector.release_frames();
+            while !channel.status().connected() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!channel.status().is_recovering());
+            assert!(!connection.status().is_recovering());
+            // The delivery tag belongs to the previous generation, the ack is not sent
+            assert!(
+                !message
+                    .delivery
+                    .acker
+                    .ack(BasicAckOptions::default())
+                    .await?
+            );
+            assert!(channel.status().connected());
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn recover_topology() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                recover_exchanges: true,
+                recover_queues: true,
+                rename_server_named_queues: true,
+                recover_bindings: true,
+                recover_consumers: true,
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .exchange_declare(
+                    "events",
+                    crate::ExchangeKind::Fanout,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let server_named = channel
+                .queue_declare("", QueueDeclareOptions::default(), FieldTable::default())
+                .await?;
+            for queue in [server_named.name().as_str(), "jobs", "archive", "skipped"] {
+                channel
+                    .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+                    .await?;
+                channel
+                    .queue_bind(
+                        queue,
+                        "events",
+                        "",
+                        QueueBindOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await?;
+            }
+            channel.skip_queue_recovery("skipped");
+            let consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions {
+                        no_ack: true,
+                        ..BasicConsumeOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+            // Lose some queues behind the back of the first channel
+            let other = connection.create_channel().await?;
+            for queue in ["archive", "skipped"] {
+                other
+                    .queue_delete(queue, QueueDeleteOptions::default())
+                    .await?;
+            }
+
+            injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+            // Consumers are recovered last
+            while channel.status().recovery_generation() == 0
+                || !broker.queue_exists("archive")
+                || broker.consumer_count("jobs") != Some(1)
+            {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!broker.queue_exists("skipped"));
+            let renamed = channel
+                .topology()
+                .queues
+                .into_iter()
+                .chain(connection.topology().queues.into_iter().map(From::from))
+                .map(|queue| queue.name.clone())
+                .find(|name| name.as_str().starts_with("amq.gen") && name != server_named.name())
+                .expect("renamed queue");
+
+            channel
+                .basic_publish(
+                    "events",
+                    "",
+                    BasicPublishOptions::default(),
+                    b"event",
+                    BasicProperties::default(),
+                )
+                .await?;
+            let delivery = consumer.clone().next().await.expect("delivery")?;
+            assert_eq!(&delivery.data[..], b"event");
+            assert_eq!(broker.message_count("archive"), Some(1));
+            assert_eq!(broker.message_count(renamed.as_str()), Some(1));
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn give_up_after_max_attempts() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                recover_exchanges: true,
+                max_recovery_attempts: Some(2),
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let (sender, receiver) = flume::unbounded();
+            connection.on_error(move |error| {
+                let _ = sender.send(error);
+            });
+            let channel = connection.create_channel().await?;
+            channel
+                .exchange_declare(
+                    "events",
+                    crate::ExchangeKind::Fanout,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            // Make the recovery of the exchange fail forever
+            let other = broker.connect(ConnectionProperties::default()).await?;
+            let other_channel = other.create_channel().await?;
+            other_channel
+                .exchange_delete("events", ExchangeDeleteOptions::default())
+                .await?;
+            other_channel
+                .exchange_declare(
+                    "events",
+                    crate::ExchangeKind::Direct,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+            let error = receiver.recv_async().await.unwrap();
+            assert!(matches!(error.kind(), crate::ErrorKind::RecoveryFailed(_)));
+            assert_eq!(connection.status().state(), crate::ConnectionState::Failed);
+            assert_eq!(channel.status().recovery_generation(), 2);
+            assert!(channel
+                .basic_publish(
+                    "events",
+                    "",
+                    BasicPublishOptions::default(),
+                    b"event",
+                    BasicProperties::default(),
+                )
+                .await
+                .is_err());
+            other.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn give_up_after_max_time() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                publish_buffer_capacity: 1,
+                max_recovery_time: Some(Duration::from_millis(20)),
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+
+            // Never let the channel recover
+            injector.hold_frames(FrameKind::Method(20, 11));
+            injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+            while !channel.status().reconnecting() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let error = channel
+                .basic_publish(
+                    "",
+                    "buffered",
+                    BasicPublishOptions::default(),
+                    b"payload",
+                    BasicProperties::default(),
+                )
+                .await
+                .unwrap_err();
+            assert!(matches!(error.kind(), crate::ErrorKind::RecoveryFailed(_)));
+            assert!(connection.status().failed());
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn per_channel_recovery() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel.set_experimental_recovery_config(RecoveryConfig {
+                auto_recover_channels: true,
+                recover_consumers: true,
+                ..RecoveryConfig::default()
+            });
+            let other = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel.basic_qos(1, BasicQosOptions::default()).await?;
+            let mut consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            let passive = QueueDeclareOptions {
+                passive: true,
+                ..QueueDeclareOptions::default()
+            };
+            for ch in [&channel, &other] {
+                assert!(ch
+                    .queue_declare("missing", passive, FieldTable::default())
+                    .await
+                    .is_err());
+            }
+            while channel.status().recovery_generation() == 0 || !channel.status().connected() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            while other.status().state() != crate::ChannelState::Closed {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(other.status().recovery_generation(), 0);
+
+            for payload in [b"first", b"other"] {
+                channel
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        payload,
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let delivery = consumer.next().await.expect("delivery")?;
+            assert_eq!(&delivery.data[..], b"first");
+            // The prefetch count has been restored
+            assert_eq!(broker.message_count("jobs"), Some(1));
+            delivery.ack(BasicAckOptions::default()).await?;
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn notifier_outcome() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let channel = connection.create_channel().await?;
+            let recovering_error = || async {
+                injector.hold_frames(FrameKind::Method(20, 11));
+                injector.fail_channel(channel.id(), 406, "PRECONDITION_FAILED - chaos");
+                while !channel.status().reconnecting() {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                channel
+                    .basic_qos(1, BasicQosOptions::default())
+                    .await
+                    .unwrap_err()
+            };
+
+            let recovering = recovering_error().await.notifier().expect("notifier");
+            assert_eq!(
+                recovering.clone().timeout(Duration::from_millis(10)).await,
+                None
+            );
+            injector.release_frames();
+            assert_eq!(recovering.await, RecoveryOutcome::Recovered);
+            assert!(channel.status().connected());
+
+            let recovering = recovering_error().await.notifier().expect("notifier");
+            connection.close(200, "OK").await?;
+            assert!(matches!(
+                recovering.timeout(Duration::from_secs(1)).await,
+                Some(RecoveryOutcome::PermanentlyFailed(_))
+            ));
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn connection_recovery_events() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let stream = FaultyStream::new(broker.stream());
+            let injector = stream.injector();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                ..RecoveryConfig::default()
+            };
+            let connection = Connection::connector_with_stream(
+                "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                stream,
+                ConnectionProperties::default().with_experimental_recovery_config(config),
+            )
+            .await?;
+            let mut events = connection.recovery_events();
+            let first = connection.create_channel().await?;
+            let second = connection.create_channel().await?;
+
+            injector.fail_channel(first.id(), 406, "PRECONDITION_FAILED - chaos");
+            assert_eq!(
+                events.next().await,
+                Some(RecoveryEvent::ChannelRecovered(first.id()))
+            );
+
+            injector.hold_frames(FrameKind::Method(20, 11));
+            injector.fail_channel(second.id(), 406, "PRECONDITION_FAILED - chaos");
+            while !second.status().reconnecting() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            connection.close(200, "OK").await?;
+            assert!(matches!(
+                events.next().await,
+                Some(RecoveryEvent::ChannelFailed(id, _)) if id == second.id()
+            ));
+            assert!(matches!(
+                events.next().await,
+                Some(RecoveryEvent::ConnectionUnusable(_))
+            ));
+            assert_eq!(events.next().await, None);
+            // Late subscribers still get told
+            assert!(matches!(
+                connection.recovery_events().collect::<Vec<_>>().await[..],
+                [RecoveryEvent::ConnectionUnusable(_)]
+            ));
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
 }
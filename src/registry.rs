@@ -6,7 +6,7 @@ use crate::{
     types::{FieldTable, ShortString},
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex, MutexGuard},
 };
 
@@ -27,6 +27,37 @@ impl Registry {
             .collect()
     }
 
+    pub(crate) fn recoverable_exchanges(&self) -> Vec<ExchangeDefinition> {
+        let inner = self.lock_inner();
+        inner
+            .exchanges
+            .values()
+            .filter(|e| !inner.unrecovered_exchanges.contains(&e.name))
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn recoverable_queues(&self) -> Vec<QueueDefinitionInternal> {
+        self.lock_inner()
+            .queues
+            .values()
+            .filter(|q| q.recover())
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn skip_exchange_recovery(&self, name: ShortString) {
+        self.lock_inner().unrecovered_exchanges.insert(name);
+    }
+
+    pub(crate) fn skip_queue_recovery(&self, name: ShortString) {
+        self.lock_inner()
+            .queues
+            .entry(name.clone())
+            .or_insert_with(|| QueueDefinitionInternal::undeclared(name))
+            .skip_recovery();
+    }
+
     pub(crate) fn register_exchange(
         &self,
         name: ShortString,
@@ -54,7 +85,9 @@ impl Registry {
     }
 
     pub(crate) fn deregister_exchange(&self, name: &str) {
-        self.lock_inner().exchanges.remove(name);
+        let mut inner = self.lock_inner();
+        inner.exchanges.remove(name);
+        inner.unrecovered_exchanges.remove(name);
     }
 
     pub(crate) fn register_exchange_binding(
@@ -133,6 +166,14 @@ impl Registry {
             .register_binding(source, routing_key, arguments);
     }
 
+    pub(crate) fn queue_bindings(&self, name: &str) -> Vec<BindingDefinition> {
+        self.lock_inner()
+            .queues
+            .get(name)
+            .map(|queue| queue.bindings.clone())
+            .unwrap_or_default()
+    }
+
     pub(crate) fn deregister_queue_binding(
         &self,
         destination: &str,
@@ -154,4 +195,102 @@ impl Registry {
 struct Inner {
     exchanges: HashMap<ShortString, ExchangeDefinition>,
     queues: HashMap<ShortString, QueueDefinitionInternal>,
+    unrecovered_exchanges: HashSet<ShortString>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, Channel,
+        ConnectionProperties, ExchangeKind,
+    };
+
+    async fn publish(channel: &Channel, routing_key: &str) -> crate::Result<()> {
+        channel
+            .basic_publish(
+                "events",
+                routing_key,
+                BasicPublishOptions::default(),
+                routing_key.as_bytes(),
+                BasicProperties::default(),
+            )
+            .await?
+            .await?;
+        Ok(())
+    }
+
+    #[test]
+    fn binding_bookkeeping() {
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .exchange_declare(
+                    "events",
+                    ExchangeKind::Direct,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .queue_declare(
+                    "audit",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            for routing_key in ["created", "deleted"] {
+                channel
+                    .queue_bind(
+                        "audit",
+                        "events",
+                        routing_key,
+                        QueueBindOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await?;
+            }
+            let mut routing_keys = channel
+                .queue_bindings("audit")
+                .into_iter()
+                .map(|binding| binding.routing_key.to_string())
+                .collect::<Vec<_>>();
+            routing_keys.sort();
+            assert_eq!(routing_keys, ["created", "deleted"]);
+
+            // "created" is kept, "updated" added and "deleted" removed
+            channel
+                .queue_rebind(
+                    "audit",
+                    "events",
+                    &["created", "updated"],
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut routing_keys = channel
+                .queue_bindings("audit")
+                .into_iter()
+                .map(|binding| binding.routing_key.to_string())
+                .collect::<Vec<_>>();
+            routing_keys.sort();
+            assert_eq!(routing_keys, ["created", "updated"]);
+            for routing_key in ["created", "deleted", "updated"] {
+                publish(&channel, routing_key).await?;
+            }
+            let mut routed = broker.messages("audit");
+            routed.sort();
+            assert_eq!(routed, [b"created".to_vec(), b"updated".to_vec()]);
+
+            channel.queue_unbind_all("audit").await?;
+            assert!(channel.queue_bindings("audit").is_empty());
+            publish(&channel, "created").await?;
+            assert_eq!(broker.message_count("audit"), Some(2));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
 }
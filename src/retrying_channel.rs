@@ -0,0 +1,409 @@
+//! Retrying idempotent operations across channel failures.
+//!
+//! A soft error closes the channel it happened on, failing all the operations in flight on
+//! that channel, related or not. A [`RetryingChannel`] retries the idempotent ones (declares,
+//! binds and qos) according to a [`RetryPolicy`], waiting for the channel to be recovered or
+//! opening a new one as needed.
+//!
+//! ```rust,no_run
+//! use lapin::{
+//!     options::*, retrying_channel::RetryPolicy, types::FieldTable, Backoff, Connection,
+//!     ConnectionProperties,
+//! };
+//! use std::time::Duration;
+//!
+//! # async_global_executor::block_on(async {
+//! let connection = Connection::connect("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default()).await?;
+//! let channel = connection
+//!     .create_retrying_channel(
+//!         RetryPolicy::new(5).with_backoff(Backoff::constant(Duration::from_millis(200))),
+//!     )
+//!     .await?;
+//! channel
+//!     .queue_declare("jobs", QueueDeclareOptions::default(), FieldTable::default())
+//!     .await?;
+//! channel
+//!     .queue_bind("jobs", "amq.direct", "jobs", QueueBindOptions::default(), FieldTable::default())
+//!     .await?;
+//! # Ok::<(), lapin::Error>(())
+//! # });
+//! ```
+
+use crate::{
+    options::{
+        BasicQosOptions, ExchangeBindOptions, ExchangeDeclareOptions, QueueBindOptions,
+        QueueDeclareOptions,
+    },
+    queue::Queue,
+    types::{FieldTable, ShortUInt},
+    Backoff, Channel, Connection, Error, ErrorKind, ExchangeKind, RecoveryOutcome, Result,
+};
+use std::{
+    fmt,
+    future::Future,
+    sync::{Mutex, MutexGuard},
+};
+use tracing::debug;
+
+/// How many times, and how often, a [`RetryingChannel`] retries an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    backoff: Backoff,
+}
+
+impl RetryPolicy {
+    /// Retry up to `max_retries` times, waiting according to the default [`Backoff`] in between.
+    ///
+    /// [`Backoff`]: ../struct.Backoff.html
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Wait according to this backoff between two attempts.
+    #[must_use]
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+}
+
+/// A channel retrying its idempotent operations when they fail with a soft error or because
+/// of the state of the channel, e.g. when another operation made the server close it.
+///
+/// Before retrying, it waits for the channel to be recovered if it is, and opens a new
+/// channel (applying the last [`basic_qos`] again) if it got closed for good. The other
+/// errors, and the last one once the policy gives up, are returned as is.
+///
+/// It is obtained by calling [`Connection::create_retrying_channel`].
+///
+/// [`basic_qos`]: #method.basic_qos
+/// [`Connection::create_retrying_channel`]: ../struct.Connection.html#method.create_retrying_channel
+pub struct RetryingChannel<'a> {
+    connection: &'a Connection,
+    policy: RetryPolicy,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    channel: Channel,
+    qos: Option<(ShortUInt, BasicQosOptions)>,
+}
+
+impl<'a> RetryingChannel<'a> {
+    pub(crate) fn new(connection: &'a Connection, channel: Channel, policy: RetryPolicy) -> Self {
+        Self {
+            connection,
+            policy,
+            inner: Mutex::new(Inner { channel, qos: None }),
+        }
+    }
+
+    /// The channel operations currently go through
+    pub fn channel(&self) -> Channel {
+        self.lock_inner().channel.clone()
+    }
+
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    /// Run an operation with the current channel, retrying it according to the policy.
+    ///
+    /// As it may run several times, the operation has to be idempotent.
+    pub async fn retry<T, F, Fut>(&self, operation: F) -> Result<T>
+    where
+        F: Fn(Channel) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let channel = self.usable_channel().await?;
+            match operation(channel.clone()).await {
+                Ok(res) => return Ok(res),
+                Err(error) if attempt < self.policy.max_retries && is_retryable(&error) => {
+                    let delay = self.policy.backoff.delay(attempt);
+                    debug!(channel=%channel.id(), %error, attempt, ?delay, "idempotent operation failed, will retry");
+                    channel.sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    pub async fn exchange_declare(
+        &self,
+        exchange: &str,
+        kind: ExchangeKind,
+        options: ExchangeDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        self.retry(|channel| {
+            let kind = kind.clone();
+            let arguments = arguments.clone();
+            async move {
+                channel
+                    .exchange_declare(exchange, kind, options, arguments)
+                    .await
+            }
+        })
+        .await
+    }
+
+    pub async fn exchange_bind(
+        &self,
+        destination: &str,
+        source: &str,
+        routing_key: &str,
+        options: ExchangeBindOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        self.retry(|channel| {
+            let arguments = arguments.clone();
+            async move {
+                channel
+                    .exchange_bind(destination, source, routing_key, options, arguments)
+                    .await
+            }
+        })
+        .await
+    }
+
+    pub async fn queue_declare(
+        &self,
+        queue: &str,
+        options: QueueDeclareOptions,
+        arguments: FieldTable,
+    ) -> Result<Queue> {
+        self.retry(|channel| {
+            let arguments = arguments.clone();
+            async move { channel.queue_declare(queue, options, arguments).await }
+        })
+        .await
+    }
+
+    pub async fn queue_bind(
+        &self,
+        queue: &str,
+        exchange: &str,
+        routing_key: &str,
+        options: QueueBindOptions,
+        arguments: FieldTable,
+    ) -> Result<()> {
+        self.retry(|channel| {
+            let arguments = arguments.clone();
+            async move {
+                channel
+                    .queue_bind(queue, exchange, routing_key, options, arguments)
+                    .await
+            }
+        })
+        .await
+    }
+
+    /// Set the qos of the channel, which is applied again to the channels opened to replace it.
+    pub async fn basic_qos(
+        &self,
+        prefetch_count: ShortUInt,
+        options: BasicQosOptions,
+    ) -> Result<()> {
+        self.retry(|channel| async move { channel.basic_qos(prefetch_count, options).await })
+            .await?;
+        self.lock_inner().qos = Some((prefetch_count, options));
+        Ok(())
+    }
+
+    /* The current channel once recovered, or a new one if it cannot be anymore */
+    async fn usable_channel(&self) -> Result<Channel> {
+        let channel = self.channel();
+        if channel.status().connected() {
+            return Ok(channel);
+        }
+        if let Some(notifier) = channel.status().state_error().notifier() {
+            debug!(channel=%channel.id(), "waiting for the channel to be recovered");
+            if notifier.await == RecoveryOutcome::Recovered && channel.status().connected() {
+                return Ok(channel);
+            }
+        }
+        let replacement = self.connection.create_channel().await?;
+        debug!(channel=%channel.id(), replacement=%replacement.id(), "replacing closed channel");
+        let qos = self.lock_inner().qos;
+        if let Some((prefetch_count, options)) = qos {
+            replacement.basic_qos(prefetch_count, options).await?;
+        }
+        let mut inner = self.lock_inner();
+        // Another operation may have replaced it in the meantime
+        if inner.channel != channel && inner.channel.status().connected() {
+            return Ok(inner.channel.clone());
+        }
+        inner.channel = replacement.clone();
+        Ok(replacement)
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for RetryingChannel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryingChannel")
+            .field("channel", &self.channel().id())
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+/* Soft errors and the channel being closed or recovering, not the connection being lost */
+fn is_retryable(error: &Error) -> bool {
+    error.is_amqp_soft_error() || matches!(error.kind(), ErrorKind::InvalidChannelState(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::{BasicConsumeOptions, BasicPublishOptions},
+        testing::MockBroker,
+        BasicProperties, ConnectionProperties, RecoveryConfig,
+    };
+    use futures_lite::future;
+    use std::time::Duration;
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy::new(max_retries).with_backoff(Backoff::constant(Duration::from_millis(1)))
+    }
+
+    fn passive() -> QueueDeclareOptions {
+        QueueDeclareOptions {
+            passive: true,
+            ..QueueDeclareOptions::default()
+        }
+    }
+
+    #[test]
+    fn retry_on_new_channel() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_retrying_channel(policy(3)).await?;
+            channel.basic_qos(1, BasicQosOptions::default()).await?;
+            let first = channel.channel();
+
+            // An unrelated failure closes the channel the declare was sent on
+            let (failed, declared) = future::zip(
+                first.queue_declare("missing", passive(), FieldTable::default()),
+                channel.queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                ),
+            )
+            .await;
+            assert!(failed.unwrap_err().is_amqp_soft_error());
+            assert_eq!(declared?.name().as_str(), "jobs");
+            assert!(!first.status().connected());
+            let replacement = channel.channel();
+            assert_ne!(replacement.id(), first.id());
+            assert!(replacement.status().connected());
+
+            // The qos got applied to the new channel
+            for _ in 0..2 {
+                replacement
+                    .basic_publish(
+                        "",
+                        "jobs",
+                        BasicPublishOptions::default(),
+                        b"job",
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            let _consumer = replacement
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .queue_declare("jobs", passive(), FieldTable::default())
+                .await?;
+            assert_eq!(broker.message_count("jobs"), Some(1));
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn retry_once_recovered() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let config = RecoveryConfig {
+                auto_recover_channels: true,
+                ..RecoveryConfig::default()
+            };
+            let connection = broker
+                .connect(ConnectionProperties::default().with_experimental_recovery_config(config))
+                .await?;
+            let channel = connection.create_retrying_channel(policy(3)).await?;
+            let first = channel.channel();
+
+            let (failed, declared) = future::zip(
+                first.queue_declare("missing", passive(), FieldTable::default()),
+                channel.queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                ),
+            )
+            .await;
+            assert!(failed.unwrap_err().is_amqp_soft_error());
+            assert_eq!(declared?.name().as_str(), "jobs");
+            // The recovered channel was kept
+            assert_eq!(channel.channel().id(), first.id());
+            assert!(first.status().connected());
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn give_up() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_retrying_channel(policy(2)).await?;
+            let first = channel.channel();
+
+            let error = channel
+                .queue_declare("missing", passive(), FieldTable::default())
+                .await
+                .unwrap_err();
+            assert!(error.is_amqp_soft_error());
+            // The first attempt and two retries, each one closing its channel
+            assert_eq!(channel.channel().id(), first.id() + 2);
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+}
@@ -1,11 +1,16 @@
 use crate::{
-    message::BasicReturnMessage, publisher_confirm::Confirmation, types::PayloadSize,
-    BasicProperties, Promise,
+    message::BasicReturnMessage,
+    publisher_confirm::{Confirmation, DroppedConfirmPolicy},
+    types::PayloadSize,
+    BasicProperties, Promise, Result,
 };
 use std::{
     collections::VecDeque,
     fmt,
+    future::Future,
+    pin::Pin,
     sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
 };
 use tracing::{trace, warn};
 
@@ -42,7 +47,27 @@ impl ReturnedMessages {
     }
 
     pub(crate) fn register_dropped_confirm(&self, promise: Promise<Confirmation>) {
-        self.lock_inner().register_dropped_confirm(promise);
+        let confirmation = promise.try_wait();
+        if let Some(Ok(Confirmation::NotRequested)) = confirmation {
+            return;
+        }
+        let policy = self.lock_inner().dropped_confirm_policy;
+        if policy == DroppedConfirmPolicy::Panic
+            && cfg!(debug_assertions)
+            && !std::thread::panicking()
+        {
+            panic!("PublisherConfirm dropped without being awaited");
+        }
+        self.lock_inner()
+            .register_dropped_confirm(promise, confirmation);
+    }
+
+    pub(crate) fn set_dropped_confirm_policy(&self, policy: DroppedConfirmPolicy) {
+        self.lock_inner().dropped_confirm_policy = policy;
+    }
+
+    pub(crate) fn poll_unhandled_confirm(&self, cx: &mut Context<'_>) -> Poll<Confirmation> {
+        self.lock_inner().poll_unhandled_confirm(cx)
     }
 
     pub(crate) fn get_waiting_message(&self) -> Option<BasicReturnMessage> {
@@ -74,6 +99,10 @@ pub struct Inner {
     waiting_messages: VecDeque<BasicReturnMessage>,
     messages: Vec<BasicReturnMessage>,
     dropped_confirms: Vec<Promise<Confirmation>>,
+    dropped_confirm_policy: DroppedConfirmPolicy,
+    unhandled_confirms: Vec<Promise<Confirmation>>,
+    unhandled_ready: VecDeque<Confirmation>,
+    unhandled_waker: Option<Waker>,
 }
 
 impl Inner {
@@ -98,7 +127,7 @@ impl Inner {
         confirm_mode: bool,
     ) {
         if let Some(message) = self.current_message.as_mut() {
-            message.receive_content(payload);
+            message.receive_content(payload, remaining_size);
         }
         if remaining_size == 0 {
             self.new_delivery_complete(confirm_mode);
@@ -119,11 +148,12 @@ impl Inner {
     fn process_dropped_confirm(
         &mut self,
         promise: Promise<Confirmation>,
+        confirmation: Option<Result<Confirmation>>,
         messages: Option<&mut Vec<BasicReturnMessage>>,
     ) {
         let messages = messages.unwrap_or(&mut self.messages);
 
-        if let Some(confirmation) = promise.try_wait() {
+        if let Some(confirmation) = confirmation {
             if let Ok(Confirmation::Nack(Some(message))) | Ok(Confirmation::Ack(Some(message))) =
                 confirmation
             {
@@ -138,9 +168,65 @@ impl Inner {
         }
     }
 
-    fn register_dropped_confirm(&mut self, promise: Promise<Confirmation>) {
-        trace!("Registering new dropped PublisherConfirm");
-        self.process_dropped_confirm(promise, None)
+    fn register_dropped_confirm(
+        &mut self,
+        promise: Promise<Confirmation>,
+        confirmation: Option<Result<Confirmation>>,
+    ) {
+        trace!(policy=?self.dropped_confirm_policy, "Registering new dropped PublisherConfirm");
+        match self.dropped_confirm_policy {
+            DroppedConfirmPolicy::Ignore => return,
+            DroppedConfirmPolicy::Warn => {
+                warn!("PublisherConfirm dropped without being awaited, its outcome is only available through wait_for_confirms");
+            }
+            DroppedConfirmPolicy::Forward => {
+                match confirmation {
+                    Some(Ok(confirmation)) => self.forward_unhandled_confirm(confirmation),
+                    Some(Err(_)) => {}
+                    None => {
+                        self.unhandled_confirms.push(promise);
+                        if let Some(waker) = self.unhandled_waker.take() {
+                            waker.wake();
+                        }
+                    }
+                }
+                return;
+            }
+            DroppedConfirmPolicy::Track | DroppedConfirmPolicy::Panic => {}
+        }
+        self.process_dropped_confirm(promise, confirmation, None)
+    }
+
+    /* Only nacks and returned messages need handling */
+    fn forward_unhandled_confirm(&mut self, confirmation: Confirmation) {
+        if let Confirmation::Nack(_) | Confirmation::Ack(Some(_)) = confirmation {
+            self.unhandled_ready.push_back(confirmation);
+            if let Some(waker) = self.unhandled_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn poll_unhandled_confirm(&mut self, cx: &mut Context<'_>) -> Poll<Confirmation> {
+        let mut ready = Vec::new();
+        self.unhandled_confirms
+            .retain_mut(|promise| match Pin::new(promise).poll(cx) {
+                Poll::Ready(res) => {
+                    ready.extend(res.ok());
+                    false
+                }
+                Poll::Pending => true,
+            });
+        for confirmation in ready {
+            self.forward_unhandled_confirm(confirmation);
+        }
+        match self.unhandled_ready.pop_front() {
+            Some(confirmation) => Poll::Ready(confirmation),
+            None => {
+                self.unhandled_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
     }
 
     fn drain(&mut self) -> Vec<BasicReturnMessage> {
@@ -153,7 +239,8 @@ impl Inner {
         let before = self.dropped_confirms.len();
         if before != 0 {
             for promise in std::mem::take(&mut self.dropped_confirms) {
-                self.process_dropped_confirm(promise, Some(&mut messages))
+                let confirmation = promise.try_wait();
+                self.process_dropped_confirm(promise, confirmation, Some(&mut messages))
             }
             trace!(
                 %before,
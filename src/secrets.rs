@@ -0,0 +1,179 @@
+use crate::{
+    auth::SASLMechanism,
+    protocol::{connection, AMQPClass},
+    uri::AMQPUserInfo,
+};
+use amq_protocol::frame::AMQPFrame;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/* Stands in for the secrets in logs, Debug output and recordings */
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// The username and password used to log in, wiped from memory once the handshake is over.
+///
+/// Unlike `auth::Credentials`, its Debug implementation doesn't show the password, and the SASL
+/// responses built from it are wiped too.
+pub(crate) struct Credentials {
+    username: Zeroizing<String>,
+    password: Zeroizing<String>,
+}
+
+impl Credentials {
+    pub(crate) fn sasl_auth_string(&self, mechanism: SASLMechanism) -> Zeroizing<String> {
+        Zeroizing::new(match mechanism {
+            SASLMechanism::AMQPlain => self.amqplain_auth_string(),
+            SASLMechanism::External => String::default(),
+            SASLMechanism::Plain => format!("\0{}\0{}", *self.username, *self.password),
+            SASLMechanism::RabbitCrDemo => self.username.to_string(),
+        })
+    }
+
+    pub(crate) fn rabbit_cr_demo_answer(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!("My password is {}", *self.password))
+    }
+
+    /* The LOGIN and PASSWORD entries of a field table, without its length */
+    fn amqplain_auth_string(&self) -> String {
+        let mut buf = Zeroizing::new(Vec::with_capacity(
+            2 * (1 + 1 + 4) + 5 + 8 + self.username.len() + self.password.len(),
+        ));
+        for (key, value) in [("LOGIN", &self.username), ("PASSWORD", &self.password)] {
+            buf.push(key.len() as u8);
+            buf.extend_from_slice(key.as_bytes());
+            buf.push(b'S');
+            buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            buf.extend_from_slice(value.as_bytes());
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+impl From<AMQPUserInfo> for Credentials {
+    fn from(user_info: AMQPUserInfo) -> Self {
+        Self {
+            username: Zeroizing::new(user_info.username),
+            password: Zeroizing::new(user_info.password),
+        }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &*self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+/// A copy of the frame without the SASL response, if it carries one
+pub(crate) fn redact_frame(frame: &AMQPFrame) -> Option<AMQPFrame> {
+    let AMQPFrame::Method(channel_id, AMQPClass::Connection(method)) = frame else {
+        return None;
+    };
+    let method = match method {
+        connection::AMQPMethod::StartOk(start_ok) => {
+            connection::AMQPMethod::StartOk(connection::StartOk {
+                response: REDACTED.into(),
+                ..start_ok.clone()
+            })
+        }
+        connection::AMQPMethod::SecureOk(_) => {
+            connection::AMQPMethod::SecureOk(connection::SecureOk {
+                response: REDACTED.into(),
+            })
+        }
+        _ => return None,
+    };
+    Some(AMQPFrame::Method(
+        *channel_id,
+        AMQPClass::Connection(method),
+    ))
+}
+
+/// Displays a frame like its own Display implementation does, without the SASL response of
+/// the connection.start-ok and connection.secure-ok methods.
+pub(crate) struct RedactedFrame<'a>(pub(crate) &'a AMQPFrame);
+
+impl fmt::Display for RedactedFrame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match redact_frame(self.0) {
+            Some(frame) => frame.fmt(f),
+            None => self.0.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frames::OutgoingFrame, types::FieldTable};
+
+    /* Frames show their SASL response as a list of bytes */
+    fn leaks(text: &str) -> bool {
+        let bytes = format!("{:?}", b"s3cr3t");
+        text.contains("s3cr3t") || text.contains(bytes.trim_matches(['[', ']']))
+    }
+
+    fn credentials() -> Credentials {
+        AMQPUserInfo {
+            username: "user".into(),
+            password: "s3cr3t".into(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn auth_strings() {
+        let reference = crate::auth::Credentials::new("user".into(), "s3cr3t".into());
+        let credentials = credentials();
+        for mechanism in [
+            SASLMechanism::AMQPlain,
+            SASLMechanism::External,
+            SASLMechanism::Plain,
+            SASLMechanism::RabbitCrDemo,
+        ] {
+            assert_eq!(
+                *credentials.sasl_auth_string(mechanism),
+                reference.sasl_auth_string(mechanism)
+            );
+        }
+        assert_eq!(
+            *credentials.rabbit_cr_demo_answer(),
+            reference.rabbit_cr_demo_answer()
+        );
+        assert!(!leaks(&format!("{:?}", credentials)));
+    }
+
+    #[test]
+    fn redacted_frames() {
+        let credentials = credentials();
+        let start_ok = AMQPFrame::Method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::StartOk(connection::StartOk {
+                client_properties: FieldTable::default(),
+                mechanism: "PLAIN".into(),
+                response: credentials
+                    .sasl_auth_string(SASLMechanism::Plain)
+                    .as_str()
+                    .into(),
+                locale: "en_US".into(),
+            })),
+        );
+        let secure_ok = AMQPFrame::Method(
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::SecureOk(connection::SecureOk {
+                response: credentials.rabbit_cr_demo_answer().as_str().into(),
+            })),
+        );
+        for frame in [start_ok, secure_ok] {
+            assert!(leaks(&frame.to_string()));
+            assert!(!leaks(&RedactedFrame(&frame).to_string()));
+            assert!(!leaks(&OutgoingFrame::from(frame).to_string()));
+        }
+        let heartbeat = AMQPFrame::Heartbeat(0);
+        assert!(redact_frame(&heartbeat).is_none());
+        assert_eq!(RedactedFrame(&heartbeat).to_string(), heartbeat.to_string());
+    }
+}
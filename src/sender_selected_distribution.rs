@@ -0,0 +1,196 @@
+use crate::{
+    types::{AMQPValue, FieldArray, FieldTable, ShortString},
+    BasicProperties,
+};
+
+const CC_HEADER: &str = "CC";
+const BCC_HEADER: &str = "BCC";
+
+/// Additional routing keys for a published message, using RabbitMQ's
+/// [sender-selected distribution](https://www.rabbitmq.com/docs/sender-selected).
+///
+/// The message is routed as if it was also published with each of these routing keys. The
+/// `BCC` routing keys are removed from the message by the broker before it's delivered.
+///
+/// ```rust
+/// use lapin::{BasicProperties, SenderSelectedDistribution};
+///
+/// let properties = SenderSelectedDistribution::default()
+///     .with_cc(["audit.orders"])
+///     .with_bcc(["archive.orders"])
+///     .apply(BasicProperties::default());
+/// let cc = SenderSelectedDistribution::from_properties(&properties).cc;
+/// assert_eq!(cc, vec!["audit.orders".into()]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SenderSelectedDistribution {
+    pub cc: Vec<ShortString>,
+    pub bcc: Vec<ShortString>,
+}
+
+impl SenderSelectedDistribution {
+    #[must_use]
+    pub fn with_cc<K: Into<ShortString>>(
+        mut self,
+        routing_keys: impl IntoIterator<Item = K>,
+    ) -> Self {
+        self.cc.extend(routing_keys.into_iter().map(Into::into));
+        self
+    }
+
+    #[must_use]
+    pub fn with_bcc<K: Into<ShortString>>(
+        mut self,
+        routing_keys: impl IntoIterator<Item = K>,
+    ) -> Self {
+        self.bcc.extend(routing_keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Read the `CC` and `BCC` headers of a message
+    ///
+    /// Values which aren't strings are ignored.
+    pub fn from_properties(properties: &BasicProperties) -> Self {
+        let headers = properties.headers().as_ref();
+        Self {
+            cc: headers
+                .map(|headers| routing_keys(headers, CC_HEADER))
+                .unwrap_or_default(),
+            bcc: headers
+                .map(|headers| routing_keys(headers, BCC_HEADER))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Set the `CC` and `BCC` headers of a message, keeping its other headers
+    ///
+    /// Headers without any routing key are left untouched.
+    pub fn apply(&self, properties: BasicProperties) -> BasicProperties {
+        if self.cc.is_empty() && self.bcc.is_empty() {
+            return properties;
+        }
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        for (header, routing_keys) in [(CC_HEADER, &self.cc), (BCC_HEADER, &self.bcc)] {
+            if !routing_keys.is_empty() {
+                headers.insert(
+                    header.into(),
+                    AMQPValue::FieldArray(FieldArray::from(
+                        routing_keys
+                            .iter()
+                            .map(|key| AMQPValue::LongString(key.as_str().into()))
+                            .collect::<Vec<_>>(),
+                    )),
+                );
+            }
+        }
+        properties.with_headers(headers)
+    }
+}
+
+fn routing_keys(headers: &FieldTable, header: &str) -> Vec<ShortString> {
+    match headers.inner().get(header) {
+        Some(AMQPValue::FieldArray(values)) => values
+            .as_slice()
+            .iter()
+            .filter_map(|value| match value {
+                AMQPValue::LongString(key) => {
+                    std::str::from_utf8(key.as_bytes()).ok().map(Into::into)
+                }
+                AMQPValue::ShortString(key) => Some(key.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*,
+        testing::MockBroker,
+        types::{FieldTable, LongString},
+        ConnectionProperties,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn keeps_other_headers() {
+        let mut headers = FieldTable::default();
+        headers.insert("x-trace".into(), AMQPValue::LongString("abc".into()));
+        let properties = SenderSelectedDistribution::default()
+            .with_cc(["a", "b"])
+            .apply(BasicProperties::default().with_headers(headers));
+        let headers = properties.headers().as_ref().unwrap();
+        assert_eq!(
+            headers.inner().get("x-trace"),
+            Some(&AMQPValue::LongString(LongString::from("abc")))
+        );
+        assert!(!headers.contains_key(BCC_HEADER));
+        assert_eq!(
+            SenderSelectedDistribution::from_properties(&properties),
+            SenderSelectedDistribution {
+                cc: vec!["a".into(), "b".into()],
+                bcc: Vec::new(),
+            }
+        );
+        assert_eq!(
+            SenderSelectedDistribution::default().apply(BasicProperties::default()),
+            BasicProperties::default()
+        );
+    }
+
+    #[test]
+    fn broker_routes_copies() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            for queue in ["orders", "audit", "archive"] {
+                channel
+                    .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+                    .await?;
+            }
+            let properties = SenderSelectedDistribution::default()
+                .with_cc(["audit"])
+                .with_bcc(["archive"])
+                .apply(BasicProperties::default());
+            channel
+                .basic_publish(
+                    "",
+                    "orders",
+                    BasicPublishOptions::default(),
+                    b"order",
+                    properties,
+                )
+                .await?;
+            for queue in ["orders", "audit", "archive"] {
+                assert_eq!(broker.messages(queue), vec![b"order".to_vec()]);
+            }
+
+            // BCC is stripped before delivery
+            let mut consumer = channel
+                .basic_consume(
+                    "archive",
+                    "archiver",
+                    BasicConsumeOptions {
+                        no_ack: true,
+                        ..BasicConsumeOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+            let delivery = consumer.next().await.unwrap()?;
+            assert_eq!(delivery.routing_key.as_str(), "orders");
+            assert_eq!(
+                SenderSelectedDistribution::from_properties(&delivery.properties),
+                SenderSelectedDistribution::default().with_cc(["audit"])
+            );
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
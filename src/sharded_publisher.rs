@@ -0,0 +1,334 @@
+use crate::{
+    message::BasicReturnMessage, options::BasicPublishOptions, publisher_confirm::PublisherConfirm,
+    wakers::Wakers, BasicProperties, Channel, Connection, ConnectionProperties, Result,
+};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt, future,
+    future::Future,
+    hash::{Hash, Hasher},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{Context, Poll},
+};
+use tracing::debug;
+
+/// How a [`ShardedPublisher`] picks the shard used for a message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShardingStrategy {
+    /// Always use the same shard for a given routing key, preserving ordering per routing key.
+    #[default]
+    ConsistentHash,
+    /// Use each shard in turn.
+    RoundRobin,
+}
+
+/// Publisher spreading messages across several connections, each with its own channel in
+/// confirm mode, to go beyond the throughput of a single connection.
+///
+/// Shards whose channel or connection got closed are transparently reopened the next time
+/// they're used, once even when several publishes notice it concurrently.
+#[derive(Clone)]
+pub struct ShardedPublisher {
+    connect: Connect,
+    strategy: ShardingStrategy,
+    shards: Arc<[ShardSlot]>,
+    next: Arc<AtomicUsize>,
+}
+
+type Connect =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<Connection>> + Send>> + Send + Sync>;
+
+#[derive(Clone)]
+struct Shard {
+    connection: Arc<Connection>,
+    channel: Channel,
+}
+
+/* A shard along with a lock held while reopening it */
+struct ShardSlot {
+    inner: Mutex<SlotInner>,
+    wakers: Wakers,
+}
+
+struct SlotInner {
+    shard: Shard,
+    reopening: bool,
+}
+
+/* Release the lock of a shard slot, even if the reopening fails or gets cancelled */
+struct ReopenGuard<'a>(&'a ShardSlot);
+
+impl ShardedPublisher {
+    /// Open `shards` connections (at least one) to the given uri.
+    pub async fn connect(
+        uri: &str,
+        properties: ConnectionProperties,
+        shards: usize,
+        strategy: ShardingStrategy,
+    ) -> Result<Self> {
+        let uri = uri.to_owned();
+        let connect: Connect = Arc::new(move || {
+            let uri = uri.clone();
+            let properties = properties.clone();
+            Box::pin(async move { Connection::connect(&uri, properties).await })
+        });
+        let mut opened = Vec::with_capacity(shards.max(1));
+        for _ in 0..shards.max(1) {
+            opened.push(Shard::open(&connect).await?);
+        }
+        Ok(Self::new(connect, opened, strategy))
+    }
+
+    /// Use `connection` as the first shard, and open the `shards - 1` other ones (and the
+    /// replacements of the closed ones) with [`Connection::sibling`], to the same vhost.
+    pub async fn from_connection(
+        connection: Connection,
+        shards: usize,
+        strategy: ShardingStrategy,
+    ) -> Result<Self> {
+        let seed = Arc::new(connection);
+        let vhost = seed.status().vhost();
+        let first = Shard {
+            channel: Shard::open_channel(&seed).await?,
+            connection: seed.clone(),
+        };
+        let connect: Connect = Arc::new(move || {
+            let seed = seed.clone();
+            let vhost = vhost.clone();
+            Box::pin(async move { seed.sibling(&vhost).await })
+        });
+        let mut opened = Vec::with_capacity(shards.max(1));
+        opened.push(first);
+        for _ in 1..shards.max(1) {
+            opened.push(Shard::open(&connect).await?);
+        }
+        Ok(Self::new(connect, opened, strategy))
+    }
+
+    fn new(connect: Connect, shards: Vec<Shard>, strategy: ShardingStrategy) -> Self {
+        Self {
+            connect,
+            strategy,
+            shards: shards
+                .into_iter()
+                .map(|shard| ShardSlot {
+                    inner: Mutex::new(SlotInner {
+                        shard,
+                        reopening: false,
+                    }),
+                    wakers: Wakers::default(),
+                })
+                .collect(),
+            next: Arc::default(),
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn strategy(&self) -> ShardingStrategy {
+        self.strategy
+    }
+
+    /// Publish a message on the shard selected by the configured strategy.
+    pub async fn basic_publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<PublisherConfirm> {
+        self.channel(self.shard_for(routing_key))
+            .await?
+            .basic_publish(exchange, routing_key, options, payload, properties)
+            .await
+    }
+
+    /// Wait for the confirmations of all the messages published on every shard.
+    pub async fn wait_for_confirms(&self) -> Result<Vec<BasicReturnMessage>> {
+        let mut returned = Vec::new();
+        for shard in self.current_shards() {
+            returned.extend(shard.channel.wait_for_confirms().await?);
+        }
+        Ok(returned)
+    }
+
+    /// Close all the connections.
+    pub async fn close(&self, reply_code: u16, reply_text: &str) -> Result<()> {
+        for shard in self.current_shards() {
+            if shard.connection.status().connected() {
+                shard.connection.close(reply_code, reply_text).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn shard_for(&self, routing_key: &str) -> usize {
+        let shards = self.shards();
+        match self.strategy {
+            ShardingStrategy::ConsistentHash => {
+                let mut hasher = DefaultHasher::new();
+                routing_key.hash(&mut hasher);
+                (hasher.finish() % shards as u64) as usize
+            }
+            ShardingStrategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % shards,
+        }
+    }
+
+    async fn channel(&self, index: usize) -> Result<Channel> {
+        let slot = &self.shards[index];
+        let shard = match future::poll_fn(|cx| slot.poll_usable(cx)).await {
+            Ok(channel) => return Ok(channel),
+            Err(shard) => shard,
+        };
+        let _guard = ReopenGuard(slot);
+        let shard = if shard.connection.status().connected() {
+            debug!(shard = index, "reopening channel of publisher shard");
+            Shard {
+                channel: Shard::open_channel(&shard.connection).await?,
+                connection: shard.connection,
+            }
+        } else {
+            debug!(shard = index, "reconnecting publisher shard");
+            let replacement = Shard::open(&self.connect).await?;
+            if !shard.connection.status().closed() {
+                // Whatever state it got stuck in, don't leak it
+                let _ = shard.connection.close(0, "").await;
+            }
+            replacement
+        };
+        let channel = shard.channel.clone();
+        slot.lock_inner().shard = shard;
+        Ok(channel)
+    }
+
+    fn current_shards(&self) -> Vec<Shard> {
+        self.shards
+            .iter()
+            .map(|slot| slot.lock_inner().shard.clone())
+            .collect()
+    }
+}
+
+impl Shard {
+    async fn open(connect: &Connect) -> Result<Self> {
+        let connection = connect().await?;
+        let channel = Self::open_channel(&connection).await?;
+        Ok(Self {
+            connection: Arc::new(connection),
+            channel,
+        })
+    }
+
+    async fn open_channel(connection: &Connection) -> Result<Channel> {
+        let channel = connection.create_channel().await?;
+        channel.confirm_select(Default::default()).await?;
+        Ok(channel)
+    }
+
+    fn usable(&self) -> bool {
+        self.channel.status().connected() || self.channel.status().reconnecting()
+    }
+}
+
+impl ShardSlot {
+    /* The channel of the shard if usable, otherwise the shard to reopen, once nobody else is
+     * reopening it */
+    fn poll_usable(&self, cx: &mut Context<'_>) -> Poll<std::result::Result<Channel, Shard>> {
+        let mut inner = self.lock_inner();
+        if inner.reopening {
+            self.wakers.register(cx.waker());
+            return Poll::Pending;
+        }
+        if inner.shard.usable() {
+            return Poll::Ready(Ok(inner.shard.channel.clone()));
+        }
+        inner.reopening = true;
+        Poll::Ready(Err(inner.shard.clone()))
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, SlotInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for ReopenGuard<'_> {
+    fn drop(&mut self) {
+        self.0.lock_inner().reopening = false;
+        self.0.wakers.wake();
+    }
+}
+
+impl fmt::Debug for ShardedPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedPublisher")
+            .field("shards", &self.shards())
+            .field("strategy", &self.strategy)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::QueueDeclareOptions, testing::MockBroker, types::FieldTable};
+    use std::time::Duration;
+
+    #[test]
+    fn reopen_shards() {
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let publisher =
+                ShardedPublisher::from_connection(connection, 2, ShardingStrategy::RoundRobin)
+                    .await?;
+            assert_eq!(broker.connection_count(), 2);
+            let channel = publisher.channel(0).await?;
+            channel
+                .queue_declare(
+                    "events",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            // A closed channel gets reopened on the same connection
+            channel.close(0, "").await?;
+            assert!(publisher.channel(0).await?.status().connected());
+            assert_eq!(broker.connection_count(), 2);
+
+            // A closed connection gets replaced, once even when noticed concurrently
+            let replaced = publisher.current_shards().remove(1);
+            replaced.connection.close(0, "").await?;
+            while broker.connection_count() != 1 {
+                channel.sleep(Duration::from_millis(5)).await;
+            }
+            let (first, second) =
+                futures_lite::future::zip(publisher.channel(1), publisher.channel(1)).await;
+            assert_eq!(first?.id(), second?.id());
+            assert_eq!(broker.connection_count(), 2);
+
+            for _ in 0..2 {
+                publisher
+                    .basic_publish(
+                        "",
+                        "events",
+                        BasicPublishOptions::default(),
+                        b"event",
+                        BasicProperties::default(),
+                    )
+                    .await?;
+            }
+            assert!(publisher.wait_for_confirms().await?.is_empty());
+            assert_eq!(broker.message_count("events"), Some(2));
+            publisher.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+use crate::{
+    consumer::Consumer,
+    message::Delivery,
+    options::{
+        BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicNackOptions,
+        BasicPublishOptions, BasicQosOptions, ConfirmSelectOptions, QueueDeclareOptions,
+    },
+    publisher_confirm::PublisherConfirm,
+    types::{FieldTable, ShortUInt},
+    Channel, Result,
+};
+use futures_core::stream::Stream;
+use std::{collections::VecDeque, future, pin::Pin};
+use tracing::debug;
+
+/// How [`shovel`] moves messages.
+///
+/// [`shovel`]: ./fn.shovel.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShovelOptions {
+    /// How many messages can be republished without having been confirmed yet, at least one
+    pub max_in_flight: usize,
+    /// Move at most this many messages
+    pub max_messages: Option<usize>,
+    pub publish_options: BasicPublishOptions,
+}
+
+impl Default for ShovelOptions {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 100,
+            max_messages: None,
+            publish_options: BasicPublishOptions::default(),
+        }
+    }
+}
+
+impl ShovelOptions {
+    #[must_use]
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = Some(max_messages);
+        self
+    }
+
+    #[must_use]
+    pub fn with_publish_options(mut self, publish_options: BasicPublishOptions) -> Self {
+        self.publish_options = publish_options;
+        self
+    }
+}
+
+/// What [`shovel`] did.
+///
+/// [`shovel`]: ./fn.shovel.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShovelReport {
+    /// The messages republished and removed from the source queue
+    pub moved: usize,
+    /// The messages the broker refused to republish, left in the source queue
+    pub refused: usize,
+}
+
+/// Move the messages of `from_queue` to `to_exchange`, without needing the broker's shovel
+/// plugin.
+///
+/// Each message is republished through `destination`, which is put in confirm mode, and only
+/// acked on `source` once the broker confirmed it, with at most `max_in_flight` messages
+/// waiting for their confirm. The two channels can be the same one, or belong to connections
+/// to different brokers for a migration.
+///
+/// Only the messages which are in the queue when starting get moved, so that this ends even if
+/// messages keep coming in. They keep their properties, and their routing key unless
+/// `routing_key` is set.
+///
+/// The prefetch count of `source` is set to `max_in_flight` for the consumer used to read the
+/// queue. If an error occurs, the messages which weren't acked yet stay in the source queue
+/// once `source` gets closed.
+///
+/// ```rust,no_run
+/// use lapin::{
+///     shovel::{shovel, ShovelOptions},
+///     Connection, ConnectionProperties,
+/// };
+///
+/// # async_global_executor::block_on(async {
+/// let uri = "amqp://127.0.0.1:5672/%2f";
+/// let connection = Connection::connect(uri, ConnectionProperties::default()).await?;
+/// let source = connection.create_channel().await?;
+/// let destination = connection.create_channel().await?;
+/// let report = shovel(
+///     &source,
+///     "jobs.dlq",
+///     &destination,
+///     "",
+///     Some("jobs"),
+///     ShovelOptions::default().with_max_in_flight(50),
+/// )
+/// .await?;
+/// println!("moved {} messages", report.moved);
+/// # Ok::<(), lapin::Error>(())
+/// # });
+/// ```
+pub async fn shovel(
+    source: &Channel,
+    from_queue: &str,
+    destination: &Channel,
+    to_exchange: &str,
+    routing_key: Option<&str>,
+    options: ShovelOptions,
+) -> Result<ShovelReport> {
+    let max_in_flight = options.max_in_flight.max(1);
+    let available = source
+        .queue_declare(
+            from_queue,
+            QueueDeclareOptions {
+                passive: true,
+                ..QueueDeclareOptions::default()
+            },
+            FieldTable::default(),
+        )
+        .await?
+        .message_count();
+    let available = usize::try_from(available).unwrap_or(usize::MAX);
+    let total = options
+        .max_messages
+        .map_or(available, |max_messages| max_messages.min(available));
+    let mut report = ShovelReport::default();
+    if total == 0 {
+        return Ok(report);
+    }
+    if !destination.status().confirm() {
+        destination
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
+    }
+    source
+        .basic_qos(
+            ShortUInt::try_from(max_in_flight).unwrap_or(ShortUInt::MAX),
+            BasicQosOptions::default(),
+        )
+        .await?;
+    let mut consumer = source
+        .basic_consume(
+            from_queue,
+            "",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    let mut in_flight = VecDeque::with_capacity(max_in_flight);
+    for _ in 0..total {
+        let Some(delivery) = next_delivery(&mut consumer).await else {
+            break;
+        };
+        let delivery = delivery?;
+        let confirm = destination
+            .basic_publish(
+                to_exchange,
+                routing_key.unwrap_or(delivery.routing_key.as_str()),
+                options.publish_options,
+                &delivery.data,
+                delivery.properties.clone(),
+            )
+            .await?;
+        in_flight.push_back((delivery, confirm));
+        if in_flight.len() >= max_in_flight {
+            if let Some((delivery, confirm)) = in_flight.pop_front() {
+                settle(delivery, confirm, &mut report).await?;
+            }
+        }
+    }
+    for (delivery, confirm) in in_flight {
+        settle(delivery, confirm, &mut report).await?;
+    }
+    source
+        .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+        .await?;
+    // Give back the messages received after the ones we wanted to move
+    while let Some(delivery) = next_delivery(&mut consumer).await {
+        requeue(&delivery?).await?;
+    }
+    debug!(
+        from_queue,
+        to_exchange,
+        moved = report.moved,
+        refused = report.refused,
+        "shovel done"
+    );
+    Ok(report)
+}
+
+async fn next_delivery(consumer: &mut Consumer) -> Option<Result<Delivery>> {
+    future::poll_fn(|cx| Pin::new(&mut *consumer).poll_next(cx)).await
+}
+
+async fn settle(
+    delivery: Delivery,
+    confirm: PublisherConfirm,
+    report: &mut ShovelReport,
+) -> Result<()> {
+    if confirm.await?.is_nack() {
+        report.refused += 1;
+        requeue(&delivery).await?;
+    } else {
+        report.moved += 1;
+        delivery.ack(BasicAckOptions::default()).await?;
+    }
+    Ok(())
+}
+
+async fn requeue(delivery: &Delivery) -> Result<()> {
+    delivery
+        .nack(BasicNackOptions {
+            requeue: true,
+            ..BasicNackOptions::default()
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::*, testing::MockBroker, BasicProperties, ConnectionProperties};
+
+    #[test]
+    fn move_messages() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let source = connection.create_channel().await?;
+            let destination = connection.create_channel().await?;
+            for queue in ["jobs.dlq", "jobs"] {
+                source
+                    .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+                    .await?;
+            }
+            for index in 0..5 {
+                source
+                    .basic_publish(
+                        "",
+                        "jobs.dlq",
+                        BasicPublishOptions::default(),
+                        format!("job {}", index).as_bytes(),
+                        BasicProperties::default().with_app_id("shop".into()),
+                    )
+                    .await?;
+            }
+
+            let report = shovel(
+                &source,
+                "jobs.dlq",
+                &destination,
+                "",
+                Some("jobs"),
+                ShovelOptions::default()
+                    .with_max_in_flight(2)
+                    .with_max_messages(4),
+            )
+            .await?;
+            assert_eq!(
+                report,
+                ShovelReport {
+                    moved: 4,
+                    refused: 0
+                }
+            );
+            for index in 0..4 {
+                let message = source
+                    .basic_get("jobs", BasicGetOptions::default())
+                    .await?
+                    .unwrap();
+                assert_eq!(message.delivery.data, format!("job {}", index).as_bytes());
+                assert_eq!(message.delivery.properties.app_id(), &Some("shop".into()));
+            }
+            assert!(source
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .is_none());
+            let left = source
+                .basic_get("jobs.dlq", BasicGetOptions::default())
+                .await?
+                .unwrap();
+            assert_eq!(&left.delivery.data[..], b"job 4");
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,78 @@
+use crate::wakers::Wakers;
+
+use std::{
+    fmt,
+    future::{self, Future},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+/// A signal telling the consumers of a service to stop, shared between all of them
+///
+/// Once triggered, it stays triggered. See [`Consumer::next_or_shutdown`].
+///
+/// ```rust
+/// use lapin::ShutdownSignal;
+///
+/// let shutdown = ShutdownSignal::default();
+/// let worker = shutdown.clone();
+/// shutdown.trigger();
+/// assert!(worker.is_triggered());
+/// ```
+///
+/// [`Consumer::next_or_shutdown`]: ./struct.Consumer.html#method.next_or_shutdown
+#[derive(Clone, Default)]
+pub struct ShutdownSignal {
+    triggered: Arc<AtomicBool>,
+    wakers: Wakers,
+}
+
+impl ShutdownSignal {
+    /// Ask everyone waiting on this signal to stop
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+        self.wakers.wake();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Trigger this signal once `signal` resolves, e.g. on SIGTERM
+    pub fn trigger_on<F: Future<Output = ()>>(&self, signal: F) -> impl Future<Output = ()> {
+        let this = self.clone();
+        async move {
+            signal.await;
+            this.trigger();
+        }
+    }
+
+    /// Wait for this signal to be triggered
+    pub async fn triggered(&self) {
+        future::poll_fn(|cx| self.poll_triggered(cx)).await
+    }
+
+    pub(crate) fn poll_triggered(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_triggered() {
+            return Poll::Ready(());
+        }
+        self.wakers.register(cx.waker());
+        // Triggered while we were registering
+        if self.is_triggered() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl fmt::Debug for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownSignal")
+            .field("triggered", &self.is_triggered())
+            .finish()
+    }
+}
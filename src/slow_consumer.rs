@@ -0,0 +1,244 @@
+use crate::types::{ChannelId, DeliveryTag, ShortString, ShortUInt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// A consumer which has had as many unacked messages as its prefetch count allows for too long
+///
+/// The broker doesn't send it any more messages until it acks some, so it's the bottleneck of
+/// its queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowConsumer {
+    pub channel_id: ChannelId,
+    pub consumer_tag: ShortString,
+    pub queue: ShortString,
+    pub prefetch_count: ShortUInt,
+    /// How long the consumer has been at its prefetch count
+    pub saturated_for: Duration,
+}
+
+pub(crate) type SlowConsumerHandler = Arc<Mutex<Option<Box<dyn FnMut(SlowConsumer) + Send>>>>;
+
+/// The deliveries of a channel which haven't been acked yet, per consumer
+#[derive(Clone, Default)]
+pub(crate) struct UnackedDeliveries(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    tags: BTreeMap<DeliveryTag, ShortString>,
+    consumers: HashMap<ShortString, Unacked>,
+}
+
+struct Unacked {
+    count: usize,
+    prefetch_count: ShortUInt,
+    saturated_since: Option<Instant>,
+    reported: bool,
+}
+
+impl UnackedDeliveries {
+    pub(crate) fn delivered(
+        &self,
+        delivery_tag: DeliveryTag,
+        consumer_tag: ShortString,
+        prefetch_count: ShortUInt,
+    ) {
+        let mut inner = self.lock_inner();
+        inner.tags.insert(delivery_tag, consumer_tag.clone());
+        let unacked = inner.consumers.entry(consumer_tag).or_insert(Unacked {
+            count: 0,
+            prefetch_count,
+            saturated_since: None,
+            reported: false,
+        });
+        unacked.count += 1;
+        unacked.prefetch_count = prefetch_count;
+        if unacked.count >= usize::from(prefetch_count) {
+            unacked.saturated_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Forget about the deliveries which got acked, nacked or rejected
+    ///
+    /// With `multiple`, this covers all the deliveries up to `delivery_tag`, or all of them if
+    /// it is 0.
+    pub(crate) fn acked(&self, delivery_tag: DeliveryTag, multiple: bool) {
+        let mut inner = self.lock_inner();
+        let consumer_tags = if multiple && delivery_tag == 0 {
+            std::mem::take(&mut inner.tags).into_values().collect()
+        } else if multiple {
+            let remaining = inner.tags.split_off(&(delivery_tag + 1));
+            std::mem::replace(&mut inner.tags, remaining)
+                .into_values()
+                .collect()
+        } else {
+            inner
+                .tags
+                .remove(&delivery_tag)
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        for consumer_tag in consumer_tags {
+            if let Some(unacked) = inner.consumers.get_mut(&consumer_tag) {
+                unacked.count -= 1;
+                if unacked.count < usize::from(unacked.prefetch_count) {
+                    unacked.saturated_since = None;
+                    unacked.reported = false;
+                }
+                if unacked.count == 0 {
+                    inner.consumers.remove(&consumer_tag);
+                }
+            }
+        }
+    }
+
+    /// Forget about all the deliveries, as their tags are no longer valid
+    pub(crate) fn clear(&self) {
+        let mut inner = self.lock_inner();
+        inner.tags.clear();
+        inner.consumers.clear();
+    }
+
+    #[cfg(test)]
+    fn count(&self, consumer_tag: &str) -> usize {
+        self.lock_inner()
+            .consumers
+            .get(consumer_tag)
+            .map_or(0, |unacked| unacked.count)
+    }
+
+    /// The consumers which have been saturated for longer than `threshold`, with their
+    /// prefetch count and for how long, each of them only being reported once until it acks
+    /// some messages
+    pub(crate) fn report_saturated(
+        &self,
+        threshold: Duration,
+    ) -> Vec<(ShortString, ShortUInt, Duration)> {
+        self.lock_inner()
+            .consumers
+            .iter_mut()
+            .filter_map(|(consumer_tag, unacked)| {
+                let saturated_for = unacked.saturated_since?.elapsed();
+                if unacked.reported || saturated_for < threshold {
+                    return None;
+                }
+                unacked.reported = true;
+                Some((consumer_tag.clone(), unacked.prefetch_count, saturated_for))
+            })
+            .collect()
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for UnackedDeliveries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("UnackedDeliveries");
+        if let Ok(inner) = self.0.try_lock() {
+            debug.field("count", &inner.tags.len());
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties,
+    };
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn saturation() {
+        let unacked = UnackedDeliveries::default();
+        for tag in 1..=3 {
+            unacked.delivered(tag, "slow".into(), 2);
+        }
+        unacked.delivered(4, "fast".into(), 2);
+        assert_eq!(unacked.count("slow"), 3);
+        let report = unacked.report_saturated(Duration::ZERO);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].0.as_str(), "slow");
+        assert_eq!(report[0].1, 2);
+        // Only reported once
+        assert!(unacked.report_saturated(Duration::ZERO).is_empty());
+        assert!(unacked.report_saturated(Duration::from_secs(60)).is_empty());
+
+        unacked.acked(2, true);
+        assert_eq!(unacked.count("slow"), 1);
+        unacked.delivered(5, "slow".into(), 2);
+        assert_eq!(unacked.report_saturated(Duration::ZERO).len(), 1);
+
+        unacked.acked(4, false);
+        assert_eq!(unacked.count("fast"), 0);
+        unacked.acked(0, true);
+        assert_eq!(unacked.count("slow"), 0);
+    }
+
+    #[test]
+    fn detection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker
+                .connect(
+                    ConnectionProperties::default()
+                        .with_slow_consumer_detection(Duration::from_millis(20)),
+                )
+                .await?;
+            let reported = Arc::new(Mutex::new(Vec::new()));
+            let handler_reported = reported.clone();
+            connection.on_slow_consumer(move |consumer| {
+                handler_reported.lock().unwrap().push(consumer);
+            });
+            let channel = connection.create_channel().await?;
+            channel.basic_qos(1, BasicQosOptions::default()).await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut consumer = channel
+                .basic_consume(
+                    "jobs",
+                    "worker",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default(),
+                )
+                .await?;
+            let delivery = consumer.next().await.unwrap()?;
+            std::thread::sleep(Duration::from_millis(100));
+            {
+                let reported = reported.lock().unwrap();
+                assert_eq!(reported.len(), 1);
+                assert_eq!(reported[0].consumer_tag.as_str(), "worker");
+                assert_eq!(reported[0].queue.as_str(), "jobs");
+                assert_eq!(reported[0].prefetch_count, 1);
+            }
+
+            delivery.ack(BasicAckOptions::default()).await?;
+            std::thread::sleep(Duration::from_millis(100));
+            assert_eq!(reported.lock().unwrap().len(), 1);
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
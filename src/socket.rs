@@ -0,0 +1,147 @@
+//! The sockets of the clients speaking the other protocols of the broker
+//!
+//! Like the AMQP connection, they connect through `AMQPUriTcpExt`, so that `amqps` URIs and the
+//! `OwnedTLSConfig` apply. The ones keeping their connection open then register it on the
+//! reactor of the `ConnectionProperties`, their frames being read and written by tasks spawned
+//! on its executor.
+
+use crate::{
+    tcp::{AMQPUriTcpExt, OwnedTLSConfig, TcpStream},
+    uri::AMQPUri,
+};
+use std::io;
+#[cfg(any(feature = "amqp1", feature = "streams"))]
+use {
+    crate::reactor::FullReactor,
+    reactor_trait::{AsyncIOHandle, IOHandle},
+    std::{
+        future::{poll_fn, Future},
+        pin::{pin, Pin},
+        sync::{Arc, Mutex, MutexGuard},
+        task::{Poll, Waker},
+        time::Duration,
+    },
+};
+
+/* Connect to the host and port of the URI, blocking until the TLS handshake, if any, is over */
+pub(crate) fn connect(uri: &AMQPUri, tls_config: &OwnedTLSConfig) -> io::Result<TcpStream> {
+    let mut res = uri.connect_with_config(tls_config.as_ref());
+    loop {
+        match res {
+            Ok(stream) => {
+                // The stream is left in non blocking mode, for a reactor
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(mid) => {
+                let mid = mid.into_mid_handshake_tls_stream()?;
+                // Wait for the broker rather than retrying the handshake in a loop
+                mid.get_ref().set_nonblocking(false)?;
+                res = mid.handshake();
+            }
+        }
+    }
+}
+
+/* A socket registered on the reactor, shared by the tasks reading and writing it. It's closed
+ * once all of them are dropped. */
+#[cfg(any(feature = "amqp1", feature = "streams"))]
+#[derive(Clone)]
+pub(crate) struct Socket(Arc<Mutex<Inner>>);
+
+#[cfg(any(feature = "amqp1", feature = "streams"))]
+struct Inner {
+    handle: Pin<Box<dyn AsyncIOHandle + Send>>,
+    closed: bool,
+    /* Woken up once the socket is closed, so that a pending read fails */
+    reader: Option<Waker>,
+}
+
+#[cfg(any(feature = "amqp1", feature = "streams"))]
+impl Socket {
+    pub(crate) fn register(stream: TcpStream, reactor: &dyn FullReactor) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        let handle = reactor.register(IOHandle::new(stream))?;
+        Ok(Self(Arc::new(Mutex::new(Inner {
+            handle: handle.into(),
+            closed: false,
+            reader: None,
+        }))))
+    }
+
+    pub(crate) async fn read_exact(&self, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let read = poll_fn(|cx| {
+                let mut inner = self.lock();
+                if inner.closed {
+                    return Poll::Ready(Err(closed()));
+                }
+                inner.reader = Some(cx.waker().clone());
+                inner.handle.as_mut().poll_read(cx, buf)
+            })
+            .await?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            buf = &mut buf[read..];
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let written = poll_fn(|cx| {
+                let mut inner = self.lock();
+                if inner.closed {
+                    return Poll::Ready(Err(closed()));
+                }
+                inner.handle.as_mut().poll_write(cx, buf)
+            })
+            .await?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            buf = &buf[written..];
+        }
+        poll_fn(|cx| self.lock().handle.as_mut().poll_flush(cx)).await
+    }
+
+    /* Fail the pending and future reads and writes */
+    pub(crate) fn close(&self) {
+        let mut inner = self.lock();
+        inner.closed = true;
+        if let Some(reader) = inner.reader.take() {
+            reader.wake();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(any(feature = "amqp1", feature = "streams"))]
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "the socket is closed")
+}
+
+/* The output of the future, or None if it didn't complete within the timeout */
+#[cfg(any(feature = "amqp1", feature = "streams"))]
+pub(crate) async fn timeout<F: Future>(
+    reactor: &(dyn FullReactor + Send + Sync),
+    timeout: Option<Duration>,
+    future: F,
+) -> Option<F::Output> {
+    let Some(timeout) = timeout else {
+        return Some(future.await);
+    };
+    let mut future = pin!(future);
+    let mut sleep = reactor.sleep(timeout);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        sleep.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
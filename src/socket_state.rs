@@ -1,6 +1,10 @@
 use crate::Result;
 use flume::{Receiver, Sender};
-use std::task::Poll;
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
 use tracing::trace;
 
 pub(crate) struct SocketState {
@@ -17,7 +21,10 @@ impl Default for SocketState {
             readable: true,
             writable: true,
             events: receiver,
-            handle: SocketStateHandle { sender },
+            handle: SocketStateHandle {
+                sender,
+                task: Arc::default(),
+            },
         }
     }
 }
@@ -25,6 +32,8 @@ impl Default for SocketState {
 #[derive(Clone)]
 pub struct SocketStateHandle {
     sender: Sender<SocketEvent>,
+    /* Task driving the io loop when it doesn't run in its own thread */
+    task: Arc<Mutex<Option<Waker>>>,
 }
 
 #[derive(Debug)]
@@ -47,6 +56,23 @@ impl SocketState {
         self.handle_event(self.events.recv().expect("waiting for socket event failed"))
     }
 
+    pub(crate) fn wait_timeout(&mut self, timeout: Duration) {
+        if let Ok(event) = self.events.recv_timeout(timeout) {
+            self.handle_event(event);
+        }
+    }
+
+    pub(crate) fn poll_wait(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        *self.handle.task.lock().unwrap_or_else(|e| e.into_inner()) = Some(cx.waker().clone());
+        match self.events.try_recv() {
+            Ok(event) => {
+                self.handle_event(event);
+                Poll::Ready(())
+            }
+            Err(_) => Poll::Pending,
+        }
+    }
+
     pub(crate) fn handle(&self) -> SocketStateHandle {
         self.handle.clone()
     }
@@ -103,9 +129,36 @@ impl SocketState {
 impl SocketStateHandle {
     pub fn send(&self, event: SocketEvent) {
         let _ = self.sender.send(event);
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.wake();
+        }
     }
 
     pub fn wake(&self) {
         self.send(SocketEvent::Wake);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn poll_wait_wakes_task() {
+        let mut socket_state = SocketState::default();
+        let woken = Arc::new(AtomicBool::new(false));
+        let waker = {
+            let woken = woken.clone();
+            waker_fn::waker_fn(move || woken.store(true, Ordering::SeqCst))
+        };
+        let mut cx = Context::from_waker(&waker);
+        socket_state.handle_write_poll::<()>(Poll::Pending);
+        assert!(socket_state.poll_wait(&mut cx).is_pending());
+        assert!(!woken.load(Ordering::SeqCst));
+        socket_state.handle().send(SocketEvent::Writable);
+        assert!(woken.load(Ordering::SeqCst));
+        assert!(socket_state.poll_wait(&mut cx).is_ready());
+        assert!(socket_state.writable());
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{
+    protocol::constants::REPLY_SUCCESS, topology::TopologyDefinition, types::ReplyCode, Backoff,
+    Connection, ConnectionProperties, Result,
+};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tracing::{debug, warn};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type Connector = Box<dyn Fn(ConnectionProperties) -> BoxFuture<Result<Connection>> + Send + Sync>;
+type Factory = Box<dyn Fn(Arc<Connection>) -> BoxFuture<Result<()>> + Send + Sync>;
+
+/// Keeps a connection alive for the whole life of a service.
+///
+/// The supervisor connects, restores the given topology and runs the registered factories,
+/// which are meant to create the channels, consumers and publishers of the application. Whenever
+/// the connection is terminally lost, it starts over with a new connection. Failed attempts are
+/// retried according to the [`Backoff`] policy, until the maximum number of consecutive failed
+/// restarts is reached.
+///
+/// ```rust,no_run
+/// use lapin::{options::*, supervisor::Supervisor, types::FieldTable, ConnectionProperties};
+///
+/// # async_global_executor::block_on(async {
+/// let supervisor = Supervisor::new("amqp://127.0.0.1:5672/%2f", ConnectionProperties::default())
+///     .with_max_restarts(10)
+///     .on_connect(|connection| async move {
+///         let channel = connection.create_channel().await?;
+///         channel
+///             .queue_declare("jobs", QueueDeclareOptions::default(), FieldTable::default())
+///             .await?;
+///         // spawn consumers...
+///         Ok(())
+///     });
+/// supervisor.run().await
+/// # });
+/// ```
+pub struct Supervisor {
+    properties: ConnectionProperties,
+    connector: Connector,
+    topology: Option<TopologyDefinition>,
+    factories: Vec<Factory>,
+    backoff: Backoff,
+    max_restarts: Option<u32>,
+    handle: SupervisorHandle,
+}
+
+impl Supervisor {
+    /// Supervise connections to the given uri.
+    pub fn new(uri: &str, properties: ConnectionProperties) -> Self {
+        let uri = uri.to_owned();
+        Self::with_connector(properties, move |properties| {
+            let uri = uri.clone();
+            async move { Connection::connect(&uri, properties).await }
+        })
+    }
+
+    /// Supervise connections established by the given connector, which gets passed the
+    /// connection properties.
+    pub fn with_connector<
+        C: Fn(ConnectionProperties) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<Connection>> + Send + 'static,
+    >(
+        properties: ConnectionProperties,
+        connector: C,
+    ) -> Self {
+        Self {
+            properties,
+            connector: Box::new(move |properties| Box::pin(connector(properties))),
+            topology: None,
+            factories: Vec::new(),
+            backoff: Backoff::default(),
+            max_restarts: None,
+            handle: SupervisorHandle::default(),
+        }
+    }
+
+    /// Restore this topology on each new connection, before running the factories.
+    #[must_use]
+    pub fn with_topology(mut self, topology: TopologyDefinition) -> Self {
+        self.topology = Some(topology);
+        self
+    }
+
+    #[must_use]
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Give up after this many consecutive failed attempts at building the connection.
+    #[must_use]
+    pub fn with_max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = Some(max_restarts);
+        self
+    }
+
+    /// Register a factory, called on each new connection, in registration order.
+    ///
+    /// An error makes the supervisor drop the connection and start over.
+    #[must_use]
+    pub fn on_connect<
+        C: Fn(Arc<Connection>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
+    >(
+        mut self,
+        factory: C,
+    ) -> Self {
+        self.factories
+            .push(Box::new(move |connection| Box::pin(factory(connection))));
+        self
+    }
+
+    /// Get a handle to access the current connection or to stop the supervisor.
+    pub fn handle(&self) -> SupervisorHandle {
+        self.handle.clone()
+    }
+
+    /// Run the supervision loop.
+    ///
+    /// This resolves once the supervisor has been shut down or the connection has been closed
+    /// gracefully, or with the last error once the maximum number of restarts is reached.
+    pub async fn run(self) -> Result<()> {
+        let reactor = self.properties.clone().take_reactor()?;
+        let mut failures = 0;
+        loop {
+            if self.handle.stopped() {
+                return Ok(());
+            }
+            match self.start().await {
+                Ok(connection) => {
+                    failures = 0;
+                    if self.handle.set_connection(Some(connection.clone())) {
+                        // Shut down while we were setting up the connection
+                        self.handle.set_connection(None);
+                        return connection.close(REPLY_SUCCESS, "shutdown").await;
+                    }
+                    std::future::poll_fn(|cx| connection.status().poll_finished(cx)).await;
+                    self.handle.set_connection(None);
+                    if self.handle.stopped() || connection.status().closed() {
+                        debug!("Connection closed, stopping supervisor");
+                        return Ok(());
+                    }
+                    warn!("Connection lost, restarting");
+                    self.handle.lock_inner().restarts += 1;
+                }
+                Err(err) => {
+                    failures += 1;
+                    if self.max_restarts.is_some_and(|max| failures > max) {
+                        return Err(err);
+                    }
+                    let delay = self.backoff.delay(failures - 1);
+                    warn!(?err, ?delay, "Failed to set up connection, retrying");
+                    reactor.sleep(delay).await;
+                    self.handle.lock_inner().restarts += 1;
+                }
+            }
+        }
+    }
+
+    async fn start(&self) -> Result<Arc<Connection>> {
+        let connection = Arc::new((self.connector)(self.properties.clone()).await?);
+        if let Err(err) = self.setup(&connection).await {
+            if connection.status().connected() {
+                let _ = connection
+                    .close(REPLY_SUCCESS, "supervisor setup failed")
+                    .await;
+            }
+            return Err(err);
+        }
+        Ok(connection)
+    }
+
+    async fn setup(&self, connection: &Arc<Connection>) -> Result<()> {
+        if let Some(topology) = self.topology.clone() {
+            connection.restore(topology).await?;
+        }
+        for factory in &self.factories {
+            factory(connection.clone()).await?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("backoff", &self.backoff)
+            .field("max_restarts", &self.max_restarts)
+            .field("factories", &self.factories.len())
+            .finish()
+    }
+}
+
+/// A handle to a running [`Supervisor`]
+#[derive(Clone, Default)]
+pub struct SupervisorHandle(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    connection: Option<Arc<Connection>>,
+    stopped: bool,
+    restarts: u64,
+}
+
+impl SupervisorHandle {
+    /// The current connection, if it is established and set up.
+    pub fn connection(&self) -> Option<Arc<Connection>> {
+        self.lock_inner().connection.clone()
+    }
+
+    /// How many times the connection has been restarted.
+    pub fn restarts(&self) -> u64 {
+        self.lock_inner().restarts
+    }
+
+    /// Stop the supervisor, closing the current connection.
+    pub async fn shutdown(&self, reply_code: ReplyCode, reply_text: &str) -> Result<()> {
+        let connection = {
+            let mut inner = self.lock_inner();
+            inner.stopped = true;
+            inner.connection.clone()
+        };
+        match connection {
+            Some(connection) if connection.status().connected() => {
+                connection.close(reply_code, reply_text).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn stopped(&self) -> bool {
+        self.lock_inner().stopped
+    }
+
+    /// Returns whether the supervisor has been stopped
+    fn set_connection(&self, connection: Option<Arc<Connection>>) -> bool {
+        let mut inner = self.lock_inner();
+        inner.connection = connection;
+        inner.stopped
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Inner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for SupervisorHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock_inner();
+        f.debug_struct("SupervisorHandle")
+            .field("connected", &inner.connection.is_some())
+            .field("stopped", &inner.stopped)
+            .field("restarts", &inner.restarts)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        options::QueueDeclareOptions,
+        testing::{FaultInjector, FaultyStream, MockBroker},
+        types::FieldTable,
+        ErrorKind,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn restart_lost_connection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let broker = MockBroker::default();
+        let injector = Arc::new(Mutex::new(None::<FaultInjector>));
+        let (sender, receiver) = flume::unbounded();
+        let supervisor = Supervisor::with_connector(ConnectionProperties::default(), {
+            let broker = broker.clone();
+            let injector = injector.clone();
+            move |properties| {
+                let stream = FaultyStream::new(broker.stream());
+                *injector.lock().unwrap() = Some(stream.injector());
+                Connection::connector_with_stream(
+                    "amqp://127.0.0.1:5672/%2f".parse().unwrap(),
+                    stream,
+                    properties,
+                )
+            }
+        })
+        .with_backoff(Backoff::constant(Duration::from_millis(1)))
+        .on_connect(move |connection| {
+            let sender = sender.clone();
+            async move {
+                let channel = connection.create_channel().await?;
+                channel
+                    .queue_declare(
+                        "jobs",
+                        QueueDeclareOptions::default(),
+                        FieldTable::default(),
+                    )
+                    .await?;
+                let _ = sender.send(());
+                Ok(())
+            }
+        });
+        let handle = supervisor.handle();
+
+        async_global_executor::block_on(async {
+            let run = async_global_executor::spawn(supervisor.run());
+            receiver.recv_async().await.unwrap();
+            assert_eq!(handle.restarts(), 0);
+            injector.lock().unwrap().take().unwrap().disconnect();
+            receiver.recv_async().await.unwrap();
+            assert_eq!(handle.restarts(), 1);
+            assert!(broker.queue_exists("jobs"));
+            handle.shutdown(200, "OK").await.unwrap();
+            run.await.unwrap();
+        });
+    }
+
+    #[test]
+    fn give_up_after_max_restarts() {
+        let supervisor = Supervisor::with_connector(ConnectionProperties::default(), |_| async {
+            Err(ErrorKind::InvalidConnectionState(crate::ConnectionState::Error).into())
+        })
+        .with_backoff(Backoff::constant(Duration::from_millis(1)))
+        .with_max_restarts(2);
+        let handle = supervisor.handle();
+        assert!(async_global_executor::block_on(supervisor.run()).is_err());
+        assert_eq!(handle.restarts(), 2);
+    }
+}
@@ -0,0 +1,1699 @@
+//! An in-memory AMQP broker to test code using lapin without a RabbitMQ server
+//!
+//! [`MockBroker`] implements enough of AMQP 0.9.1 for unit tests: exchanges (direct, fanout,
+//! topic and headers) and queues with their bindings, publishing (with mandatory returns,
+//! publisher confirms and sender-selected distribution), consuming (exclusively or not),
+//! basic.get, acks, nacks and rejects. Connections to it go through an in-memory stream instead
+//! of a TCP socket. Like
+//! RabbitMQ, it closes the connection with `UNEXPECTED_FRAME` when the content frames of a
+//! publish get interleaved with other frames of its channel.
+//!
+//! [`RecordingStream`] records all the frames of a session, against a real broker or not, to a
+//! file which [`ReplayStream`] can then play back to write regression tests for tricky protocol
+//! sequences.
+//!
+//! [`FaultyStream`] injects faults in a live connection (dropped socket, held back or duplicated
+//! frames, corrupted heartbeats, channel errors) to check the recovery and retry logic of an
+//! application deterministically.
+//!
+//! [`DeterministicExecutor`] and [`ManualClock`] run the connection tasks in a reproducible order
+//! and control time, to test promise resolution order, timeouts and heartbeats without sleeping.
+//!
+//! ```rust,no_run
+//! use futures_lite::StreamExt;
+//! use lapin::{options::*, testing::MockBroker, types::FieldTable, BasicProperties, ConnectionProperties};
+//!
+//! # async_global_executor::block_on(async {
+//! let broker = MockBroker::default();
+//! let connection = broker.connect(ConnectionProperties::default()).await?;
+//! let channel = connection.create_channel().await?;
+//! channel.queue_declare("hello", QueueDeclareOptions::default(), FieldTable::default()).await?;
+//! channel.basic_publish("", "hello", BasicPublishOptions::default(), b"Hello world!", BasicProperties::default()).await?;
+//! assert_eq!(broker.message_count("hello"), Some(1));
+//! # Ok::<(), lapin::Error>(())
+//! # });
+//! ```
+
+pub use crate::{
+    deterministic::{DeterministicExecutor, ManualClock},
+    fault_injection::{FaultInjector, FaultyStream, FrameKind},
+    recording::{RecordingStream, ReplayStream},
+};
+
+use crate::{
+    protocol::{basic, channel, confirm, connection, exchange, queue, AMQPClass},
+    recording::frame_size,
+    secrets::RedactedFrame,
+    types::{AMQPValue, ChannelId, FieldTable, LongLongUInt},
+    uri::AMQPUri,
+    BasicProperties, Capabilities, Connection, ConnectionProperties, Result,
+    SenderSelectedDistribution,
+};
+use amq_protocol::{
+    frame::{gen_frame, parse_frame, AMQPContentHeader, AMQPFrame, WriteContext},
+    protocol::{constants::FRAME_METHOD, AMQPHardError, AMQPSoftError},
+};
+use futures_io::{AsyncRead, AsyncWrite};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+use tracing::{error, trace};
+
+const FRAME_MAX: u32 = 131072;
+const CHANNEL_MAX: u16 = 2047;
+
+/// An in-memory AMQP broker
+#[derive(Clone, Default)]
+pub struct MockBroker(Arc<Mutex<Broker>>);
+
+impl MockBroker {
+    /// Connect to this broker
+    pub async fn connect(&self, options: ConnectionProperties) -> Result<Connection> {
+        self.connect_uri(
+            "amqp://127.0.0.1:5672/%2f".parse().expect("valid uri"),
+            options,
+        )
+        .await
+    }
+
+    /* Like connecting over TCP, so that the connection can have siblings */
+    fn connect_uri(
+        &self,
+        uri: AMQPUri,
+        options: ConnectionProperties,
+    ) -> Pin<Box<dyn Future<Output = Result<Connection>> + Send>> {
+        let broker = self.clone();
+        Box::pin(async move {
+            let mut connection =
+                Connection::connector_with_stream(uri.clone(), broker.stream(), options.clone())
+                    .await?;
+            connection.set_sibling(
+                uri,
+                options,
+                Arc::new(move |uri, options| broker.connect_uri(uri, options)),
+            );
+            Ok(connection)
+        })
+    }
+
+    /// Advertise these capabilities during the handshake instead of the default ones, to
+    /// mimic another broker
+    #[must_use]
+    pub fn with_capabilities(self, capabilities: Capabilities) -> Self {
+        self.lock_inner().capabilities = capabilities;
+        self
+    }
+
+    /// Stop reading what the clients send, like a broker whose TCP receive window is full, so
+    /// that their frames pile up in lapin until [`resume`] gets called
+    ///
+    /// [`resume`]: #method.resume
+    pub fn pause(&self) {
+        self.lock_inner().paused = true;
+    }
+
+    /// Read what the clients send again after [`pause`]
+    ///
+    /// [`pause`]: #method.pause
+    pub fn resume(&self) {
+        let writers = {
+            let mut inner = self.lock_inner();
+            inner.paused = false;
+            std::mem::take(&mut inner.paused_writers)
+        };
+        for writer in writers {
+            writer.wake();
+        }
+    }
+
+    /// Open a new in-memory stream to this broker, to be used with
+    /// [`Connection::connector_with_stream`].
+    ///
+    /// [`Connection::connector_with_stream`]: ../struct.Connection.html#method.connector_with_stream
+    pub fn stream(&self) -> MockStream {
+        let pipe = Arc::new(Pipe::default());
+        let id = self.lock_inner().register(pipe.clone());
+        MockStream {
+            broker: self.clone(),
+            id,
+            pipe,
+        }
+    }
+
+    /// Number of connections currently open to this broker
+    pub fn connection_count(&self) -> usize {
+        self.lock_inner().connections.len()
+    }
+
+    pub fn exchange_exists(&self, exchange: &str) -> bool {
+        self.lock_inner().exchanges.contains_key(exchange)
+    }
+
+    pub fn queue_exists(&self, queue: &str) -> bool {
+        self.lock_inner().queues.contains_key(queue)
+    }
+
+    /// Number of messages ready to be delivered in the given queue
+    pub fn message_count(&self, queue: &str) -> Option<usize> {
+        self.lock_inner()
+            .queues
+            .get(queue)
+            .map(|queue| queue.messages.len())
+    }
+
+    pub fn consumer_count(&self, queue: &str) -> Option<usize> {
+        self.lock_inner()
+            .queues
+            .get(queue)
+            .map(|queue| queue.consumers.len())
+    }
+
+    /// Payloads of the messages ready to be delivered in the given queue
+    pub fn messages(&self, queue: &str) -> Vec<Vec<u8>> {
+        self.lock_inner()
+            .queues
+            .get(queue)
+            .map(|queue| {
+                queue
+                    .messages
+                    .iter()
+                    .map(|message| message.payload.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, Broker> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for MockBroker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockBroker").finish()
+    }
+}
+
+/// An in-memory stream connected to a [`MockBroker`]
+pub struct MockStream {
+    broker: MockBroker,
+    id: u64,
+    pipe: Arc<Pipe>,
+}
+
+impl AsyncRead for MockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.pipe.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MockStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.pipe.closed() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let mut broker = self.broker.lock_inner();
+        if broker.paused {
+            broker.paused_writers.push(cx.waker().clone());
+            return Poll::Pending;
+        }
+        broker.receive(self.id, buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.broker.lock_inner().disconnect(self.id);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MockStream {
+    fn drop(&mut self) {
+        self.broker.lock_inner().disconnect(self.id);
+    }
+}
+
+impl fmt::Debug for MockStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockStream").field("id", &self.id).finish()
+    }
+}
+
+/* Data sent by the broker, waiting to be read by the client */
+#[derive(Default)]
+struct Pipe(Mutex<PipeInner>);
+
+#[derive(Default)]
+struct PipeInner {
+    data: VecDeque<u8>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Pipe {
+    fn write(&self, bytes: &[u8]) {
+        let waker = {
+            let mut inner = self.lock_inner();
+            inner.data.extend(bytes);
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn close(&self) {
+        let waker = {
+            let mut inner = self.lock_inner();
+            inner.closed = true;
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn closed(&self) -> bool {
+        self.lock_inner().closed
+    }
+
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut inner = self.lock_inner();
+        if inner.data.is_empty() {
+            if inner.closed {
+                return Poll::Ready(Ok(0));
+            }
+            inner.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let len = buf.len().min(inner.data.len());
+        for (dst, src) in buf.iter_mut().zip(inner.data.drain(..len)) {
+            *dst = src;
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn lock_inner(&self) -> MutexGuard<'_, PipeInner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct Broker {
+    next_id: u64,
+    connections: HashMap<u64, MockConnection>,
+    exchanges: HashMap<String, Exchange>,
+    queues: HashMap<String, Queue>,
+    capabilities: Capabilities,
+    paused: bool,
+    paused_writers: Vec<Waker>,
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        let exchanges = [
+            ("", "direct"),
+            ("amq.direct", "direct"),
+            ("amq.fanout", "fanout"),
+            ("amq.topic", "topic"),
+            ("amq.headers", "headers"),
+            ("amq.match", "headers"),
+        ]
+        .into_iter()
+        .map(|(name, kind)| {
+            (
+                name.to_owned(),
+                Exchange {
+                    kind: kind.to_owned(),
+                    bindings: Vec::new(),
+                },
+            )
+        })
+        .collect();
+        Self {
+            next_id: 0,
+            connections: HashMap::default(),
+            exchanges,
+            queues: HashMap::default(),
+            paused: false,
+            paused_writers: Vec::new(),
+            capabilities: [
+                "publisher_confirms",
+                "exchange_exchange_bindings",
+                "basic.nack",
+                "consumer_cancel_notify",
+            ]
+            .into_iter()
+            .fold(Capabilities::none(), |capabilities, capability| {
+                capabilities.with(capability, true)
+            }),
+        }
+    }
+}
+
+struct MockConnection {
+    pipe: Arc<Pipe>,
+    input: Vec<u8>,
+    frame_max: u32,
+    channels: HashMap<ChannelId, MockChannel>,
+    /* Waiting for connection.close-ok after a connection error, ignoring anything else */
+    closing: bool,
+}
+
+#[derive(Default)]
+struct MockChannel {
+    closing: bool,
+    confirm: bool,
+    published: LongLongUInt,
+    delivery_tag: LongLongUInt,
+    prefetch: u16,
+    unacked: BTreeMap<LongLongUInt, (String, Message)>,
+    publish: Option<Publish>,
+}
+
+struct Publish {
+    exchange: String,
+    routing_key: String,
+    mandatory: bool,
+    properties: BasicProperties,
+    header_received: bool,
+    body_size: u64,
+    payload: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct Message {
+    exchange: String,
+    routing_key: String,
+    properties: BasicProperties,
+    payload: Vec<u8>,
+    redelivered: bool,
+}
+
+struct Exchange {
+    kind: String,
+    bindings: Vec<Binding>,
+}
+
+struct Binding {
+    destination: Destination,
+    routing_key: String,
+    arguments: FieldTable,
+}
+
+#[derive(PartialEq)]
+enum Destination {
+    Queue(String),
+    Exchange(String),
+}
+
+#[derive(Default)]
+struct Queue {
+    messages: VecDeque<Message>,
+    consumers: Vec<Consumer>,
+    next_consumer: usize,
+}
+
+struct Consumer {
+    connection: u64,
+    channel: ChannelId,
+    tag: String,
+    no_ack: bool,
+    exclusive: bool,
+}
+
+impl MockConnection {
+    fn next_frame(&mut self) -> Option<Option<AMQPFrame>> {
+        let size = frame_size(&self.input)?;
+        let frame = match parse_frame(&self.input[..size]) {
+            Ok((_, frame)) => Some(frame),
+            Err(_) if self.input[0] == FRAME_METHOD => {
+                // Act as a broker plugin echoing the methods it provides
+                trace!("mock broker echoing unknown method");
+                self.pipe.write(&self.input[..size]);
+                None
+            }
+            Err(err) => {
+                error!(?err, "mock broker failed to parse frame");
+                None
+            }
+        };
+        self.input.drain(..size);
+        Some(frame)
+    }
+
+    fn send(&self, frame: AMQPFrame) {
+        trace!(%frame, "mock broker sending frame");
+        match gen_frame(&frame)(WriteContext::from(Vec::new())) {
+            Ok(ctx) => self.pipe.write(&ctx.write),
+            Err(err) => error!(?err, "mock broker failed to serialize frame"),
+        }
+    }
+
+    fn send_method(&self, channel: ChannelId, method: AMQPClass) {
+        self.send(AMQPFrame::Method(channel, method));
+    }
+
+    fn send_content(&self, channel: ChannelId, method: AMQPClass, message: &Message) {
+        self.send_method(channel, method);
+        self.send(AMQPFrame::Header(
+            channel,
+            60,
+            Box::new(AMQPContentHeader {
+                class_id: 60,
+                body_size: message.payload.len() as u64,
+                properties: message.properties.clone(),
+            }),
+        ));
+        for chunk in message.payload.chunks(self.frame_max as usize - 8) {
+            self.send(AMQPFrame::Body(channel, chunk.to_vec()));
+        }
+    }
+}
+
+impl Broker {
+    fn register(&mut self, pipe: Arc<Pipe>) -> u64 {
+        self.next_id += 1;
+        self.connections.insert(
+            self.next_id,
+            MockConnection {
+                pipe,
+                input: Vec::new(),
+                frame_max: FRAME_MAX,
+                channels: HashMap::default(),
+                closing: false,
+            },
+        );
+        self.next_id
+    }
+
+    fn disconnect(&mut self, id: u64) {
+        let channels = self
+            .connections
+            .get(&id)
+            .map(|connection| connection.channels.keys().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for channel in channels {
+            self.close_channel(id, channel);
+        }
+        if let Some(connection) = self.connections.remove(&id) {
+            connection.pipe.close();
+        }
+    }
+
+    fn generate_name(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}-{}", prefix, self.next_id)
+    }
+
+    fn receive(&mut self, id: u64, data: &[u8]) {
+        if let Some(connection) = self.connections.get_mut(&id) {
+            connection.input.extend_from_slice(data);
+        }
+        while let Some(frame) = self
+            .connections
+            .get_mut(&id)
+            .and_then(MockConnection::next_frame)
+        {
+            if let Some(frame) = frame {
+                trace!(frame=%RedactedFrame(&frame), "mock broker received frame");
+                self.handle_frame(id, frame);
+            }
+        }
+    }
+
+    fn send_method(&self, id: u64, channel: ChannelId, method: AMQPClass) {
+        if let Some(connection) = self.connections.get(&id) {
+            connection.send_method(channel, method);
+        }
+    }
+
+    fn handle_frame(&mut self, id: u64, frame: AMQPFrame) {
+        if self
+            .connections
+            .get(&id)
+            .is_some_and(|connection| connection.closing)
+        {
+            if let AMQPFrame::Method(0, AMQPClass::Connection(connection::AMQPMethod::CloseOk(_))) =
+                frame
+            {
+                self.disconnect(id);
+            }
+            return;
+        }
+        if !self.check_content_sequence(id, &frame) {
+            return;
+        }
+        match frame {
+            AMQPFrame::ProtocolHeader(_) => {
+                let mut server_properties = FieldTable::default();
+                server_properties.insert(
+                    "capabilities".into(),
+                    AMQPValue::FieldTable(self.capabilities.field_table()),
+                );
+                self.send_method(
+                    id,
+                    0,
+                    AMQPClass::Connection(connection::AMQPMethod::Start(connection::Start {
+                        version_major: 0,
+                        version_minor: 9,
+                        server_properties,
+                        mechanisms: "PLAIN AMQPLAIN".into(),
+                        locales: "en_US".into(),
+                    })),
+                );
+            }
+            AMQPFrame::Method(0, AMQPClass::Connection(method)) => {
+                self.handle_connection_method(id, method)
+            }
+            AMQPFrame::Method(channel, method) => self.handle_channel_method(id, channel, method),
+            AMQPFrame::Header(channel, _, header) => {
+                if let Some(publish) = self.publish(id, channel) {
+                    publish.header_received = true;
+                    publish.body_size = header.body_size;
+                    publish.properties = header.properties;
+                    if publish.body_size == 0 {
+                        self.complete_publish(id, channel);
+                    }
+                }
+            }
+            AMQPFrame::Body(channel, payload) => {
+                if let Some(publish) = self.publish(id, channel) {
+                    publish.payload.extend(payload);
+                    if publish.payload.len() as u64 >= publish.body_size {
+                        self.complete_publish(id, channel);
+                    }
+                }
+            }
+            AMQPFrame::Heartbeat(_) => {}
+        }
+    }
+
+    fn handle_connection_method(&mut self, id: u64, method: connection::AMQPMethod) {
+        use connection::AMQPMethod;
+
+        let reply = match method {
+            AMQPMethod::StartOk(_) => Some(AMQPMethod::Tune(connection::Tune {
+                channel_max: CHANNEL_MAX,
+                frame_max: FRAME_MAX,
+                heartbeat: 0,
+            })),
+            AMQPMethod::TuneOk(tune_ok) => {
+                if let Some(connection) = self.connections.get_mut(&id) {
+                    if tune_ok.frame_max != 0 {
+                        connection.frame_max = tune_ok.frame_max.min(FRAME_MAX);
+                    }
+                }
+                None
+            }
+            AMQPMethod::Open(_) => Some(AMQPMethod::OpenOk(connection::OpenOk {})),
+            AMQPMethod::Close(_) => {
+                let channels = self
+                    .connections
+                    .get(&id)
+                    .map(|connection| connection.channels.keys().copied().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                for channel in channels {
+                    self.close_channel(id, channel);
+                }
+                Some(AMQPMethod::CloseOk(connection::CloseOk {}))
+            }
+            AMQPMethod::UpdateSecret(_) => {
+                Some(AMQPMethod::UpdateSecretOk(connection::UpdateSecretOk {}))
+            }
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            self.send_method(id, 0, AMQPClass::Connection(reply));
+        }
+    }
+
+    /* A publish is made of its method, header and body frames, with nothing in between on its channel */
+    fn check_content_sequence(&mut self, id: u64, frame: &AMQPFrame) -> bool {
+        let channel_id = match frame {
+            AMQPFrame::Method(channel_id, _)
+            | AMQPFrame::Header(channel_id, _, _)
+            | AMQPFrame::Body(channel_id, _) => *channel_id,
+            AMQPFrame::ProtocolHeader(_) | AMQPFrame::Heartbeat(_) => return true,
+        };
+        // Frames for a closing channel get discarded until its close-ok
+        let Some(channel) = self
+            .channel(id, channel_id)
+            .filter(|channel| !channel.closing)
+        else {
+            return true;
+        };
+        let expected = match (channel.publish.as_ref(), frame) {
+            (None, AMQPFrame::Method(..)) => return true,
+            (Some(publish), AMQPFrame::Header(..)) if !publish.header_received => return true,
+            (Some(publish), AMQPFrame::Body(..)) if publish.header_received => return true,
+            (None, _) => "a method",
+            (Some(publish), _) if publish.header_received => "a content body",
+            (Some(_), _) => "a content header",
+        };
+        self.connection_error(
+            id,
+            AMQPHardError::UNEXPECTEDFRAME.get_id(),
+            format!(
+                "UNEXPECTED_FRAME - expected {} on channel {}, got {}",
+                expected,
+                channel_id,
+                RedactedFrame(frame)
+            ),
+        );
+        false
+    }
+
+    fn connection_error(&mut self, id: u64, reply_code: u16, reply_text: String) {
+        trace!(reply_code, %reply_text, "mock broker closing connection");
+        let channels = self
+            .connections
+            .get(&id)
+            .map(|connection| connection.channels.keys().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for channel in channels {
+            self.close_channel(id, channel);
+        }
+        if let Some(connection) = self.connections.get_mut(&id) {
+            connection.channels.clear();
+            connection.closing = true;
+        }
+        self.send_method(
+            id,
+            0,
+            AMQPClass::Connection(connection::AMQPMethod::Close(connection::Close {
+                reply_code,
+                reply_text: reply_text.into(),
+                class_id: 0,
+                method_id: 0,
+            })),
+        );
+    }
+
+    fn channel(&mut self, id: u64, channel: ChannelId) -> Option<&mut MockChannel> {
+        self.connections.get_mut(&id)?.channels.get_mut(&channel)
+    }
+
+    fn publish(&mut self, id: u64, channel: ChannelId) -> Option<&mut Publish> {
+        self.channel(id, channel)?.publish.as_mut()
+    }
+
+    fn handle_channel_method(&mut self, id: u64, channel_id: ChannelId, method: AMQPClass) {
+        if let AMQPClass::Channel(channel::AMQPMethod::Open(_)) = method {
+            if let Some(connection) = self.connections.get_mut(&id) {
+                connection
+                    .channels
+                    .insert(channel_id, MockChannel::default());
+            }
+            self.send_method(
+                id,
+                channel_id,
+                AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {})),
+            );
+            return;
+        }
+        let Some(channel) = self.channel(id, channel_id) else {
+            return;
+        };
+        if channel.closing {
+            // Only wait for the client to acknowledge the close
+            if let AMQPClass::Channel(channel::AMQPMethod::CloseOk(_)) = method {
+                if let Some(connection) = self.connections.get_mut(&id) {
+                    connection.channels.remove(&channel_id);
+                }
+            }
+            return;
+        }
+        if let Err((code, text)) = self.handle_method(id, channel_id, &method) {
+            self.channel_error(id, channel_id, code, text, &method);
+        }
+    }
+
+    fn handle_method(
+        &mut self,
+        id: u64,
+        channel_id: ChannelId,
+        method: &AMQPClass,
+    ) -> std::result::Result<(), (u16, String)> {
+        let reply = match method {
+            AMQPClass::Channel(channel::AMQPMethod::Close(_)) => {
+                self.close_channel(id, channel_id);
+                if let Some(connection) = self.connections.get_mut(&id) {
+                    connection.channels.remove(&channel_id);
+                }
+                Some(AMQPClass::Channel(channel::AMQPMethod::CloseOk(
+                    channel::CloseOk {},
+                )))
+            }
+            AMQPClass::Channel(channel::AMQPMethod::Flow(flow)) => Some(AMQPClass::Channel(
+                channel::AMQPMethod::FlowOk(channel::FlowOk {
+                    active: flow.active,
+                }),
+            )),
+            AMQPClass::Exchange(method) => self
+                .handle_exchange_method(method)?
+                .map(AMQPClass::Exchange),
+            AMQPClass::Queue(method) => self.handle_queue_method(method)?.map(AMQPClass::Queue),
+            AMQPClass::Basic(method) => self
+                .handle_basic_method(id, channel_id, method)?
+                .map(AMQPClass::Basic),
+            AMQPClass::Confirm(confirm::AMQPMethod::Select(select)) => {
+                if let Some(channel) = self.channel(id, channel_id) {
+                    channel.confirm = true;
+                }
+                (!select.nowait).then_some(AMQPClass::Confirm(confirm::AMQPMethod::SelectOk(
+                    confirm::SelectOk {},
+                )))
+            }
+            AMQPClass::Tx(_) => {
+                return Err((
+                    AMQPHardError::NOTIMPLEMENTED.get_id(),
+                    "NOT_IMPLEMENTED - transactions are not supported by the mock broker".into(),
+                ));
+            }
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            self.send_method(id, channel_id, reply);
+        }
+        Ok(())
+    }
+
+    fn handle_exchange_method(
+        &mut self,
+        method: &exchange::AMQPMethod,
+    ) -> std::result::Result<Option<exchange::AMQPMethod>, (u16, String)> {
+        use exchange::AMQPMethod;
+
+        Ok(match method {
+            AMQPMethod::Declare(declare) => {
+                let name = declare.exchange.to_string();
+                let kind = declare.kind.to_string();
+                match self.exchanges.get(&name) {
+                    Some(exchange) if !declare.passive && exchange.kind != kind => {
+                        return Err((
+                            AMQPSoftError::PRECONDITIONFAILED.get_id(),
+                            format!(
+                                "PRECONDITION_FAILED - inequivalent arg 'type' for exchange '{}'",
+                                name
+                            ),
+                        ));
+                    }
+                    Some(_) => {}
+                    None if declare.passive => return Err(not_found("exchange", &name)),
+                    None => {
+                        if name.starts_with("amq.") {
+                            return Err((
+                                AMQPSoftError::ACCESSREFUSED.get_id(),
+                                format!("ACCESS_REFUSED - exchange name '{}' contains reserved prefix 'amq.*'", name),
+                            ));
+                        }
+                        self.exchanges.insert(
+                            name,
+                            Exchange {
+                                kind,
+                                bindings: Vec::new(),
+                            },
+                        );
+                    }
+                }
+                (!declare.nowait).then_some(AMQPMethod::DeclareOk(exchange::DeclareOk {}))
+            }
+            AMQPMethod::Delete(delete) => {
+                let name = delete.exchange.to_string();
+                self.exchanges.remove(&name);
+                let destination = Destination::Exchange(name);
+                for exchange in self.exchanges.values_mut() {
+                    exchange
+                        .bindings
+                        .retain(|binding| binding.destination != destination);
+                }
+                (!delete.nowait).then_some(AMQPMethod::DeleteOk(exchange::DeleteOk {}))
+            }
+            AMQPMethod::Bind(bind) => {
+                let destination = bind.destination.to_string();
+                if !self.exchanges.contains_key(&destination) {
+                    return Err(not_found("exchange", &destination));
+                }
+                self.bind(
+                    bind.source.as_str(),
+                    Destination::Exchange(destination),
+                    bind.routing_key.as_str(),
+                    &bind.arguments,
+                )?;
+                (!bind.nowait).then_some(AMQPMethod::BindOk(exchange::BindOk {}))
+            }
+            AMQPMethod::Unbind(unbind) => {
+                self.unbind(
+                    unbind.source.as_str(),
+                    Destination::Exchange(unbind.destination.to_string()),
+                    unbind.routing_key.as_str(),
+                );
+                (!unbind.nowait).then_some(AMQPMethod::UnbindOk(exchange::UnbindOk {}))
+            }
+            _ => None,
+        })
+    }
+
+    fn handle_queue_method(
+        &mut self,
+        method: &queue::AMQPMethod,
+    ) -> std::result::Result<Option<queue::AMQPMethod>, (u16, String)> {
+        use queue::AMQPMethod;
+
+        Ok(match method {
+            AMQPMethod::Declare(declare) => {
+                let name = if declare.queue.as_str().is_empty() {
+                    self.generate_name("amq.gen")
+                } else {
+                    declare.queue.to_string()
+                };
+                if !self.queues.contains_key(&name) {
+                    if declare.passive {
+                        return Err(not_found("queue", &name));
+                    }
+                    self.queues.insert(name.clone(), Queue::default());
+                }
+                let queue = &self.queues[&name];
+                (!declare.nowait).then(|| {
+                    AMQPMethod::DeclareOk(queue::DeclareOk {
+                        queue: name.as_str().into(),
+                        message_count: queue.messages.len() as u32,
+                        consumer_count: queue.consumers.len() as u32,
+                    })
+                })
+            }
+            AMQPMethod::Bind(bind) => {
+                let name = bind.queue.to_string();
+                if !self.queues.contains_key(&name) {
+                    return Err(not_found("queue", &name));
+                }
+                self.bind(
+                    bind.exchange.as_str(),
+                    Destination::Queue(name),
+                    bind.routing_key.as_str(),
+                    &bind.arguments,
+                )?;
+                (!bind.nowait).then_some(AMQPMethod::BindOk(queue::BindOk {}))
+            }
+            AMQPMethod::Unbind(unbind) => {
+                self.unbind(
+                    unbind.exchange.as_str(),
+                    Destination::Queue(unbind.queue.to_string()),
+                    unbind.routing_key.as_str(),
+                );
+                Some(AMQPMethod::UnbindOk(queue::UnbindOk {}))
+            }
+            AMQPMethod::Purge(purge) => {
+                let name = purge.queue.to_string();
+                let queue = self
+                    .queues
+                    .get_mut(&name)
+                    .ok_or_else(|| not_found("queue", &name))?;
+                let message_count = queue.messages.len() as u32;
+                queue.messages.clear();
+                (!purge.nowait).then_some(AMQPMethod::PurgeOk(queue::PurgeOk { message_count }))
+            }
+            AMQPMethod::Delete(delete) => {
+                let name = delete.queue.to_string();
+                let message_count = match self.queues.remove(&name) {
+                    Some(queue) => {
+                        for consumer in queue.consumers {
+                            self.send_method(
+                                consumer.connection,
+                                consumer.channel,
+                                AMQPClass::Basic(basic::AMQPMethod::Cancel(basic::Cancel {
+                                    consumer_tag: consumer.tag.into(),
+                                    nowait: true,
+                                })),
+                            );
+                        }
+                        queue.messages.len() as u32
+                    }
+                    None => 0,
+                };
+                let destination = Destination::Queue(name);
+                for exchange in self.exchanges.values_mut() {
+                    exchange
+                        .bindings
+                        .retain(|binding| binding.destination != destination);
+                }
+                (!delete.nowait).then_some(AMQPMethod::DeleteOk(queue::DeleteOk { message_count }))
+            }
+            _ => None,
+        })
+    }
+
+    fn handle_basic_method(
+        &mut self,
+        id: u64,
+        channel_id: ChannelId,
+        method: &basic::AMQPMethod,
+    ) -> std::result::Result<Option<basic::AMQPMethod>, (u16, String)> {
+        use basic::AMQPMethod;
+
+        Ok(match method {
+            AMQPMethod::Qos(qos) => {
+                if let Some(channel) = self.channel(id, channel_id) {
+                    channel.prefetch = qos.prefetch_count;
+                }
+                self.send_method(
+                    id,
+                    channel_id,
+                    AMQPClass::Basic(AMQPMethod::QosOk(basic::QosOk {})),
+                );
+                self.dispatch_all();
+                None
+            }
+            AMQPMethod::Consume(consume) => {
+                let name = consume.queue.to_string();
+                if !self.queues.contains_key(&name) {
+                    return Err(not_found("queue", &name));
+                }
+                let tag = if consume.consumer_tag.as_str().is_empty() {
+                    self.generate_name("amq.ctag")
+                } else {
+                    consume.consumer_tag.to_string()
+                };
+                if let Some(queue) = self.queues.get_mut(&name) {
+                    if queue.consumers.iter().any(|consumer| consumer.exclusive)
+                        || (consume.exclusive && !queue.consumers.is_empty())
+                    {
+                        return Err((
+                            AMQPSoftError::ACCESSREFUSED.get_id(),
+                            format!(
+                                "ACCESS_REFUSED - queue '{}' in vhost '/' in exclusive use",
+                                name
+                            ),
+                        ));
+                    }
+                    queue.consumers.push(Consumer {
+                        connection: id,
+                        channel: channel_id,
+                        tag: tag.clone(),
+                        no_ack: consume.no_ack,
+                        exclusive: consume.exclusive,
+                    });
+                }
+                if !consume.nowait {
+                    self.send_method(
+                        id,
+                        channel_id,
+                        AMQPClass::Basic(AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                            consumer_tag: tag.into(),
+                        })),
+                    );
+                }
+                self.dispatch(&name);
+                None
+            }
+            AMQPMethod::Cancel(cancel) => {
+                for queue in self.queues.values_mut() {
+                    queue.consumers.retain(|consumer| {
+                        consumer.connection != id
+                            || consumer.channel != channel_id
+                            || consumer.tag != cancel.consumer_tag.as_str()
+                    });
+                }
+                (!cancel.nowait).then(|| {
+                    AMQPMethod::CancelOk(basic::CancelOk {
+                        consumer_tag: cancel.consumer_tag.clone(),
+                    })
+                })
+            }
+            AMQPMethod::Publish(publish) => {
+                if let Some(channel) = self.channel(id, channel_id) {
+                    channel.publish = Some(Publish {
+                        exchange: publish.exchange.to_string(),
+                        routing_key: publish.routing_key.to_string(),
+                        mandatory: publish.mandatory,
+                        properties: BasicProperties::default(),
+                        header_received: false,
+                        body_size: 0,
+                        payload: Vec::new(),
+                    });
+                }
+                None
+            }
+            AMQPMethod::Get(get) => {
+                let name = get.queue.to_string();
+                let queue = self
+                    .queues
+                    .get_mut(&name)
+                    .ok_or_else(|| not_found("queue", &name))?;
+                let Some(message) = queue.messages.pop_front() else {
+                    return Ok(Some(AMQPMethod::GetEmpty(basic::GetEmpty {})));
+                };
+                let message_count = queue.messages.len() as u32;
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return Ok(None);
+                };
+                let Some(channel) = connection.channels.get_mut(&channel_id) else {
+                    return Ok(None);
+                };
+                channel.delivery_tag += 1;
+                let delivery_tag = channel.delivery_tag;
+                if !get.no_ack {
+                    channel
+                        .unacked
+                        .insert(delivery_tag, (name, message.clone()));
+                }
+                connection.send_content(
+                    channel_id,
+                    AMQPClass::Basic(AMQPMethod::GetOk(basic::GetOk {
+                        delivery_tag,
+                        redelivered: message.redelivered,
+                        exchange: message.exchange.as_str().into(),
+                        routing_key: message.routing_key.as_str().into(),
+                        message_count,
+                    })),
+                    &message,
+                );
+                None
+            }
+            AMQPMethod::Ack(ack) => {
+                self.settle(id, channel_id, ack.delivery_tag, ack.multiple, false)?;
+                None
+            }
+            AMQPMethod::Nack(nack) => {
+                self.settle(
+                    id,
+                    channel_id,
+                    nack.delivery_tag,
+                    nack.multiple,
+                    nack.requeue,
+                )?;
+                None
+            }
+            AMQPMethod::Reject(reject) => {
+                self.settle(id, channel_id, reject.delivery_tag, false, reject.requeue)?;
+                None
+            }
+            AMQPMethod::RecoverAsync(_) => {
+                self.requeue_unacked(id, channel_id);
+                None
+            }
+            AMQPMethod::Recover(_) => {
+                self.requeue_unacked(id, channel_id);
+                Some(AMQPMethod::RecoverOk(basic::RecoverOk {}))
+            }
+            _ => None,
+        })
+    }
+
+    fn bind(
+        &mut self,
+        source: &str,
+        destination: Destination,
+        routing_key: &str,
+        arguments: &FieldTable,
+    ) -> std::result::Result<(), (u16, String)> {
+        if source.is_empty() {
+            return Err((
+                AMQPSoftError::ACCESSREFUSED.get_id(),
+                "ACCESS_REFUSED - operation not permitted on the default exchange".into(),
+            ));
+        }
+        let exchange = self
+            .exchanges
+            .get_mut(source)
+            .ok_or_else(|| not_found("exchange", source))?;
+        if !exchange
+            .bindings
+            .iter()
+            .any(|binding| binding.destination == destination && binding.routing_key == routing_key)
+        {
+            exchange.bindings.push(Binding {
+                destination,
+                routing_key: routing_key.to_owned(),
+                arguments: arguments.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn unbind(&mut self, source: &str, destination: Destination, routing_key: &str) {
+        if let Some(exchange) = self.exchanges.get_mut(source) {
+            exchange.bindings.retain(|binding| {
+                binding.destination != destination || binding.routing_key != routing_key
+            });
+        }
+    }
+
+    fn complete_publish(&mut self, id: u64, channel_id: ChannelId) {
+        let Some(channel) = self.channel(id, channel_id) else {
+            return;
+        };
+        let Some(publish) = channel.publish.take() else {
+            return;
+        };
+        if !self.exchanges.contains_key(&publish.exchange) {
+            let error = not_found("exchange", &publish.exchange);
+            self.channel_error(
+                id,
+                channel_id,
+                error.0,
+                error.1,
+                &AMQPClass::Basic(basic::AMQPMethod::Publish(basic::Publish {
+                    exchange: publish.exchange.as_str().into(),
+                    routing_key: publish.routing_key.as_str().into(),
+                    mandatory: publish.mandatory,
+                    immediate: false,
+                })),
+            );
+            return;
+        }
+        let mut message = Message {
+            exchange: publish.exchange,
+            routing_key: publish.routing_key,
+            properties: publish.properties,
+            payload: publish.payload,
+            redelivered: false,
+        };
+        // Sender-selected distribution: also route the message with its CC and BCC keys
+        let distribution = SenderSelectedDistribution::from_properties(&message.properties);
+        let mut queues = self.route(&message);
+        for routing_key in distribution.cc.iter().chain(&distribution.bcc) {
+            let copy = Message {
+                routing_key: routing_key.to_string(),
+                ..message.clone()
+            };
+            for queue in self.route(&copy) {
+                if !queues.contains(&queue) {
+                    queues.push(queue);
+                }
+            }
+        }
+        if let Some(headers) = message.properties.headers().as_ref() {
+            if headers.contains_key("BCC") {
+                let headers = headers
+                    .inner()
+                    .iter()
+                    .filter(|(key, _)| key.as_str() != "BCC")
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect::<BTreeMap<_, _>>();
+                message.properties = message.properties.clone().with_headers(headers.into());
+            }
+        }
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+        if queues.is_empty() && publish.mandatory {
+            connection.send_content(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Return(basic::Return {
+                    reply_code: AMQPSoftError::NOROUTE.get_id(),
+                    reply_text: "NO_ROUTE".into(),
+                    exchange: message.exchange.as_str().into(),
+                    routing_key: message.routing_key.as_str().into(),
+                })),
+                &message,
+            );
+        }
+        if let Some(channel) = connection.channels.get_mut(&channel_id) {
+            if channel.confirm {
+                channel.published += 1;
+                let delivery_tag = channel.published;
+                connection.send_method(
+                    channel_id,
+                    AMQPClass::Basic(basic::AMQPMethod::Ack(basic::Ack {
+                        delivery_tag,
+                        multiple: false,
+                    })),
+                );
+            }
+        }
+        for name in &queues {
+            if let Some(queue) = self.queues.get_mut(name) {
+                queue.messages.push_back(message.clone());
+            }
+        }
+        for name in queues {
+            self.dispatch(&name);
+        }
+    }
+
+    fn route(&self, message: &Message) -> Vec<String> {
+        if message.exchange.is_empty() {
+            return if self.queues.contains_key(&message.routing_key) {
+                vec![message.routing_key.clone()]
+            } else {
+                Vec::new()
+            };
+        }
+        let mut queues = Vec::new();
+        let mut visited = HashSet::new();
+        let mut exchanges = vec![message.exchange.as_str()];
+        while let Some(name) = exchanges.pop() {
+            if !visited.insert(name) {
+                continue;
+            }
+            let Some(exchange) = self.exchanges.get(name) else {
+                continue;
+            };
+            for binding in &exchange.bindings {
+                if !binding_matches(&exchange.kind, binding, message) {
+                    continue;
+                }
+                match &binding.destination {
+                    Destination::Queue(queue) => {
+                        if !queues.contains(queue) {
+                            queues.push(queue.clone());
+                        }
+                    }
+                    Destination::Exchange(exchange) => exchanges.push(exchange),
+                }
+            }
+        }
+        queues
+    }
+
+    fn dispatch_all(&mut self) {
+        let queues = self.queues.keys().cloned().collect::<Vec<_>>();
+        for name in queues {
+            self.dispatch(&name);
+        }
+    }
+
+    // Deliver the ready messages of a queue to its consumers, in a round-robin fashion
+    fn dispatch(&mut self, name: &str) {
+        let Some(queue) = self.queues.get_mut(name) else {
+            return;
+        };
+        while !queue.messages.is_empty() && !queue.consumers.is_empty() {
+            let count = queue.consumers.len();
+            let Some(index) =
+                (0..count)
+                    .map(|i| (queue.next_consumer + i) % count)
+                    .find(|&index| {
+                        let consumer = &queue.consumers[index];
+                        consumer.no_ack
+                            || self
+                                .connections
+                                .get(&consumer.connection)
+                                .and_then(|connection| connection.channels.get(&consumer.channel))
+                                .is_some_and(|channel| {
+                                    !channel.closing
+                                        && (channel.prefetch == 0
+                                            || channel.unacked.len() < channel.prefetch as usize)
+                                })
+                    })
+            else {
+                return;
+            };
+            queue.next_consumer = (index + 1) % count;
+            let consumer = &queue.consumers[index];
+            let Some(connection) = self.connections.get_mut(&consumer.connection) else {
+                return;
+            };
+            let Some(channel) = connection.channels.get_mut(&consumer.channel) else {
+                return;
+            };
+            let Some(message) = queue.messages.pop_front() else {
+                return;
+            };
+            channel.delivery_tag += 1;
+            let delivery_tag = channel.delivery_tag;
+            if !consumer.no_ack {
+                channel
+                    .unacked
+                    .insert(delivery_tag, (name.to_owned(), message.clone()));
+            }
+            connection.send_content(
+                consumer.channel,
+                AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                    consumer_tag: consumer.tag.as_str().into(),
+                    delivery_tag,
+                    redelivered: message.redelivered,
+                    exchange: message.exchange.as_str().into(),
+                    routing_key: message.routing_key.as_str().into(),
+                })),
+                &message,
+            );
+        }
+    }
+
+    fn settle(
+        &mut self,
+        id: u64,
+        channel_id: ChannelId,
+        delivery_tag: LongLongUInt,
+        multiple: bool,
+        requeue: bool,
+    ) -> std::result::Result<(), (u16, String)> {
+        let Some(channel) = self.channel(id, channel_id) else {
+            return Ok(());
+        };
+        let settled = if multiple {
+            let tags = channel
+                .unacked
+                .keys()
+                .copied()
+                .filter(|&tag| delivery_tag == 0 || tag <= delivery_tag)
+                .collect::<Vec<_>>();
+            tags.into_iter()
+                .filter_map(|tag| channel.unacked.remove(&tag))
+                .collect()
+        } else {
+            vec![channel.unacked.remove(&delivery_tag).ok_or_else(|| {
+                (
+                    AMQPSoftError::PRECONDITIONFAILED.get_id(),
+                    format!(
+                        "PRECONDITION_FAILED - unknown delivery tag {}",
+                        delivery_tag
+                    ),
+                )
+            })?]
+        };
+        if requeue {
+            self.requeue(settled);
+        }
+        self.dispatch_all();
+        Ok(())
+    }
+
+    fn requeue(&mut self, messages: Vec<(String, Message)>) {
+        for (name, mut message) in messages.into_iter().rev() {
+            if let Some(queue) = self.queues.get_mut(&name) {
+                message.redelivered = true;
+                queue.messages.push_front(message);
+            }
+        }
+    }
+
+    fn requeue_unacked(&mut self, id: u64, channel_id: ChannelId) {
+        let Some(channel) = self.channel(id, channel_id) else {
+            return;
+        };
+        let unacked = std::mem::take(&mut channel.unacked);
+        self.requeue(unacked.into_values().collect());
+        self.dispatch_all();
+    }
+
+    // Cancel the consumers of a channel and requeue its unacked messages
+    fn close_channel(&mut self, id: u64, channel_id: ChannelId) {
+        for queue in self.queues.values_mut() {
+            queue
+                .consumers
+                .retain(|consumer| consumer.connection != id || consumer.channel != channel_id);
+            queue.next_consumer = 0;
+        }
+        self.requeue_unacked(id, channel_id);
+    }
+
+    fn channel_error(
+        &mut self,
+        id: u64,
+        channel_id: ChannelId,
+        reply_code: u16,
+        reply_text: String,
+        method: &AMQPClass,
+    ) {
+        trace!(reply_code, %reply_text, "mock broker closing channel");
+        self.close_channel(id, channel_id);
+        if let Some(channel) = self.channel(id, channel_id) {
+            channel.closing = true;
+            channel.publish = None;
+        }
+        self.send_method(
+            id,
+            channel_id,
+            AMQPClass::Channel(channel::AMQPMethod::Close(channel::Close {
+                reply_code,
+                reply_text: reply_text.into(),
+                class_id: method.get_amqp_class_id(),
+                method_id: method.get_amqp_method_id(),
+            })),
+        );
+    }
+}
+
+fn not_found(kind: &str, name: &str) -> (u16, String) {
+    (
+        AMQPSoftError::NOTFOUND.get_id(),
+        format!("NOT_FOUND - no {} '{}' in vhost '/'", kind, name),
+    )
+}
+
+fn binding_matches(kind: &str, binding: &Binding, message: &Message) -> bool {
+    match kind {
+        "fanout" => true,
+        "topic" => topic_matches(&binding.routing_key, &message.routing_key),
+        "headers" => headers_match(&binding.arguments, message.properties.headers().as_ref()),
+        _ => binding.routing_key == message.routing_key,
+    }
+}
+
+fn topic_matches(pattern: &str, routing_key: &str) -> bool {
+    fn matches(pattern: &[&str], words: &[&str]) -> bool {
+        match (pattern.split_first(), words.split_first()) {
+            (None, None) => true,
+            (Some((&"#", rest)), _) => {
+                matches(rest, words) || (!words.is_empty() && matches(pattern, &words[1..]))
+            }
+            (Some((&"*", rest)), Some((_, words))) => matches(rest, words),
+            (Some((word, rest)), Some((other, words))) if word == other => matches(rest, words),
+            _ => false,
+        }
+    }
+    let pattern = pattern.split('.').collect::<Vec<_>>();
+    let words = routing_key.split('.').collect::<Vec<_>>();
+    matches(&pattern, &words)
+}
+
+fn headers_match(arguments: &FieldTable, headers: Option<&FieldTable>) -> bool {
+    let any = matches!(
+        arguments.inner().get("x-match"),
+        Some(AMQPValue::LongString(x_match)) if x_match.to_string() == "any"
+    );
+    let mut expected = arguments
+        .inner()
+        .iter()
+        .filter(|(key, _)| !key.as_str().starts_with("x-"));
+    let matches = |(key, value): (&_, &AMQPValue)| {
+        headers.and_then(|headers| headers.inner().get(key)) == Some(value)
+    };
+    if any {
+        expected.any(matches)
+    } else {
+        expected.all(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::*, ExchangeKind};
+    use futures_lite::StreamExt;
+
+    #[test]
+    fn topic_routing() {
+        assert!(topic_matches("a.*", "a.b"));
+        assert!(!topic_matches("a.*", "a.b.c"));
+        assert!(topic_matches("a.#", "a.b.c"));
+        assert!(topic_matches("a.#", "a"));
+        assert!(topic_matches("#.c", "a.b.c"));
+        assert!(!topic_matches("a.b", "a.c"));
+    }
+
+    #[test]
+    fn publish_consume_ack() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .exchange_declare(
+                    "events",
+                    ExchangeKind::Topic,
+                    ExchangeDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let queue = channel
+                .queue_declare("", QueueDeclareOptions::default(), FieldTable::default())
+                .await?;
+            channel
+                .queue_bind(
+                    queue.name().as_str(),
+                    "events",
+                    "user.*",
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+
+            let confirm = channel
+                .basic_publish(
+                    "events",
+                    "user.created",
+                    BasicPublishOptions::default(),
+                    b"hello",
+                    BasicProperties::default(),
+                )
+                .await?
+                .await?;
+            assert!(confirm.is_ack());
+            assert_eq!(broker.message_count(queue.name().as_str()), Some(1));
+
+            let confirm = channel
+                .basic_publish(
+                    "events",
+                    "order.created",
+                    BasicPublishOptions {
+                        mandatory: true,
+                        ..BasicPublishOptions::default()
+                    },
+                    b"unroutable",
+                    BasicProperties::default(),
+                )
+                .await?
+                .await?;
+            let returned = confirm.take_message().expect("returned message");
+            assert_eq!(&returned.delivery.data[..], b"unroutable");
+
+            let mut consumer = channel
+                .basic_consume(
+                    queue.name().as_str(),
+                    "",
+                    BasicConsumeOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let delivery = consumer.next().await.expect("delivery")?;
+            assert_eq!(&delivery.data[..], b"hello");
+            assert_eq!(delivery.routing_key.as_str(), "user.created");
+            delivery.nack(BasicNackOptions::default()).await?;
+            assert_eq!(broker.message_count(queue.name().as_str()), Some(0));
+
+            connection.close(200, "OK").await
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn get_requeue_and_errors() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            channel
+                .basic_publish(
+                    "",
+                    "jobs",
+                    BasicPublishOptions::default(),
+                    b"job",
+                    BasicProperties::default(),
+                )
+                .await?;
+
+            let message = channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .expect("message");
+            assert!(!message.delivery.redelivered);
+            message
+                .delivery
+                .reject(BasicRejectOptions { requeue: true })
+                .await?;
+            let message = channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .expect("message");
+            assert!(message.delivery.redelivered);
+            message.delivery.ack(BasicAckOptions::default()).await?;
+            assert!(channel
+                .basic_get("jobs", BasicGetOptions::default())
+                .await?
+                .is_none());
+
+            let error = channel
+                .queue_declare(
+                    "missing",
+                    QueueDeclareOptions {
+                        passive: true,
+                        ..QueueDeclareOptions::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await
+                .unwrap_err();
+            assert!(error.is_amqp_soft_error());
+            assert!(!channel.status().connected());
+            Ok::<(), crate::Error>(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn unexpected_frame() {
+        let publish = AMQPClass::Basic(basic::AMQPMethod::Publish(basic::Publish {
+            exchange: "".into(),
+            routing_key: "jobs".into(),
+            mandatory: false,
+            immediate: false,
+        }));
+        let mut broker = Broker::default();
+        let pipe = Arc::new(Pipe::default());
+        let id = broker.register(pipe.clone());
+        for frame in [
+            AMQPFrame::Method(
+                1,
+                AMQPClass::Channel(channel::AMQPMethod::Open(channel::Open {})),
+            ),
+            AMQPFrame::Method(1, publish.clone()),
+            // Not the header of the publish
+            AMQPFrame::Method(1, publish),
+        ] {
+            let bytes = gen_frame(&frame)(WriteContext::from(Vec::new()))
+                .unwrap()
+                .write;
+            broker.receive(id, &bytes);
+        }
+        let sent = pipe.lock_inner().data.iter().copied().collect::<Vec<_>>();
+        let frames = std::iter::successors(Some(&sent[..]), |data| {
+            frame_size(data).map(|size| &data[size..])
+        })
+        .filter_map(|data| parse_frame(data).ok().map(|(_, frame)| frame))
+        .collect::<Vec<_>>();
+        assert!(matches!(
+            frames.last(),
+            Some(AMQPFrame::Method(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::Close(close))
+            )) if close.reply_code == AMQPHardError::UNEXPECTEDFRAME.get_id()
+        ));
+        assert!(broker.connections[&id].closing);
+    }
+}
@@ -0,0 +1,122 @@
+//! Conversions between AMQP timestamps, which are a number of seconds since the UNIX epoch, and
+//! date types.
+//!
+//! Conversions with `time::OffsetDateTime` require the `time` feature, and the ones with
+//! `chrono::DateTime` the `chrono` feature.
+//!
+//! ```rust
+//! use lapin::{fields, timestamp, types::AMQPValue, BasicProperties, FieldTableExt};
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! let sent_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+//! let properties = BasicProperties::default()
+//!     .with_timestamp(timestamp::from_system_time(sent_at))
+//!     .with_headers(fields! { "x-sent-at" => sent_at });
+//! assert_eq!(properties.timestamp(), &Some(1_700_000_000));
+//! let headers = properties.headers().as_ref().unwrap();
+//! assert_eq!(headers.get_timestamp("x-sent-at"), Some(sent_at));
+//! ```
+
+use crate::types::{AMQPValue, Timestamp};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The current time, as an AMQP timestamp
+pub fn now() -> Timestamp {
+    from_system_time(SystemTime::now())
+}
+
+/// Truncate a time to the second, times before the UNIX epoch becoming 0
+pub fn from_system_time(time: SystemTime) -> Timestamp {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn to_system_time(timestamp: Timestamp) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(timestamp)
+}
+
+/// Truncate a date to the second, dates before the UNIX epoch becoming 0
+#[cfg(feature = "time")]
+pub fn from_offset_date_time(date: time::OffsetDateTime) -> Timestamp {
+    date.unix_timestamp().try_into().unwrap_or_default()
+}
+
+/// `None` if the timestamp is too far in the future to be represented
+#[cfg(feature = "time")]
+pub fn to_offset_date_time(timestamp: Timestamp) -> Option<time::OffsetDateTime> {
+    time::OffsetDateTime::from_unix_timestamp(timestamp.try_into().ok()?).ok()
+}
+
+/// Truncate a date to the second, dates before the UNIX epoch becoming 0
+#[cfg(feature = "chrono")]
+pub fn from_date_time<Tz: chrono::TimeZone>(date: chrono::DateTime<Tz>) -> Timestamp {
+    date.timestamp().try_into().unwrap_or_default()
+}
+
+/// `None` if the timestamp is too far in the future to be represented
+#[cfg(feature = "chrono")]
+pub fn to_date_time(timestamp: Timestamp) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::from_timestamp(timestamp.try_into().ok()?, 0)
+}
+
+/// Read a timestamp from a header value, either a timestamp or a non negative integer
+pub(crate) fn from_value(value: &AMQPValue) -> Option<Timestamp> {
+    match *value {
+        AMQPValue::Timestamp(timestamp) => Some(timestamp),
+        AMQPValue::LongLongInt(seconds) => seconds.try_into().ok(),
+        AMQPValue::LongInt(seconds) => seconds.try_into().ok(),
+        AMQPValue::LongUInt(seconds) => Some(seconds.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::Delivery, BasicProperties};
+
+    #[test]
+    fn conversions() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_500);
+        assert_eq!(from_system_time(time), 1);
+        assert_eq!(to_system_time(1), UNIX_EPOCH + Duration::from_secs(1));
+        assert_eq!(from_system_time(UNIX_EPOCH - Duration::from_secs(1)), 0);
+        assert_eq!(from_value(&AMQPValue::LongLongInt(-1)), None);
+        assert_eq!(from_value(&AMQPValue::LongUInt(42)), Some(42));
+    }
+
+    #[test]
+    fn delivery_age() {
+        let mut delivery = Delivery::new(1, 1, "".into(), "".into(), false, None, None, None);
+        assert_eq!(delivery.age(), None);
+        delivery.properties = BasicProperties::default().with_timestamp(now() - 60);
+        let age = delivery.age().unwrap();
+        assert!(age >= Duration::from_secs(60) && age < Duration::from_secs(62));
+        delivery.properties = BasicProperties::default().with_timestamp(now() + 60);
+        assert_eq!(delivery.age(), None);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn offset_date_time() {
+        let date = time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        assert_eq!(from_offset_date_time(date), 1_700_000_000);
+        assert_eq!(to_offset_date_time(1_700_000_000), Some(date));
+        assert_eq!(to_offset_date_time(u64::MAX), None);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn date_time() {
+        let date = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(from_date_time(date), 1_700_000_000);
+        assert_eq!(from_date_time(date.fixed_offset()), 1_700_000_000);
+        assert_eq!(
+            from_date_time(chrono::DateTime::UNIX_EPOCH - chrono::TimeDelta::seconds(1)),
+            0
+        );
+        assert_eq!(to_date_time(1_700_000_000), Some(date));
+        assert_eq!(to_date_time(u64::MAX), None);
+    }
+}
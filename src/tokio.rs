@@ -0,0 +1,286 @@
+//! Run lapin on a tokio runtime
+//!
+//! [`ConnectionProperties::with_tokio`] makes the connection spawn its tasks (the io loop,
+//! heartbeats, consumer delegates, internal RPC) on the given runtime, and registers its socket
+//! and its timers in the IO and time drivers of that same runtime. They are bound to the
+//! [`Handle`] rather than to whichever runtime happens to be current in the calling thread, so
+//! the connection works from within another runtime, or from no runtime at all.
+//!
+//! The TCP connection and the TLS handshake run on the blocking pool of the runtime. TLS is
+//! lapin's own stream, over the registered socket, rather than `tokio-rustls` or
+//! `tokio-native-tls`. The dispatch workers, when enabled, keep running in threads of their own.
+//!
+//! `with_tokio` is only available on unix, where tokio can register the file descriptor of the
+//! socket. Elsewhere, [`TokioExecutor`] can still run the tasks, the socket and the timers
+//! being left to the default reactor.
+//!
+//! ```rust,no_run
+//! use lapin::{Connection, ConnectionProperties};
+//!
+//! #[tokio::main]
+//! async fn main() -> lapin::Result<()> {
+//!     let options = ConnectionProperties::default()
+//!         .with_tokio(tokio::runtime::Handle::current());
+//!     let _connection = Connection::connect("amqp://127.0.0.1:5672/%2f", options).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [`ConnectionProperties::with_tokio`]: ../struct.ConnectionProperties.html#method.with_tokio
+
+use ::tokio::{runtime::Handle, task::JoinHandle, time};
+use async_trait::async_trait;
+use executor_trait::{BlockingExecutor, Executor, FullExecutor, LocalExecutorError, Task};
+use futures_core::Stream;
+use reactor_trait::TimeReactor;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// An executor spawning tasks on a tokio runtime
+#[derive(Debug, Clone)]
+pub struct TokioExecutor(Handle);
+
+impl TokioExecutor {
+    pub fn new(handle: Handle) -> Self {
+        Self(handle)
+    }
+}
+
+impl FullExecutor for TokioExecutor {}
+
+impl Executor for TokioExecutor {
+    fn block_on(&self, f: Pin<Box<dyn Future<Output = ()>>>) {
+        self.0.block_on(f);
+    }
+
+    fn spawn(&self, f: Pin<Box<dyn Future<Output = ()> + Send>>) -> Box<dyn Task> {
+        Box::new(TokioTask(self.0.spawn(f)))
+    }
+
+    fn spawn_local(
+        &self,
+        f: Pin<Box<dyn Future<Output = ()>>>,
+    ) -> Result<Box<dyn Task>, LocalExecutorError> {
+        Err(LocalExecutorError(f))
+    }
+}
+
+#[async_trait]
+impl BlockingExecutor for TokioExecutor {
+    async fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send + 'static>) {
+        self.0
+            .spawn_blocking(f)
+            .await
+            .expect("blocking task failed");
+    }
+}
+
+struct TokioTask(JoinHandle<()>);
+
+#[async_trait(?Send)]
+impl Task for TokioTask {
+    async fn cancel(self: Box<Self>) -> Option<()> {
+        self.0.abort();
+        self.0.await.ok()
+    }
+}
+
+impl Future for TokioTask {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|res| res.expect("task has been canceled"))
+    }
+}
+
+/// A reactor registering sockets and timers in the drivers of a tokio runtime
+#[derive(Debug, Clone)]
+pub struct TokioReactor(Handle);
+
+impl TokioReactor {
+    pub fn new(handle: Handle) -> Self {
+        Self(handle)
+    }
+}
+
+#[async_trait]
+impl TimeReactor for TokioReactor {
+    async fn sleep(&self, dur: Duration) {
+        let sleep = {
+            let _guard = self.0.enter();
+            time::sleep(dur)
+        };
+        sleep.await;
+    }
+
+    fn interval(&self, dur: Duration) -> Box<dyn Stream<Item = Instant>> {
+        let _guard = self.0.enter();
+        Box::new(Interval(time::interval(dur)))
+    }
+}
+
+struct Interval(time::Interval);
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_tick(cx).map(|instant| Some(instant.into_std()))
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::TokioReactor;
+    use ::tokio::io::unix::AsyncFd;
+    use futures_io::{AsyncRead, AsyncWrite};
+    use reactor_trait::{AsyncIOHandle, IOHandle, Reactor};
+    use std::{
+        io::{self, IoSlice, IoSliceMut, Read, Write},
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    impl Reactor for TokioReactor {
+        fn register(&self, socket: IOHandle) -> io::Result<Box<dyn AsyncIOHandle + Send>> {
+            let _guard = self.0.enter();
+            Ok(Box::new(TokioIO(AsyncFd::new(socket)?)))
+        }
+    }
+
+    struct TokioIO(AsyncFd<IOHandle>);
+
+    impl TokioIO {
+        fn poll_io<R>(
+            &mut self,
+            cx: &mut Context<'_>,
+            write: bool,
+            mut op: impl FnMut(&mut IOHandle) -> io::Result<R>,
+        ) -> Poll<io::Result<R>> {
+            loop {
+                let ready = if write {
+                    self.0.poll_write_ready_mut(cx)
+                } else {
+                    self.0.poll_read_ready_mut(cx)
+                };
+                let mut guard = match ready {
+                    Poll::Ready(Ok(guard)) => guard,
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => return Poll::Pending,
+                };
+                if let Ok(res) = guard.try_io(|socket| op(socket.get_mut())) {
+                    return Poll::Ready(res);
+                }
+            }
+        }
+    }
+
+    impl AsyncRead for TokioIO {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut()
+                .poll_io(cx, false, |socket| socket.read(&mut *buf))
+        }
+
+        fn poll_read_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &mut [IoSliceMut<'_>],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut()
+                .poll_io(cx, false, |socket| socket.read_vectored(&mut *bufs))
+        }
+    }
+
+    impl AsyncWrite for TokioIO {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut().poll_io(cx, true, |socket| socket.write(buf))
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            self.get_mut()
+                .poll_io(cx, true, |socket| socket.write_vectored(bufs))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.get_mut().poll_io(cx, true, |socket| socket.flush())
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.poll_flush(cx)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timers_outside_of_the_runtime() {
+        let runtime = ::tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let reactor = TokioReactor::new(runtime.handle().clone());
+        // No tokio runtime is running on this thread
+        futures_lite::future::block_on(async {
+            let start = Instant::now();
+            reactor.sleep(Duration::from_millis(10)).await;
+            assert!(start.elapsed() >= Duration::from_millis(10));
+            let mut interval = Box::into_pin(reactor.interval(Duration::from_millis(5)));
+            futures_lite::StreamExt::next(&mut interval).await;
+            futures_lite::StreamExt::next(&mut interval).await;
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn io_loop_on_the_runtime() {
+        use crate::{
+            options::QueueDeclareOptions, testing::MockBroker, types::FieldTable,
+            ConnectionProperties,
+        };
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let runtime = ::tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let properties = ConnectionProperties::default().with_tokio(runtime.handle().clone());
+        assert!(properties.spawn_io_loop);
+        let broker = MockBroker::default();
+        // Nothing but the runtime drives the connection
+        futures_lite::future::block_on(async {
+            let connection = broker.connect(properties).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            connection.close(0, "").await
+        })
+        .unwrap();
+        assert!(broker.queue_exists("jobs"));
+    }
+}
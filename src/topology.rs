@@ -5,6 +5,7 @@ use crate::{
     options::{BasicConsumeOptions, ExchangeDeclareOptions, QueueDeclareOptions},
     queue::Queue,
     types::{FieldTable, ShortString},
+    Error,
 };
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
@@ -73,6 +74,68 @@ pub struct ConsumerDefinition {
     pub arguments: FieldTable,
 }
 
+/// A declaration made by [`Connection::declare_all`]
+///
+/// [`Connection::declare_all`]: ../struct.Connection.html#method.declare_all
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Declaration {
+    /// The declaration of an exchange
+    Exchange(ShortString),
+    /// The binding of the `destination` exchange to the `source` one
+    ExchangeBinding {
+        destination: ShortString,
+        source: ShortString,
+        routing_key: ShortString,
+    },
+    /// The declaration of a queue
+    Queue(ShortString),
+    /// The binding of a queue to an exchange
+    QueueBinding {
+        queue: ShortString,
+        exchange: ShortString,
+        routing_key: ShortString,
+    },
+}
+
+/// A declaration which failed, along with the reason why
+#[derive(Clone, Debug)]
+pub struct DeclareFailure {
+    pub declaration: Declaration,
+    pub error: Error,
+}
+
+/// The outcome of [`Connection::declare_all`], listed in the order of the topology definition
+///
+/// [`Connection::declare_all`]: ../struct.Connection.html#method.declare_all
+#[derive(Clone, Debug, Default)]
+pub struct DeclareReport {
+    pub(crate) declared: Vec<Declaration>,
+    pub(crate) queues: Vec<Queue>,
+    pub(crate) failures: Vec<DeclareFailure>,
+}
+
+impl DeclareReport {
+    /// The declarations which succeeded
+    pub fn declared(&self) -> &[Declaration] {
+        &self.declared
+    }
+
+    /// The queues successfully declared, with their message and consumer counts
+    pub fn queues(&self) -> &[Queue] {
+        &self.queues
+    }
+
+    /// The declarations which failed
+    pub fn failures(&self) -> &[DeclareFailure] {
+        &self.failures
+    }
+
+    /// Whether all the declarations succeeded
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
 #[derive(Default)]
 pub struct RestoredTopology {
     pub(crate) queues: Vec<Queue>,
@@ -69,6 +69,7 @@ impl From<ChannelDefinitionInternal> for ChannelDefinition {
 pub(crate) struct QueueDefinitionInternal {
     definition: QueueDefinition,
     declared: bool,
+    recover: bool,
 }
 
 impl QueueDefinitionInternal {
@@ -85,6 +86,7 @@ impl QueueDefinitionInternal {
                 bindings: Vec::new(),
             },
             declared: true,
+            recover: true,
         }
     }
 
@@ -97,6 +99,7 @@ impl QueueDefinitionInternal {
                 bindings: Vec::new(),
             },
             declared: false,
+            recover: true,
         }
     }
 
@@ -110,6 +113,19 @@ impl QueueDefinitionInternal {
         self.declared
     }
 
+    pub(crate) fn recover(&self) -> bool {
+        self.recover
+    }
+
+    pub(crate) fn skip_recovery(&mut self) {
+        self.recover = false;
+    }
+
+    /// Server-named queues get a name in the reserved amq. namespace
+    pub(crate) fn is_server_named(&self) -> bool {
+        self.definition.name.as_str().starts_with("amq.")
+    }
+
     pub(crate) fn is_exclusive(&self) -> bool {
         self.definition.options.is_some_and(|o| o.exclusive)
     }
@@ -154,6 +170,7 @@ impl From<QueueDefinition> for QueueDefinitionInternal {
         Self {
             definition,
             declared: true,
+            recover: true,
         }
     }
 }
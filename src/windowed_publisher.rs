@@ -0,0 +1,275 @@
+#[cfg(feature = "tower")]
+use crate::Error;
+use crate::{publisher_confirm::Confirmation, Channel, Envelope, Result};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+/// The future returned by [`WindowedPublisher::call`], resolving once the broker confirmed the
+/// message
+///
+/// [`WindowedPublisher::call`]: ./struct.WindowedPublisher.html#method.call
+pub type PublishFuture = Pin<Box<dyn Future<Output = Result<Confirmation>> + Send>>;
+
+/// Publisher handle limiting how many messages can be waiting for their publisher confirm.
+///
+/// It follows the `poll_ready`/`call` contract of `tower::Service<Envelope>`, which it
+/// implements with the `tower` feature: [`poll_ready`] only returns `Ready` once there is room
+/// in the confirm window, and reserves that room for the next [`call`]. Each clone of the handle
+/// shares the same window but has its own reservation.
+///
+/// The channel should be in confirm mode, otherwise the messages are confirmed right away and
+/// the window never fills up.
+///
+/// [`poll_ready`]: #method.poll_ready
+/// [`call`]: #method.call
+pub struct WindowedPublisher {
+    channel: Channel,
+    window: Arc<Mutex<Window>>,
+    reserved: Option<Permit>,
+}
+
+impl WindowedPublisher {
+    /// Allow at most `size` messages (at least one) to wait for their confirm
+    pub fn new(channel: Channel, size: usize) -> Self {
+        Self {
+            channel,
+            window: Arc::new(Mutex::new(Window {
+                size: size.max(1),
+                in_flight: 0,
+                wakers: Vec::new(),
+            })),
+            reserved: None,
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// The number of messages published through this window and not confirmed yet
+    pub fn in_flight(&self) -> usize {
+        lock_window(&self.window).in_flight
+    }
+
+    /// Wait for room in the confirm window, reserving it for the next call
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.reserved.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        let mut window = lock_window(&self.window);
+        if window.in_flight < window.size {
+            window.in_flight += 1;
+            drop(window);
+            self.reserved = Some(Permit(self.window.clone()));
+            return Poll::Ready(Ok(()));
+        }
+        window.wakers.push(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Wait for room in the confirm window
+    pub async fn ready(&mut self) -> Result<&mut Self> {
+        std::future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        Ok(self)
+    }
+
+    /// Publish a message, the room it takes in the window being released once it got confirmed
+    ///
+    /// If `poll_ready` wasn't called first, the message is published even if the window is
+    /// full.
+    pub fn call(&mut self, envelope: Envelope) -> PublishFuture {
+        let permit = self.reserved.take().unwrap_or_else(|| {
+            lock_window(&self.window).in_flight += 1;
+            Permit(self.window.clone())
+        });
+        let channel = self.channel.clone();
+        Box::pin(async move {
+            let _permit = permit;
+            channel.publish(&envelope).await?.await
+        })
+    }
+}
+
+#[cfg(feature = "tower")]
+impl tower::Service<Envelope> for WindowedPublisher {
+    type Response = Confirmation;
+    type Error = Error;
+    type Future = PublishFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        WindowedPublisher::poll_ready(self, cx)
+    }
+
+    fn call(&mut self, envelope: Envelope) -> Self::Future {
+        WindowedPublisher::call(self, envelope)
+    }
+}
+
+impl Clone for WindowedPublisher {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            window: self.window.clone(),
+            reserved: None,
+        }
+    }
+}
+
+impl fmt::Debug for WindowedPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let window = lock_window(&self.window);
+        f.debug_struct("WindowedPublisher")
+            .field("channel", &self.channel)
+            .field("size", &window.size)
+            .field("in_flight", &window.in_flight)
+            .finish()
+    }
+}
+
+struct Window {
+    size: usize,
+    in_flight: usize,
+    wakers: Vec<Waker>,
+}
+
+/* Room taken in the window, released when dropped */
+struct Permit(Arc<Mutex<Window>>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut window = lock_window(&self.0);
+            window.in_flight -= 1;
+            std::mem::take(&mut window.wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+fn lock_window(window: &Mutex<Window>) -> MutexGuard<'_, Window> {
+    window.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::*, testing::MockBroker, types::FieldTable, ConnectionProperties};
+    use futures_lite::future;
+
+    #[test]
+    fn backpressure() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut publisher = WindowedPublisher::new(channel.clone(), 1);
+            let mut other = publisher.clone();
+            publisher.ready().await?;
+            // The only room in the window is reserved
+            assert!(future::poll_once(other.ready()).await.is_none());
+            let confirm = publisher.call(Envelope::new("", "jobs", b"first".to_vec()));
+            assert_eq!(publisher.in_flight(), 1);
+            assert!(confirm.await?.is_ack());
+            assert_eq!(publisher.in_flight(), 0);
+            other.ready().await?;
+            assert!(other
+                .call(Envelope::new("", "jobs", b"second".to_vec()))
+                .await?
+                .is_ack());
+            assert_eq!(
+                channel
+                    .queue_declare(
+                        "jobs",
+                        QueueDeclareOptions {
+                            passive: true,
+                            ..QueueDeclareOptions::default()
+                        },
+                        FieldTable::default(),
+                    )
+                    .await?
+                    .message_count(),
+                2
+            );
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+
+    #[cfg(feature = "tower")]
+    #[test]
+    fn tower_service() {
+        use tower::Service;
+
+        /* Any tower service would do */
+        async fn send<S: Service<Envelope>>(
+            service: &mut S,
+            envelope: Envelope,
+        ) -> std::result::Result<S::Response, S::Error> {
+            std::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+            service.call(envelope).await
+        }
+
+        let _ = tracing_subscriber::fmt::try_init();
+
+        async_global_executor::block_on(async {
+            let broker = MockBroker::default();
+            let connection = broker.connect(ConnectionProperties::default()).await?;
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            channel
+                .queue_declare(
+                    "jobs",
+                    QueueDeclareOptions::default(),
+                    FieldTable::default(),
+                )
+                .await?;
+            let mut publisher = WindowedPublisher::new(channel.clone(), 1);
+            let mut other = publisher.clone();
+            assert!(
+                send(&mut publisher, Envelope::new("", "jobs", b"first".to_vec()))
+                    .await?
+                    .is_ack()
+            );
+
+            // The only room in the window is reserved
+            std::future::poll_fn(|cx| Service::poll_ready(&mut publisher, cx)).await?;
+            let waiting = future::poll_once(std::future::poll_fn(|cx| {
+                Service::poll_ready(&mut other, cx)
+            }))
+            .await;
+            assert!(waiting.is_none());
+            let confirm = Service::call(
+                &mut publisher,
+                Envelope::new("", "jobs", b"second".to_vec()),
+            );
+            assert!(confirm.await?.is_ack());
+            assert!(
+                send(&mut other, Envelope::new("", "jobs", b"third".to_vec()))
+                    .await?
+                    .is_ack()
+            );
+            assert_eq!(broker.message_count("jobs"), Some(3));
+            connection.close(0, "").await
+        })
+        .unwrap();
+    }
+}
@@ -79,6 +79,15 @@ impl Channel {
       return Err(self.status.state_error());
     }
 
+    {{#if method.metadata.capability ~}}
+    self.require_capability("{{method.metadata.capability}}")?;
+    {{/if ~}}
+    {{#if method.metadata.throttle_hook ~}}
+    self.throttle_{{snake class.name false}}_{{snake method.name false}}({{#each method.metadata.throttle_hook.params as |param| ~}}{{#unless @first ~}}, {{/unless ~}}{{param}}{{/each ~}}).await?;
+    {{/if ~}}
+    {{#if method.metadata.prepare_hook ~}}
+    let ({{#each method.metadata.prepare_hook.params as |param| ~}}{{#unless @first ~}}, {{/unless ~}}{{param}}{{/each ~}}) = self.prepare_{{snake class.name false}}_{{snake method.name false}}({{#each method.metadata.prepare_hook.params as |param| ~}}{{#unless @first ~}}, {{/unless ~}}{{param}}{{/each ~}});
+    {{/if ~}}
     {{#if method.metadata.start_hook ~}}
     {{#if method.metadata.start_hook.returns ~}}let start_hook_res = {{/if ~}}self.before_{{snake class.name false}}_{{snake method.name false}}({{#if method.metadata.start_hook.params ~}}{{#each method.metadata.start_hook.params as |param| ~}}{{#unless @first ~}}, {{/unless ~}}{{param}}{{/each ~}}{{/if ~}});
     {{/if ~}}
@@ -30,7 +30,7 @@ impl ConsumerDelegate for Subscriber {
             if let Some(delivery) = delivery.unwrap() {
                 info!(data=%std::str::from_utf8(&delivery.data).unwrap());
 
-                assert_eq!(delivery.data, b"Hello world!");
+                assert_eq!(delivery.data, &b"Hello world!"[..]);
 
                 subscriber.hello_world.fetch_add(1, Ordering::SeqCst);
 